@@ -0,0 +1,472 @@
+//Throughput and latency benchmarks for each node type. Every scenario drives a node
+//with a synthetic request stream, writing its output to an in-memory sink, and times
+//each call to handle_event. Run with:
+//
+//    cargo bench --bench nodes -- [FILTER] [--time SECS] [--out PATH]
+//
+//Results are written as JSON (default target/bench/nodes.json). If the file already
+//exists, each scenario is compared against the previous run before it is replaced,
+//and scenarios skipped by the filter keep their previous results.
+
+use event_horizon::node::broadcast::{BroadcastBody, BroadcastNode};
+use event_horizon::node::echo::{EchoBody, EchoNode};
+use event_horizon::node::generate_id::{GenerateGuidBody, GenerateGuidNode};
+use event_horizon::node::grow_counter::{CounterBody, CounterNode};
+use event_horizon::node::kafka::{KafkaBody, KafkaNode};
+use event_horizon::node::kv_store::{KVStoreBody, KVStoreNode};
+use event_horizon::rng::Rng;
+use event_horizon::{Event, MaelstromMessage, Node, NodeMetadata, Reply};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: cargo bench --bench nodes -- [FILTER] [--time SECS] [--out PATH]";
+
+//Scenarios stop after this many ops even if time remains, to bound memory use.
+const MAX_OPS: usize = 1_000_000;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct BenchResult {
+    name: String,
+    node: String,
+    ops: usize,
+    //Throughput over the time spent inside handle_event
+    ops_per_sec: f64,
+    p50_ns: u64,
+    p99_ns: u64,
+    p999_ns: u64,
+    max_ns: u64,
+    //Bytes the node wrote per op, on average
+    output_bytes_per_op: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct BenchReport {
+    results: Vec<BenchResult>,
+}
+
+struct Bench {
+    filter: Option<String>,
+    time: Duration,
+    results: Vec<BenchResult>,
+}
+
+fn message<Body>(src: &str, body: Body) -> Event<Body> {
+    Event::Message(MaelstromMessage {
+        src: src.to_owned(),
+        dest: "n0".to_owned(),
+        body,
+    })
+}
+
+fn percentile(sorted: &[u64], fraction: f64) -> u64 {
+    let rank = ((sorted.len() as f64 * fraction).ceil() as usize).max(1);
+    sorted[rank.min(sorted.len()) - 1]
+}
+
+impl Bench {
+    fn run<Body, NodeState>(
+        &mut self,
+        name: &str,
+        node: &str,
+        node_count: usize,
+        setup: impl FnOnce(&mut NodeState, &mut Vec<u8>),
+        mut request: impl FnMut(usize, &mut Rng) -> Event<Body>,
+    ) where
+        NodeState: Node<Body>,
+        Body: Reply<NodeState>,
+    {
+        //! Time `request` events against a fresh node until the time budget or
+        //! MAX_OPS runs out. Building each event is not timed.
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !name.contains(filter.as_str()))
+        {
+            return;
+        }
+        let node_metadata = NodeMetadata {
+            node_id: "n0".to_owned(),
+            node_ids: (0..node_count).map(|index| format!("n{}", index)).collect(),
+        };
+        //Timer events are never read, so only the scenario drives the node.
+        let (event_tx, event_rx) = channel();
+        let mut node_state = NodeState::node_init(node_metadata, event_tx);
+        let mut output = Vec::new();
+        setup(&mut node_state, &mut output);
+
+        let mut rng = Rng::seeded(0x5EED);
+        let mut latencies = Vec::new();
+        let mut output_bytes = 0;
+        let mut busy = Duration::ZERO;
+        while busy < self.time && latencies.len() < MAX_OPS {
+            let event = request(latencies.len(), &mut rng);
+            output.clear();
+            let start = Instant::now();
+            node_state.handle_event(event, &mut output);
+            let elapsed = start.elapsed();
+            busy += elapsed;
+            output_bytes += output.len();
+            latencies.push(elapsed.as_nanos() as u64);
+        }
+        drop(event_rx);
+
+        let ops = latencies.len();
+        if ops == 0 {
+            println!("{:<32} ran no ops, skipped", name);
+            return;
+        }
+        latencies.sort_unstable();
+        let result = BenchResult {
+            name: name.to_owned(),
+            node: node.to_owned(),
+            ops,
+            ops_per_sec: ops as f64 / busy.as_secs_f64(),
+            p50_ns: percentile(&latencies, 0.5),
+            p99_ns: percentile(&latencies, 0.99),
+            p999_ns: percentile(&latencies, 0.999),
+            max_ns: latencies[ops - 1],
+            output_bytes_per_op: output_bytes as f64 / ops as f64,
+        };
+        println!(
+            "{:<32} {:>12.0} ops/s  p50 {:>9}ns  p99 {:>9}ns  p999 {:>9}ns",
+            result.name, result.ops_per_sec, result.p50_ns, result.p99_ns, result.p999_ns
+        );
+        self.results.push(result);
+    }
+}
+
+fn echo(bench: &mut Bench) {
+    bench.run::<EchoBody, EchoNode>(
+        "echo",
+        "echo",
+        1,
+        |_, _| {},
+        |op, _| {
+            message(
+                "c1",
+                EchoBody::Echo {
+                    msg_id: op,
+                    echo: "Please echo 35".to_owned(),
+                },
+            )
+        },
+    );
+}
+
+fn generate_id(bench: &mut Bench) {
+    bench.run::<GenerateGuidBody, GenerateGuidNode>(
+        "generate_id",
+        "generate_id",
+        3,
+        |_, _| {},
+        |op, _| message("c1", GenerateGuidBody::Generate { msg_id: op }),
+    );
+}
+
+fn grow_counter(bench: &mut Bench) {
+    bench.run::<CounterBody, CounterNode>(
+        "grow_counter/add_read",
+        "grow_counter",
+        5,
+        |_, _| {},
+        |op, rng| {
+            if rng.chance(0.5) {
+                message(
+                    "c1",
+                    CounterBody::Add {
+                        msg_id: op,
+                        delta: rng.below(10),
+                    },
+                )
+            } else {
+                message("c1", CounterBody::Read { msg_id: op })
+            }
+        },
+    );
+    //Merging counters from a large cluster
+    let node_counter_map: HashMap<String, usize> = (0..100)
+        .map(|index| (format!("n{}", index), index))
+        .collect();
+    bench.run::<CounterBody, CounterNode>(
+        "grow_counter/update_counters_100",
+        "grow_counter",
+        100,
+        |_, _| {},
+        move |op, _| {
+            message(
+                "n1",
+                CounterBody::UpdateCounters {
+                    msg_id: op,
+                    node_counter_map: node_counter_map.clone(),
+                },
+            )
+        },
+    );
+}
+
+fn kafka(bench: &mut Bench) {
+    bench.run::<KafkaBody, KafkaNode>(
+        "kafka/send",
+        "kafka",
+        1,
+        |_, _| {},
+        |op, rng| {
+            message(
+                "c1",
+                KafkaBody::Send {
+                    msg_id: op,
+                    key: format!("k{}", rng.below(100)),
+                    msg: op,
+                },
+            )
+        },
+    );
+    for keys in [10, 1_000, 10_000] {
+        //Every key holds 100 messages, and each poll asks for every key
+        //from a random offset.
+        let key_names: Vec<String> = (0..keys).map(|key| format!("k{}", key)).collect();
+        let setup_keys = key_names.clone();
+        bench.run::<KafkaBody, KafkaNode>(
+            &format!("kafka/poll_{}_keys", keys),
+            "kafka",
+            1,
+            move |node, output| {
+                for key in setup_keys.iter() {
+                    for msg in 0..100 {
+                        let send = message(
+                            "c1",
+                            KafkaBody::Send {
+                                msg_id: msg,
+                                key: key.clone(),
+                                msg,
+                            },
+                        );
+                        node.handle_event(send, output);
+                    }
+                }
+            },
+            move |op, rng| {
+                let offsets = key_names
+                    .iter()
+                    .map(|key| (key.clone(), rng.below(100)))
+                    .collect();
+                message(
+                    "c1",
+                    KafkaBody::Poll {
+                        msg_id: op,
+                        offsets,
+                    },
+                )
+            },
+        );
+    }
+}
+
+fn kv_store(bench: &mut Bench) {
+    for size in [1, 10, 100] {
+        bench.run::<KVStoreBody, KVStoreNode>(
+            &format!("kv_store/txn_{}", size),
+            "kv_store",
+            3,
+            |_, _| {},
+            move |op, rng| {
+                let txn = (0..size)
+                    .map(|_| {
+                        let key = rng.below(1_000);
+                        if rng.chance(0.5) {
+                            ("r".to_owned(), key, None)
+                        } else {
+                            ("w".to_owned(), key, Some(rng.below(1_000_000)))
+                        }
+                    })
+                    .collect();
+                message("c1", KVStoreBody::Txn { msg_id: op, txn })
+            },
+        );
+    }
+    //Replicating writes to the other nodes, interleaved with client txns
+    bench.run::<KVStoreBody, KVStoreNode>(
+        "kv_store/txn_10_propogate",
+        "kv_store",
+        5,
+        |_, _| {},
+        |op, rng| {
+            if op % 10 == 9 {
+                return Event::PropogateWrites;
+            }
+            let txn = (0..10)
+                .map(|_| ("w".to_owned(), rng.below(1_000), Some(op)))
+                .collect();
+            message("c1", KVStoreBody::Txn { msg_id: op, txn })
+        },
+    );
+}
+
+fn broadcast_node_with(
+    messages: usize,
+    neighbors: usize,
+) -> impl FnOnce(&mut BroadcastNode, &mut Vec<u8>) {
+    move |node, _| {
+        let payloads = (0..messages).map(|message| json!(message)).collect();
+        node.deliver(&(0..messages).collect(), payloads, Vec::new());
+        node.neighbors = (1..=neighbors).map(|index| format!("n{}", index)).collect();
+    }
+}
+
+fn broadcast(bench: &mut Bench) {
+    bench.run::<BroadcastBody, BroadcastNode>(
+        "broadcast/broadcast",
+        "broadcast",
+        5,
+        broadcast_node_with(0, 4),
+        |op, _| {
+            message(
+                "c1",
+                BroadcastBody::Broadcast {
+                    msg_id: op,
+                    message: json!(op),
+                },
+            )
+        },
+    );
+    for messages in [1_000, 100_000] {
+        bench.run::<BroadcastBody, BroadcastNode>(
+            &format!("broadcast/read_{}", messages),
+            "broadcast",
+            5,
+            broadcast_node_with(messages, 4),
+            |op, _| message("c1", BroadcastBody::Read { msg_id: op }),
+        );
+        //Neighbors that never ack are sent the whole set every tick.
+        bench.run::<BroadcastBody, BroadcastNode>(
+            &format!("broadcast/gossip_tick_{}", messages),
+            "broadcast",
+            5,
+            broadcast_node_with(messages, 4),
+            |_, _| Event::PropogateWrites,
+        );
+        //Gossip from a neighbor overlapping what this node already holds
+        bench.run::<BroadcastBody, BroadcastNode>(
+            &format!("broadcast/receive_gossip_{}", messages),
+            "broadcast",
+            5,
+            broadcast_node_with(messages, 4),
+            move |op, rng| {
+                let start = rng.below(messages);
+                message(
+                    "n1",
+                    BroadcastBody::Gossip {
+                        msg_id: op,
+                        message: (start..start + 100).collect(),
+                        payloads: (start..start + 100).map(|message| json!(message)).collect(),
+                        clocks: Vec::new(),
+                    },
+                )
+            },
+        );
+    }
+}
+
+fn compare(previous: &BenchReport, results: &[BenchResult]) {
+    //! Print how each scenario changed since the previous run.
+    let previous: HashMap<&str, &BenchResult> = previous
+        .results
+        .iter()
+        .map(|result| (result.name.as_str(), result))
+        .collect();
+    println!("\nChange since the previous run:");
+    for result in results {
+        if let Some(before) = previous.get(result.name.as_str()) {
+            let change = |before: f64, after: f64| (after - before) / before * 100.0;
+            println!(
+                "{:<32} ops/s {:>+7.1}%  p99 {:>+7.1}%",
+                result.name,
+                change(before.ops_per_sec, result.ops_per_sec),
+                change(before.p99_ns as f64, result.p99_ns as f64)
+            );
+        }
+    }
+}
+
+fn main() {
+    let mut bench = Bench {
+        filter: None,
+        time: Duration::from_secs(1),
+        results: Vec::new(),
+    };
+    let mut out = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/bench/nodes.json");
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            //Passed by cargo bench
+            "--bench" => {}
+            "--time" => {
+                //Zero, negative, NaN or overflowing times are refused rather than
+                //running no ops or panicking.
+                bench.time = args
+                    .next()
+                    .and_then(|seconds| seconds.parse::<f64>().ok())
+                    .filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .unwrap_or_else(|| {
+                        eprintln!("--time expects a positive number of seconds\n{}", USAGE);
+                        process::exit(2);
+                    });
+            }
+            "--out" => match args.next() {
+                Some(path) => out = PathBuf::from(path),
+                None => {
+                    eprintln!("--out expects a path\n{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            filter if !filter.starts_with('-') => bench.filter = Some(filter.to_owned()),
+            other => {
+                eprintln!("Unknown argument {}\n{}", other, USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    echo(&mut bench);
+    generate_id(&mut bench);
+    grow_counter(&mut bench);
+    kafka(&mut bench);
+    kv_store(&mut bench);
+    broadcast(&mut bench);
+
+    let previous: Option<BenchReport> = fs::read_to_string(&out)
+        .ok()
+        .and_then(|previous| serde_json::from_str(&previous).ok());
+    if let Some(previous) = previous.as_ref() {
+        compare(previous, &bench.results);
+    }
+    //Scenarios filtered out of this run keep their previous results.
+    let mut results = bench.results;
+    for result in previous
+        .map(|previous| previous.results)
+        .unwrap_or_default()
+    {
+        if !results.iter().any(|current| current.name == result.name) {
+            results.push(result);
+        }
+    }
+    let report = BenchReport { results };
+    if let Some(dir) = out.parent() {
+        fs::create_dir_all(dir).expect("Could not create the results directory");
+    }
+    let json = serde_json::to_string_pretty(&report).expect("Could not serialize results");
+    fs::write(&out, json + "\n").expect("Could not write results");
+    println!("\nResults written to {}", out.display());
+}
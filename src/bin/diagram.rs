@@ -0,0 +1,97 @@
+use event_horizon::diagram::{Diagram, DiagramFilter};
+use event_horizon::trace;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str = "Usage: diagram TRACE [options]
+
+Renders a trace.jsonl recorded by the runner as a message-flow diagram.
+
+Options:
+  --format mermaid|svg   output format (default: svg if --out ends in .svg, else mermaid)
+  --type TYPE,...        keep only these message types and their _ok replies
+  --node NODE,...        keep only messages sent or received by these nodes
+  --from MS              keep only messages sent at or after MS milliseconds
+  --to MS                keep only messages sent at or before MS milliseconds
+  --out PATH             write to PATH instead of stdout";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn parse_ms(flag: &str, value: &str) -> u64 {
+    let ms: f64 = value
+        .parse()
+        .unwrap_or_else(|_| fail(&format!("Invalid value for {}: {}", flag, value)));
+    (ms * 1_000_000.0) as u64
+}
+
+fn main() {
+    let mut trace_path = None;
+    let mut format = None;
+    let mut filter = DiagramFilter::default();
+    let mut out = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        if flag == "-h" || flag == "--help" {
+            println!("{}", USAGE);
+            return;
+        }
+        if !flag.starts_with("--") {
+            trace_path = Some(PathBuf::from(flag));
+            continue;
+        }
+        let value = args
+            .next()
+            .unwrap_or_else(|| fail(&format!("Missing value for {}", flag)));
+        let list = || value.split(',').map(str::to_owned);
+        match flag.as_str() {
+            "--format" => format = Some(value),
+            "--type" => filter.types.extend(list()),
+            "--node" => filter.nodes.extend(list()),
+            "--from" => filter.from = Some(parse_ms(&flag, &value)),
+            "--to" => filter.to = Some(parse_ms(&flag, &value)),
+            "--out" => out = Some(PathBuf::from(value)),
+            _ => fail(&format!("Unknown option: {}", flag)),
+        }
+    }
+
+    let trace_path = trace_path.unwrap_or_else(|| fail("A trace file is required"));
+    let trace = File::open(&trace_path)
+        .and_then(|file| trace::read_jsonl(BufReader::new(file)))
+        .unwrap_or_else(|err| {
+            eprintln!("Could not read {}: {}", trace_path.display(), err);
+            process::exit(1);
+        });
+    let format = format.unwrap_or_else(|| match out.as_ref().and_then(|out| out.extension()) {
+        Some(extension) if extension == "svg" => "svg".to_owned(),
+        _ => "mermaid".to_owned(),
+    });
+
+    let diagram = Diagram::from_trace(&trace, &filter);
+    let rendered = match format.as_str() {
+        "mermaid" => diagram.to_mermaid(),
+        "svg" => diagram.to_svg(),
+        _ => fail(&format!("Unknown format: {}", format)),
+    };
+    match out {
+        Some(out) => {
+            fs::write(&out, rendered).unwrap_or_else(|err| {
+                eprintln!("Could not write {}: {}", out.display(), err);
+                process::exit(1);
+            });
+            eprintln!(
+                "{} messages between {} participants written to {}",
+                diagram.arrows.len(),
+                diagram.participants.len(),
+                out.display()
+            );
+        }
+        None => print!("{}", rendered),
+    }
+}
@@ -0,0 +1,201 @@
+use event_horizon::repl::{self, Command};
+use serde_json::{json, Value};
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{self, ChildStdin, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: repl [options]
+
+Starts an event-horizon node and sends it messages typed as shorthand commands.
+
+Options:
+  --node NAME           node type: echo, generate_id, broadcast, grow_counter, kafka or kv_store
+                        (default: EVENT_HORIZON_NODE, else kv_store)
+  --node-id ID          the node's id (default n1)
+  --node-ids ID,...     every node id in the cluster (default n1,n2,n3)
+  --client ID           the client id requests are sent from (default c1)
+  --bin PATH            node binary (default: event-horizon next to this binary)
+  --json                print raw JSON lines instead of summaries";
+
+//How long to wait for replies before showing the prompt again
+const REPLY_WAIT: Duration = Duration::from_millis(100);
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+struct Session {
+    stdin: ChildStdin,
+    node_id: String,
+    node_ids: Vec<String>,
+    client_id: String,
+    next_msg_id: u64,
+}
+
+impl Session {
+    fn send(&mut self, src: &str, mut body: Value) -> io::Result<()> {
+        if body.get("msg_id").is_none() {
+            body["msg_id"] = json!(self.next_msg_id);
+            self.next_msg_id += 1;
+        }
+        let message = json!({"src": src, "dest": self.node_id, "body": body});
+        writeln!(self.stdin, "{}", message)?;
+        self.stdin.flush()
+    }
+
+    fn default_peer(&self) -> String {
+        //! Peer messages come from the first other node unless `from` says otherwise.
+        self.node_ids
+            .iter()
+            .find(|node_id| **node_id != self.node_id)
+            .cloned()
+            .unwrap_or_else(|| "n0".to_owned())
+    }
+}
+
+fn print_output(output: &Receiver<String>, client_id: &str, raw: bool, wait: Duration) {
+    //! Print whatever the node wrote, waiting up to `wait` for the first line.
+    let mut timeout = wait;
+    while let Ok(line) = output.recv_timeout(timeout) {
+        match serde_json::from_str::<Value>(&line) {
+            Ok(message) if !raw => println!("{}", repl::pretty(&message, client_id)),
+            _ => println!("{}", line),
+        }
+        //Gather lines that arrive together without waiting the full time again.
+        timeout = Duration::from_millis(10);
+    }
+}
+
+fn main() {
+    let mut node_type = env::var("EVENT_HORIZON_NODE").unwrap_or_else(|_| "kv_store".to_owned());
+    let mut node_id = "n1".to_owned();
+    let mut node_ids = None;
+    let mut client_id = "c1".to_owned();
+    let mut bin = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join("event-horizon")))
+        .unwrap_or_else(|| PathBuf::from("target/debug/event-horizon"));
+    let mut raw = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--json" => {
+                raw = true;
+                continue;
+            }
+            _ => {}
+        }
+        let value = args
+            .next()
+            .unwrap_or_else(|| fail(&format!("Missing value for {}", flag)));
+        match flag.as_str() {
+            "--node" => node_type = value,
+            "--node-id" => node_id = value,
+            "--node-ids" => node_ids = Some(value.split(',').map(str::to_owned).collect()),
+            "--client" => client_id = value,
+            "--bin" => bin = PathBuf::from(value),
+            _ => fail(&format!("Unknown option: {}", flag)),
+        }
+    }
+    let node_ids: Vec<String> = node_ids.unwrap_or_else(|| {
+        let mut node_ids = vec!["n1".to_owned(), "n2".to_owned(), "n3".to_owned()];
+        if !node_ids.contains(&node_id) {
+            node_ids.insert(0, node_id.clone());
+        }
+        node_ids
+    });
+    if !node_ids.contains(&node_id) {
+        fail(&format!("--node-ids must include {}", node_id));
+    }
+
+    let mut child = process::Command::new(&bin)
+        .env("EVENT_HORIZON_NODE", &node_type)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|err| {
+            eprintln!("Could not start {}: {}", bin.display(), err);
+            process::exit(1);
+        });
+    let stdout = child.stdout.take().expect("Child stdout was piped");
+    let (output_tx, output) = channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if output_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut session = Session {
+        stdin: child.stdin.take().expect("Child stdin was piped"),
+        node_id,
+        node_ids,
+        client_id,
+        next_msg_id: 1,
+    };
+    let init = json!({"type": "init", "node_id": session.node_id, "node_ids": session.node_ids});
+    if let Err(err) = session.send("c0", init) {
+        eprintln!("Could not send init: {}", err);
+        process::exit(1);
+    }
+    match output.recv_timeout(Duration::from_secs(5)) {
+        Ok(line) if line.contains("init_ok") => println!(
+            "{} node {} is up in cluster [{}]. Type 'help' for commands.",
+            node_type,
+            session.node_id,
+            session.node_ids.join(", ")
+        ),
+        _ => {
+            eprintln!("{} did not reply init_ok", bin.display());
+            let _ = child.kill();
+            process::exit(1);
+        }
+    }
+
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        //Show anything the node sent on its own, such as gossip, before prompting.
+        print_output(&output, &session.client_id, raw, Duration::ZERO);
+        print!("{}> ", session.node_id);
+        let _ = io::stdout().flush();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match repl::parse(&line, &session.node_ids) {
+            Ok(Command::Help) => println!("{}", repl::HELP),
+            Ok(Command::Quit) => break,
+            Ok(Command::Send { src, body }) => {
+                let src = src.unwrap_or_else(|| {
+                    if repl::is_peer_message(&body) {
+                        session.default_peer()
+                    } else {
+                        session.client_id.clone()
+                    }
+                });
+                if let Err(err) = session.send(&src, body) {
+                    eprintln!("The node has exited: {}", err);
+                    break;
+                }
+                print_output(&output, &session.client_id, raw, REPLY_WAIT);
+            }
+            Err(err) => println!("{}", err),
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
@@ -0,0 +1,196 @@
+use event_horizon::rng::Rng;
+use event_horizon::runner::nemesis::{Fault, NemesisConfig};
+use event_horizon::runner::network::{Latency, LinkConfig, Network};
+use event_horizon::runner::workload::Workload;
+use event_horizon::runner::{self, ClusterConfig, RunConfig};
+use event_horizon::service::local::LocalConfig;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: runner -w <workload> [options]
+
+Runs a workload against a local cluster of event-horizon processes.
+
+Options:
+  -w, --workload NAME          echo, unique-ids, broadcast or g-counter
+  --bin PATH                   node binary (default: event-horizon next to this binary)
+  --node-count N               number of nodes (default 3)
+  --concurrency N|Kn           number of clients, e.g. 4 or 2n (default n)
+  --rate OPS                   total requests per second (default 10)
+  --time-limit SECS            length of the workload (default 10)
+  --recovery-time SECS         wait after healing before final reads (default 5)
+  --request-timeout MS         client request timeout (default 1000)
+  --nemesis FAULT,...          partition[:halves+majority-ring+isolate], loss:P,
+                               latency:SPEC, duplicate:P, reorder:P
+  --nemesis-interval SECS      time between fault start and heal (default 5)
+  --latency SPEC               baseline latency: MS, uniform:MIN:MAX or exp:MEAN
+  --loss P                     baseline packet loss probability
+  --duplicate P                baseline duplication probability
+  --reorder P                  baseline reordering probability
+  --link SRC>DEST,KEY=V,...    override one link, e.g. n0>n1,loss=0.5,latency=exp:20
+  --service-latency SPEC       time seq-kv, lin-kv and lww-kv take to answer (default 0)
+  --seq-kv-lag N               seq-kv reads may be up to N writes stale (default 16)
+  --seq-kv-age MS              and replaced up to MS ago (default 1000)
+  --lww-kv-propagation SPEC    delay before other nodes see an lww-kv write (default 100)
+  --lww-kv-skew MS             bound on each node's lww-kv clock offset (default 0)
+  --env KEY=VALUE              extra environment variable for every node
+  --seed N                     random seed (default: from the clock)
+  --out DIR                    output directory (default store/latest)";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| fail(&format!("Invalid value for {}: {}", flag, value)))
+}
+
+fn parse_latency(spec: &str) -> Latency {
+    Latency::parse(spec).unwrap_or_else(|| fail(&format!("Invalid latency: {}", spec)))
+}
+
+fn parse_link(spec: &str, link: &mut LinkConfig) {
+    link.set(spec)
+        .unwrap_or_else(|| fail(&format!("Invalid link setting: {}", spec)));
+}
+
+fn main() {
+    let mut workload = None;
+    let mut bin = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join("event-horizon")))
+        .unwrap_or_else(|| PathBuf::from("target/debug/event-horizon"));
+    let mut node_count = 3;
+    let mut concurrency = None;
+    let mut rate = 10.0;
+    let mut time_limit = 10.0;
+    let mut recovery_time = 5.0;
+    let mut request_timeout = 1000;
+    let mut faults = Vec::new();
+    let mut nemesis_interval = 5.0;
+    let mut network = Network::default();
+    let mut link_specs = Vec::new();
+    let mut services = LocalConfig::default();
+    let mut node_env = Vec::new();
+    let mut seed = None;
+    let mut out_dir = PathBuf::from("store/latest");
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        if flag == "-h" || flag == "--help" {
+            println!("{}", USAGE);
+            return;
+        }
+        let value = args
+            .next()
+            .unwrap_or_else(|| fail(&format!("Missing value for {}", flag)));
+        match flag.as_str() {
+            "-w" | "--workload" => {
+                workload = Some(
+                    Workload::parse(&value)
+                        .unwrap_or_else(|| fail(&format!("Unknown workload: {}", value))),
+                )
+            }
+            "--bin" => bin = PathBuf::from(value),
+            "--node-count" => node_count = parse(&flag, &value),
+            "--concurrency" => concurrency = Some(value),
+            "--rate" => rate = parse(&flag, &value),
+            "--time-limit" => time_limit = parse(&flag, &value),
+            "--recovery-time" => recovery_time = parse(&flag, &value),
+            "--request-timeout" => request_timeout = parse(&flag, &value),
+            "--nemesis" => {
+                for spec in value.split(',') {
+                    faults.push(
+                        Fault::parse(spec)
+                            .unwrap_or_else(|| fail(&format!("Unknown fault: {}", spec))),
+                    );
+                }
+            }
+            "--nemesis-interval" => nemesis_interval = parse(&flag, &value),
+            "--latency" => parse_link(&format!("latency={}", value), &mut network.default_link),
+            "--loss" => parse_link(&format!("loss={}", value), &mut network.default_link),
+            "--duplicate" => parse_link(&format!("duplicate={}", value), &mut network.default_link),
+            "--reorder" => parse_link(&format!("reorder={}", value), &mut network.default_link),
+            "--link" => link_specs.push(value),
+            "--service-latency" => services.latency = parse_latency(&value),
+            "--seq-kv-lag" => services.max_lag = parse(&flag, &value),
+            "--seq-kv-age" => services.max_age = Duration::from_millis(parse(&flag, &value)),
+            "--lww-kv-propagation" => services.propagation = parse_latency(&value),
+            "--lww-kv-skew" => services.clock_skew_ms = parse(&flag, &value),
+            "--env" => match value.split_once('=') {
+                Some((key, value)) => node_env.push((key.to_owned(), value.to_owned())),
+                None => fail(&format!("Invalid --env: {}", value)),
+            },
+            "--seed" => seed = Some(parse(&flag, &value)),
+            "--out" => out_dir = PathBuf::from(value),
+            _ => fail(&format!("Unknown option: {}", flag)),
+        }
+    }
+
+    //Per-link overrides start from the baseline link.
+    for spec in link_specs {
+        network
+            .add_link(&spec)
+            .unwrap_or_else(|| fail(&format!("Invalid --link: {}", spec)));
+    }
+
+    let workload = workload.unwrap_or_else(|| fail("A workload is required"));
+    let concurrency = match concurrency {
+        Some(concurrency) => match concurrency.strip_suffix('n') {
+            Some(multiple) => parse::<usize>("--concurrency", multiple) * node_count,
+            None => parse("--concurrency", &concurrency),
+        },
+        None => node_count,
+    };
+    let seed = seed.unwrap_or_else(|| Rng::from_time().next_u64());
+    let config = RunConfig {
+        cluster: ClusterConfig {
+            bin,
+            node_type: workload.node_type().to_owned(),
+            node_count,
+            env: node_env,
+            network,
+            services,
+            seed,
+            log_dir: None,
+            state_dir: None,
+        },
+        workload,
+        concurrency,
+        rate,
+        time_limit: Duration::from_secs_f64(time_limit),
+        recovery_time: Duration::from_secs_f64(recovery_time),
+        request_timeout: Duration::from_millis(request_timeout),
+        nemesis: (!faults.is_empty()).then(|| NemesisConfig {
+            faults,
+            interval: Duration::from_secs_f64(nemesis_interval),
+        }),
+        out_dir: Some(out_dir.clone()),
+    };
+
+    eprintln!("Running with seed {}", seed);
+    match runner::run(config) {
+        Ok(report) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report.results).expect("Results are valid JSON")
+            );
+            eprintln!(
+                "History, trace and node logs written to {}",
+                out_dir.display()
+            );
+            if !report.valid {
+                process::exit(1);
+            }
+        }
+        Err(err) => {
+            eprintln!("Run failed: {}", err);
+            process::exit(2);
+        }
+    }
+}
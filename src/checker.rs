@@ -0,0 +1,4 @@
+pub mod broadcast;
+pub mod counter;
+pub mod linearizability;
+pub mod unique_ids;
@@ -0,0 +1,121 @@
+use crate::history::{History, OpType};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastReport {
+    pub valid: bool,
+    //Number of broadcasts that were acknowledged
+    pub acked: usize,
+    pub final_reads: usize,
+    //Acknowledged values missing from at least one final read
+    pub lost: Vec<u64>,
+    //Values read that were never broadcast
+    pub unexpected: Vec<u64>,
+    //How long acknowledged broadcasts took to reach every read, if any did
+    pub convergence: Option<Convergence>,
+}
+
+//Milliseconds from a broadcast's acknowledgement until every read invoked since
+//includes it, over the broadcasts that were not lost. Reads only sample the nodes, so
+//a broadcast converges when the last read that missed it completed, and one no read
+//missed converges at once.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Convergence {
+    pub median_ms: u64,
+    pub p99_ms: u64,
+    pub max_ms: u64,
+}
+
+impl Convergence {
+    fn of(mut nanos: Vec<u64>) -> Option<Self> {
+        nanos.sort_unstable();
+        let quantile = |q: f64| nanos[((nanos.len() - 1) as f64 * q).round() as usize] / 1_000_000;
+        (!nanos.is_empty()).then(|| Convergence {
+            median_ms: quantile(0.5),
+            p99_ms: quantile(0.99),
+            max_ms: quantile(1.0),
+        })
+    }
+}
+
+pub fn check(history: &History) -> BroadcastReport {
+    //! Every acknowledged broadcast must appear in every final read. Final reads
+    //! are the reads invoked after the last broadcast completed.
+    let pairs = history.pairs();
+    let mut attempted = BTreeSet::new();
+    let mut acked = BTreeMap::new();
+    let mut last_broadcast = 0;
+    for (invoke, completion) in pairs.iter().filter(|(invoke, _)| invoke.f == "broadcast") {
+        let message = match invoke.value["message"].as_u64() {
+            Some(message) => message,
+            None => continue,
+        };
+        attempted.insert(message);
+        last_broadcast = last_broadcast.max(invoke.time);
+        if let Some(completion) = completion {
+            last_broadcast = last_broadcast.max(completion.time);
+            if completion.op_type == OpType::Ok {
+                acked.insert(message, completion.time);
+            }
+        }
+    }
+
+    //Every successful read: when it was invoked and completed, and what it read
+    let reads: Vec<(u64, u64, BTreeSet<u64>)> = pairs
+        .iter()
+        .filter(|(invoke, _)| invoke.f == "read")
+        .filter_map(|(invoke, completion)| Some((invoke, (*completion)?)))
+        .filter(|(_, completion)| completion.op_type == OpType::Ok)
+        .map(|(invoke, completion)| {
+            let read = completion.value["messages"]
+                .as_array()
+                .map(|messages| messages.iter().filter_map(Value::as_u64).collect())
+                .unwrap_or_default();
+            (invoke.time, completion.time, read)
+        })
+        .collect();
+    let final_reads: Vec<&BTreeSet<u64>> = reads
+        .iter()
+        .filter(|(invoked, _, _)| *invoked > last_broadcast)
+        .map(|(_, _, read)| read)
+        .collect();
+
+    let lost: Vec<u64> = acked
+        .keys()
+        .filter(|message| final_reads.iter().any(|read| !read.contains(message)))
+        .copied()
+        .collect();
+    let convergence = Convergence::of(
+        acked
+            .iter()
+            .filter(|(message, _)| lost.binary_search(message).is_err())
+            .map(|(message, acked_at)| {
+                reads
+                    .iter()
+                    .filter(|(invoked, _, read)| invoked > acked_at && !read.contains(message))
+                    .map(|(_, completed, _)| completed - acked_at)
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect(),
+    );
+    let unexpected: Vec<u64> = final_reads
+        .iter()
+        .copied()
+        .flatten()
+        .filter(|message| !attempted.contains(message))
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    BroadcastReport {
+        valid: !final_reads.is_empty() && lost.is_empty() && unexpected.is_empty(),
+        acked: acked.len(),
+        final_reads: final_reads.len(),
+        lost,
+        unexpected,
+        convergence,
+    }
+}
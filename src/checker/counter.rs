@@ -0,0 +1,51 @@
+use crate::history::{History, OpType};
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CounterReport {
+    pub valid: bool,
+    //Sum of the acknowledged adds. Every final read must be at least this.
+    pub lower_bound: u64,
+    //Sum of every add that may have happened. No final read may exceed this.
+    pub upper_bound: u64,
+    pub final_reads: Vec<u64>,
+}
+
+pub fn check(history: &History) -> CounterReport {
+    //! Final reads, invoked after the last add completed, must fall between
+    //! the acknowledged adds and all adds that were not definitely failed.
+    let pairs = history.pairs();
+    let (mut lower_bound, mut upper_bound, mut last_add) = (0, 0, 0);
+    for (invoke, completion) in pairs.iter().filter(|(invoke, _)| invoke.f == "add") {
+        let delta = invoke.value["delta"].as_u64().unwrap_or(0);
+        last_add = last_add.max(invoke.time);
+        match completion {
+            Some(completion) if completion.op_type == OpType::Ok => {
+                lower_bound += delta;
+                upper_bound += delta;
+                last_add = last_add.max(completion.time);
+            }
+            Some(completion) if completion.op_type == OpType::Fail => {
+                last_add = last_add.max(completion.time);
+            }
+            _ => upper_bound += delta,
+        }
+    }
+
+    let final_reads: Vec<u64> = pairs
+        .iter()
+        .filter(|(invoke, _)| invoke.f == "read" && invoke.time > last_add)
+        .filter_map(|(_, completion)| *completion)
+        .filter(|completion| completion.op_type == OpType::Ok)
+        .filter_map(|completion| completion.value["value"].as_u64())
+        .collect();
+    CounterReport {
+        valid: !final_reads.is_empty()
+            && final_reads
+                .iter()
+                .all(|read| (lower_bound..=upper_bound).contains(read)),
+        lower_bound,
+        upper_bound,
+        final_reads,
+    }
+}
@@ -0,0 +1,526 @@
+use crate::history::{History, OpType, Process};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+//The value held by a single register. None means the register
+//has never been written.
+pub type RegisterState = Option<usize>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RegisterOp {
+    Read { value: Option<usize> },
+    Write { value: usize },
+    Cas { from: usize, to: usize },
+}
+
+impl RegisterOp {
+    fn step(&self, state: RegisterState) -> Option<RegisterState> {
+        //! Apply the op to the register state, returning the new state
+        //! if the op is legal from the given state.
+        match *self {
+            RegisterOp::Read { value } if value == state => Some(state),
+            RegisterOp::Write { value } => Some(Some(value)),
+            RegisterOp::Cas { from, to } if state == Some(from) => Some(Some(to)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterOperation {
+    pub process: usize,
+    //Time the op was invoked
+    pub call: u64,
+    //Time the op completed. None means the outcome is unknown (an info op),
+    //so the op may take effect at any point after its call, or never.
+    pub ret: Option<u64>,
+    pub op: RegisterOp,
+}
+
+impl RegisterOperation {
+    fn ret_time(&self) -> u64 {
+        self.ret.unwrap_or(u64::MAX)
+    }
+
+    fn is_required(&self) -> bool {
+        self.ret.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counterexample {
+    //The completed op that no legal ordering could fit.
+    pub culprit: RegisterOperation,
+    //Every op that must be ordered together with the culprit, ordered by call time.
+    //All ops outside the window either finished before it started or began after the culprit returned.
+    pub window: Vec<RegisterOperation>,
+    //The register states that the history before the window can legally end in,
+    //leaving the window's info ops to take effect within it. The window cannot be
+    //linearized starting from any of them.
+    pub states: Vec<RegisterState>,
+}
+
+pub fn check(history: &[RegisterOperation]) -> Result<(), Counterexample> {
+    //! Check a single-register history of completed and info ops (failed ops
+    //! must already be removed) for linearizability. The history is split
+    //! at quiescent points, and the configurations the register can be in are
+    //! carried from one segment to the next. On failure, the failing segment is
+    //! shrunk to the shortest failing prefix and a window around the culprit.
+    let mut ops: Vec<RegisterOperation> = history
+        .iter()
+        .filter(|operation| {
+            //A read with an unknown outcome constrains nothing.
+            operation.is_required() || !matches!(operation.op, RegisterOp::Read { .. })
+        })
+        .cloned()
+        .collect();
+    ops.sort_by_key(|operation| (operation.call, operation.ret_time()));
+
+    //Most linearizable histories fall to a depth-first pass that places ops in
+    //call order, so try that first, giving up if it wanders.
+    let mut configs = vec![(vec![0; ops.len().div_ceil(64)], None)];
+    let budget = 4 * ops.len() + 1000;
+    if !search(&ops, &configs, Some(budget)).0.is_empty() {
+        return Ok(());
+    }
+
+    //Some op could not be placed. Carry every reachable configuration across
+    //segments to find the one that fails, then shrink it to a counterexample.
+    let mut start = 0;
+    for end in segment_ends(&ops) {
+        let (next_configs, furthest) = search(&ops[..end], &configs, None);
+        if next_configs.is_empty() {
+            return Err(shrink(&ops[..end], start, &configs, furthest));
+        }
+        configs = next_configs;
+        start = end;
+    }
+    Ok(())
+}
+
+//The ops linearized so far, one bit per op, and the register state after them
+type Config = (Vec<u64>, RegisterState);
+
+fn is_set(bits: &[u64], index: usize) -> bool {
+    bits[index / 64] & (1 << (index % 64)) != 0
+}
+
+fn is_subset(bits: &[u64], of: &[u64]) -> bool {
+    bits.iter().zip(of).all(|(bits, of)| bits & !of == 0)
+}
+
+//The info ops applied by each configuration explored, by the completed ops it
+//has linearized and its state. Info ops need never take effect, so applying
+//more of them than another configuration with the same key adds nothing.
+#[derive(Default)]
+struct Explored(HashMap<(Vec<u64>, RegisterState), Vec<Vec<u64>>>);
+
+impl Explored {
+    fn split(linearized: &[u64], info_ops: &[u64]) -> (Vec<u64>, Vec<u64>) {
+        linearized
+            .iter()
+            .zip(info_ops)
+            .map(|(bits, info)| (bits & !info, bits & info))
+            .unzip()
+    }
+
+    fn insert(&mut self, (linearized, state): &Config, info_ops: &[u64]) -> Option<usize> {
+        //! Record the configuration unless one that applied a subset of its info ops
+        //! has been seen, returning how many info ops it applied. Seen ones that
+        //! applied a superset no longer need exploring.
+        let (required, applied) = Explored::split(linearized, info_ops);
+        let seen = self.0.entry((required, *state)).or_default();
+        if seen.iter().any(|other| is_subset(other, &applied)) {
+            return None;
+        }
+        seen.retain(|other| !is_subset(&applied, other));
+        let level = applied.iter().map(|bits| bits.count_ones() as usize).sum();
+        seen.push(applied);
+        Some(level)
+    }
+
+    fn is_current(&self, linearized: &[u64], state: RegisterState, info_ops: &[u64]) -> bool {
+        //! Whether a queued configuration has not since been pruned.
+        let (required, applied) = Explored::split(linearized, info_ops);
+        self.0[&(required, state)].contains(&applied)
+    }
+}
+
+fn segment_ends(ops: &[RegisterOperation]) -> Vec<usize> {
+    //! Split call-ordered ops wherever every earlier completed op has returned
+    //! before the next op is called. Info ops never return, so they do not hold
+    //! segments together. Any that have not taken effect carry into later segments.
+    let mut ends = Vec::new();
+    let mut latest_return = 0;
+    for (index, operation) in ops.iter().enumerate() {
+        if index > 0 && latest_return < operation.call {
+            ends.push(index);
+        }
+        if let Some(ret) = operation.ret {
+            latest_return = latest_return.max(ret);
+        }
+    }
+    ends.push(ops.len());
+    ends
+}
+
+fn search(
+    ops: &[RegisterOperation],
+    initial: &[Config],
+    budget: Option<usize>,
+) -> (Vec<Config>, u64) {
+    //! Search for legal orderings of the ops, starting from any of the initial
+    //! configurations, and return the configurations the ops can end in, and the
+    //! latest horizon reached: some ordering placed every op that returned before
+    //! it. With a budget, the search goes depth first and stops at the first legal
+    //! ordering, or after exploring that many configurations. At each step only
+    //! ops called before the earliest pending return may be linearized, and
+    //! configurations no more permissive than one already explored are pruned.
+    let words = initial
+        .first()
+        .map_or(0, |(linearized, _)| linearized.len());
+    let mut info_ops = vec![0; words];
+    let mut pending_info = Vec::new();
+    for (index, operation) in ops.iter().enumerate() {
+        if !operation.is_required() {
+            info_ops[index / 64] |= 1 << (index % 64);
+            //An info read constrains nothing, so it is never placed.
+            if !matches!(operation.op, RegisterOp::Read { .. }) {
+                pending_info.push(index);
+            }
+        }
+    }
+    let mut explored = Explored::default();
+    //Configurations to explore, by how many info ops they have applied. Ones
+    //that applied fewer are explored first, so they prune the rest in time.
+    //A search for any one ordering goes depth first instead, which finds it sooner.
+    let mut stacks: Vec<Vec<Config>> = Vec::new();
+    let queue = |config: Config, explored: &mut Explored, stacks: &mut Vec<Vec<Config>>| {
+        if let Some(level) = explored.insert(&config, &info_ops) {
+            let level = if budget.is_some() { 0 } else { level };
+            if stacks.len() <= level {
+                stacks.resize_with(level + 1, Vec::new);
+            }
+            stacks[level].push(config);
+        }
+    };
+    for config in initial {
+        queue(config.clone(), &mut explored, &mut stacks);
+    }
+    let mut finals = Vec::new();
+    let mut furthest = 0;
+    let mut budget = budget;
+
+    while let Some((linearized, state)) = stacks.iter_mut().find_map(Vec::pop) {
+        if !explored.is_current(&linearized, state, &info_ops) {
+            continue;
+        }
+        match &mut budget {
+            Some(0) => break,
+            Some(left) => *left -= 1,
+            None => {}
+        }
+        //Ops are sorted by call, so the search can start at the first completed op
+        //not yet linearized, plus any info ops before it that have not taken
+        //effect, and stop once calls pass the earliest pending return.
+        let first = linearized
+            .iter()
+            .zip(&info_ops)
+            .position(|(bits, info)| bits | info != u64::MAX)
+            .map_or(ops.len(), |word| {
+                word * 64 + (linearized[word] | info_ops[word]).trailing_ones() as usize
+            })
+            .min(ops.len());
+        let mut horizon = u64::MAX;
+        let mut candidates: Vec<usize> = pending_info
+            .iter()
+            .copied()
+            .take_while(|index| *index < first)
+            .filter(|index| !is_set(&linearized, *index))
+            .collect();
+        for (index, operation) in ops.iter().enumerate().skip(first) {
+            if operation.call > horizon {
+                break;
+            }
+            let placeable =
+                operation.is_required() || !matches!(operation.op, RegisterOp::Read { .. });
+            if placeable && !is_set(&linearized, index) {
+                horizon = horizon.min(operation.ret_time());
+                candidates.push(index);
+            }
+        }
+        candidates.retain(|index| ops[*index].call <= horizon);
+        furthest = furthest.max(horizon);
+        //Identical info ops that can both take effect now stay interchangeable from
+        //here on, as the horizon only grows. Only try the first.
+        let mut tried = HashSet::new();
+        candidates.retain(|index| ops[*index].is_required() || tried.insert(ops[*index].op));
+        //Delaying an info op never rules out an ordering, so one only takes effect
+        //to make a blocked read or cas legal, directly or through an info cas that
+        //starts from the state it leaves.
+        let blocked: Vec<RegisterOp> = candidates
+            .iter()
+            .map(|index| &ops[*index])
+            .filter(|operation| operation.is_required() && operation.op.step(state).is_none())
+            .map(|operation| operation.op)
+            .collect();
+        let info_cas: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|index| {
+                !ops[*index].is_required() && matches!(ops[*index].op, RegisterOp::Cas { .. })
+            })
+            .collect();
+        candidates.retain(|index| {
+            ops[*index].is_required()
+                || !blocked.is_empty()
+                    && ops[*index].op.step(state).is_some_and(|next| {
+                        blocked.iter().any(|op| op.step(next).is_some())
+                            || info_cas
+                                .iter()
+                                .any(|other| other != index && ops[*other].op.step(next).is_some())
+                    })
+        });
+
+        if horizon == u64::MAX {
+            //Every completed op has been placed. Info ops left pending may still
+            //take effect in a later segment.
+            finals.push((linearized.clone(), state));
+            if budget.is_some() {
+                break;
+            }
+        }
+
+        //A legal read leaves the state untouched, so placing it right away never
+        //rules out an ordering. Only branch on the state-changing ops.
+        let legal_read = candidates.iter().copied().find(|index| {
+            matches!(ops[*index].op, RegisterOp::Read { .. })
+                && ops[*index].op.step(state).is_some()
+        });
+        if let Some(read) = legal_read {
+            candidates = vec![read];
+        }
+
+        //Push in reverse so completed ops are explored first, earliest call first.
+        //Orderings that leave info ops pending are then found before ones that
+        //apply them, and prune those.
+        candidates.sort_by_key(|index| (ops[*index].is_required(), std::cmp::Reverse(*index)));
+        for index in candidates {
+            if let Some(next_state) = ops[index].op.step(state) {
+                let mut next_linearized = linearized.clone();
+                next_linearized[index / 64] |= 1 << (index % 64);
+                queue((next_linearized, next_state), &mut explored, &mut stacks);
+            }
+        }
+    }
+    (finals, furthest)
+}
+
+fn prefix(ops: &[RegisterOperation], until: u64) -> Vec<RegisterOperation> {
+    //! The history as it looked at time `until`: ops called later are dropped,
+    //! and ops still in flight become info ops.
+    ops.iter()
+        .take_while(|operation| operation.call <= until)
+        .map(|operation| match operation.ret {
+            Some(ret) if ret <= until => operation.clone(),
+            _ => RegisterOperation {
+                ret: None,
+                ..operation.clone()
+            },
+        })
+        .collect()
+}
+
+fn ignored(operation: &RegisterOperation) -> RegisterOperation {
+    //! An op that constrains nothing, standing in for one left out of a search
+    //! so that the configurations' bits still line up with the ops.
+    RegisterOperation {
+        ret: None,
+        op: RegisterOp::Read { value: None },
+        ..operation.clone()
+    }
+}
+
+fn shrink(
+    ops: &[RegisterOperation],
+    start: usize,
+    initial: &[Config],
+    until: u64,
+) -> Counterexample {
+    //! Given a segment, starting at `start`, that cannot be linearized from the
+    //! initial configurations, and the furthest horizon its search reached, build
+    //! a counterexample. No ordering placed every op returning by `until`, so the
+    //! history up to then is the shortest failing prefix, and the op whose return
+    //! ends it is the culprit.
+    let failing = prefix(ops, until);
+    //Several ops may return at the same instant. The culprit is the one whose
+    //removal lets the prefix be linearized again.
+    let returning: Vec<usize> = (start..failing.len())
+        .filter(|index| failing[*index].ret == Some(until))
+        .collect();
+    let culprit = match returning[..] {
+        [only] => only,
+        _ => returning
+            .iter()
+            .copied()
+            .find(|index| {
+                let mut without = failing.clone();
+                without[*index] = ignored(&failing[*index]);
+                !search(&without, initial, None).0.is_empty()
+            })
+            .unwrap_or(returning[0]),
+    };
+    let culprit = failing[culprit].clone();
+
+    //The window opens at the earliest call among completed ops concurrent with
+    //the culprit, widened until none straddles its start. Every completed op that
+    //returned before then precedes the whole window in real time.
+    let concurrent = |window_start: u64| {
+        ops[..failing.len()]
+            .iter()
+            .enumerate()
+            .filter(move |(_, operation)| {
+                operation.ret.is_some_and(|ret| ret >= window_start)
+                    || !operation.is_required() && operation.call >= window_start
+            })
+    };
+    let mut window_start = culprit.call;
+    loop {
+        let earliest_call = concurrent(window_start)
+            .filter(|(_, operation)| operation.is_required())
+            .map(|(_, operation)| operation.call)
+            .min()
+            .unwrap_or(window_start);
+        if earliest_call >= window_start {
+            break;
+        }
+        window_start = earliest_call;
+    }
+    //The configurations as of the window's start. Info ops called before it
+    //may have taken effect by then, or may yet take effect within it.
+    let before: Vec<_> = ops[..failing.len()]
+        .iter()
+        .map(|operation| match operation.ret {
+            Some(ret) if ret < window_start => operation.clone(),
+            None if operation.call < window_start => operation.clone(),
+            _ => ignored(operation),
+        })
+        .collect();
+    let (configs, _) = search(&before, initial, None);
+    let pending = |index: usize| {
+        !ops[index].is_required()
+            && configs
+                .iter()
+                .any(|(linearized, _)| !is_set(linearized, index))
+    };
+    //Report the window's ops as they appear in the full history, so ops that
+    //were still in flight at the culprit's return keep their real return times.
+    let window = ops[..failing.len()]
+        .iter()
+        .enumerate()
+        .filter(|(index, operation)| {
+            let in_window = operation.ret_time() >= window_start
+                && (operation.is_required() || operation.call >= window_start);
+            (in_window || operation.call < window_start && pending(*index))
+                && (operation.ret.is_some_and(|ret| ret <= until)
+                    || !matches!(operation.op, RegisterOp::Read { .. }))
+        })
+        .map(|(_, operation)| operation.clone())
+        .collect();
+
+    let states: BTreeSet<RegisterState> = configs.into_iter().map(|(_, state)| state).collect();
+    Counterexample {
+        culprit,
+        window,
+        states: states.into_iter().collect(),
+    }
+}
+
+pub fn register_histories(history: &History) -> BTreeMap<String, Vec<RegisterOperation>> {
+    //! Split a client history of read, write and cas ops into one register history
+    //! per key. Values may be Maelstrom `[key value]` tuples, or request payloads
+    //! such as `{"key": 1, "from": 2, "to": 3}` from a derived history.
+    //! Failed ops and ops on non-integer values are dropped.
+    let mut registers: BTreeMap<String, Vec<RegisterOperation>> = BTreeMap::new();
+    for (invoke, completion) in history.pairs() {
+        let process = match invoke.process {
+            Process::Client(process) => process,
+            Process::Named(_) => continue,
+        };
+        let ret = match completion {
+            Some(completion) if completion.op_type == OpType::Fail => continue,
+            Some(completion) if completion.op_type == OpType::Ok => Some(completion.time),
+            _ => None,
+        };
+        let (key, argument) = split_key(&invoke.value);
+        let op = match invoke.f.as_str() {
+            "read" => {
+                let result = match (ret, completion) {
+                    (Some(_), Some(completion)) => split_key(&completion.value).1,
+                    //An unanswered read constrains nothing.
+                    _ => continue,
+                };
+                let value = match field(&result, "value") {
+                    Value::Null => None,
+                    value => match value.as_u64() {
+                        Some(value) => Some(value as usize),
+                        None => continue,
+                    },
+                };
+                RegisterOp::Read { value }
+            }
+            "write" => match field(&argument, "value").as_u64() {
+                Some(value) => RegisterOp::Write {
+                    value: value as usize,
+                },
+                None => continue,
+            },
+            "cas" => {
+                let (from, to) = match &argument {
+                    Value::Array(from_to) if from_to.len() == 2 => (&from_to[0], &from_to[1]),
+                    argument => (&argument["from"], &argument["to"]),
+                };
+                match (from.as_u64(), to.as_u64()) {
+                    (Some(from), Some(to)) => RegisterOp::Cas {
+                        from: from as usize,
+                        to: to as usize,
+                    },
+                    _ => continue,
+                }
+            }
+            _ => continue,
+        };
+        registers.entry(key).or_default().push(RegisterOperation {
+            process,
+            call: invoke.time,
+            ret,
+            op,
+        });
+    }
+    registers
+}
+
+fn split_key(value: &Value) -> (String, Value) {
+    match value {
+        Value::Array(tuple) if tuple.len() == 2 => (key_name(&tuple[0]), tuple[1].clone()),
+        Value::Object(payload) if payload.contains_key("key") => {
+            (key_name(&payload["key"]), value.clone())
+        }
+        value => (String::new(), value.clone()),
+    }
+}
+
+fn key_name(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.clone(),
+        key => key.to_string(),
+    }
+}
+
+fn field(value: &Value, name: &str) -> Value {
+    //! Payload objects carry values in a named field. Anything else is the value itself.
+    match value {
+        Value::Object(payload) => payload.get(name).cloned().unwrap_or(Value::Null),
+        value => value.clone(),
+    }
+}
@@ -0,0 +1,33 @@
+use crate::history::{History, OpType};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct UniqueIdsReport {
+    pub valid: bool,
+    pub generated: usize,
+    //Ids handed out more than once, with how often each was seen
+    pub duplicates: Vec<(String, usize)>,
+}
+
+pub fn check(history: &History) -> UniqueIdsReport {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for operation in history.ops.iter() {
+        if operation.f == "generate" && operation.op_type == OpType::Ok {
+            let id = match &operation.value["id"] {
+                serde_json::Value::String(id) => id.clone(),
+                id => id.to_string(),
+            };
+            *counts.entry(id).or_insert(0) += 1;
+        }
+    }
+    let generated = counts.values().sum();
+    let mut duplicates: Vec<(String, usize)> =
+        counts.into_iter().filter(|(_, count)| *count > 1).collect();
+    duplicates.sort();
+    UniqueIdsReport {
+        valid: duplicates.is_empty(),
+        generated,
+        duplicates,
+    }
+}
@@ -0,0 +1,226 @@
+use serde::Deserialize;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+//The clock every node reads timestamps from. By default it is the system clock,
+//but a fixed offset, a gradual drift and sudden jumps can be injected to test
+//timestamp-dependent logic against clock skew.
+pub struct Clock {
+    state: Mutex<ClockState>,
+}
+
+struct ClockState {
+    //Fixed skew, including any jumps, in nanoseconds
+    offset_nanos: i64,
+    //How fast the clock drifts, in parts per million. 1000 ppm gains 1ms every second.
+    drift_ppm: f64,
+    //When the current drift started
+    drift_since: Option<Instant>,
+    //The offset and drift configured at startup, which a reset returns to
+    baseline: (i64, f64),
+    //A fixed reading that replaces the system clock, for deterministic tests
+    frozen: Option<u64>,
+    //The last incarnation handed out, so each one is later than the one before
+    last_incarnation: u64,
+    //A fixed incarnation for deterministic tests. No clock control reaches it.
+    pinned_incarnation: Option<u64>,
+}
+
+impl ClockState {
+    fn drifted_nanos(&self) -> i64 {
+        self.drift_since.map_or(0, |since| {
+            (since.elapsed().as_nanos() as f64 * self.drift_ppm / 1_000_000.0) as i64
+        })
+    }
+
+    fn settle_drift(&mut self) {
+        //! Fold the drift accumulated so far into the fixed offset.
+        self.offset_nanos += self.drifted_nanos();
+        self.drift_since = Some(Instant::now());
+    }
+}
+
+impl Clock {
+    pub const fn new() -> Self {
+        Clock {
+            state: Mutex::new(ClockState {
+                offset_nanos: 0,
+                drift_ppm: 0.0,
+                drift_since: None,
+                baseline: (0, 0.0),
+                frozen: None,
+                last_incarnation: 0,
+                pinned_incarnation: None,
+            }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, ClockState> {
+        self.state.lock().expect("Clock lock poisoned")
+    }
+
+    pub fn now_nanos(&self) -> u64 {
+        //! Nanoseconds since the Unix epoch, as this node sees it.
+        if let Some(frozen) = self.state().frozen {
+            return frozen;
+        }
+        let state = self.state();
+        (system_nanos() as i64 + state.offset_nanos + state.drifted_nanos()).max(0) as u64
+    }
+
+    pub fn incarnation(&self) -> u64 {
+        //! A number naming one process of a node, for IDs that must not repeat when
+        //! it restarts. It reads the system clock as is, never skewed, jumped or
+        //! frozen, and each call returns a later value than the one before.
+        let mut state = self.state();
+        if let Some(pinned) = state.pinned_incarnation {
+            return pinned;
+        }
+        state.last_incarnation = system_nanos().max(state.last_incarnation + 1);
+        state.last_incarnation
+    }
+
+    pub fn pin_incarnation(&self, incarnation: Option<u64>) {
+        //! Make every incarnation `incarnation`. Passing None goes back to the system clock.
+        self.state().pinned_incarnation = incarnation;
+    }
+
+    pub fn set_offset_ms(&self, offset_ms: i64) {
+        let mut state = self.state();
+        state.settle_drift();
+        state.offset_nanos = offset_ms * 1_000_000;
+    }
+
+    pub fn freeze_at(&self, nanos: Option<u64>) {
+        //! Make every reading return `nanos`, ignoring offset and drift.
+        //! Passing None goes back to the system clock.
+        self.state().frozen = nanos;
+    }
+
+    pub fn jump_ms(&self, jump_ms: i64) {
+        //! Move the clock suddenly forward (positive) or backward (negative).
+        self.state().offset_nanos += jump_ms * 1_000_000;
+    }
+
+    pub fn set_drift_ppm(&self, drift_ppm: f64) {
+        let mut state = self.state();
+        state.settle_drift();
+        state.drift_ppm = drift_ppm;
+    }
+
+    pub fn reset(&self) {
+        //! Undo any injected skew, returning to the configured baseline.
+        let mut state = self.state();
+        let (offset_nanos, drift_ppm) = state.baseline;
+        state.offset_nanos = offset_nanos;
+        state.drift_ppm = drift_ppm;
+        state.drift_since = Some(Instant::now());
+    }
+
+    pub fn apply(&self, control: &ClockControl) {
+        if control.reset {
+            self.reset();
+        }
+        if let Some(offset_ms) = control.offset_ms {
+            self.set_offset_ms(offset_ms);
+        }
+        if let Some(jump_ms) = control.jump_ms {
+            self.jump_ms(jump_ms);
+        }
+        if let Some(drift_ppm) = control.drift_ppm {
+            self.set_drift_ppm(drift_ppm);
+        }
+    }
+}
+
+fn system_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System clock is before the Unix epoch")
+        .as_nanos() as u64
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::new()
+    }
+}
+
+static CLOCK: Clock = Clock::new();
+
+pub fn global() -> &'static Clock {
+    &CLOCK
+}
+
+pub fn now_nanos() -> u64 {
+    CLOCK.now_nanos()
+}
+
+pub fn incarnation() -> u64 {
+    CLOCK.incarnation()
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ClockControl {
+    #[serde(default)]
+    pub reset: bool,
+    pub offset_ms: Option<i64>,
+    pub jump_ms: Option<i64>,
+    pub drift_ppm: Option<f64>,
+}
+
+fn env_setting<T: std::str::FromStr>(name: &str, node_id: &str) -> Option<T> {
+    //! Read a setting that is either a single value for every node, such as `250`,
+    //! or per-node values such as `n1=250,n2=-100`.
+    let value = env::var(name).ok()?;
+    if !value.contains('=') {
+        return value.trim().parse().ok();
+    }
+    value
+        .split(',')
+        .filter_map(|setting| setting.split_once('='))
+        .find(|(node, _)| node.trim() == node_id)
+        .and_then(|(_, value)| value.trim().parse().ok())
+}
+
+pub fn configure_from_env(node_id: &str) {
+    //! Apply EVENT_HORIZON_CLOCK_OFFSET_MS and EVENT_HORIZON_CLOCK_DRIFT_PPM
+    //! to the global clock.
+    if let Some(offset_ms) = env_setting("EVENT_HORIZON_CLOCK_OFFSET_MS", node_id) {
+        CLOCK.set_offset_ms(offset_ms);
+    }
+    if let Some(drift_ppm) = env_setting("EVENT_HORIZON_CLOCK_DRIFT_PPM", node_id) {
+        CLOCK.set_drift_ppm(drift_ppm);
+    }
+    let mut state = CLOCK.state();
+    state.baseline = (state.offset_nanos, state.drift_ppm);
+}
+
+pub fn handle_control(line: &str) -> bool {
+    //! If the line is a `clock_nemesis` control message, apply it to the global
+    //! clock and return true. Control messages are never replied to.
+    #[derive(Deserialize)]
+    struct ControlMessage {
+        body: ControlBody,
+    }
+    #[derive(Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum ControlBody {
+        ClockNemesis(ClockControl),
+    }
+
+    //Skip the parse for the vast majority of lines.
+    if !line.contains("clock_nemesis") {
+        return false;
+    }
+    match serde_json::from_str::<ControlMessage>(line) {
+        Ok(ControlMessage {
+            body: ControlBody::ClockNemesis(control),
+        }) => {
+            CLOCK.apply(&control);
+            true
+        }
+        Err(_) => false,
+    }
+}
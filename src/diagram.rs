@@ -0,0 +1,344 @@
+use crate::history::payload;
+use crate::trace::TraceEntry;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+
+//Renders a recorded trace as a message-flow diagram: a Mermaid sequence diagram, or a
+//standalone SVG Lamport diagram with one lifeline per participant. Requests and the
+//replies that name them in `in_reply_to` are drawn as linked pairs.
+
+#[derive(Debug, Clone, Default)]
+pub struct DiagramFilter {
+    //Message types to keep. A type also keeps its `_ok` reply, so `read`
+    //keeps both `read` and `read_ok`. Empty keeps every type.
+    pub types: HashSet<String>,
+    //Keep messages sent or received by one of these nodes. Empty keeps every node.
+    pub nodes: HashSet<String>,
+    //Keep messages sent within this window, in nanoseconds since the start of the trace
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl DiagramFilter {
+    fn keeps(&self, entry: &TraceEntry) -> bool {
+        let message = &entry.message;
+        let message_type = body_str(&message.body, "type").unwrap_or_default();
+        (self.types.is_empty()
+            || self.types.contains(message_type)
+            || message_type
+                .strip_suffix("_ok")
+                .is_some_and(|request| self.types.contains(request)))
+            && (self.nodes.is_empty()
+                || self.nodes.contains(&message.src)
+                || self.nodes.contains(&message.dest))
+            && self.from.is_none_or(|from| entry.time >= from)
+            && self.to.is_none_or(|to| entry.time <= to)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrowKind {
+    //A request whose reply is also in the diagram
+    Request,
+    //A reply to a request in the diagram
+    Reply,
+    //Anything else: gossip, requests never answered, replies to unseen requests
+    Oneway,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arrow {
+    pub src: String,
+    pub dest: String,
+    pub sent: u64,
+    //None if the message was dropped
+    pub received: Option<u64>,
+    pub label: String,
+    pub kind: ArrowKind,
+    //Request and reply arrows of the same pair share a number
+    pub pair: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagram {
+    pub participants: Vec<String>,
+    //Ordered by send time
+    pub arrows: Vec<Arrow>,
+}
+
+//Payloads longer than this are cut short in labels.
+const MAX_LABEL_CHARS: usize = 48;
+
+fn body_str<'a>(body: &'a Value, field: &str) -> Option<&'a str> {
+    body.get(field).and_then(Value::as_str)
+}
+
+fn label(body: &Value) -> String {
+    //! The message type, its id or the id it replies to, and a short payload.
+    let mut label = body_str(body, "type").unwrap_or("?").to_owned();
+    if let Some(in_reply_to) = body.get("in_reply_to").and_then(Value::as_u64) {
+        let _ = write!(label, " ↩{}", in_reply_to);
+    } else if let Some(msg_id) = body.get("msg_id").and_then(Value::as_u64) {
+        let _ = write!(label, " #{}", msg_id);
+    }
+    if let Some(body) = body.as_object() {
+        let payload = payload(body);
+        if payload
+            .as_object()
+            .is_some_and(|payload| !payload.is_empty())
+        {
+            let mut text = payload.to_string();
+            if text.chars().count() > MAX_LABEL_CHARS {
+                text = text.chars().take(MAX_LABEL_CHARS - 1).collect::<String>() + "…";
+            }
+            label.push(' ');
+            label.push_str(&text);
+        }
+    }
+    label
+}
+
+fn participant_order(node_id: &str) -> (u8, u64, &str) {
+    //! Nodes first, then clients, then anything else (services, the nemesis),
+    //! numbered ids in numeric order.
+    let group = match node_id.chars().next() {
+        Some('n') => 0,
+        Some('c') => 1,
+        _ => 2,
+    };
+    let number = node_id
+        .get(1..)
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(u64::MAX);
+    (group, number, node_id)
+}
+
+impl Diagram {
+    pub fn from_trace(trace: &[TraceEntry], filter: &DiagramFilter) -> Self {
+        //! Build a diagram of the messages the filter keeps. A request and its reply
+        //! are paired when both are kept.
+        let mut entries: Vec<&TraceEntry> =
+            trace.iter().filter(|entry| filter.keeps(entry)).collect();
+        entries.sort_by_key(|entry| entry.time);
+
+        //Maps (requester, responder, msg_id) to the request's arrow.
+        let mut requests: HashMap<(&str, &str, u64), usize> = HashMap::new();
+        let mut arrows = Vec::new();
+        let mut participants = BTreeSet::new();
+        let mut pairs = 0;
+        for entry in entries {
+            let message = &entry.message;
+            participants.insert(participant_order(&message.src));
+            participants.insert(participant_order(&message.dest));
+            let mut arrow = Arrow {
+                src: message.src.clone(),
+                dest: message.dest.clone(),
+                sent: entry.time,
+                received: entry.received,
+                label: label(&message.body),
+                kind: ArrowKind::Oneway,
+                pair: None,
+            };
+            let request = message
+                .body
+                .get("in_reply_to")
+                .and_then(Value::as_u64)
+                .and_then(|in_reply_to| {
+                    requests.remove(&(message.dest.as_str(), message.src.as_str(), in_reply_to))
+                });
+            if let Some(request) = request {
+                let request: &mut Arrow = &mut arrows[request];
+                request.kind = ArrowKind::Request;
+                request.pair = Some(pairs);
+                arrow.kind = ArrowKind::Reply;
+                arrow.pair = Some(pairs);
+                pairs += 1;
+            } else if let Some(msg_id) = message.body.get("msg_id").and_then(Value::as_u64) {
+                requests.insert((&message.src, &message.dest, msg_id), arrows.len());
+            }
+            arrows.push(arrow);
+        }
+        Diagram {
+            participants: participants
+                .into_iter()
+                .map(|(_, _, node_id)| node_id.to_owned())
+                .collect(),
+            arrows,
+        }
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        //! A Mermaid sequence diagram. Requests activate the responder until their
+        //! reply, replies are dashed, and dropped messages end in a cross.
+        let mut mermaid = String::from("sequenceDiagram\n");
+        for participant in self.participants.iter() {
+            let _ = writeln!(mermaid, "    participant {}", participant);
+        }
+        for arrow in self.arrows.iter() {
+            let line = match (arrow.kind, arrow.received) {
+                (ArrowKind::Reply, None) => "--x",
+                (ArrowKind::Reply, Some(_)) => "-->>",
+                (_, None) => "-x",
+                (_, Some(_)) => "->>",
+            };
+            let activation = match arrow.kind {
+                ArrowKind::Request => "+",
+                ArrowKind::Reply => "-",
+                ArrowKind::Oneway => "",
+            };
+            let _ = writeln!(
+                mermaid,
+                "    {}{}{}{}: {}",
+                arrow.src,
+                line,
+                activation,
+                arrow.dest,
+                //';' ends a Mermaid statement and '#' starts an entity code.
+                arrow
+                    .label
+                    .chars()
+                    .map(|character| match character {
+                        '#' => "#35;".to_owned(),
+                        ';' => "#59;".to_owned(),
+                        character => character.to_string(),
+                    })
+                    .collect::<String>()
+            );
+        }
+        mermaid
+    }
+
+    pub fn to_svg(&self) -> String {
+        //! A standalone SVG Lamport diagram. Every send and receive gets its own row,
+        //! in time order, so idle gaps are squeezed out but causality is kept. Each
+        //! request/reply pair is drawn in its own color.
+        const COLUMN_WIDTH: u64 = 180;
+        const ROW_HEIGHT: u64 = 22;
+        const MARGIN: u64 = 70;
+        const HEADER: u64 = 40;
+
+        let mut instants: Vec<u64> = self
+            .arrows
+            .iter()
+            .flat_map(|arrow| [Some(arrow.sent), arrow.received])
+            .flatten()
+            .collect();
+        instants.sort_unstable();
+        instants.dedup();
+        let row = |time: u64| instants.binary_search(&time).unwrap_or_else(|row| row) as u64;
+        let y = |row: u64| HEADER + ROW_HEIGHT * (row + 1);
+        let column: HashMap<&str, u64> = self
+            .participants
+            .iter()
+            .enumerate()
+            .map(|(index, participant)| {
+                (participant.as_str(), MARGIN + COLUMN_WIDTH * index as u64)
+            })
+            .collect();
+        let width = MARGIN * 2 + COLUMN_WIDTH * self.participants.len().saturating_sub(1) as u64;
+        let height = y(instants.len() as u64) + ROW_HEIGHT;
+
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="monospace" font-size="11">"#
+        );
+        let _ = writeln!(
+            svg,
+            r#"<defs><marker id="head" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="7" markerHeight="7" orient="auto-start-reverse"><path d="M 0 0 L 10 5 L 0 10 z" fill="context-stroke"/></marker></defs>"#
+        );
+        let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+        for participant in self.participants.iter() {
+            let x = column[participant.as_str()];
+            let _ = writeln!(
+                svg,
+                r##"<text x="{x}" y="{}" text-anchor="middle" font-weight="bold" font-size="13">{}</text>"##,
+                HEADER - 15,
+                escape(participant)
+            );
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{x}" y1="{}" x2="{x}" y2="{}" stroke="#bbb" stroke-dasharray="4 3"/>"##,
+                HEADER - 5,
+                height - ROW_HEIGHT / 2
+            );
+        }
+        //Time labels for each row, in milliseconds since the start of the trace
+        for (index, time) in instants.iter().enumerate() {
+            let _ = writeln!(
+                svg,
+                r##"<text x="4" y="{}" fill="#999" font-size="9">{:.1}ms</text>"##,
+                y(index as u64) + 3,
+                *time as f64 / 1_000_000.0
+            );
+        }
+        for arrow in self.arrows.iter() {
+            let (x1, x2) = (column[arrow.src.as_str()], column[arrow.dest.as_str()]);
+            let y1 = y(row(arrow.sent));
+            let color = match arrow.pair {
+                Some(pair) => pair_color(pair),
+                None => "#888".to_owned(),
+            };
+            let dash = if arrow.kind == ArrowKind::Reply {
+                r#" stroke-dasharray="6 3""#
+            } else {
+                ""
+            };
+            let title = format!("{} → {}: {}", arrow.src, arrow.dest, arrow.label);
+            match arrow.received {
+                Some(received) => {
+                    let y2 = y(row(received));
+                    let _ = writeln!(
+                        svg,
+                        r#"<g><title>{}</title><line x1="{x1}" y1="{y1}" x2="{x2}" y2="{y2}" stroke="{color}" stroke-width="1.5"{dash} marker-end="url(#head)"/>"#,
+                        escape(&title)
+                    );
+                }
+                None => {
+                    //Dropped: the arrow stops halfway and ends in a cross.
+                    let (xm, ym) = ((x1 + x2) / 2, y1 + ROW_HEIGHT / 2);
+                    let _ = writeln!(
+                        svg,
+                        r##"<g><title>{} (dropped)</title><line x1="{x1}" y1="{y1}" x2="{xm}" y2="{ym}" stroke="{color}" stroke-width="1.5"{dash}/><path d="M {} {} L {} {} M {} {} L {} {}" stroke="#d00" stroke-width="2"/>"##,
+                        escape(&title),
+                        xm - 4,
+                        ym - 4,
+                        xm + 4,
+                        ym + 4,
+                        xm - 4,
+                        ym + 4,
+                        xm + 4,
+                        ym - 4
+                    );
+                }
+            }
+            let (anchor, text_x) = if x2 >= x1 {
+                ("start", x1 + 6)
+            } else {
+                ("end", x1 - 6)
+            };
+            let _ = writeln!(
+                svg,
+                r#"<text x="{text_x}" y="{}" text-anchor="{anchor}" fill="{color}">{}</text></g>"#,
+                y1 - 4,
+                escape(&arrow.label)
+            );
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+fn pair_color(pair: usize) -> String {
+    //! Spread pair colors around the hue wheel using the golden angle.
+    let hue = (pair as f64 * 137.508) % 360.0;
+    format!("hsl({:.0}, 70%, 40%)", hue)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
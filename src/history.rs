@@ -0,0 +1,245 @@
+use crate::trace::TraceEntry;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+pub mod edn;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum OpType {
+    Invoke,
+    Ok,
+    Fail,
+    Info,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum Process {
+    //A client process, numbered like Maelstrom's worker threads
+    Client(usize),
+    //A named process, such as the nemesis
+    Named(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Operation {
+    #[serde(rename = "type")]
+    pub op_type: OpType,
+    pub process: Process,
+    //Nanoseconds since the start of the test
+    pub time: u64,
+    pub f: String,
+    pub value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    //The request's msg_id, when the history was recorded from messages. A client
+    //may have several requests in flight, and this tells their completions apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct History {
+    pub ops: Vec<Operation>,
+}
+
+//Maelstrom error codes that leave the outcome of a request unknown.
+//Every other error code means the request definitely did not happen.
+const INDEFINITE_ERROR_CODES: [u64; 2] = [0, 13];
+
+impl History {
+    pub fn read_jsonl(reader: impl BufRead) -> io::Result<Self> {
+        //! Read a history with one JSON operation per line. Blank lines are skipped.
+        let mut ops = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let operation = serde_json::from_str(&line)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            ops.push(operation);
+        }
+        Ok(History { ops })
+    }
+
+    pub fn write_jsonl(&self, mut writer: impl Write) -> io::Result<()> {
+        for operation in self.ops.iter() {
+            serde_json::to_writer(&mut writer, operation)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    pub fn from_edn(source: &str) -> Result<Self, edn::EdnError> {
+        //! Convert a Maelstrom `history.edn` file. Keywords become strings, and
+        //! EDN sets and lists become JSON arrays.
+        let mut ops = Vec::new();
+        for form in edn::parse_all(source)? {
+            match form {
+                //Some writers wrap the whole history in a single vector.
+                Value::Array(forms) => {
+                    for form in forms {
+                        ops.push(edn::operation(form)?);
+                    }
+                }
+                form => ops.push(edn::operation(form)?),
+            }
+        }
+        Ok(History { ops })
+    }
+
+    pub fn from_trace(trace: &[TraceEntry]) -> Self {
+        //! Derive a client history from a trace of messages. A request from a client
+        //! (a node id starting with 'c') is an invoke, and the node's reply to it is the
+        //! completion. Requests that never got a reply are completed as info ops at the end.
+        let mut ops = Vec::new();
+        //Maps (client, msg_id) to the index of the invoke.
+        let mut pending: HashMap<(String, u64), usize> = HashMap::new();
+
+        for entry in trace.iter() {
+            let message = &entry.message;
+            let body = match message.body.as_object() {
+                Some(body) => body,
+                None => continue,
+            };
+            if let Some(process) = client_process(&message.src) {
+                let msg_id = match body.get("msg_id").and_then(Value::as_u64) {
+                    Some(msg_id) => msg_id,
+                    None => continue,
+                };
+                pending.insert((message.src.clone(), msg_id), ops.len());
+                ops.push(Operation {
+                    op_type: OpType::Invoke,
+                    process,
+                    time: entry.time,
+                    f: body_type(body),
+                    value: payload(body),
+                    error: None,
+                    index: None,
+                    msg_id: Some(msg_id),
+                });
+            } else if let Some(process) = client_process(&message.dest) {
+                let invoke_index = match body
+                    .get("in_reply_to")
+                    .and_then(Value::as_u64)
+                    .and_then(|in_reply_to| pending.remove(&(message.dest.clone(), in_reply_to)))
+                {
+                    Some(invoke_index) => invoke_index,
+                    None => continue,
+                };
+                let f = ops[invoke_index].f.clone();
+                let msg_id = ops[invoke_index].msg_id;
+                let completion = if body_type(body) == "error" {
+                    Operation {
+                        op_type: error_type(body),
+                        process,
+                        time: entry.time,
+                        f,
+                        value: ops[invoke_index].value.clone(),
+                        error: Some(payload(body)),
+                        index: None,
+                        msg_id,
+                    }
+                } else {
+                    Operation {
+                        op_type: OpType::Ok,
+                        process,
+                        time: entry.time,
+                        f,
+                        value: payload(body),
+                        error: None,
+                        index: None,
+                        msg_id,
+                    }
+                };
+                ops.push(completion);
+            }
+        }
+
+        //Anything still pending has an unknown outcome.
+        let end = trace.last().map_or(0, |entry| entry.time);
+        let mut unanswered: Vec<usize> = pending.into_values().collect();
+        unanswered.sort_unstable();
+        for invoke_index in unanswered {
+            let invoke = &ops[invoke_index];
+            ops.push(Operation {
+                op_type: OpType::Info,
+                time: end,
+                ..invoke.clone()
+            });
+        }
+        let mut history = History { ops };
+        history.reindex();
+        history
+    }
+
+    pub fn reindex(&mut self) {
+        //! Number the ops in order, as Maelstrom does.
+        for (index, operation) in self.ops.iter_mut().enumerate() {
+            operation.index = Some(index);
+        }
+    }
+
+    pub fn pairs(&self) -> Vec<(&Operation, Option<&Operation>)> {
+        //! Match every invoke with the completion from the same process and
+        //! request, if one exists. Ops from named processes (the nemesis) are skipped.
+        let mut pairs = Vec::new();
+        let mut open: HashMap<(&Process, Option<u64>), usize> = HashMap::new();
+        for operation in self.ops.iter() {
+            if let Process::Named(_) = operation.process {
+                continue;
+            }
+            match operation.op_type {
+                OpType::Invoke => {
+                    open.insert((&operation.process, operation.msg_id), pairs.len());
+                    pairs.push((operation, None));
+                }
+                _ => {
+                    if let Some(pair_index) = open.remove(&(&operation.process, operation.msg_id)) {
+                        pairs[pair_index].1 = Some(operation);
+                    }
+                }
+            }
+        }
+        pairs
+    }
+}
+
+pub(crate) fn error_type(body: &Map<String, Value>) -> OpType {
+    //! Whether an error reply means the request definitely failed.
+    match body.get("code").and_then(Value::as_u64) {
+        Some(code) if !INDEFINITE_ERROR_CODES.contains(&code) => OpType::Fail,
+        _ => OpType::Info,
+    }
+}
+
+fn client_process(node_id: &str) -> Option<Process> {
+    node_id
+        .strip_prefix('c')
+        .and_then(|number| number.parse().ok())
+        .map(Process::Client)
+}
+
+pub(crate) fn body_type(body: &Map<String, Value>) -> String {
+    body.get("type")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .trim_end_matches("_ok")
+        .to_owned()
+}
+
+pub(crate) fn payload(body: &Map<String, Value>) -> Value {
+    //! The body without its message envelope fields.
+    let payload: Map<String, Value> = body
+        .iter()
+        .filter(|(field, _)| !matches!(field.as_str(), "type" | "msg_id" | "in_reply_to"))
+        .map(|(field, value)| (field.clone(), value.clone()))
+        .collect();
+    Value::Object(payload)
+}
@@ -0,0 +1,335 @@
+use super::{OpType, Operation, Process};
+use serde_json::{Map, Number, Value};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnError {
+    //Byte offset into the source where parsing failed, or None where a form
+    //parsed but could not be converted
+    pub position: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for EdnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "EDN error at byte {}: {}", position, self.message),
+            None => write!(f, "EDN error: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for EdnError {}
+
+pub fn parse_all(source: &str) -> Result<Vec<Value>, EdnError> {
+    //! Parse every top-level EDN form in the source into JSON.
+    let mut parser = Parser {
+        source: source.as_bytes(),
+        position: 0,
+    };
+    let mut forms = Vec::new();
+    while let Some(form) = parser.next_form()? {
+        forms.push(form);
+    }
+    Ok(forms)
+}
+
+pub fn operation(form: Value) -> Result<Operation, EdnError> {
+    //! Convert a parsed history op map, such as
+    //! `{:type :ok, :f :read, :value 3, :process 0, :time 1234, :index 7}`.
+    let invalid = |message: &str| EdnError {
+        position: None,
+        message: format!("{}: {}", message, form),
+    };
+    let op = form.as_object().ok_or_else(|| invalid("op is not a map"))?;
+    let op_type = match op.get("type").and_then(Value::as_str) {
+        Some("invoke") => OpType::Invoke,
+        Some("ok") => OpType::Ok,
+        Some("fail") => OpType::Fail,
+        Some("info") => OpType::Info,
+        _ => return Err(invalid("op has no valid :type")),
+    };
+    let process = match op.get("process") {
+        Some(Value::Number(number)) => Process::Client(
+            number
+                .as_u64()
+                .ok_or_else(|| invalid("op has a negative :process"))? as usize,
+        ),
+        Some(Value::String(name)) => Process::Named(name.clone()),
+        _ => return Err(invalid("op has no :process")),
+    };
+    Ok(Operation {
+        op_type,
+        process,
+        time: op.get("time").and_then(Value::as_u64).unwrap_or(0),
+        f: match op.get("f") {
+            Some(Value::String(f)) => f.clone(),
+            Some(f) => f.to_string(),
+            None => return Err(invalid("op has no :f")),
+        },
+        value: op.get("value").cloned().unwrap_or(Value::Null),
+        error: op.get("error").cloned(),
+        index: op
+            .get("index")
+            .and_then(Value::as_u64)
+            .map(|index| index as usize),
+        msg_id: None,
+    })
+}
+
+struct Parser<'a> {
+    source: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: impl Into<String>) -> EdnError {
+        EdnError {
+            position: Some(self.position),
+            message: message.into(),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        //Commas are whitespace in EDN, and comments run to the end of the line.
+        while let Some(byte) = self.peek() {
+            if byte.is_ascii_whitespace() || byte == b',' {
+                self.position += 1;
+            } else if byte == b';' {
+                while !matches!(self.peek(), None | Some(b'\n')) {
+                    self.position += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn skip_ignored(&mut self) -> Result<(), EdnError> {
+        //! Skip whitespace, comments and `#_` discarded forms, which may come
+        //! right before a closing delimiter or the end of input.
+        loop {
+            self.skip_whitespace();
+            if !self.source[self.position..].starts_with(b"#_") {
+                return Ok(());
+            }
+            self.position += 2;
+            self.form()?;
+        }
+    }
+
+    fn next_form(&mut self) -> Result<Option<Value>, EdnError> {
+        self.skip_ignored()?;
+        if self.peek().is_none() {
+            return Ok(None);
+        }
+        self.form().map(Some)
+    }
+
+    fn form(&mut self) -> Result<Value, EdnError> {
+        self.skip_ignored()?;
+        match self.peek() {
+            None => Err(self.error("unexpected end of input")),
+            Some(b'{') => {
+                self.position += 1;
+                self.map()
+            }
+            Some(b'[') => {
+                self.position += 1;
+                self.sequence(b']').map(Value::Array)
+            }
+            Some(b'(') => {
+                self.position += 1;
+                self.sequence(b')').map(Value::Array)
+            }
+            Some(b'"') => {
+                self.position += 1;
+                self.string().map(Value::String)
+            }
+            Some(b'#') => {
+                self.position += 1;
+                match self.peek() {
+                    Some(b'{') => {
+                        self.position += 1;
+                        self.sequence(b'}').map(Value::Array)
+                    }
+                    _ => {
+                        //A tagged literal or record, such as #inst "..." or
+                        //#jepsen.history.Op{...}. The tag is dropped.
+                        self.token();
+                        self.form()
+                    }
+                }
+            }
+            Some(b'\\') => {
+                self.position += 1;
+                let character = self.token();
+                Ok(Value::String(match character {
+                    "newline" => "\n".to_owned(),
+                    "space" => " ".to_owned(),
+                    "tab" => "\t".to_owned(),
+                    character => character.to_owned(),
+                }))
+            }
+            Some(b'}' | b']' | b')') => Err(self.error("unbalanced closing delimiter")),
+            Some(_) => self.atom(),
+        }
+    }
+
+    fn sequence(&mut self, close: u8) -> Result<Vec<Value>, EdnError> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_ignored()?;
+            match self.peek() {
+                None => return Err(self.error("unterminated collection")),
+                Some(byte) if byte == close => {
+                    self.position += 1;
+                    return Ok(items);
+                }
+                Some(_) => items.push(self.form()?),
+            }
+        }
+    }
+
+    fn map(&mut self) -> Result<Value, EdnError> {
+        let items = self.sequence(b'}')?;
+        if items.len() % 2 != 0 {
+            return Err(self.error("map has an odd number of forms"));
+        }
+        let mut map = Map::new();
+        let mut items = items.into_iter();
+        while let (Some(key), Some(value)) = (items.next(), items.next()) {
+            let key = match key {
+                Value::String(key) => key,
+                key => key.to_string(),
+            };
+            map.insert(key, value);
+        }
+        Ok(Value::Object(map))
+    }
+
+    fn string(&mut self) -> Result<String, EdnError> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.position += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escaped = self
+                        .peek()
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.position += 1;
+                    match escaped {
+                        b'n' => bytes.push(b'\n'),
+                        b't' => bytes.push(b'\t'),
+                        b'r' => bytes.push(b'\r'),
+                        b'u' => {
+                            let mut encoded = [0; 4];
+                            bytes.extend_from_slice(
+                                self.unicode_escape()?.encode_utf8(&mut encoded).as_bytes(),
+                            );
+                        }
+                        other => bytes.push(other),
+                    }
+                }
+                Some(byte) => {
+                    bytes.push(byte);
+                    self.position += 1;
+                }
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("string is not valid UTF-8"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, EdnError> {
+        //! Read the four hex digits of a `\u` escape.
+        let digits = self
+            .source
+            .get(self.position..self.position + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .filter(|digits| digits.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("\\u escape needs four hex digits"))?;
+        let code = u32::from_str_radix(digits, 16).unwrap_or_default();
+        self.position += 4;
+        Ok(code)
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, EdnError> {
+        //! Decode the rest of a `\uXXXX` escape. Characters outside the Basic
+        //! Multilingual Plane are written as a pair of escaped UTF-16 surrogates.
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.source[self.position..].starts_with(b"\\u") {
+                return Err(self.error("unpaired surrogate in \\u escape"));
+            }
+            self.position += 2;
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("unpaired surrogate in \\u escape"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate in \\u escape"))
+    }
+
+    fn token(&mut self) -> &str {
+        //! Consume a run of bytes up to the next delimiter.
+        let start = self.position;
+        while let Some(byte) = self.peek() {
+            if byte.is_ascii_whitespace() || b",;{}[]()\"".contains(&byte) {
+                break;
+            }
+            self.position += 1;
+        }
+        //Tokens only ever split the source at ASCII delimiters.
+        std::str::from_utf8(&self.source[start..self.position]).unwrap_or_default()
+    }
+
+    fn atom(&mut self) -> Result<Value, EdnError> {
+        let start = self.position;
+        let token = self.token();
+        let value = match token {
+            "" => {
+                return Err(EdnError {
+                    position: Some(start),
+                    message: "expected a form".to_owned(),
+                })
+            }
+            "nil" => Value::Null,
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            keyword if keyword.starts_with(':') => Value::String(keyword[1..].to_owned()),
+            number
+                if number.starts_with(|c: char| c.is_ascii_digit())
+                    || (number.len() > 1 && number.starts_with(['-', '+'])) =>
+            {
+                //Drop EDN's bigint (N) and bigdecimal (M) suffixes.
+                let number = number.trim_end_matches(['N', 'M']);
+                if let Ok(integer) = number.parse::<i64>() {
+                    Value::Number(integer.into())
+                } else if let Ok(integer) = number.parse::<u64>() {
+                    Value::Number(integer.into())
+                } else {
+                    //Anything else that merely looks numeric is kept as a symbol.
+                    number
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(Number::from_f64)
+                        .map_or_else(|| Value::String(number.to_owned()), Value::Number)
+                }
+            }
+            symbol => Value::String(symbol.to_owned()),
+        };
+        Ok(value)
+    }
+}
@@ -59,19 +59,24 @@ impl MaelstromInit {
         //reply InitOk. Construct and return a struct containing
         //the key metadata for the node runtime
         let init_message = MaelstromInit::read_stdin();
-        let init_copy = init_message.clone();
+        let mut stdout_handle = std::io::stdout().lock();
+        init_message.respond(&mut stdout_handle)
+    }
+
+    pub fn respond(self, output: &mut impl Write) -> NodeMetadata {
+        //! Write the InitOk reply for this Init to output, and return
+        //! the node metadata it carries.
+        let init_copy = self.clone();
 
         //Reply to the MaelStromInit message.
         let init_reply = MaelstromInit {
-            src: init_message.dest,
-            dest: init_message.src,
-            body: init_message.body.into_reply(),
+            src: self.dest,
+            dest: self.src,
+            body: self.body.into_reply(),
         };
-        let mut stdout_handle = std::io::stdout().lock();
-        serde_json::to_writer(&mut stdout_handle, &init_reply)
-            .expect("Unable to serialize to writer.");
+        serde_json::to_writer(&mut *output, &init_reply).expect("Unable to serialize to writer.");
         //Maelstrom requires a new line character
-        stdout_handle
+        output
             .write_all(b"\n")
             .expect("Unable to write newline character");
 
@@ -0,0 +1,15 @@
+pub mod checker;
+pub mod clock;
+pub mod diagram;
+pub mod history;
+pub mod init;
+pub mod node;
+pub mod repl;
+pub mod rng;
+pub mod runner;
+pub mod service;
+pub mod trace;
+pub mod transcript;
+
+pub use init::NodeMetadata;
+pub use node::{Event, MaelstromMessage, Node, Reply};
@@ -1,15 +1,16 @@
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::env;
 use std::io::{self, BufRead};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
 
-mod node;
-use node::kv_store;
-use node::{Event, MaelstromMessage, Node, Reply};
-
-mod init;
-use init::NodeMetadata;
+use event_horizon::clock;
+use event_horizon::init::{self, NodeMetadata};
+use event_horizon::node::{
+    broadcast, echo, generate_id, grow_counter, kafka, kv_store, parse_event,
+};
+use event_horizon::{Event, Node, Reply};
 
 fn node_runtime<Body, NodeState>(
     mut node: NodeState,
@@ -24,12 +25,15 @@ fn node_runtime<Body, NodeState>(
     thread::spawn(move || {
         for line in io::stdin().lock().lines() {
             let line = line.expect("Nothing recieved via stdin...");
-            let message: Result<MaelstromMessage<Body>, _> = serde_json::from_str(&line);
-            match message {
-                Ok(inbound_message) => {
-                    tx.send(Event::Message(inbound_message))
-                        .expect("Failed to transmit to stdout thread");
+            if clock::handle_control(&line) {
+                continue;
+            }
+            match parse_event(&line) {
+                Ok(Some(event)) => {
+                    tx.send(event).expect("Failed to transmit to stdout thread");
                 }
+                //A service reply taken by a blocked caller
+                Ok(None) => {}
                 Err(_) => eprintln!("Received the following invalid maelstrom message: {}", line),
             }
         }
@@ -40,10 +44,31 @@ fn node_runtime<Body, NodeState>(
     }
 }
 
-fn main() {
-    let node_metadata = init::MaelstromInit::init_node();
+fn run<Body, NodeState>(node_metadata: NodeMetadata)
+where
+    NodeState: Node<Body>,
+    Body: Serialize + DeserializeOwned + Reply<NodeState> + Send + 'static,
+{
     let (tx, rx) = channel();
     let init_event_tx = tx.clone();
-    let node = Node::node_init(node_metadata, init_event_tx);
-    node_runtime::<kv_store::KVStoreBody, kv_store::KVStoreNode>(node, tx, rx);
+    let node = NodeState::node_init(node_metadata, init_event_tx);
+    node_runtime::<Body, NodeState>(node, tx, rx);
+}
+
+fn main() {
+    //The node type is picked with the EVENT_HORIZON_NODE environment variable,
+    //which Maelstrom passes through to the binary. Defaults to kv_store.
+    let node_type = env::var("EVENT_HORIZON_NODE").unwrap_or_else(|_| "kv_store".to_owned());
+    let runtime = match node_type.as_str() {
+        "echo" => run::<echo::EchoBody, echo::EchoNode>,
+        "generate_id" => run::<generate_id::GenerateGuidBody, generate_id::GenerateGuidNode>,
+        "broadcast" => run::<broadcast::BroadcastBody, broadcast::BroadcastNode>,
+        "grow_counter" => run::<grow_counter::CounterBody, grow_counter::CounterNode>,
+        "kafka" => run::<kafka::KafkaBody, kafka::KafkaNode>,
+        "kv_store" => run::<kv_store::KVStoreBody, kv_store::KVStoreNode>,
+        other => panic!("Unknown EVENT_HORIZON_NODE: {}", other),
+    };
+    let node_metadata = init::MaelstromInit::init_node();
+    clock::configure_from_env(&node_metadata.node_id);
+    runtime(node_metadata);
 }
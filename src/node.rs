@@ -1,8 +1,11 @@
-use crate::init;
+use crate::{init, service};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::io;
+use serde_json::Value;
 use std::io::Write;
 use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
 
 pub mod broadcast;
 pub mod echo;
@@ -16,12 +19,47 @@ pub enum Event<Body> {
     //the Node should react to in some way.
     Message(MaelstromMessage<Body>),
     PropogateWrites,
+    //A reply from one of Maelstrom's services, such as lin-kv,
+    //that no blocking caller was waiting for.
+    ServiceReply(MaelstromMessage<Value>),
+}
+
+pub fn parse_event<Body: DeserializeOwned>(
+    line: &str,
+) -> Result<Option<Event<Body>>, serde_json::Error> {
+    //! Parse a line of input into an Event. Service replies are handed to any
+    //! caller blocked on them first, in which case there is no Event.
+    #[derive(Deserialize)]
+    struct Source {
+        src: String,
+    }
+    let source: Source = serde_json::from_str(line)?;
+    if service::is_service(&source.src) {
+        let message = serde_json::from_str(line)?;
+        Ok(service::deliver(message).map(Event::ServiceReply))
+    } else {
+        //Parsed from the line itself rather than via a Value, since map keys
+        //such as kv_store's integer keys only deserialize from strings that way.
+        Ok(Some(Event::Message(serde_json::from_str(line)?)))
+    }
+}
+
+pub fn spawn_ticker<Body: Send + 'static>(interval: Duration, event_tx: Sender<Event<Body>>) {
+    //! Send a PropogateWrites Event every interval, until the receiving
+    //! end of the channel is dropped.
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if event_tx.send(Event::PropogateWrites).is_err() {
+            break;
+        }
+    });
 }
 
 pub trait Node<Body> {
     //Core trait representing a type that can be used as a Node in a Maelstrom Challenge.
     fn node_init(node_metadata: init::NodeMetadata, event_tx: Sender<Event<Body>>) -> Self;
-    fn handle_event(&mut self, event: Event<Body>, stdout_handle: &mut io::StdoutLock)
+    //Outbound messages are written to `output`, which is stdout when running under Maelstrom.
+    fn handle_event(&mut self, event: Event<Body>, output: &mut impl Write)
     where
         Body: Reply<Self>,
         Self: Sized;
@@ -46,17 +84,17 @@ impl<Body> MaelstromMessage<Body>
 where
     Body: Serialize,
 {
-    pub fn send(&mut self, mut stdout_handle: &mut io::StdoutLock) {
-        //Given a stdout handle, write the MaelStrom Message via serde_json
-        serde_json::to_writer(&mut stdout_handle, self).expect("Unable to serialize to writer.");
+    pub fn send(&mut self, output: &mut (impl Write + ?Sized)) {
+        //Given an output handle, write the MaelStrom Message via serde_json
+        serde_json::to_writer(&mut *output, self).expect("Unable to serialize to writer.");
         //Maelstrom requires a new line character
-        stdout_handle
+        output
             .write_all(b"\n")
             .expect("Unable to write newline character");
     }
     pub fn message_reply<NodeState>(
         self,
-        stdout_handle: &mut io::StdoutLock,
+        output: &mut (impl Write + ?Sized),
         node_state: &mut NodeState,
     ) where
         Body: Reply<NodeState>,
@@ -70,7 +108,7 @@ where
                 dest: self.src,
                 body: reply_body,
             };
-            message.send(stdout_handle);
+            message.send(output);
         }
     }
 }
@@ -1,20 +1,233 @@
+pub mod batching;
+pub mod causal;
+pub mod digest;
+pub mod interval_set;
+pub mod plumtree;
+pub mod push_pull;
+pub mod stability;
+pub mod topology;
+pub mod total;
+
 use crate::node::MaelstromMessage;
 
-use super::{Event, Node, Reply};
+use super::{spawn_ticker, Event, Node, Reply};
+use batching::{BatchConfig, GossipBatcher};
+use causal::{Causal, VectorClock};
+use digest::{AntiEntropy, RangeDigest};
+use interval_set::IntervalSet;
+use plumtree::Plumtree;
+use push_pull::{PushPull, PushPullConfig};
 use serde::{self, Deserialize, Serialize};
-use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet};
+use serde_json::Value;
+use stability::Stability;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
 use std::sync::mpsc::Sender;
-use std::{io, thread};
+use std::time::{Duration, Instant};
+use topology::Topology;
+use total::{Slot, TotalOrder};
 
 pub struct BroadcastNode {
     pub node_id: String,
     pub neighbors: Vec<String>,
     pub current_msg_id: usize,
-    pub messages: HashSet<usize>,
+    //Every node in the cluster, sorted so that each one's index is its origin
+    pub node_ids: Vec<String>,
+    //This node's index among the cluster's nodes, and the sequence number its
+    //next broadcast will be given
+    pub origin: usize,
+    pub next_seq: usize,
+    //Where next_seq is kept when EVENT_HORIZON_STATE_DIR is set, so a node that is
+    //killed and restarted carries on from it instead of reusing ids it gave out
+    pub seq_file: Option<PathBuf>,
+    //The packed MessageId of every broadcast this node has
+    pub messages: IntervalSet,
+    //The payload of each, by id, so reads list them by origin and then sequence
+    pub payloads: BTreeMap<usize, Value>,
     //Stores a Mapping of Node ID to messages we know the other node
-    //has seen
-    pub confirmed_seen: HashMap<String, HashSet<usize>>,
+    //has seen, leaving out stable messages, which every node has
+    pub confirmed_seen: HashMap<String, IntervalSet>,
+    //Set when stability is tracked: what every node is known to have, and so
+    //which messages are stable
+    pub stability: Option<Stability>,
+    //Where neighbors come from. Any topology but Provided is built at init, and
+    //the one Maelstrom sends is ignored.
+    pub topology: Topology,
+    //Set when gossip is batched and flushed adaptively instead of every 250ms
+    pub batching: Option<GossipBatcher>,
+    //Set when messages are spread by Plumtree instead of gossip
+    pub plumtree: Option<Plumtree>,
+    //Set when new messages are pushed once, and anything missed is pulled after
+    //comparing digests
+    pub anti_entropy: Option<AntiEntropy>,
+    //Set when messages are exchanged with random peers instead of neighbors
+    pub push_pull: Option<PushPull>,
+    //Set when messages are delivered, and read, in causal order
+    pub causal: Option<Causal>,
+    //Set when messages are delivered, and read, in the order a sequencer gives them
+    pub total: Option<TotalOrder>,
+}
+
+//Broadcasts are told apart by the node a client sent them to and the order it
+//received them in, not by their payload, so the same payload can be broadcast
+//twice. On the wire the two are packed into one integer with the origin in the
+//high bits, so each node's broadcasts are a single run of ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MessageId {
+    pub origin: usize,
+    pub seq: usize,
+}
+
+impl MessageId {
+    pub const SEQ_BITS: u32 = 32;
+
+    pub fn pack(self) -> usize {
+        (self.origin << Self::SEQ_BITS) | self.seq
+    }
+
+    pub fn unpack(id: usize) -> Self {
+        MessageId {
+            origin: id >> Self::SEQ_BITS,
+            seq: id & ((1 << Self::SEQ_BITS) - 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GossipMode {
+    //Gossip every neighbor what it has not acked every 250ms
+    Periodic,
+    //Hold new messages and flush them adaptively
+    Batched(BatchConfig),
+    //Eager push along a spanning tree, lazy push of ids to the other neighbors
+    Plumtree,
+    //Push new messages once, and pull whatever a digest shows is missing
+    Digest,
+    //Exchange what each side is missing with random peers, whatever the topology
+    PushPull(PushPullConfig),
+}
+
+//What order messages are delivered in, whichever way they are gossiped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryOrder {
+    //Delivered as soon as they arrive, and read by origin and sequence
+    Unordered,
+    //Held until their causal predecessors are delivered, and read in delivery order
+    Causal,
+    //Delivered in one order on every node, as a sequencer places them
+    Total { election_timeout: Duration },
+}
+
+impl DeliveryOrder {
+    pub fn parse(spec: &str) -> Option<Self> {
+        //! Parse `unordered`, `causal`, or `total`, optionally followed by an
+        //! election timeout in milliseconds, e.g. `total:election_timeout=200`.
+        let (name, settings) = spec.split_once(':').unwrap_or((spec, ""));
+        match name {
+            "unordered" if settings.is_empty() => Some(DeliveryOrder::Unordered),
+            "causal" if settings.is_empty() => Some(DeliveryOrder::Causal),
+            "total" => {
+                let mut election_timeout = total::ELECTION_TIMEOUT;
+                for setting in settings.split(',').filter(|setting| !setting.is_empty()) {
+                    match setting.split_once('=')? {
+                        ("election_timeout", value) => {
+                            election_timeout = value
+                                .parse::<u64>()
+                                .ok()
+                                .filter(|millis| *millis > 0)
+                                .map(Duration::from_millis)?
+                        }
+                        _ => return None,
+                    }
+                }
+                Some(DeliveryOrder::Total { election_timeout })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl GossipMode {
+    pub fn parse(spec: &str) -> Option<Self> {
+        //! Parse `periodic`, `plumtree`, `digest`, a batching spec such as
+        //! `batched:size=16`, or a push-pull spec such as `push-pull:fanout=2`.
+        match spec {
+            "periodic" => Some(GossipMode::Periodic),
+            "plumtree" => Some(GossipMode::Plumtree),
+            "digest" => Some(GossipMode::Digest),
+            spec => BatchConfig::parse(spec)
+                .map(GossipMode::Batched)
+                .or_else(|| PushPullConfig::parse(spec).map(GossipMode::PushPull)),
+        }
+    }
+}
+
+//How a broadcast node gossips, orders and tracks messages, picked with
+//EVENT_HORIZON_* variables
+#[derive(Debug, Clone, PartialEq)]
+pub struct BroadcastConfig {
+    //EVENT_HORIZON_GOSSIP, defaulting to `periodic`
+    pub gossip: GossipMode,
+    //EVENT_HORIZON_ORDER, defaulting to `unordered`
+    pub order: DeliveryOrder,
+    //EVENT_HORIZON_TOPOLOGY, defaulting to `provided`
+    pub topology: Topology,
+    //EVENT_HORIZON_STABILITY, `on` or `off`, defaulting to `off`
+    pub stability: bool,
+    //Set from EVENT_HORIZON_STATE_DIR, where each node keeps its next sequence number
+    pub state_dir: Option<PathBuf>,
+}
+
+impl BroadcastConfig {
+    pub fn from_env() -> Self {
+        BroadcastConfig::from_vars(|name| env::var(name).ok())
+    }
+
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        //! Read each setting with `var`, panicking on a value it does not know.
+        let on_off = |spec: &str| match spec {
+            "on" => Some(true),
+            "off" => Some(false),
+            _ => None,
+        };
+        BroadcastConfig {
+            gossip: setting(
+                &var,
+                "EVENT_HORIZON_GOSSIP",
+                GossipMode::parse,
+                GossipMode::Periodic,
+            ),
+            order: setting(
+                &var,
+                "EVENT_HORIZON_ORDER",
+                DeliveryOrder::parse,
+                DeliveryOrder::Unordered,
+            ),
+            topology: setting(
+                &var,
+                "EVENT_HORIZON_TOPOLOGY",
+                Topology::parse,
+                Topology::Provided,
+            ),
+            stability: setting(&var, "EVENT_HORIZON_STABILITY", on_off, false),
+            state_dir: var("EVENT_HORIZON_STATE_DIR").map(PathBuf::from),
+        }
+    }
+}
+
+fn setting<T>(
+    var: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    parse: impl Fn(&str) -> Option<T>,
+    default: T,
+) -> T {
+    match var(name) {
+        Some(spec) => parse(&spec).unwrap_or_else(|| panic!("Unknown {}: {}", name, spec)),
+        None => default,
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -23,7 +236,7 @@ pub struct BroadcastNode {
 pub enum BroadcastBody {
     Broadcast {
         msg_id: usize,
-        message: usize,
+        message: Value,
     },
     BroadcastOk {
         msg_id: usize,
@@ -35,7 +248,7 @@ pub enum BroadcastBody {
     ReadOk {
         msg_id: usize,
         in_reply_to: usize,
-        messages: Vec<usize>,
+        messages: Vec<Value>,
     },
     Topology {
         msg_id: usize,
@@ -45,15 +258,158 @@ pub enum BroadcastBody {
         in_reply_to: usize,
         msg_id: usize,
     },
+    //The ids of the messages gossiped, and the payload of each in id order. With
+    //causal delivery, the vector clock of each too.
     Gossip {
         msg_id: usize,
-        message: Vec<usize>,
+        message: IntervalSet,
+        payloads: Vec<Value>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        clocks: Vec<VectorClock>,
     },
     GossipOk {
         in_reply_to: usize,
         msg_id: usize,
-        ack_message: Vec<usize>,
+        ack_message: IntervalSet,
+    },
+    //Plumtree's lazy push: messages the sender has, which the receiver can graft.
+    //If `ack` is set, answered by a GossipOk with those the receiver already has.
+    #[serde(rename = "ihave")]
+    IHave {
+        msg_id: usize,
+        messages: IntervalSet,
+        ack: bool,
+    },
+    //Asks the receiver to push these messages, and push to the sender from now on
+    Graft {
+        msg_id: usize,
+        messages: IntervalSet,
+    },
+    //Asks the receiver to stop pushing to the sender, which was pushed a message twice
+    Prune {
+        msg_id: usize,
+    },
+    //A summary of the sender's messages in each range, for the receiver to compare
+    //against its own
+    Digest {
+        msg_id: usize,
+        ranges: Vec<RangeDigest>,
+    },
+    //Asks for the receiver's messages in these ranges, other than those the sender has
+    Pull {
+        msg_id: usize,
+        ranges: Vec<(usize, usize)>,
+        have: IntervalSet,
+    },
+    PullOk {
+        in_reply_to: usize,
+        msg_id: usize,
+        messages: IntervalSet,
+        payloads: Vec<Value>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        clocks: Vec<VectorClock>,
+    },
+    //Asks for the node's vector clock: how many broadcasts from each node it has
+    //delivered, counting only those with every earlier one from that node
+    ReadClock {
+        msg_id: usize,
+    },
+    ReadClockOk {
+        in_reply_to: usize,
+        msg_id: usize,
+        clock: BTreeMap<String, usize>,
+    },
+    //The sequence from `start` on, with the payload of each message placed, sent by
+    //the sequencer of `term`. `previous_term` is the term of the position before
+    //`start`, and positions before `committed` are committed.
+    Sequence {
+        msg_id: usize,
+        term: u64,
+        start: usize,
+        previous_term: u64,
+        entries: Vec<Slot>,
+        payloads: Vec<Value>,
+        committed: usize,
+    },
+    //`have` is how much of the sequence the node now holds, or, when the positions
+    //sent did not follow on from its own, where the sequencer should resend from
+    SequenceOk {
+        in_reply_to: usize,
+        msg_id: usize,
+        term: u64,
+        have: usize,
+        success: bool,
+    },
+    //A node standing to be the sequencer of `term`, and how far along its sequence is
+    RequestVote {
+        msg_id: usize,
+        term: u64,
+        length: usize,
+        last_term: u64,
+    },
+    RequestVoteOk {
+        in_reply_to: usize,
+        msg_id: usize,
+        term: u64,
+        granted: bool,
     },
+    //For each node, how many broadcasts from each origin, indexed as in the sorted
+    //node ids, it is known to have with none missing before
+    Stability {
+        msg_id: usize,
+        clocks: BTreeMap<String, VectorClock>,
+    },
+    //The counts now known for the nodes a Stability message counted
+    StabilityOk {
+        in_reply_to: usize,
+        msg_id: usize,
+        clocks: BTreeMap<String, VectorClock>,
+    },
+    //The ids a node has, sent to a random peer
+    Exchange {
+        msg_id: usize,
+        have: IntervalSet,
+    },
+    //The messages the sender of an Exchange lacks, and the ids this node has
+    ExchangeOk {
+        in_reply_to: usize,
+        msg_id: usize,
+        message: IntervalSet,
+        payloads: Vec<Value>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        clocks: Vec<VectorClock>,
+        have: IntervalSet,
+    },
+    //Asks which messages every node has. `low_watermark` is how many broadcasts
+    //from each node every node has, and `stable` the ids of those broadcasts.
+    ReadStable {
+        msg_id: usize,
+    },
+    ReadStableOk {
+        in_reply_to: usize,
+        msg_id: usize,
+        stable: IntervalSet,
+        low_watermark: BTreeMap<String, usize>,
+    },
+    //Sent for a read_stable when stability is not tracked
+    Error {
+        in_reply_to: usize,
+        code: u64,
+        text: String,
+    },
+}
+
+impl BroadcastBody {
+    fn orders(&self) -> bool {
+        //! Whether the body is one of total-order delivery's own messages.
+        matches!(
+            self,
+            BroadcastBody::Sequence { .. }
+                | BroadcastBody::SequenceOk { .. }
+                | BroadcastBody::RequestVote { .. }
+                | BroadcastBody::RequestVoteOk { .. }
+        )
+    }
 }
 
 impl Reply<BroadcastNode> for BroadcastBody {
@@ -63,45 +419,67 @@ impl Reply<BroadcastNode> for BroadcastBody {
                 msg_id,
                 mut topology,
             } => {
-                //Add this Node's topology to the Node State.
-                let (_, neighbors) = match topology.entry(node_state.node_id.clone()) {
-                    Entry::Occupied(entry) => entry.remove_entry(),
-                    Entry::Vacant(_) => {
-                        panic!("Node ID {} was not in topology map!", node_state.node_id);
+                //Add this Node's topology to the Node State, unless it built its own or
+                //picks peers at random. A map that leaves this node out gives it no
+                //neighbors.
+                if node_state.topology == Topology::Provided && node_state.push_pull.is_none() {
+                    if let Some(batching) = node_state.batching.as_mut() {
+                        batching.hops = topology::eccentricity(&topology, &node_state.node_id);
                     }
-                };
-                node_state.neighbors = neighbors;
+                    node_state.neighbors = topology.remove(&node_state.node_id).unwrap_or_default();
+                }
                 Some(BroadcastBody::TopologyOk {
                     in_reply_to: msg_id,
                     msg_id: node_state.current_msg_id,
                 })
             }
             BroadcastBody::Broadcast { msg_id, message } => {
-                node_state.messages.insert(message);
+                node_state.accept(message);
                 Some(BroadcastBody::BroadcastOk {
                     msg_id: node_state.current_msg_id,
                     in_reply_to: msg_id,
                 })
             }
             BroadcastBody::Read { msg_id } => {
-                let seen_messages: Vec<_> = node_state.messages.iter().copied().collect();
+                let messages = match (node_state.causal.as_ref(), node_state.total.as_ref()) {
+                    (Some(causal), _) => node_state.payloads_of(causal.order.iter().copied()),
+                    (_, Some(total)) => node_state.payloads_of(total.delivered()),
+                    _ => node_state.payloads.values().cloned().collect(),
+                };
                 Some(BroadcastBody::ReadOk {
                     msg_id: node_state.current_msg_id,
                     in_reply_to: msg_id,
-                    messages: seen_messages,
+                    messages,
                 })
             }
-            BroadcastBody::Gossip { msg_id, message } => {
+            BroadcastBody::ReadClock { msg_id } => Some(BroadcastBody::ReadClockOk {
+                in_reply_to: msg_id,
+                msg_id: node_state.current_msg_id,
+                clock: node_state.clock(),
+            }),
+            BroadcastBody::ReadStable { msg_id } => Some(node_state.read_stable(msg_id)),
+            BroadcastBody::Stability { msg_id, clocks } => {
+                node_state.stability_received(src, msg_id, clocks)
+            }
+            BroadcastBody::StabilityOk { clocks, .. } => {
+                if let Some(stability) = node_state.stability.as_mut() {
+                    stability.merge_from(src, clocks);
+                }
+                node_state.stabilize();
+                None
+            }
+            BroadcastBody::Gossip {
+                msg_id,
+                message,
+                payloads,
+                clocks,
+            } => {
                 //When recieving a Gossip message, first add the gossip messages to
-                // the messages Hashset.
-                node_state.messages.extend(message.clone());
+                // the messages set.
+                node_state.deliver(&message, payloads, clocks);
                 //Then, add the Messages to the confirmed seen HashMap with
                 //key=src node. (If a node sent you a message, if must already have seen those messages).
-                node_state
-                    .confirmed_seen
-                    .entry(src.to_owned())
-                    .or_default()
-                    .extend(message.clone());
+                node_state.confirm(src, &message);
                 Some(BroadcastBody::GossipOk {
                     in_reply_to: msg_id,
                     msg_id: node_state.current_msg_id,
@@ -113,11 +491,7 @@ impl Reply<BroadcastNode> for BroadcastBody {
                 // If recieving a GossipOk, add the ack_messages to
                 //the confirmed seen HashMap for the src Node (if they acked,
                 //then we know they recieved these messages
-                node_state
-                    .confirmed_seen
-                    .entry(src.to_owned())
-                    .or_default()
-                    .extend(ack_message);
+                node_state.confirm(src, &ack_message);
                 None
             }
 
@@ -126,58 +500,339 @@ impl Reply<BroadcastNode> for BroadcastBody {
     }
 }
 
-impl Node<BroadcastBody> for BroadcastNode {
-    fn node_init(
+impl BroadcastNode {
+    pub fn with_config(
         node_metadata: crate::init::NodeMetadata,
+        config: BroadcastConfig,
         event_tx: Sender<Event<BroadcastBody>>,
     ) -> Self {
-        thread::spawn(move || loop {
-            thread::sleep(std::time::Duration::from_millis(250));
-            event_tx
-                .send(Event::PropogateWrites)
-                .expect("Unable to drop PropogateWrites down the channel");
-        });
+        //! A node set up as `config` says, which node_init reads from the environment.
+        let mode = config.gossip;
+        let tick = match &mode {
+            GossipMode::Periodic => Duration::from_millis(250),
+            //Ticks decide whether a batch is due, so they come often.
+            GossipMode::Batched(_) => batching::MIN_INTERVAL,
+            GossipMode::Plumtree => plumtree::TICK,
+            GossipMode::Digest => digest::TICK,
+            GossipMode::PushPull(config) => config.tick(),
+        };
+        spawn_ticker(tick, event_tx);
+        let mut batching = match &mode {
+            GossipMode::Batched(config) => Some(GossipBatcher::new(
+                config.clone(),
+                node_metadata.node_ids.len(),
+                Instant::now(),
+            )),
+            _ => None,
+        };
+        let topology = config.topology;
+        let built = topology.build(&node_metadata.node_ids);
+        if let (Some(batching), Some(built)) = (batching.as_mut(), built.as_ref()) {
+            batching.hops = topology::eccentricity(built, &node_metadata.node_id);
+        }
+        let neighbors = built
+            .and_then(|mut built| built.remove(&node_metadata.node_id))
+            .unwrap_or_default();
+        let mut node_ids = node_metadata.node_ids;
+        node_ids.sort_by(|a, b| topology::by_number(a, b));
+        let origin = node_ids
+            .iter()
+            .position(|node_id| *node_id == node_metadata.node_id)
+            .unwrap_or_default();
+        let seq_file = config
+            .state_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.next_seq", node_metadata.node_id)));
+        let next_seq = seq_file
+            .as_ref()
+            .and_then(|seq_file| fs::read_to_string(seq_file).ok())
+            .and_then(|next_seq| next_seq.trim().parse().ok())
+            .unwrap_or(0);
+        let (total, restored) = match config.order {
+            DeliveryOrder::Total { election_timeout } => {
+                let state_file = config
+                    .state_dir
+                    .as_ref()
+                    .map(|dir| dir.join(format!("{}.total", node_metadata.node_id)));
+                let (total, restored) = TotalOrder::new(
+                    &node_metadata.node_id,
+                    &node_ids,
+                    election_timeout,
+                    state_file,
+                    Instant::now(),
+                );
+                (Some(total), restored)
+            }
+            _ => (None, Vec::new()),
+        };
         BroadcastNode {
             current_msg_id: 0,
             node_id: node_metadata.node_id,
-            messages: HashSet::new(),
-            neighbors: Vec::new(),
+            node_ids,
+            origin,
+            next_seq,
+            seq_file,
+            messages: restored.iter().map(|(id, _)| *id).collect(),
+            payloads: restored.into_iter().collect(),
+            neighbors,
             confirmed_seen: HashMap::new(),
+            stability: config.stability.then(|| Stability::new(Instant::now())),
+            topology,
+            batching,
+            plumtree: (mode == GossipMode::Plumtree).then(Plumtree::new),
+            anti_entropy: (mode == GossipMode::Digest).then(AntiEntropy::new),
+            push_pull: match mode {
+                GossipMode::PushPull(config) => Some(PushPull::new(config, origin)),
+                _ => None,
+            },
+            causal: (config.order == DeliveryOrder::Causal).then(Causal::new),
+            total,
         }
     }
-    fn handle_event(&mut self, event: Event<BroadcastBody>, stdout_handle: &mut io::StdoutLock)
+}
+
+impl Node<BroadcastBody> for BroadcastNode {
+    fn node_init(
+        node_metadata: crate::init::NodeMetadata,
+        event_tx: Sender<Event<BroadcastBody>>,
+    ) -> Self {
+        BroadcastNode::with_config(node_metadata, BroadcastConfig::from_env(), event_tx)
+    }
+    fn handle_event(&mut self, event: Event<BroadcastBody>, output: &mut impl Write)
     where
         BroadcastBody: Reply<Self>,
         Self: Sized,
     {
+        //Ordering and stability run alongside whichever way messages are gossiped.
+        if matches!(event, Event::PropogateWrites) {
+            if self.total.is_some() {
+                self.total_tick(output);
+            }
+            if self.stability.is_some() {
+                self.stability_tick(output);
+            }
+        }
         match event {
+            Event::Message(message) if self.total.is_some() && message.body.orders() => {
+                self.total_message(message.src, message.body, output);
+            }
+            Event::Message(message) if self.plumtree.is_some() => {
+                self.plumtree_message(message, output);
+            }
+            Event::Message(message) if self.anti_entropy.is_some() => {
+                self.digest_message(message, output);
+            }
+            Event::Message(message) if self.push_pull.is_some() => {
+                self.push_pull_message(message, output);
+            }
+            Event::Message(message) if self.batching.is_some() => {
+                let now = Instant::now();
+                if let BroadcastBody::GossipOk { in_reply_to, .. } = &message.body {
+                    self.batcher().acked(*in_reply_to, now);
+                }
+                let known = self.messages.len();
+                message.message_reply(output, self);
+                self.current_msg_id += 1;
+                let learned = self.messages.len() - known;
+                self.batcher().added(learned, now);
+                if self.batcher().due(now) {
+                    self.flush(output, now);
+                }
+            }
             Event::Message(message) => {
-                message.message_reply(stdout_handle, self);
+                message.message_reply(output, self);
                 self.current_msg_id += 1;
             }
+            Event::ServiceReply(_) => {}
+            Event::PropogateWrites if self.plumtree.is_some() => self.plumtree_tick(output),
+            Event::PropogateWrites if self.anti_entropy.is_some() => self.digest_tick(output),
+            Event::PropogateWrites if self.push_pull.is_some() => self.push_pull_tick(output),
+            Event::PropogateWrites if self.batching.is_some() => {
+                let now = Instant::now();
+                let neighbors = self.neighbors.len();
+                self.batcher().tick(neighbors, now);
+                if self.batcher().due(now) || self.batcher().retry_due(now) {
+                    self.flush(output, now);
+                }
+            }
             Event::PropogateWrites => {
-                // In the event of a PropogateWrites Event, for each Neightbor, either Gossip
-                // the difference between the Current Messages and the Confirmed seen for the specified
-                //Node or send all messages.
-                for neighbor in self.neighbors.iter() {
-                    let message: Vec<usize> = match self.confirmed_seen.get(neighbor) {
-                        Some(known) => self.messages.difference(known).copied().collect(),
-                        None => self.messages.iter().copied().collect(),
-                    };
-                    if !message.is_empty() {
-                        let mut maelstrom_message = MaelstromMessage {
-                            src: self.node_id.clone(),
-                            dest: neighbor.clone(),
-                            body: BroadcastBody::Gossip {
-                                msg_id: self.current_msg_id,
-                                message,
-                            },
-                        };
-                        maelstrom_message.send(stdout_handle);
-                        self.current_msg_id += 1;
-                    }
+                self.gossip(output);
+            }
+        }
+    }
+}
+
+impl BroadcastNode {
+    fn next_id(&self) -> usize {
+        //! The id the next client broadcast will be given.
+        MessageId {
+            origin: self.origin,
+            seq: self.next_seq,
+        }
+        .pack()
+    }
+
+    fn accept(&mut self, payload: Value) -> usize {
+        //! Give a client's broadcast the next id from this node, and keep it.
+        let id = self.next_id();
+        self.next_seq += 1;
+        if let Some(seq_file) = self.seq_file.as_ref() {
+            //Written before the broadcast is acknowledged, and swapped in whole so a
+            //kill part way through leaves the last count.
+            let partial = seq_file.with_extension("next_seq.partial");
+            fs::write(&partial, self.next_seq.to_string())
+                .and_then(|_| fs::rename(&partial, seq_file))
+                .expect("Unable to persist next_seq");
+        }
+        self.messages.insert(id);
+        self.payloads.insert(id, payload);
+        if let Some(causal) = self.causal.as_mut() {
+            causal.stamp(id);
+        }
+        id
+    }
+
+    pub fn deliver(
+        &mut self,
+        ids: &IntervalSet,
+        payloads: Vec<Value>,
+        mut clocks: Vec<VectorClock>,
+    ) -> IntervalSet {
+        //! Keep the messages another node sent, paired up with their payloads, and
+        //! clocks if any, in id order, and return the ids that were new.
+        clocks.resize(payloads.len(), VectorClock::new());
+        let mut new = IntervalSet::new();
+        for ((id, payload), clock) in ids.iter().zip(payloads).zip(clocks) {
+            if self.messages.insert(id) {
+                self.payloads.insert(id, payload);
+                if let Some(causal) = self.causal.as_mut() {
+                    causal.receive(id, clock);
                 }
+                new.insert(id);
+            }
+        }
+        if let Some(causal) = self.causal.as_mut() {
+            causal.deliver_ready();
+        }
+        new
+    }
+
+    fn payloads_of(&self, ids: impl IntoIterator<Item = usize>) -> Vec<Value> {
+        ids.into_iter()
+            .map(|id| self.payloads.get(&id).cloned().unwrap_or_default())
+            .collect()
+    }
+
+    fn clocks_of(&self, ids: impl IntoIterator<Item = usize>) -> Vec<VectorClock> {
+        //! The clock of each message, or none without causal delivery.
+        match self.causal.as_ref() {
+            Some(causal) => ids.into_iter().map(|id| causal.clock_of(id)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn gossip_body(&self, ids: IntervalSet) -> BroadcastBody {
+        BroadcastBody::Gossip {
+            msg_id: self.current_msg_id,
+            payloads: self.payloads_of(ids.iter()),
+            clocks: self.clocks_of(ids.iter()),
+            message: ids,
+        }
+    }
+
+    fn clock(&self) -> BTreeMap<String, usize> {
+        //! With causal delivery, what has been delivered from each node. Otherwise,
+        //! how many broadcasts from each node this node has with none missing before.
+        self.node_ids
+            .iter()
+            .enumerate()
+            .map(|(origin, node_id)| {
+                let count = match self.causal.as_ref() {
+                    Some(causal) => causal.delivered.get(origin).copied().unwrap_or(0),
+                    None => self.contiguous(origin),
+                };
+                (node_id.clone(), count)
+            })
+            .collect()
+    }
+
+    fn contiguous(&self, origin: usize) -> usize {
+        //! How many broadcasts from an origin this node has, with none missing before.
+        let first = MessageId { origin, seq: 0 }.pack();
+        let last = MessageId {
+            origin,
+            seq: (1 << MessageId::SEQ_BITS) - 1,
+        }
+        .pack();
+        self.messages
+            .run_end(first)
+            .map_or(0, |end| end.min(last) - first + 1)
+    }
+
+    fn send_to(&mut self, dest: &str, body: BroadcastBody, output: &mut impl Write) {
+        let mut message = MaelstromMessage {
+            src: self.node_id.clone(),
+            dest: dest.to_owned(),
+            body,
+        };
+        message.send(output);
+        self.current_msg_id += 1;
+    }
+
+    fn confirm(&mut self, peer: &str, messages: &IntervalSet) {
+        //! Note that a neighbor has these messages, unless they are already stable.
+        let unstable = self.unstable(messages);
+        self.confirmed_seen
+            .entry(peer.to_owned())
+            .or_default()
+            .union_with(&unstable);
+    }
+
+    fn unconfirmed(&self, peer: &str, messages: &IntervalSet) -> IntervalSet {
+        //! The messages a neighbor is not known to have.
+        let unstable = self.unstable(messages);
+        match self.confirmed_seen.get(peer) {
+            Some(known) => unstable.difference(known),
+            None => unstable,
+        }
+    }
+
+    fn confirmed(&self, peer: &str, id: usize) -> bool {
+        self.is_stable(id)
+            || self
+                .confirmed_seen
+                .get(peer)
+                .is_some_and(|known| known.contains(id))
+    }
+
+    fn batcher(&mut self) -> &mut GossipBatcher {
+        self.batching
+            .as_mut()
+            .expect("Only used with batched gossip")
+    }
+
+    fn flush(&mut self, output: &mut impl Write, now: Instant) {
+        let sent = self.gossip(output);
+        self.batcher().flushed(sent, now);
+    }
+
+    fn gossip(&mut self, output: &mut impl Write) -> Vec<usize> {
+        //! For each Neighbor, Gossip the difference between the Current Messages and the
+        //! Confirmed seen for the specified Node, or all messages. Returns the msg_ids sent.
+        let mut sent = Vec::new();
+        for neighbor in self.neighbors.iter() {
+            let message = self.unconfirmed(neighbor, &self.messages);
+            if !message.is_empty() {
+                let mut maelstrom_message = MaelstromMessage {
+                    src: self.node_id.clone(),
+                    dest: neighbor.clone(),
+                    body: self.gossip_body(message),
+                };
+                maelstrom_message.send(output);
+                sent.push(self.current_msg_id);
+                self.current_msg_id += 1;
             }
         }
+        sent
     }
 }
@@ -0,0 +1,217 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+//Batched gossip. Instead of gossiping on a fixed timer, a node holds the messages
+//it learns of and flushes them to its neighbors on whichever comes first: the
+//batch filling up, the oldest message waiting out the flush interval, or the
+//oldest message reaching its share of the latency budget.
+//
+//The flush interval adapts to load. Every node flushes a gossip and gets an ack
+//back from each neighbor per interval, however many messages the batch holds, so
+//the interval that meets the msgs-per-op goal shrinks as broadcasts arrive faster.
+//A message waits half an interval on average at each hop, so the median latency
+//goal caps the interval, after the round trip measured to neighbors. When both
+//goals cannot be met, the latency goal wins.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchConfig {
+    //Flush as soon as this many new messages are waiting
+    pub size: usize,
+    //The flush interval never grows past this
+    pub max_delay: Duration,
+    //If set, a message is never held longer than its share of this end-to-end
+    //budget, split evenly across the hops to the furthest node
+    pub budget: Option<Duration>,
+    //Goal for messages between nodes per broadcast, cluster-wide
+    pub msgs_per_op: f64,
+    //Goal for the median time for a broadcast to reach every node
+    pub median_latency: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            size: 32,
+            max_delay: Duration::from_millis(500),
+            budget: None,
+            msgs_per_op: 30.0,
+            median_latency: Duration::from_millis(400),
+        }
+    }
+}
+
+impl BatchConfig {
+    pub fn parse(spec: &str) -> Option<Self> {
+        //! Parse `batched`, optionally followed by `:` and comma-separated settings,
+        //! e.g. `batched:size=16,max_delay=300,budget=800,msgs_per_op=20,median_latency=500`.
+        //! Times are in milliseconds.
+        let (name, settings) = spec.split_once(':').unwrap_or((spec, ""));
+        if name != "batched" {
+            return None;
+        }
+        let mut config = BatchConfig::default();
+        for setting in settings.split(',').filter(|setting| !setting.is_empty()) {
+            let (key, value) = setting.split_once('=')?;
+            let millis = || value.parse::<u64>().ok().map(Duration::from_millis);
+            match key {
+                "size" => config.size = value.parse().ok().filter(|size| *size > 0)?,
+                "max_delay" => config.max_delay = millis()?,
+                "budget" => config.budget = Some(millis()?),
+                "msgs_per_op" => {
+                    config.msgs_per_op = value.parse().ok().filter(|goal| *goal > 0.0)?
+                }
+                "median_latency" => config.median_latency = millis()?,
+                _ => return None,
+            }
+        }
+        Some(config)
+    }
+}
+
+//The flush interval is never shorter than this, the batching tick
+pub const MIN_INTERVAL: Duration = Duration::from_millis(10);
+
+//How often the arrival rate is measured and the interval adapted
+const ADAPT_EVERY: Duration = Duration::from_secs(1);
+
+//Weight of the newest sample in the rate, round trip and interval averages
+const SMOOTHING: f64 = 0.3;
+
+pub struct GossipBatcher {
+    pub config: BatchConfig,
+    //Nodes in the cluster, which all share the msgs-per-op goal
+    pub node_count: usize,
+    //Hops from this node to the furthest node
+    pub hops: usize,
+    //The current flush interval
+    pub interval: Duration,
+    //New messages per second, averaged
+    pub rate: f64,
+    //Round trip from a gossip to its ack, averaged
+    pub round_trip: Option<Duration>,
+    //Flushes that sent gossip
+    pub flushes: u64,
+    //When each new message waiting for the next flush arrived
+    pending: VecDeque<Instant>,
+    last_flush: Instant,
+    window_start: Instant,
+    window_new: usize,
+    //When each gossip not yet acked was sent, by msg_id
+    unacked: HashMap<usize, Instant>,
+}
+
+impl GossipBatcher {
+    pub fn new(config: BatchConfig, node_count: usize, now: Instant) -> Self {
+        GossipBatcher {
+            interval: config.max_delay.max(MIN_INTERVAL),
+            config,
+            node_count: node_count.max(1),
+            hops: 1,
+            rate: 0.0,
+            round_trip: None,
+            flushes: 0,
+            pending: VecDeque::new(),
+            last_flush: now,
+            window_start: now,
+            window_new: 0,
+            unacked: HashMap::new(),
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn added(&mut self, count: usize, now: Instant) {
+        //! Note messages this node has just learned of.
+        self.pending.extend(std::iter::repeat_n(now, count));
+        self.window_new += count;
+    }
+
+    pub fn hold_limit(&self) -> Duration {
+        //! The longest the oldest message may wait: the interval, capped by its
+        //! share of the latency budget.
+        match self.config.budget {
+            Some(budget) => self.interval.min(budget / self.hops.max(1) as u32),
+            None => self.interval,
+        }
+    }
+
+    pub fn due(&self, now: Instant) -> bool {
+        //! Whether the waiting messages should be flushed now.
+        match self.pending.front() {
+            Some(oldest) => {
+                self.pending.len() >= self.config.size
+                    || now.duration_since(*oldest) >= self.hold_limit()
+            }
+            None => false,
+        }
+    }
+
+    pub fn retry_due(&self, now: Instant) -> bool {
+        //! Whether gossip that may not have arrived should be sent again, though
+        //! nothing new is waiting.
+        self.pending.is_empty() && now.duration_since(self.last_flush) >= self.config.max_delay
+    }
+
+    pub fn flushed(&mut self, sent: Vec<usize>, now: Instant) {
+        //! Note a flush, and the msg_ids of the gossip it sent.
+        self.pending.clear();
+        self.last_flush = now;
+        if !sent.is_empty() {
+            self.flushes += 1;
+        }
+        //Acks lost to the network would otherwise be waited for forever.
+        let forget = self.config.max_delay * 10;
+        self.unacked
+            .retain(|_, sent| now.duration_since(*sent) < forget);
+        self.unacked
+            .extend(sent.into_iter().map(|msg_id| (msg_id, now)));
+    }
+
+    pub fn acked(&mut self, in_reply_to: usize, now: Instant) {
+        //! Note a GossipOk, measuring the round trip of the gossip it answers.
+        if let Some(sent) = self.unacked.remove(&in_reply_to) {
+            let sample = now.duration_since(sent);
+            self.round_trip = Some(match self.round_trip {
+                Some(round_trip) => round_trip.mul_f64(1.0 - SMOOTHING) + sample.mul_f64(SMOOTHING),
+                None => sample,
+            });
+        }
+    }
+
+    pub fn tick(&mut self, neighbors: usize, now: Instant) {
+        //! Measure the arrival rate once per ADAPT_EVERY and adapt the interval to it.
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed < ADAPT_EVERY {
+            return;
+        }
+        let rate = self.window_new as f64 / elapsed.as_secs_f64();
+        self.rate = self.rate * (1.0 - SMOOTHING) + rate * SMOOTHING;
+        self.window_start = now;
+        self.window_new = 0;
+        let target = self.target_interval(neighbors);
+        self.interval = self
+            .interval
+            .mul_f64(1.0 - SMOOTHING)
+            .saturating_add(target.mul_f64(SMOOTHING))
+            .clamp(MIN_INTERVAL, self.config.max_delay.max(MIN_INTERVAL));
+    }
+
+    pub fn target_interval(&self, neighbors: usize) -> Duration {
+        //! The interval the goals call for at the current rate and round trip.
+        let max_delay = self.config.max_delay.max(MIN_INTERVAL);
+        //A gossip and its ack per neighbor, per node, per interval.
+        let per_flush = (2 * neighbors.max(1) * self.node_count) as f64;
+        let for_msgs = if self.rate > 0.0 {
+            Duration::try_from_secs_f64(per_flush / (self.rate * self.config.msgs_per_op))
+                .unwrap_or(max_delay)
+        } else {
+            max_delay
+        };
+        let per_hop = self.config.median_latency / self.hops.max(1) as u32;
+        let link = self.round_trip.unwrap_or_default() / 2;
+        let for_latency = per_hop.saturating_sub(link) * 2;
+        for_msgs.min(for_latency).clamp(MIN_INTERVAL, max_delay)
+    }
+}
@@ -0,0 +1,106 @@
+use super::MessageId;
+use std::collections::{BTreeSet, HashMap};
+
+//Causal delivery. A broadcast is stamped with a vector clock: for each origin, how
+//many of its broadcasts the node it entered at had delivered, counting itself for
+//its own origin. A node holds a message it receives back until it has delivered
+//everything the clock counts, so delivery, and reads, which list messages in the
+//order they were delivered, never put a message before one it may depend on.
+//Held messages are still gossiped on, so whatever they wait for keeps spreading.
+
+//Counts by origin, the index of each node in the sorted node ids. Trailing zeros
+//are left off, so a clock is only as long as the highest origin it counts.
+pub type VectorClock = Vec<usize>;
+
+#[derive(Debug, Default)]
+pub struct Causal {
+    //Broadcasts delivered from each origin, which are always the first ones it sent
+    pub delivered: VectorClock,
+    //Ids in the order they were delivered
+    pub order: Vec<usize>,
+    //The clock each message was broadcast with, by id
+    clocks: HashMap<usize, VectorClock>,
+    //Received, but waiting for a message the clock counts
+    held: BTreeSet<usize>,
+}
+
+impl Causal {
+    pub fn new() -> Self {
+        Causal::default()
+    }
+
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+
+    pub fn clock_of(&self, id: usize) -> VectorClock {
+        self.clocks.get(&id).cloned().unwrap_or_default()
+    }
+
+    pub fn stamp(&mut self, id: usize) {
+        //! Stamp and deliver a broadcast from this node, which depends on everything
+        //! it has delivered so far.
+        let MessageId { origin, seq } = MessageId::unpack(id);
+        let mut clock = self.delivered.clone();
+        if clock.len() <= origin {
+            clock.resize(origin + 1, 0);
+        }
+        clock[origin] = seq + 1;
+        self.clocks.insert(id, clock);
+        self.deliver(id);
+    }
+
+    pub fn receive(&mut self, id: usize, clock: VectorClock) {
+        //! Hold a message from another node until it can be delivered.
+        self.clocks.insert(id, clock);
+        self.held.insert(id);
+    }
+
+    pub fn deliver_ready(&mut self) -> usize {
+        //! Deliver every held message whose predecessors have all been delivered,
+        //! returning how many were. Held ids are in origin then sequence order, so a
+        //! pass delivers a run from one origin in order, and passes repeat while one
+        //! origin's deliveries unblock another's.
+        let mut delivered = 0;
+        loop {
+            let before = delivered;
+            for id in self.held.clone() {
+                if self.ready(id) {
+                    self.held.remove(&id);
+                    self.deliver(id);
+                    delivered += 1;
+                }
+            }
+            if delivered == before {
+                return delivered;
+            }
+        }
+    }
+
+    fn count(&self, origin: usize) -> usize {
+        self.delivered.get(origin).copied().unwrap_or(0)
+    }
+
+    fn ready(&self, id: usize) -> bool {
+        //! A message is next from its origin, and everything else its clock counts has
+        //! been delivered. A message with no clock only waits for its origin's order.
+        let MessageId { origin, seq } = MessageId::unpack(id);
+        let clock = self.clocks.get(&id);
+        self.count(origin) == seq
+            && clock.is_none_or(|clock| {
+                clock
+                    .iter()
+                    .enumerate()
+                    .all(|(other, count)| other == origin || self.count(other) >= *count)
+            })
+    }
+
+    fn deliver(&mut self, id: usize) {
+        let origin = MessageId::unpack(id).origin;
+        if self.delivered.len() <= origin {
+            self.delivered.resize(origin + 1, 0);
+        }
+        self.delivered[origin] += 1;
+        self.order.push(id);
+    }
+}
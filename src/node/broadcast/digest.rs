@@ -0,0 +1,293 @@
+use super::causal::VectorClock;
+use super::interval_set::IntervalSet;
+use super::{BroadcastBody, BroadcastNode};
+use crate::node::MaelstromMessage;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+//Digest-based anti-entropy. New messages are pushed to each neighbor once, and
+//never resent. Anything a push missed, say to a partition, is found by comparing
+//digests: every ROUND_EVERY a node sends each neighbor the count and hash of its
+//message ids over the whole range of ids. Where the neighbor's differ, it either
+//splits the range at quantiles of its own ids and sends back a digest of each
+//part, or, if it has few messages there, pulls the range: it sends the ids it has
+//in it, and gets back only the messages it is missing. Ranges that match cost
+//nothing more, so a round between nodes that agree is one message, and a
+//round after a long partition ships about what is missing. Pulled messages are
+//then pushed on like new ones, so a repair spreads past the first node that makes it.
+
+//How often new messages are pushed
+pub const TICK: Duration = Duration::from_millis(250);
+
+//How often digests are exchanged
+const ROUND_EVERY: Duration = Duration::from_secs(1);
+
+//A node with at most this many messages in a differing range pulls it
+const LEAF: usize = 8;
+
+//How many parts a differing range is split into
+const FANOUT: usize = 16;
+
+//A summary of the messages in the inclusive range [start, end]: how many there
+//are, and the XOR of a hash of each, so two sets can be compared in any order.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RangeDigest(pub usize, pub usize, pub usize, pub u64);
+
+impl RangeDigest {
+    pub fn of(set: &IntervalSet, start: usize, end: usize) -> Self {
+        //! Digest the messages of a set that fall in [start, end].
+        let count = runs_within(set, start, end)
+            .map(|(from, to)| to - from + 1)
+            .sum();
+        let hash = within(set, start, end).fold(0, |hash, message| hash ^ mix(message as u64));
+        RangeDigest(start, end, count, hash)
+    }
+
+    pub fn matches(&self, other: &RangeDigest) -> bool {
+        self.2 == other.2 && self.3 == other.3
+    }
+}
+
+fn mix(value: u64) -> u64 {
+    //! The splitmix64 finalizer, so nearby values hash far apart.
+    let mut z = value.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+fn runs_within(
+    set: &IntervalSet,
+    start: usize,
+    end: usize,
+) -> impl Iterator<Item = (usize, usize)> + '_ {
+    //! The set's runs that overlap [start, end], cut down to fit it.
+    let first = set.runs().partition_point(|(_, to)| *to < start);
+    set.runs()[first..]
+        .iter()
+        .take_while(move |(from, _)| *from <= end)
+        .map(move |(from, to)| ((*from).max(start), (*to).min(end)))
+}
+
+pub fn within(set: &IntervalSet, start: usize, end: usize) -> impl Iterator<Item = usize> + '_ {
+    runs_within(set, start, end).flat_map(|(from, to)| from..=to)
+}
+
+pub fn split(set: &IntervalSet, start: usize, end: usize, parts: usize) -> Vec<(usize, usize)> {
+    //! Split [start, end] into up to `parts` contiguous ranges holding about as many
+    //! of the set's messages each. The other side may hold different messages, but
+    //! the ranges still cover everything between start and end.
+    let count: usize = runs_within(set, start, end)
+        .map(|(from, to)| to - from + 1)
+        .sum();
+    if count == 0 {
+        return vec![(start, end)];
+    }
+    //Each part starts at the message that many parts of the way through the range.
+    let mut quantiles = (1..parts).map(|part| part * count / parts).peekable();
+    let mut bounds = Vec::new();
+    let mut before = 0;
+    for (from, to) in runs_within(set, start, end) {
+        let len = to - from + 1;
+        while let Some(quantile) = quantiles.next_if(|quantile| *quantile < before + len) {
+            bounds.push(from + (quantile - before));
+        }
+        before += len;
+    }
+    bounds.retain(|bound| *bound > start);
+    bounds.dedup();
+    let mut ranges = Vec::new();
+    let mut from = start;
+    for bound in bounds {
+        ranges.push((from, bound - 1));
+        from = bound;
+    }
+    ranges.push((from, end));
+    ranges
+}
+
+pub struct AntiEntropy {
+    //Messages learned since the last tick, to push on
+    recent: IntervalSet,
+    last_round: Option<Instant>,
+    pub rounds: u64,
+    //Messages learned by pulling, rather than from a push
+    pub pulled: u64,
+}
+
+impl Default for AntiEntropy {
+    fn default() -> Self {
+        AntiEntropy::new()
+    }
+}
+
+impl AntiEntropy {
+    pub fn new() -> Self {
+        AntiEntropy {
+            recent: IntervalSet::new(),
+            last_round: None,
+            rounds: 0,
+            pulled: 0,
+        }
+    }
+}
+
+impl BroadcastNode {
+    fn anti_entropy(&mut self) -> &mut AntiEntropy {
+        self.anti_entropy
+            .as_mut()
+            .expect("Only used with digest anti-entropy")
+    }
+
+    fn learn(
+        &mut self,
+        ids: &IntervalSet,
+        payloads: Vec<Value>,
+        clocks: Vec<VectorClock>,
+    ) -> usize {
+        //! Add messages, queueing those that were new to be pushed, and count them.
+        let new = self.deliver(ids, payloads, clocks);
+        self.anti_entropy().recent.union_with(&new);
+        new.len()
+    }
+
+    pub(super) fn digest_message(
+        &mut self,
+        message: MaelstromMessage<BroadcastBody>,
+        output: &mut impl Write,
+    ) {
+        let src = message.src.clone();
+        match message.body {
+            BroadcastBody::Broadcast { .. } => {
+                let id = self.next_id();
+                message.message_reply(output, self);
+                self.current_msg_id += 1;
+                self.anti_entropy().recent.insert(id);
+            }
+            BroadcastBody::Gossip {
+                message: pushed,
+                payloads,
+                clocks,
+                ..
+            } => {
+                self.confirm(&src, &pushed);
+                self.learn(&pushed, payloads, clocks);
+            }
+            BroadcastBody::Digest { ranges, .. } => self.compare(&src, ranges, output),
+            BroadcastBody::Pull {
+                msg_id,
+                ranges,
+                have,
+            } => {
+                //Only ids come with a pull, so anything the puller has that this node
+                //lacks is pulled in turn, when this node compares the puller's digest.
+                self.confirm(&src, &have);
+                let mut missing = IntervalSet::new();
+                for (start, end) in ranges {
+                    let mut mine = IntervalSet::new();
+                    for (from, to) in runs_within(&self.messages, start, end) {
+                        mine.insert_run(from, to);
+                    }
+                    missing.union_with(&mine.difference(&have));
+                }
+                let body = BroadcastBody::PullOk {
+                    in_reply_to: msg_id,
+                    msg_id: self.current_msg_id,
+                    payloads: self.payloads_of(missing.iter()),
+                    clocks: self.clocks_of(missing.iter()),
+                    messages: missing,
+                };
+                self.send_to(&src, body, output);
+            }
+            BroadcastBody::PullOk {
+                messages: pulled,
+                payloads,
+                clocks,
+                ..
+            } => {
+                self.confirm(&src, &pulled);
+                let learned = self.learn(&pulled, payloads, clocks);
+                self.anti_entropy().pulled += learned as u64;
+            }
+            _ => {
+                message.message_reply(output, self);
+                self.current_msg_id += 1;
+            }
+        }
+    }
+
+    fn compare(&mut self, src: &str, ranges: Vec<RangeDigest>, output: &mut impl Write) {
+        //! Answer a neighbor's digest: split the ranges that differ where this node
+        //! has many messages, and pull those where it has few.
+        let mut finer = Vec::new();
+        let mut pulls = Vec::new();
+        let mut have = IntervalSet::new();
+        for theirs in ranges {
+            let RangeDigest(start, end, _, _) = theirs;
+            let mine = RangeDigest::of(&self.messages, start, end);
+            if mine.matches(&theirs) {
+                continue;
+            }
+            if mine.2 <= LEAF {
+                pulls.push((start, end));
+                for (from, to) in runs_within(&self.messages, start, end) {
+                    have.insert_run(from, to);
+                }
+            } else {
+                finer.extend(
+                    split(&self.messages, start, end, FANOUT)
+                        .into_iter()
+                        .map(|(start, end)| RangeDigest::of(&self.messages, start, end)),
+                );
+            }
+        }
+        if !finer.is_empty() {
+            let body = BroadcastBody::Digest {
+                msg_id: self.current_msg_id,
+                ranges: finer,
+            };
+            self.send_to(src, body, output);
+        }
+        if !pulls.is_empty() {
+            let body = BroadcastBody::Pull {
+                msg_id: self.current_msg_id,
+                ranges: pulls,
+                have,
+            };
+            self.send_to(src, body, output);
+        }
+    }
+
+    pub(super) fn digest_tick(&mut self, output: &mut impl Write) {
+        //Push what was learned since the last tick to neighbors not known to have it.
+        let recent = std::mem::take(&mut self.anti_entropy().recent);
+        for neighbor in self.neighbors.clone() {
+            let pushed = self.unconfirmed(&neighbor, &recent);
+            if !pushed.is_empty() {
+                let body = self.gossip_body(pushed);
+                self.send_to(&neighbor, body, output);
+            }
+        }
+
+        let now = Instant::now();
+        let anti_entropy = self.anti_entropy();
+        if anti_entropy
+            .last_round
+            .is_some_and(|last| now.duration_since(last) < ROUND_EVERY)
+        {
+            return;
+        }
+        anti_entropy.last_round = Some(now);
+        anti_entropy.rounds += 1;
+        let whole = RangeDigest::of(&self.messages, 0, usize::MAX);
+        for neighbor in self.neighbors.clone() {
+            let body = BroadcastBody::Digest {
+                msg_id: self.current_msg_id,
+                ranges: vec![whole],
+            };
+            self.send_to(&neighbor, body, output);
+        }
+    }
+}
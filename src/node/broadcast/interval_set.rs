@@ -0,0 +1,238 @@
+use serde::de::{Deserializer, Error};
+use serde::ser::{SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+//A set of integers stored as sorted, disjoint runs. Broadcast values are mostly
+//handed out in order, so a node's messages, and what each neighbor has confirmed,
+//collapse to a few runs, and unions and differences walk runs instead of values.
+//
+//On the wire a run of one or two values is written as plain numbers, and a longer
+//run as a [start, end] pair, so `[1, 2, [4, 9]]` holds 1, 2 and 4 through 9. Small
+//sets look the same as a flat list, and a flat list is always read correctly.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct IntervalSet {
+    //Inclusive runs, sorted, with a gap between each
+    runs: Vec<(usize, usize)>,
+    len: usize,
+}
+
+impl IntervalSet {
+    pub fn new() -> Self {
+        IntervalSet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn runs(&self) -> &[(usize, usize)] {
+        &self.runs
+    }
+
+    pub fn contains(&self, value: usize) -> bool {
+        let index = self.runs.partition_point(|(_, end)| *end < value);
+        self.runs
+            .get(index)
+            .is_some_and(|(start, _)| *start <= value)
+    }
+
+    pub fn run_end(&self, value: usize) -> Option<usize> {
+        //! The last value of the run holding this value, if the set holds it.
+        let index = self.runs.partition_point(|(_, end)| *end < value);
+        self.runs
+            .get(index)
+            .filter(|(start, _)| *start <= value)
+            .map(|(_, end)| *end)
+    }
+
+    pub fn insert(&mut self, value: usize) -> bool {
+        //! Add a value, returning whether it was new.
+        let index = self.runs.partition_point(|(_, end)| *end < value);
+        let joins_next = match self.runs.get(index) {
+            Some((start, _)) if *start <= value => return false,
+            Some((start, _)) => *start - 1 == value,
+            None => false,
+        };
+        let joins_previous = index > 0 && self.runs[index - 1].1 + 1 == value;
+        match (joins_previous, joins_next) {
+            (true, true) => {
+                self.runs[index - 1].1 = self.runs[index].1;
+                self.runs.remove(index);
+            }
+            (true, false) => self.runs[index - 1].1 = value,
+            (false, true) => self.runs[index].0 = value,
+            (false, false) => self.runs.insert(index, (value, value)),
+        }
+        self.len += 1;
+        true
+    }
+
+    pub fn insert_run(&mut self, start: usize, end: usize) {
+        //! Add every value from start to end inclusive.
+        match self.runs.last() {
+            //Runs read in order, as they are written, are appended.
+            Some((_, last)) if start > last.saturating_add(1) => self.push_run(start, end),
+            None => self.push_run(start, end),
+            Some(_) => {
+                let run = IntervalSet {
+                    runs: vec![(start, end)],
+                    len: end - start + 1,
+                };
+                self.union_with(&run);
+            }
+        }
+    }
+
+    fn push_run(&mut self, start: usize, end: usize) {
+        self.runs.push((start, end));
+        self.len += end - start + 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        //! The values in ascending order.
+        self.runs.iter().flat_map(|(start, end)| *start..=*end)
+    }
+
+    pub fn union_with(&mut self, other: &IntervalSet) {
+        if other.is_empty() {
+            return;
+        }
+        let mut merged = IntervalSet::new();
+        let mut mine = self.runs.iter().peekable();
+        let mut theirs = other.runs.iter().peekable();
+        loop {
+            let next = match (mine.peek(), theirs.peek()) {
+                (Some(a), Some(b)) if a.0 <= b.0 => mine.next(),
+                (Some(_), Some(_)) => theirs.next(),
+                (Some(_), None) => mine.next(),
+                (None, Some(_)) => theirs.next(),
+                (None, None) => break,
+            };
+            let (start, end) = *next.unwrap();
+            match merged.runs.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => {
+                    if end > last.1 {
+                        merged.len += end - last.1;
+                        last.1 = end;
+                    }
+                }
+                _ => merged.push_run(start, end),
+            }
+        }
+        *self = merged;
+    }
+
+    pub fn difference(&self, other: &IntervalSet) -> IntervalSet {
+        //! The values in this set and not in the other.
+        let mut difference = IntervalSet::new();
+        let mut theirs = other.runs.iter().peekable();
+        for (start, end) in self.runs.iter().copied() {
+            //Skip runs that end before this one starts.
+            while theirs
+                .next_if(|(_, their_end)| *their_end < start)
+                .is_some()
+            {}
+            //The start of what is left of this run, if any is
+            let mut from = Some(start);
+            while let (Some(uncovered), Some((their_start, their_end))) =
+                (from, theirs.peek().copied())
+            {
+                if *their_start > end {
+                    break;
+                }
+                if *their_start > uncovered {
+                    difference.push_run(uncovered, their_start - 1);
+                }
+                //A run reaching past this one may cover the next too, so is kept.
+                if *their_end >= end {
+                    from = None;
+                    break;
+                }
+                from = Some(their_end + 1);
+                theirs.next();
+            }
+            if let Some(uncovered) = from {
+                difference.push_run(uncovered, end);
+            }
+        }
+        difference
+    }
+}
+
+impl fmt::Debug for IntervalSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.runs.iter().map(|(start, end)| start..=end))
+            .finish()
+    }
+}
+
+impl FromIterator<usize> for IntervalSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(values: I) -> Self {
+        let mut set = IntervalSet::new();
+        set.extend(values);
+        set
+    }
+}
+
+impl Extend<usize> for IntervalSet {
+    fn extend<I: IntoIterator<Item = usize>>(&mut self, values: I) {
+        for value in values {
+            self.insert(value);
+        }
+    }
+}
+
+impl<'a> Extend<&'a usize> for IntervalSet {
+    fn extend<I: IntoIterator<Item = &'a usize>>(&mut self, values: I) {
+        self.extend(values.into_iter().copied());
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Element {
+    Value(usize),
+    Run(usize, usize),
+}
+
+impl Serialize for IntervalSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for (start, end) in self.runs.iter().copied() {
+            match end - start {
+                0 => seq.serialize_element(&start)?,
+                1 => {
+                    seq.serialize_element(&start)?;
+                    seq.serialize_element(&end)?;
+                }
+                _ => seq.serialize_element(&Element::Run(start, end))?,
+            }
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for IntervalSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut set = IntervalSet::new();
+        for element in Vec::<Element>::deserialize(deserializer)? {
+            match element {
+                Element::Value(value) => set.insert_run(value, value),
+                Element::Run(start, end) if start <= end => set.insert_run(start, end),
+                Element::Run(start, end) => {
+                    return Err(D::Error::custom(format!(
+                        "Run {} ends before it starts at {}",
+                        end, start
+                    )))
+                }
+            }
+        }
+        Ok(set)
+    }
+}
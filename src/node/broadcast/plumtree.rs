@@ -0,0 +1,284 @@
+use super::causal::VectorClock;
+use super::interval_set::IntervalSet;
+use super::{BroadcastBody, BroadcastNode};
+use crate::node::MaelstromMessage;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+//Plumtree (Leitão, Pereira and Rodrigues, "Epidemic Broadcast Trees"). Each node
+//splits its neighbors into eager peers, which it pushes new messages to at once,
+//and lazy peers, which it only tells the ids of new messages with an IHave. A node
+//pushed a message it already has prunes the sender to lazy, so the eager links
+//settle into a spanning tree. A node told of a message it does not get in time
+//grafts the teller to eager and asks it for the message, which repairs the tree
+//after partitions.
+//
+//Lazy pushes are batched, one IHave per lazy peer per tick, and sent once. The
+//paper assumes reliable links, and Maelstrom's drop messages, so every
+//ANNOUNCE_EVERY a node also tells each neighbor, eager or lazy, whatever it has
+//not heard the neighbor has, and asks for a GossipOk naming what the neighbor
+//already had. That catches lost pushes, IHaves and grafts alike.
+
+//How often grafts and lazy pushes are sent
+pub const TICK: Duration = Duration::from_millis(250);
+
+//How long to wait for a push after hearing of a message before grafting. Pushes
+//travel the tree, which may be a longer way round than the IHave came.
+const GRAFT_TIMEOUT: Duration = Duration::from_millis(600);
+
+//How often neighbors are told what they have not acknowledged
+const ANNOUNCE_EVERY: Duration = Duration::from_secs(1);
+
+pub struct Plumtree {
+    pub eager: BTreeSet<String>,
+    pub lazy: BTreeSet<String>,
+    //Messages heard of but not received, by id
+    missing: BTreeMap<usize, Missing>,
+    //Messages learned since the last lazy push
+    lazy_queue: Vec<usize>,
+    last_announce: Instant,
+    pub grafts: u64,
+    pub prunes: u64,
+}
+
+struct Missing {
+    //Neighbors that said they have it, in the order they said so
+    announcers: Vec<String>,
+    //When the message was heard of, or last grafted for
+    since: Instant,
+    //Grafts sent for it so far, which picks the next announcer to ask
+    grafted: usize,
+}
+
+impl Default for Plumtree {
+    fn default() -> Self {
+        Plumtree::new()
+    }
+}
+
+impl Plumtree {
+    pub fn new() -> Self {
+        Plumtree {
+            eager: BTreeSet::new(),
+            lazy: BTreeSet::new(),
+            missing: BTreeMap::new(),
+            lazy_queue: Vec::new(),
+            last_announce: Instant::now(),
+            grafts: 0,
+            prunes: 0,
+        }
+    }
+
+    pub fn missing(&self) -> Vec<usize> {
+        self.missing.keys().copied().collect()
+    }
+
+    fn sync_peers(&mut self, neighbors: &[String]) {
+        //! Start new neighbors as eager, and forget nodes that are no longer neighbors.
+        let neighbors: BTreeSet<&String> = neighbors.iter().collect();
+        self.eager.retain(|peer| neighbors.contains(peer));
+        self.lazy.retain(|peer| neighbors.contains(peer));
+        for neighbor in neighbors {
+            if !self.lazy.contains(neighbor) {
+                self.eager.insert(neighbor.clone());
+            }
+        }
+    }
+
+    fn make_eager(&mut self, peer: &str) {
+        self.lazy.remove(peer);
+        self.eager.insert(peer.to_owned());
+    }
+
+    fn make_lazy(&mut self, peer: &str) {
+        self.eager.remove(peer);
+        self.lazy.insert(peer.to_owned());
+    }
+}
+
+impl BroadcastNode {
+    fn tree(&mut self) -> &mut Plumtree {
+        self.plumtree.as_mut().expect("Only used in Plumtree mode")
+    }
+
+    pub(super) fn plumtree_message(
+        &mut self,
+        message: MaelstromMessage<BroadcastBody>,
+        output: &mut impl Write,
+    ) {
+        let neighbors = self.neighbors.clone();
+        self.tree().sync_peers(&neighbors);
+        let src = message.src.clone();
+        match message.body {
+            BroadcastBody::Broadcast { .. } => {
+                //A client's broadcast is always new, as it is given a new id.
+                let id = self.next_id();
+                message.message_reply(output, self);
+                self.current_msg_id += 1;
+                self.tree().lazy_queue.push(id);
+                self.push(&IntervalSet::from_iter([id]), None, output);
+            }
+            BroadcastBody::Gossip {
+                message: pushed,
+                payloads,
+                clocks,
+                ..
+            } => self.pushed(&src, pushed, payloads, clocks, output),
+            BroadcastBody::IHave {
+                msg_id,
+                messages: announced,
+                ack,
+            } => {
+                self.confirm(&src, &announced);
+                let now = Instant::now();
+                let mut have = IntervalSet::new();
+                for id in announced.iter() {
+                    if self.messages.contains(id) {
+                        have.insert(id);
+                        continue;
+                    }
+                    let missing = self.tree().missing.entry(id).or_insert(Missing {
+                        announcers: Vec::new(),
+                        since: now,
+                        grafted: 0,
+                    });
+                    if !missing.announcers.contains(&src) {
+                        missing.announcers.push(src.clone());
+                    }
+                }
+                if ack && !have.is_empty() {
+                    let body = BroadcastBody::GossipOk {
+                        in_reply_to: msg_id,
+                        msg_id: self.current_msg_id,
+                        ack_message: have,
+                    };
+                    self.send_to(&src, body, output);
+                }
+            }
+            BroadcastBody::Graft {
+                messages: wanted, ..
+            } => {
+                self.tree().make_eager(&src);
+                let have: IntervalSet = wanted
+                    .iter()
+                    .filter(|id| self.messages.contains(*id))
+                    .collect();
+                if !have.is_empty() {
+                    let body = self.gossip_body(have);
+                    self.send_to(&src, body, output);
+                }
+            }
+            BroadcastBody::Prune { .. } => self.tree().make_lazy(&src),
+            _ => {
+                message.message_reply(output, self);
+                self.current_msg_id += 1;
+            }
+        }
+    }
+
+    fn pushed(
+        &mut self,
+        src: &str,
+        pushed: IntervalSet,
+        payloads: Vec<Value>,
+        clocks: Vec<VectorClock>,
+        output: &mut impl Write,
+    ) {
+        //! Deliver a push, forwarding whatever is new to the other eager peers. A push
+        //! of nothing new means the sender's link is redundant, so it is pruned.
+        self.confirm(src, &pushed);
+        let new = self.deliver(&pushed, payloads, clocks);
+        if new.is_empty() {
+            self.tree().make_lazy(src);
+            self.tree().prunes += 1;
+            let body = BroadcastBody::Prune {
+                msg_id: self.current_msg_id,
+            };
+            return self.send_to(src, body, output);
+        }
+        let tree = self.tree();
+        for id in new.iter() {
+            tree.missing.remove(&id);
+        }
+        tree.lazy_queue.extend(new.iter());
+        tree.make_eager(src);
+        self.push(&new, Some(src), output);
+    }
+
+    fn push(&mut self, messages: &IntervalSet, except: Option<&str>, output: &mut impl Write) {
+        let eager: Vec<String> = self.tree().eager.iter().cloned().collect();
+        for peer in eager {
+            if Some(peer.as_str()) == except {
+                continue;
+            }
+            let body = self.gossip_body(messages.clone());
+            self.send_to(&peer, body, output);
+        }
+    }
+
+    pub(super) fn plumtree_tick(&mut self, output: &mut impl Write) {
+        let neighbors = self.neighbors.clone();
+        let now = Instant::now();
+        let tree = self.tree();
+        tree.sync_peers(&neighbors);
+
+        //Graft for messages no push has brought, asking each announcer in turn.
+        let mut grafts: BTreeMap<String, IntervalSet> = BTreeMap::new();
+        for (id, missing) in tree.missing.iter_mut() {
+            if now.duration_since(missing.since) < GRAFT_TIMEOUT {
+                continue;
+            }
+            let announcer = &missing.announcers[missing.grafted % missing.announcers.len()];
+            grafts.entry(announcer.clone()).or_default().insert(*id);
+            missing.since = now;
+            missing.grafted += 1;
+        }
+        for (peer, messages) in grafts {
+            let tree = self.tree();
+            tree.make_eager(&peer);
+            tree.grafts += 1;
+            let body = BroadcastBody::Graft {
+                msg_id: self.current_msg_id,
+                messages,
+            };
+            self.send_to(&peer, body, output);
+        }
+
+        //Lazy push what was learned since the last tick.
+        let tree = self.tree();
+        let learned = std::mem::take(&mut tree.lazy_queue);
+        let lazy: Vec<String> = tree.lazy.iter().cloned().collect();
+        for peer in lazy {
+            let announced: IntervalSet = learned
+                .iter()
+                .filter(|id| !self.confirmed(&peer, **id))
+                .copied()
+                .collect();
+            self.announce(&peer, announced, false, output);
+        }
+
+        //Every so often, tell every neighbor what it has not acknowledged.
+        let tree = self.tree();
+        if now.duration_since(tree.last_announce) < ANNOUNCE_EVERY {
+            return;
+        }
+        tree.last_announce = now;
+        for peer in neighbors {
+            let announced = self.unconfirmed(&peer, &self.messages);
+            self.announce(&peer, announced, true, output);
+        }
+    }
+
+    fn announce(&mut self, peer: &str, messages: IntervalSet, ack: bool, output: &mut impl Write) {
+        if !messages.is_empty() {
+            let body = BroadcastBody::IHave {
+                msg_id: self.current_msg_id,
+                messages,
+                ack,
+            };
+            self.send_to(peer, body, output);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use super::causal::VectorClock;
+use super::interval_set::IntervalSet;
+use super::{BroadcastBody, BroadcastNode};
+use crate::node::MaelstromMessage;
+use crate::rng::Rng;
+use serde_json::Value;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+//Push-pull gossip with random peers. Every round a node picks `fanout` peers at
+//random from all the cluster's nodes, ignoring the topology, and sends each the ids
+//it has, which run-encode to a few runs. The peer answers with the messages the
+//node lacks and the ids it has itself, and the node pushes back whatever the peer
+//lacks as ordinary gossip. Only missing messages move, and as peers are drawn from
+//every node, a topology that is disconnected, or names nodes that do not exist,
+//does not keep a message from reaching every node.
+
+//The longest tick, so rounds start close to when they are due
+const MAX_TICK: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PushPullConfig {
+    //Peers exchanged with each round
+    pub fanout: usize,
+    pub interval: Duration,
+    //Seeds peer selection, so runs are repeatable. Each node adds its origin, so
+    //nodes still pick different peers.
+    pub seed: Option<u64>,
+}
+
+impl Default for PushPullConfig {
+    fn default() -> Self {
+        PushPullConfig {
+            fanout: 3,
+            interval: Duration::from_millis(200),
+            seed: None,
+        }
+    }
+}
+
+impl PushPullConfig {
+    pub fn parse(spec: &str) -> Option<Self> {
+        //! Parse `push-pull`, optionally followed by `:` and comma-separated settings,
+        //! e.g. `push-pull:fanout=2,interval=100,seed=7`. The interval is in milliseconds.
+        let (name, settings) = spec.split_once(':').unwrap_or((spec, ""));
+        if name != "push-pull" {
+            return None;
+        }
+        let mut config = PushPullConfig::default();
+        for setting in settings.split(',').filter(|setting| !setting.is_empty()) {
+            let (key, value) = setting.split_once('=')?;
+            match key {
+                "fanout" => config.fanout = value.parse().ok().filter(|fanout| *fanout > 0)?,
+                "interval" => {
+                    config.interval = value
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|millis| *millis > 0)
+                        .map(Duration::from_millis)?
+                }
+                "seed" => config.seed = Some(value.parse().ok()?),
+                _ => return None,
+            }
+        }
+        Some(config)
+    }
+
+    pub fn tick(&self) -> Duration {
+        self.interval.min(MAX_TICK)
+    }
+}
+
+#[derive(Debug)]
+pub struct PushPull {
+    pub config: PushPullConfig,
+    rng: Rng,
+    last_round: Option<Instant>,
+    pub rounds: u64,
+    //Exchanges a peer answered
+    pub exchanges: u64,
+    //Messages learned from answers, and pushed back to peers
+    pub pulled: u64,
+    pub pushed: u64,
+}
+
+impl PushPull {
+    pub fn new(config: PushPullConfig, origin: usize) -> Self {
+        let rng = match config.seed {
+            Some(seed) => Rng::seeded(seed.wrapping_add(origin as u64)),
+            None => Rng::from_time(),
+        };
+        PushPull {
+            config,
+            rng,
+            last_round: None,
+            rounds: 0,
+            exchanges: 0,
+            pulled: 0,
+            pushed: 0,
+        }
+    }
+
+    pub fn peers(&mut self, node_ids: &[String], node_id: &str) -> Vec<String> {
+        //! Up to `fanout` other nodes, picked at random.
+        let mut others: Vec<String> = node_ids
+            .iter()
+            .filter(|other| *other != node_id)
+            .cloned()
+            .collect();
+        self.rng.shuffle(&mut others);
+        others.truncate(self.config.fanout);
+        others
+    }
+}
+
+impl BroadcastNode {
+    fn push_pull(&mut self) -> &mut PushPull {
+        self.push_pull
+            .as_mut()
+            .expect("Only used with push-pull gossip")
+    }
+
+    pub(super) fn push_pull_message(
+        &mut self,
+        message: MaelstromMessage<BroadcastBody>,
+        output: &mut impl Write,
+    ) {
+        let src = message.src.clone();
+        match message.body {
+            BroadcastBody::Exchange { msg_id, have } => {
+                //Reply with what the sender lacks, and everything this node has, so
+                //the sender can push back what this node lacks.
+                self.confirm(&src, &have);
+                let missing = self.messages.difference(&have);
+                let body = BroadcastBody::ExchangeOk {
+                    in_reply_to: msg_id,
+                    msg_id: self.current_msg_id,
+                    payloads: self.payloads_of(missing.iter()),
+                    clocks: self.clocks_of(missing.iter()),
+                    message: missing,
+                    have: self.messages.clone(),
+                };
+                self.send_to(&src, body, output);
+            }
+            BroadcastBody::ExchangeOk {
+                message: pulled,
+                payloads,
+                clocks,
+                have,
+                ..
+            } => self.exchanged(&src, pulled, payloads, clocks, have, output),
+            _ => {
+                message.message_reply(output, self);
+                self.current_msg_id += 1;
+            }
+        }
+    }
+
+    fn exchanged(
+        &mut self,
+        src: &str,
+        pulled: IntervalSet,
+        payloads: Vec<Value>,
+        clocks: Vec<VectorClock>,
+        have: IntervalSet,
+        output: &mut impl Write,
+    ) {
+        //! Keep what the peer sent, and push back what it lacks.
+        let learned = self.deliver(&pulled, payloads, clocks);
+        self.confirm(src, &have);
+        let pushed = self.unconfirmed(src, &self.messages);
+        let push_pull = self.push_pull();
+        push_pull.exchanges += 1;
+        push_pull.pulled += learned.len() as u64;
+        push_pull.pushed += pushed.len() as u64;
+        if !pushed.is_empty() {
+            let body = self.gossip_body(pushed);
+            self.send_to(src, body, output);
+        }
+    }
+
+    pub(super) fn push_pull_tick(&mut self, output: &mut impl Write) {
+        let now = Instant::now();
+        let push_pull = self.push_pull();
+        if push_pull
+            .last_round
+            .is_some_and(|last| now.duration_since(last) < push_pull.config.interval)
+        {
+            return;
+        }
+        push_pull.last_round = Some(now);
+        push_pull.rounds += 1;
+        let peers = self.random_peers();
+        for peer in peers {
+            let body = BroadcastBody::Exchange {
+                msg_id: self.current_msg_id,
+                have: self.messages.clone(),
+            };
+            self.send_to(&peer, body, output);
+        }
+    }
+
+    pub(super) fn random_peers(&mut self) -> Vec<String> {
+        let node_ids = self.node_ids.clone();
+        let node_id = self.node_id.clone();
+        self.push_pull().peers(&node_ids, &node_id)
+    }
+}
@@ -0,0 +1,245 @@
+use super::causal::VectorClock;
+use super::interval_set::IntervalSet;
+use super::{BroadcastBody, BroadcastNode, MessageId};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+//Stability: a message is stable once every node has it. Each node counts the
+//broadcasts from each origin it has with none missing before, and every round sends
+//its neighbors the latest counts it knows for every node, its own included, so
+//counts spread across any connected topology. Only counts a neighbor has not
+//acknowledged are sent, so a quiet cluster sends nothing. The lowest count for an
+//origin across all nodes is its low watermark, and that many of the origin's first
+//broadcasts are stable. No neighbor needs a stable message again, so stable ids are
+//dropped from what each neighbor is confirmed to have, which then only holds the
+//unstable tail.
+
+pub const ROUND_EVERY: Duration = Duration::from_secs(1);
+
+//Maelstrom's error code for a request the node does not support
+const NOT_SUPPORTED: u64 = 10;
+
+#[derive(Debug)]
+pub struct Stability {
+    //The latest counts known for each node, by node id, indexed by origin
+    pub clocks: BTreeMap<String, VectorClock>,
+    pub stable: IntervalSet,
+    //The counts each peer is known to have, from what it sent or acknowledged
+    acked: HashMap<String, BTreeMap<String, VectorClock>>,
+    last_round: Instant,
+    pub rounds: u64,
+}
+
+impl Stability {
+    pub fn new(now: Instant) -> Self {
+        Stability {
+            clocks: BTreeMap::new(),
+            stable: IntervalSet::new(),
+            acked: HashMap::new(),
+            last_round: now,
+            rounds: 0,
+        }
+    }
+
+    pub fn merge(&mut self, clocks: BTreeMap<String, VectorClock>) {
+        merge(&mut self.clocks, clocks);
+    }
+
+    pub fn merge_from(&mut self, peer: &str, clocks: BTreeMap<String, VectorClock>) {
+        //! Merge counts a peer sent or acknowledged, which it is now known to have.
+        merge(
+            self.acked.entry(peer.to_owned()).or_default(),
+            clocks.clone(),
+        );
+        self.merge(clocks);
+    }
+
+    pub fn unacked(&self, peer: &str) -> BTreeMap<String, VectorClock> {
+        //! The counts, by node, that are higher than the peer is known to have.
+        let acked = self.acked.get(peer);
+        self.clocks
+            .iter()
+            .filter(|(node_id, clock)| {
+                let known = acked.and_then(|acked| acked.get(*node_id));
+                clock.iter().enumerate().any(|(origin, count)| {
+                    *count
+                        > known
+                            .and_then(|known| known.get(origin))
+                            .copied()
+                            .unwrap_or(0)
+                })
+            })
+            .map(|(node_id, clock)| (node_id.clone(), clock.clone()))
+            .collect()
+    }
+
+    pub fn counts_for(&self, node_ids: &[String]) -> BTreeMap<String, VectorClock> {
+        //! The counts known for these nodes.
+        node_ids
+            .iter()
+            .filter_map(|node_id| Some((node_id.clone(), self.clocks.get(node_id)?.clone())))
+            .collect()
+    }
+
+    pub fn low_watermark(&self, node_ids: &[String]) -> VectorClock {
+        //! For each origin, how many of its broadcasts every node has.
+        (0..node_ids.len())
+            .map(|origin| {
+                node_ids
+                    .iter()
+                    .map(|node_id| {
+                        self.clocks
+                            .get(node_id)
+                            .and_then(|clock| clock.get(origin))
+                            .copied()
+                            .unwrap_or(0)
+                    })
+                    .min()
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+}
+
+fn merge(known: &mut BTreeMap<String, VectorClock>, clocks: BTreeMap<String, VectorClock>) {
+    //! Counts only grow, so keep the highest known for each node and origin.
+    for (node_id, clock) in clocks {
+        let known = known.entry(node_id).or_default();
+        if known.len() < clock.len() {
+            known.resize(clock.len(), 0);
+        }
+        for (count, theirs) in known.iter_mut().zip(clock) {
+            *count = (*count).max(theirs);
+        }
+    }
+}
+
+impl BroadcastNode {
+    fn tracker(&mut self) -> &mut Stability {
+        self.stability
+            .as_mut()
+            .expect("Only used when stability is tracked")
+    }
+
+    pub(super) fn stability_tick(&mut self, output: &mut impl Write) {
+        let now = Instant::now();
+        if now.duration_since(self.tracker().last_round) < ROUND_EVERY {
+            return;
+        }
+        self.tracker().last_round = now;
+        self.stability_round(output);
+    }
+
+    pub fn stability_round(&mut self, output: &mut impl Write) {
+        //! Send every neighbor, or with push-pull gossip some random peers, the counts
+        //! they have not acknowledged.
+        if self.stability.is_none() {
+            return;
+        }
+        self.tracker().rounds += 1;
+        self.stabilize();
+        let peers = match self.push_pull {
+            Some(_) => self.random_peers(),
+            None => self.neighbors.clone(),
+        };
+        for neighbor in peers {
+            let clocks = self.tracker().unacked(&neighbor);
+            if clocks.is_empty() {
+                continue;
+            }
+            let body = BroadcastBody::Stability {
+                msg_id: self.current_msg_id,
+                clocks,
+            };
+            self.send_to(&neighbor, body, output);
+        }
+    }
+
+    pub(super) fn stability_received(
+        &mut self,
+        peer: &str,
+        msg_id: usize,
+        clocks: BTreeMap<String, VectorClock>,
+    ) -> Option<BroadcastBody> {
+        //! Merge a peer's counts, and acknowledge them with the counts now known for
+        //! the same nodes.
+        let node_ids: Vec<String> = clocks.keys().cloned().collect();
+        self.stability.as_mut()?.merge_from(peer, clocks);
+        self.stabilize();
+        let clocks = self.tracker().counts_for(&node_ids);
+        Some(BroadcastBody::StabilityOk {
+            in_reply_to: msg_id,
+            msg_id: self.current_msg_id,
+            clocks,
+        })
+    }
+
+    pub fn stabilize(&mut self) {
+        //! Count what this node has, work out what is stable, and compact what each
+        //! neighbor is confirmed to have.
+        if self.stability.is_none() {
+            return;
+        }
+        let own = (0..self.node_ids.len())
+            .map(|origin| self.contiguous(origin))
+            .collect();
+        let stability = self.stability.as_mut().expect("Checked above");
+        stability.merge(BTreeMap::from([(self.node_id.clone(), own)]));
+        let mut stable = IntervalSet::new();
+        for (origin, count) in stability
+            .low_watermark(&self.node_ids)
+            .into_iter()
+            .enumerate()
+        {
+            if count > 0 {
+                let first = MessageId { origin, seq: 0 }.pack();
+                stable.insert_run(first, first + count - 1);
+            }
+        }
+        if stable == stability.stable {
+            return;
+        }
+        for known in self.confirmed_seen.values_mut() {
+            *known = known.difference(&stable);
+        }
+        stability.stable = stable;
+    }
+
+    pub(super) fn read_stable(&mut self, msg_id: usize) -> BroadcastBody {
+        //! What every node is known to have, or an error if stability is not tracked.
+        self.stabilize();
+        match self.stability.as_ref() {
+            Some(stability) => BroadcastBody::ReadStableOk {
+                in_reply_to: msg_id,
+                msg_id: self.current_msg_id,
+                stable: stability.stable.clone(),
+                low_watermark: self
+                    .node_ids
+                    .iter()
+                    .cloned()
+                    .zip(stability.low_watermark(&self.node_ids))
+                    .collect(),
+            },
+            None => BroadcastBody::Error {
+                in_reply_to: msg_id,
+                code: NOT_SUPPORTED,
+                text: "stability is not tracked; set EVENT_HORIZON_STABILITY=on".to_owned(),
+            },
+        }
+    }
+
+    pub(super) fn unstable(&self, messages: &IntervalSet) -> IntervalSet {
+        //! The messages that are not yet known to be stable.
+        match self.stability.as_ref() {
+            Some(stability) => messages.difference(&stability.stable),
+            None => messages.clone(),
+        }
+    }
+
+    pub(super) fn is_stable(&self, id: usize) -> bool {
+        self.stability
+            .as_ref()
+            .is_some_and(|stability| stability.stable.contains(id))
+    }
+}
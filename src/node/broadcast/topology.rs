@@ -0,0 +1,128 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+//Topologies a broadcast node can build for itself from the cluster's node IDs.
+//Edges always run both ways, since gossip only reaches a node's neighbors, and
+//every node sorts the IDs the same way, so every node builds the same topology.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    //Use the topology Maelstrom sends
+    Provided,
+    //A spanning tree, where each node's children are the next `fanout` nodes in a
+    //breadth-first numbering
+    Tree { fanout: usize },
+    //Every node neighbors the first node, and only it
+    Star,
+    //Each node neighbors the nodes either side of it, plus `chords` nodes spaced
+    //evenly around the ring
+    Ring { chords: usize },
+    //Nodes laid out row by row in a square grid, neighboring the nodes above,
+    //below, left and right of them
+    Grid,
+}
+
+impl Topology {
+    pub fn parse(spec: &str) -> Option<Self> {
+        //! Parse a topology such as `provided`, `tree`, `tree:3` (fan-out, default 4),
+        //! `star`, `ring`, `ring:2` (chords per node, default 0) or `grid`.
+        let (name, argument) = spec.split_once(':').unwrap_or((spec, ""));
+        let count = |default: usize| match argument {
+            "" => Some(default),
+            argument => argument.parse::<usize>().ok(),
+        };
+        match name {
+            "provided" if argument.is_empty() => Some(Topology::Provided),
+            "tree" => count(4)
+                .filter(|fanout| *fanout > 0)
+                .map(|fanout| Topology::Tree { fanout }),
+            "star" if argument.is_empty() => Some(Topology::Star),
+            "ring" => count(0).map(|chords| Topology::Ring { chords }),
+            "grid" if argument.is_empty() => Some(Topology::Grid),
+            _ => None,
+        }
+    }
+
+    pub fn build(&self, node_ids: &[String]) -> Option<HashMap<String, Vec<String>>> {
+        //! Every node's neighbors, or None if the topology is Maelstrom's to give.
+        let mut nodes: Vec<&String> = node_ids.iter().collect();
+        nodes.sort_by(|a, b| by_number(a, b));
+        nodes.dedup();
+        let count = nodes.len();
+        let mut edges = BTreeSet::new();
+        match *self {
+            Topology::Provided => return None,
+            Topology::Tree { fanout } => {
+                for child in 1..count {
+                    edges.insert(((child - 1) / fanout, child));
+                }
+            }
+            Topology::Star => {
+                for leaf in 1..count {
+                    edges.insert((0, leaf));
+                }
+            }
+            Topology::Ring { chords } => {
+                for index in 0..count {
+                    edges.insert((index, (index + 1) % count));
+                    //Chords cut across the ring at evenly spaced distances.
+                    for chord in 1..=chords {
+                        edges.insert((index, (index + count * chord / (chords + 1)) % count));
+                    }
+                }
+            }
+            Topology::Grid => {
+                let width = (count as f64).sqrt().ceil().max(1.0) as usize;
+                for index in 0..count {
+                    if index + width < count {
+                        edges.insert((index, index + width));
+                    }
+                    if (index + 1) % width != 0 && index + 1 < count {
+                        edges.insert((index, index + 1));
+                    }
+                }
+            }
+        }
+        let mut topology: HashMap<String, Vec<String>> = nodes
+            .iter()
+            .map(|node_id| (node_id.to_string(), Vec::new()))
+            .collect();
+        for (a, b) in edges {
+            if a == b {
+                continue;
+            }
+            for (from, to) in [(a, b), (b, a)] {
+                let neighbors = topology.get_mut(nodes[from]).expect("Node is in topology");
+                if !neighbors.contains(nodes[to]) {
+                    neighbors.push(nodes[to].clone());
+                }
+            }
+        }
+        for neighbors in topology.values_mut() {
+            neighbors.sort_by(|a, b| by_number(a, b));
+        }
+        Some(topology)
+    }
+}
+
+pub(super) fn by_number(a: &str, b: &str) -> std::cmp::Ordering {
+    //! Order node IDs by length first, so n2 comes before n10.
+    (a.len(), a).cmp(&(b.len(), b))
+}
+
+pub fn eccentricity(topology: &HashMap<String, Vec<String>>, node_id: &str) -> usize {
+    //! Hops from a node to the furthest node it can reach.
+    let mut hops = HashMap::from([(node_id, 0)]);
+    let mut frontier = VecDeque::from([node_id]);
+    let mut furthest = 0;
+    while let Some(current) = frontier.pop_front() {
+        let distance = hops[current];
+        furthest = furthest.max(distance);
+        for neighbor in topology.get(current).into_iter().flatten() {
+            if !hops.contains_key(neighbor.as_str()) {
+                hops.insert(neighbor, distance + 1);
+                frontier.push_back(neighbor);
+            }
+        }
+    }
+    furthest
+}
@@ -0,0 +1,517 @@
+use super::interval_set::IntervalSet;
+use super::{BroadcastBody, BroadcastNode};
+use crate::rng::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+//Total-order broadcast. Messages still spread by gossip, and one node, the
+//sequencer, places each message it learns of at the end of a sequence shared by
+//every node. It sends each other node the part of the sequence it lacks with the
+//payloads, and a position is committed once a majority holds it. Every node
+//delivers committed positions in order, so reads list the same messages in the same
+//order everywhere. A node sent a part that starts past the end of its sequence
+//answers with how much it has, and the sequencer resends from there.
+//
+//The first node is the sequencer to begin with. A node that hears nothing from a
+//sequencer for an election timeout stands for the next term, and becomes the
+//sequencer once a majority votes for it. A node votes once per term, and only for a
+//node whose sequence is at least as far along as its own, so a new sequencer holds
+//every committed position, and a sequencer cut off from the majority commits nothing.
+//
+//When EVENT_HORIZON_STATE_DIR is set, a node writes its term, vote and sequence there
+//before it answers a vote or a sequence, and reads them back when restarted. A node
+//that came back empty would vote for any sequencer, even one missing committed
+//positions, which would then replace them.
+
+//Randomized up to twice this, so that nodes rarely stand at once
+pub const ELECTION_TIMEOUT: Duration = Duration::from_millis(1000);
+//Positions sent to a node at a time
+const MAX_ENTRIES: usize = 512;
+
+//A position in the sequence: the term of the sequencer that placed it, and the id of
+//the message, or none for the marker a new sequencer starts its term with
+pub type Slot = (u64, Option<usize>);
+
+//What a restarted node reads back, with the payload of each position
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    term: u64,
+    voted_for: Option<String>,
+    sequence: Vec<Slot>,
+    payloads: Vec<Value>,
+    committed: usize,
+}
+
+#[derive(Debug)]
+enum Role {
+    Follower,
+    Candidate {
+        votes: BTreeSet<String>,
+    },
+    Sequencer {
+        //The position to send each node from, and how much it is known to hold
+        next: HashMap<String, usize>,
+        matched: HashMap<String, usize>,
+    },
+}
+
+#[derive(Debug)]
+pub struct TotalOrder {
+    node_id: String,
+    peers: Vec<String>,
+    pub term: u64,
+    //The sequencer of the current term, once known
+    pub sequencer: Option<String>,
+    voted_for: Option<String>,
+    role: Role,
+    pub sequence: Vec<Slot>,
+    //The ids placed in the sequence, so none is placed twice
+    placed: IntervalSet,
+    //Positions before this are committed, and delivered
+    pub committed: usize,
+    last_heard: Instant,
+    timeout: Duration,
+    election_timeout: Duration,
+    rng: Rng,
+    pub elections: u64,
+    //Where the state a restart must keep is written, when EVENT_HORIZON_STATE_DIR is set
+    state_file: Option<PathBuf>,
+    //Set when that state has changed since it was last written
+    dirty: bool,
+}
+
+impl TotalOrder {
+    pub fn new(
+        node_id: &str,
+        node_ids: &[String],
+        election_timeout: Duration,
+        state_file: Option<PathBuf>,
+        now: Instant,
+    ) -> (Self, Vec<(usize, Value)>) {
+        //! Node ids are sorted, and the first is the sequencer for term 0. A node
+        //! restarted with its state saved in `state_file` carries on from it, as a
+        //! follower; the messages it had placed are returned, with their payloads.
+        let peers: Vec<String> = node_ids
+            .iter()
+            .filter(|peer| *peer != node_id)
+            .cloned()
+            .collect();
+        let first = node_ids.first().cloned();
+        let role = if first.as_deref() == Some(node_id) {
+            Role::Sequencer {
+                next: peers.iter().map(|peer| (peer.clone(), 0)).collect(),
+                matched: peers.iter().map(|peer| (peer.clone(), 0)).collect(),
+            }
+        } else {
+            Role::Follower
+        };
+        let mut total = TotalOrder {
+            node_id: node_id.to_owned(),
+            peers,
+            term: 0,
+            sequencer: first,
+            voted_for: None,
+            role,
+            sequence: Vec::new(),
+            placed: IntervalSet::new(),
+            committed: 0,
+            last_heard: now,
+            timeout: election_timeout,
+            election_timeout,
+            rng: Rng::from_time(),
+            elections: 0,
+            state_file,
+            dirty: false,
+        };
+        total.heard(now);
+        let saved: Option<Saved> = total
+            .state_file
+            .as_ref()
+            .and_then(|state_file| fs::read_to_string(state_file).ok())
+            .and_then(|saved| serde_json::from_str(&saved).ok());
+        let mut restored = Vec::new();
+        if let Some(saved) = saved {
+            total.term = saved.term;
+            total.voted_for = saved.voted_for;
+            //Which node leads the saved term is not known until it is heard from.
+            total.sequencer = None;
+            total.role = Role::Follower;
+            for (slot, payload) in saved.sequence.into_iter().zip(saved.payloads) {
+                if let Some(id) = slot.1 {
+                    restored.push((id, payload));
+                }
+                total.place(slot);
+            }
+            total.committed = saved.committed.min(total.sequence.len());
+            total.dirty = false;
+        }
+        (total, restored)
+    }
+
+    pub fn is_sequencer(&self) -> bool {
+        matches!(self.role, Role::Sequencer { .. })
+    }
+
+    pub fn delivered(&self) -> impl Iterator<Item = usize> + '_ {
+        //! The ids of the committed positions, in order.
+        self.sequence[..self.committed]
+            .iter()
+            .filter_map(|(_, id)| *id)
+    }
+
+    fn majority(&self) -> usize {
+        let nodes = self.peers.len() + 1;
+        nodes / 2 + 1
+    }
+
+    fn last_term(&self) -> u64 {
+        self.sequence.last().map_or(0, |(term, _)| *term)
+    }
+
+    fn heard(&mut self, now: Instant) {
+        //! Restart the election timer, with a new random timeout.
+        let base = self.election_timeout.as_millis() as usize;
+        self.last_heard = now;
+        self.timeout = Duration::from_millis((base + self.rng.below(base.max(1))) as u64);
+    }
+
+    fn observe(&mut self, term: u64) -> bool {
+        //! Follow a later term, forgetting the vote and sequencer of this one.
+        //! Returns whether the term was later.
+        if term <= self.term {
+            return false;
+        }
+        self.term = term;
+        self.voted_for = None;
+        self.sequencer = None;
+        self.role = Role::Follower;
+        self.dirty = true;
+        true
+    }
+
+    fn place(&mut self, entry: Slot) {
+        if let Some(id) = entry.1 {
+            self.placed.insert(id);
+        }
+        self.sequence.push(entry);
+        self.dirty = true;
+    }
+
+    fn truncate(&mut self, length: usize) {
+        //! Drop uncommitted positions that a later sequencer replaced.
+        self.sequence.truncate(length);
+        self.placed = self.sequence.iter().filter_map(|(_, id)| *id).collect();
+        self.dirty = true;
+    }
+
+    fn advance_commit(&mut self) {
+        //! Commit what a majority holds, once it includes a position from this term.
+        let Role::Sequencer { matched, .. } = &self.role else {
+            return;
+        };
+        let mut held: Vec<usize> = matched.values().copied().collect();
+        held.push(self.sequence.len());
+        held.sort_unstable_by(|a, b| b.cmp(a));
+        let length = held[self.majority() - 1];
+        if length > self.committed && self.sequence[length - 1].0 == self.term {
+            self.committed = length;
+            self.dirty = true;
+        }
+    }
+}
+
+impl BroadcastNode {
+    fn total(&mut self) -> &mut TotalOrder {
+        self.total
+            .as_mut()
+            .expect("Only used with total-order delivery")
+    }
+
+    pub(super) fn total_message(
+        &mut self,
+        src: String,
+        body: BroadcastBody,
+        output: &mut impl Write,
+    ) {
+        let now = Instant::now();
+        match body {
+            BroadcastBody::Sequence {
+                msg_id,
+                term,
+                start,
+                previous_term,
+                entries,
+                payloads,
+                committed,
+            } => {
+                let total = self.total();
+                total.observe(term);
+                let mut kept = Vec::new();
+                let (success, have) = if term < total.term {
+                    (false, 0)
+                } else {
+                    total.role = Role::Follower;
+                    total.sequencer = Some(src.clone());
+                    total.heard(now);
+                    if start > total.sequence.len() {
+                        //A gap, so ask for everything after what this node has.
+                        (false, total.sequence.len())
+                    } else if start > 0 && total.sequence[start - 1].0 != previous_term {
+                        //Committed positions always agree, so resend from there.
+                        (false, total.committed)
+                    } else {
+                        let end = start + entries.len();
+                        for (index, (entry, payload)) in
+                            (start..).zip(entries.into_iter().zip(payloads))
+                        {
+                            match total.sequence.get(index) {
+                                Some((placed_term, _)) if *placed_term == entry.0 => continue,
+                                Some(_) => total.truncate(index),
+                                None => {}
+                            }
+                            total.place(entry);
+                            if let Some(id) = entry.1 {
+                                kept.push((id, payload));
+                            }
+                        }
+                        let committed = total.committed.max(committed.min(end));
+                        if committed > total.committed {
+                            total.committed = committed;
+                            total.dirty = true;
+                        }
+                        (true, end)
+                    }
+                };
+                for (id, payload) in kept {
+                    if self.messages.insert(id) {
+                        self.payloads.insert(id, payload);
+                    }
+                }
+                self.save_total();
+                let body = BroadcastBody::SequenceOk {
+                    in_reply_to: msg_id,
+                    msg_id: self.current_msg_id,
+                    term: self.total().term,
+                    have,
+                    success,
+                };
+                self.send_to(&src, body, output);
+            }
+            BroadcastBody::SequenceOk {
+                term,
+                have,
+                success,
+                ..
+            } => {
+                let total = self.total();
+                if total.observe(term) || term != total.term {
+                    return;
+                }
+                let length = total.sequence.len();
+                let Role::Sequencer { next, matched } = &mut total.role else {
+                    return;
+                };
+                let behind = if success {
+                    let held = matched.entry(src.clone()).or_default();
+                    *held = (*held).max(have);
+                    next.insert(src.clone(), have);
+                    //More to send, beyond what one message holds
+                    have < length
+                } else {
+                    next.insert(src.clone(), have.min(length));
+                    true
+                };
+                total.advance_commit();
+                if behind {
+                    self.replicate(&src, output);
+                }
+            }
+            BroadcastBody::RequestVote {
+                msg_id,
+                term,
+                length,
+                last_term,
+            } => {
+                let total = self.total();
+                total.observe(term);
+                let granted = term == total.term
+                    && total.voted_for.as_ref().is_none_or(|voted| *voted == src)
+                    && (last_term, length) >= (total.last_term(), total.sequence.len());
+                if granted {
+                    total.voted_for = Some(src.clone());
+                    total.dirty = true;
+                    total.heard(now);
+                }
+                self.save_total();
+                let body = BroadcastBody::RequestVoteOk {
+                    in_reply_to: msg_id,
+                    msg_id: self.current_msg_id,
+                    term: self.total().term,
+                    granted,
+                };
+                self.send_to(&src, body, output);
+            }
+            BroadcastBody::RequestVoteOk { term, granted, .. } => {
+                let total = self.total();
+                if total.observe(term) || term != total.term || !granted {
+                    return;
+                }
+                let majority = total.majority();
+                if let Role::Candidate { votes } = &mut total.role {
+                    votes.insert(src);
+                    if votes.len() >= majority {
+                        self.lead(output);
+                    }
+                }
+            }
+            _ => unreachable!("Not a total-order message"),
+        }
+    }
+
+    pub(super) fn total_tick(&mut self, output: &mut impl Write) {
+        let now = Instant::now();
+        let total = self.total();
+        if total.is_sequencer() {
+            self.place_new();
+            self.save_total();
+            for peer in self.total().peers.clone() {
+                self.replicate(&peer, output);
+            }
+            return;
+        }
+        if now.duration_since(total.last_heard) < total.timeout {
+            return;
+        }
+        //Stand for the next term.
+        total.term += 1;
+        total.voted_for = Some(total.node_id.clone());
+        total.dirty = true;
+        total.sequencer = None;
+        total.role = Role::Candidate {
+            votes: BTreeSet::from([total.node_id.clone()]),
+        };
+        total.elections += 1;
+        total.heard(now);
+        self.save_total();
+        let total = self.total();
+        if total.majority() == 1 {
+            return self.lead(output);
+        }
+        let (term, length, last_term) = (total.term, total.sequence.len(), total.last_term());
+        for peer in total.peers.clone() {
+            let body = BroadcastBody::RequestVote {
+                msg_id: self.current_msg_id,
+                term,
+                length,
+                last_term,
+            };
+            self.send_to(&peer, body, output);
+        }
+    }
+
+    fn lead(&mut self, output: &mut impl Write) {
+        //! Become the sequencer, starting the term with a marker, so that positions
+        //! placed in earlier terms commit along with it.
+        let total = self.total();
+        let length = total.sequence.len();
+        total.role = Role::Sequencer {
+            next: total
+                .peers
+                .iter()
+                .map(|peer| (peer.clone(), length))
+                .collect(),
+            matched: total.peers.iter().map(|peer| (peer.clone(), 0)).collect(),
+        };
+        total.sequencer = Some(total.node_id.clone());
+        let term = total.term;
+        total.place((term, None));
+        self.total_tick(output);
+        self.total().advance_commit();
+    }
+
+    fn save_total(&mut self) {
+        //! Write the state a restart must keep, if it changed, before this node acts
+        //! on it. Swapped in whole, like next_seq, so a kill part way through leaves
+        //! the last state.
+        let total = self
+            .total
+            .as_ref()
+            .expect("Only used with total-order delivery");
+        let Some(state_file) = total.state_file.as_ref().filter(|_| total.dirty) else {
+            return;
+        };
+        let saved = Saved {
+            term: total.term,
+            voted_for: total.voted_for.clone(),
+            sequence: total.sequence.clone(),
+            payloads: total
+                .sequence
+                .iter()
+                .map(|(_, id)| {
+                    id.and_then(|id| self.payloads.get(&id))
+                        .cloned()
+                        .unwrap_or_default()
+                })
+                .collect(),
+            committed: total.committed,
+        };
+        let partial = state_file.with_extension("total.partial");
+        fs::write(&partial, serde_json::to_string(&saved).unwrap())
+            .and_then(|_| fs::rename(&partial, state_file))
+            .expect("Unable to persist total order state");
+        self.total().dirty = false;
+    }
+
+    fn place_new(&mut self) {
+        //! Place every message this node has that is not yet in the sequence.
+        let placed = &self
+            .total
+            .as_ref()
+            .expect("Only used with total-order delivery")
+            .placed;
+        let unplaced = self.messages.difference(placed);
+        let total = self.total();
+        let term = total.term;
+        for id in unplaced.iter() {
+            total.place((term, Some(id)));
+        }
+        total.advance_commit();
+    }
+
+    fn replicate(&mut self, peer: &str, output: &mut impl Write) {
+        //! Send a node the sequence from where it is known to need it.
+        let total = self
+            .total
+            .as_ref()
+            .expect("Only used with total-order delivery");
+        let Role::Sequencer { next, .. } = &total.role else {
+            return;
+        };
+        let start = next.get(peer).copied().unwrap_or_default();
+        let end = total.sequence.len().min(start + MAX_ENTRIES);
+        let entries = total.sequence[start..end].to_vec();
+        let body = BroadcastBody::Sequence {
+            msg_id: self.current_msg_id,
+            term: total.term,
+            start,
+            previous_term: start
+                .checked_sub(1)
+                .map_or(0, |previous| total.sequence[previous].0),
+            payloads: entries
+                .iter()
+                .map(|(_, id)| match id {
+                    Some(id) => self.payloads.get(id).cloned().unwrap_or_default(),
+                    None => Value::Null,
+                })
+                .collect(),
+            entries,
+            committed: total.committed,
+        };
+        self.send_to(peer, body, output);
+    }
+}
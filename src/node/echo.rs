@@ -1,7 +1,7 @@
 use super::{Event, Node, Reply};
 use crate::NodeMetadata;
 use serde::{self, Deserialize, Serialize};
-use std::io;
+use std::io::Write;
 use std::sync::mpsc::Sender;
 
 pub struct EchoNode {
@@ -41,9 +41,9 @@ impl Node<EchoBody> for EchoNode {
         EchoNode { current_msg_id: 0 }
     }
 
-    fn handle_event(&mut self, event: Event<EchoBody>, stdout_handle: &mut io::StdoutLock) {
+    fn handle_event(&mut self, event: Event<EchoBody>, output: &mut impl Write) {
         if let Event::Message(message) = event {
-            message.message_reply(stdout_handle, self);
+            message.message_reply(output, self);
             self.current_msg_id += 1;
         }
     }
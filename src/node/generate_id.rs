@@ -1,14 +1,29 @@
-use super::{Event, Node, Reply};
-use crate::NodeMetadata;
+use super::{spawn_ticker, Event, Node, Reply};
+use crate::clock;
+use crate::service::tso::TsoClient;
+use crate::{MaelstromMessage, NodeMetadata};
 use serde::{self, Deserialize, Serialize};
-use std::io;
+use std::io::Write;
 use std::sync::mpsc::Sender;
+use std::time::Duration;
 
 pub struct GenerateGuidNode {
     pub current_msg_id: usize,
     pub node_id: String,
+    //Nanosecond timestamp of when this process started, from the unskewed system
+    //clock. Message IDs restart at 0 when a node crashes and restarts, so IDs must
+    //also name the incarnation.
+    pub incarnation: u64,
+    //When set, IDs lead with a timestamp from lin-tso, so they sort in the
+    //real-time order they were generated in.
+    pub tso: Option<TsoClient<GenerateGuidNode>>,
+    //The lin-tso timestamp for the request being answered
+    pub request_timestamp: Option<u64>,
 }
 
+//How long to wait for lin-tso before asking again
+const TSO_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
@@ -24,28 +39,87 @@ pub enum GenerateGuidBody {
 }
 
 impl Node<GenerateGuidBody> for GenerateGuidNode {
-    fn node_init(node_metadata: NodeMetadata, _: Sender<Event<GenerateGuidBody>>) -> Self {
+    fn node_init(node_metadata: NodeMetadata, event_tx: Sender<Event<GenerateGuidBody>>) -> Self {
+        let tso = TsoClient::from_env(&node_metadata.node_id);
+        if tso.is_some() {
+            //Only needed to retry timestamp requests lost on the way to lin-tso.
+            spawn_ticker(Duration::from_millis(100), event_tx);
+        }
         GenerateGuidNode {
             current_msg_id: 0,
             node_id: node_metadata.node_id,
+            incarnation: clock::incarnation(),
+            tso,
+            request_timestamp: None,
         }
     }
 
-    fn handle_event(&mut self, event: Event<GenerateGuidBody>, stdout_handle: &mut io::StdoutLock) {
-        if let Event::Message(message) = event {
-            message.message_reply(stdout_handle, self);
-            self.current_msg_id += 1;
+    fn handle_event(&mut self, event: Event<GenerateGuidBody>, output: &mut impl Write) {
+        match event {
+            Event::Message(message) if self.tso.is_some() => self.reply_stamped(message, output),
+            Event::Message(message) => {
+                message.message_reply(output, self);
+                self.current_msg_id += 1;
+            }
+            Event::ServiceReply(reply) => {
+                if self.tso.is_some() {
+                    TsoClient::dispatch(self, GenerateGuidNode::tso, &reply, output);
+                }
+            }
+            Event::PropogateWrites => {
+                if self.tso.is_some() {
+                    TsoClient::expire(self, GenerateGuidNode::tso, TSO_TIMEOUT, output);
+                }
+            }
         }
     }
 }
 
+impl GenerateGuidNode {
+    fn tso(&mut self) -> &mut TsoClient<GenerateGuidNode> {
+        self.tso.as_mut().expect("Only used in lin-tso mode")
+    }
+
+    fn reply_stamped(
+        &mut self,
+        message: MaelstromMessage<GenerateGuidBody>,
+        output: &mut dyn Write,
+    ) {
+        //! Reply once lin-tso has given the request a timestamp, asking again if
+        //! the request times out.
+        self.tso().timestamp(
+            output,
+            move |node: &mut GenerateGuidNode, timestamp, output| match timestamp {
+                Ok(timestamp) => {
+                    node.request_timestamp = Some(timestamp);
+                    message.message_reply(output, node);
+                    node.request_timestamp = None;
+                    node.current_msg_id += 1;
+                }
+                Err(_) => node.reply_stamped(message, output),
+            },
+        );
+    }
+}
+
 impl Reply<GenerateGuidNode> for GenerateGuidBody {
     fn into_reply(self, node_state: &mut GenerateGuidNode, _: &str) -> Option<Self> {
         match self {
             GenerateGuidBody::Generate { msg_id } => {
-                //Because node_id is unique for a given node, and
-                //Message IDs are unique per source node
-                let unique_id = format!("{}|{}", &node_state.node_id, node_state.current_msg_id);
+                //Because node_id is unique for a given node, the incarnation is unique
+                //per process of that node, and Message IDs are unique per process
+                let unique_id = match node_state.request_timestamp {
+                    //Requests batched onto one lin-tso request share a timestamp, so
+                    //node_id and Message ID still tell them apart. Zero-padded to sort.
+                    Some(timestamp) => format!(
+                        "{:020}|{}|{}",
+                        timestamp, &node_state.node_id, node_state.current_msg_id
+                    ),
+                    None => format!(
+                        "{}|{}|{}",
+                        &node_state.node_id, node_state.incarnation, node_state.current_msg_id
+                    ),
+                };
                 Some(GenerateGuidBody::GenerateOk {
                     id: unique_id,
                     msg_id: node_state.current_msg_id,
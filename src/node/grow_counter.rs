@@ -1,10 +1,15 @@
+use crate::node::spawn_ticker;
+use crate::service::kv::{self, KvClient, KvNode};
+use crate::service::ServiceError;
 use crate::{Event, MaelstromMessage, Node, Reply};
 use serde::{self, Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::io;
+use std::env;
+use std::io::Write;
 use std::sync::mpsc::Sender;
-use std::thread;
+use std::time::Duration;
 
 pub struct CounterNode {
     pub node_id: String,
@@ -12,11 +17,79 @@ pub struct CounterNode {
     pub current_msg_id: usize,
     //A local copy of the current Nodes and their counter value
     pub node_counter_map: HashMap<String, usize>,
+    //Set when the counter is kept in seq-kv instead of gossiped between nodes
+    pub seq_kv: Option<SeqKvCounter>,
 }
 
-impl Node<CounterBody> for CounterNode {
-    fn node_init(
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterMode {
+    //Send every node this node's counter map each second
+    Gossip,
+    //Keep one counter in seq-kv, which every node adds to by CAS
+    SeqKv,
+    //Keep a counter per node in seq-kv, each added to only by its own node, and sum them to read
+    SeqKvPerNode,
+}
+
+impl CounterMode {
+    pub fn from_env() -> Self {
+        CounterMode::from_vars(|name| env::var(name).ok())
+    }
+
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        //! Picked with EVENT_HORIZON_COUNTER: `gossip` (the default), `seq-kv` or `seq-kv-per-node`.
+        match var("EVENT_HORIZON_COUNTER").as_deref() {
+            Some("gossip") | None => CounterMode::Gossip,
+            Some("seq-kv") => CounterMode::SeqKv,
+            Some("seq-kv-per-node") => CounterMode::SeqKvPerNode,
+            Some(other) => panic!("Unknown EVENT_HORIZON_COUNTER: {}", other),
+        }
+    }
+}
+
+pub struct SeqKvCounter {
+    pub per_node: bool,
+    pub kv: KvClient<CounterNode>,
+    //Counts the writes made to force fresh reads, so each writes a new value
+    pub sync_writes: u64,
+    //Reads waiting on sub-counters, by an id of their own
+    reads: HashMap<u64, PendingRead>,
+    next_read: u64,
+}
+
+impl SeqKvCounter {
+    pub fn new(node_id: &str, per_node: bool) -> Self {
+        SeqKvCounter {
+            per_node,
+            kv: KvClient::seq(node_id),
+            sync_writes: 0,
+            reads: HashMap::new(),
+            next_read: 0,
+        }
+    }
+}
+
+struct PendingAdd {
+    client: String,
+    msg_id: usize,
+    delta: usize,
+    //How many times the add has been tried before
+    attempt: u32,
+}
+
+struct PendingRead {
+    client: String,
+    msg_id: usize,
+    //How many times the read has been tried before
+    attempt: u32,
+    remaining: usize,
+    sum: u64,
+}
+
+impl CounterNode {
+    pub fn with_mode(
         node_metadata: crate::init::NodeMetadata,
+        mode: CounterMode,
         event_tx: Sender<Event<CounterBody>>,
     ) -> Self {
         let other_node_ids: Vec<_> = node_metadata
@@ -25,33 +98,71 @@ impl Node<CounterBody> for CounterNode {
             .filter(|node_id| node_id != &node_metadata.node_id)
             .collect();
 
-        thread::spawn(move || loop {
+        let seq_kv = match mode {
+            CounterMode::Gossip => None,
+            mode => Some(SeqKvCounter::new(
+                &node_metadata.node_id,
+                mode == CounterMode::SeqKvPerNode,
+            )),
+        };
+        if seq_kv.is_some() {
+            spawn_ticker(kv::TICK, event_tx);
+        } else {
             //Every few seconds, send a copy of your nodes
             //counter values to the other nodes.
-            thread::sleep(std::time::Duration::from_secs(1));
-            event_tx
-                .send(Event::PropogateWrites)
-                .expect("Write Propogrator Thread failed to transmit");
-        });
+            spawn_ticker(Duration::from_secs(1), event_tx);
+        }
 
         CounterNode {
             node_id: node_metadata.node_id,
             current_msg_id: 0,
             other_node_ids,
             node_counter_map: HashMap::new(),
+            seq_kv,
         }
     }
+}
 
-    fn handle_event(&mut self, event: Event<CounterBody>, stdout_handle: &mut io::StdoutLock)
+impl Node<CounterBody> for CounterNode {
+    fn node_init(
+        node_metadata: crate::init::NodeMetadata,
+        event_tx: Sender<Event<CounterBody>>,
+    ) -> Self {
+        CounterNode::with_mode(node_metadata, CounterMode::from_env(), event_tx)
+    }
+
+    fn handle_event(&mut self, event: Event<CounterBody>, output: &mut impl Write)
     where
         CounterBody: Reply<Self>,
         Self: Sized,
     {
         match event {
+            Event::Message(message) if self.seq_kv.is_some() => match message.body {
+                CounterBody::Add { msg_id, delta } => {
+                    let add = PendingAdd {
+                        client: message.src,
+                        msg_id,
+                        delta,
+                        attempt: 0,
+                    };
+                    self.add(add, output)
+                }
+                CounterBody::Read { msg_id } => self.read(message.src, msg_id, 0, output),
+                _ => {}
+            },
             Event::Message(message) => {
-                message.message_reply(stdout_handle, self);
+                message.message_reply(output, self);
                 self.current_msg_id += 1;
             }
+            Event::ServiceReply(reply) => {
+                if self.seq_kv.is_some() {
+                    KvClient::dispatch(self, CounterNode::kv, &reply, output);
+                }
+            }
+            Event::PropogateWrites if self.seq_kv.is_some() => {
+                KvClient::expire(self, CounterNode::kv, kv::REQUEST_TIMEOUT, output);
+                KvClient::run_retries(self, CounterNode::kv, output);
+            }
             Event::PropogateWrites => {
                 //When a PropogateWrite is triggered, send a copy
                 //of this Nodes counter_map to every other node.
@@ -64,7 +175,7 @@ impl Node<CounterBody> for CounterNode {
                             node_counter_map: self.node_counter_map.clone(),
                         },
                     };
-                    counter_value.send(stdout_handle);
+                    counter_value.send(output);
                     self.current_msg_id += 1;
                 }
             }
@@ -72,6 +183,190 @@ impl Node<CounterBody> for CounterNode {
     }
 }
 
+impl KvNode for CounterNode {
+    type Body = CounterBody;
+
+    fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    fn count_sent(&mut self) {
+        self.current_msg_id += 1;
+    }
+
+    fn error_body(in_reply_to: usize, code: u64, text: String) -> CounterBody {
+        CounterBody::Error {
+            in_reply_to,
+            code,
+            text,
+        }
+    }
+}
+
+impl CounterNode {
+    fn seq_kv(&mut self) -> &mut SeqKvCounter {
+        self.seq_kv.as_mut().expect("Only used in seq-kv mode")
+    }
+
+    fn kv(&mut self) -> &mut KvClient<CounterNode> {
+        &mut self.seq_kv().kv
+    }
+
+    fn add(&mut self, add: PendingAdd, output: &mut dyn Write) {
+        //! Read the counter this node adds to, then CAS it to the sum. A stale read
+        //! fails the CAS, and the add starts over from a fresh read after a backoff.
+        let key = if self.seq_kv().per_node {
+            format!("counter/{}", self.node_id)
+        } else {
+            "counter".to_owned()
+        };
+        self.kv().read(
+            output,
+            key.clone(),
+            move |node: &mut CounterNode, value, output| {
+                let current = match value {
+                    Ok(value) => value.as_u64().unwrap_or(0) as usize,
+                    Err(ServiceError::KeyDoesNotExist) => 0,
+                    Err(err) => return node.retry_add(add, err, true, output),
+                };
+                node.kv().cas(
+                    output,
+                    key,
+                    current,
+                    current + add.delta,
+                    true,
+                    move |node: &mut CounterNode, result, output| match result {
+                        Ok(_) => {
+                            let body = CounterBody::AddOk {
+                                msg_id: node.current_msg_id,
+                                in_reply_to: add.msg_id,
+                            };
+                            node.reply(add.client, body, output);
+                        }
+                        Err(err) => node.retry_add(add, err, false, output),
+                    },
+                );
+            },
+        );
+    }
+
+    fn retry_add(
+        &mut self,
+        add: PendingAdd,
+        err: ServiceError,
+        idempotent: bool,
+        output: &mut dyn Write,
+    ) {
+        //! Try the add again after a backoff, unless the CAS may have applied or the
+        //! add is out of attempts.
+        match self.kv().backoff(add.attempt, &err, idempotent) {
+            Some(delay) => self.kv().after(delay, move |node, output| {
+                let attempt = add.attempt + 1;
+                node.add(PendingAdd { attempt, ..add }, output)
+            }),
+            None => self.fail(add.client, add.msg_id, "add", &err, output),
+        }
+    }
+
+    fn read(&mut self, client: String, msg_id: usize, attempt: u32, output: &mut dyn Write) {
+        //! seq-kv may serve reads from the past. Writing a value no other write has
+        //! made orders this node's later reads after it, so they see the present.
+        let node_id = self.node_id.clone();
+        let seq_kv = self.seq_kv();
+        seq_kv.sync_writes += 1;
+        let nonce = format!("{}-{}", node_id, seq_kv.sync_writes);
+        seq_kv.kv.write(
+            output,
+            format!("sync/{}", node_id),
+            nonce,
+            move |node: &mut CounterNode, result, output| match result {
+                Ok(_) => node.read_fresh(client, msg_id, attempt, output),
+                Err(err) => node.retry_read(client, msg_id, attempt, err, output),
+            },
+        );
+    }
+
+    fn retry_read(
+        &mut self,
+        client: String,
+        msg_id: usize,
+        attempt: u32,
+        err: ServiceError,
+        output: &mut dyn Write,
+    ) {
+        //! Reads change nothing but the sync key, so any failure is safe to retry.
+        match self.kv().backoff(attempt, &err, true) {
+            Some(delay) => self.kv().after(delay, move |node, output| {
+                node.read(client, msg_id, attempt + 1, output)
+            }),
+            None => self.fail(client, msg_id, "read", &err, output),
+        }
+    }
+
+    fn read_fresh(&mut self, client: String, msg_id: usize, attempt: u32, output: &mut dyn Write) {
+        let keys: Vec<String> = if self.seq_kv().per_node {
+            std::iter::once(&self.node_id)
+                .chain(self.other_node_ids.iter())
+                .map(|node_id| format!("counter/{}", node_id))
+                .collect()
+        } else {
+            vec!["counter".to_owned()]
+        };
+        let seq_kv = self.seq_kv();
+        let read_id = seq_kv.next_read;
+        seq_kv.next_read += 1;
+        seq_kv.reads.insert(
+            read_id,
+            PendingRead {
+                client,
+                msg_id,
+                attempt,
+                remaining: keys.len(),
+                sum: 0,
+            },
+        );
+        for key in keys {
+            seq_kv
+                .kv
+                .read(output, key, move |node: &mut CounterNode, value, output| {
+                    node.read_part(read_id, value, output)
+                });
+        }
+    }
+
+    fn read_part(
+        &mut self,
+        read_id: u64,
+        value: Result<Value, ServiceError>,
+        output: &mut dyn Write,
+    ) {
+        //! Add one counter to a pending read, replying once every counter is in.
+        let reads = &mut self.seq_kv().reads;
+        let Some(read) = reads.get_mut(&read_id) else {
+            //An earlier part failed and the read started over.
+            return;
+        };
+        match value {
+            Ok(value) => read.sum += value.as_u64().unwrap_or(0),
+            Err(ServiceError::KeyDoesNotExist) => {}
+            Err(err) => {
+                let read = reads.remove(&read_id).expect("Read is pending");
+                return self.retry_read(read.client, read.msg_id, read.attempt, err, output);
+            }
+        }
+        read.remaining -= 1;
+        if read.remaining == 0 {
+            let read = reads.remove(&read_id).expect("Read is pending");
+            let body = CounterBody::ReadOk {
+                msg_id: self.current_msg_id,
+                in_reply_to: read.msg_id,
+                value: read.sum as usize,
+            };
+            self.reply(read.client, body, output);
+        }
+    }
+}
+
 #[derive(Serialize, Debug, Deserialize, Clone)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
@@ -96,6 +391,12 @@ pub enum CounterBody {
         msg_id: usize,
         node_counter_map: HashMap<String, usize>,
     },
+    //Sent in seq-kv mode when a request gives up on seq-kv
+    Error {
+        in_reply_to: usize,
+        code: u64,
+        text: String,
+    },
 }
 
 impl Reply<CounterNode> for CounterBody {
@@ -1,14 +1,113 @@
-use super::{Event, Node, Reply};
+use super::{spawn_ticker, Event, Node, Reply};
+use crate::service::kv::{self, KvClient, KvNode};
+use crate::service::ServiceError;
 use serde::{self, Deserialize, Serialize};
-use std::collections::HashMap;
-use std::io;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::io::Write;
 use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 
 pub struct KafkaNode {
     pub node_id: String,
     pub current_message_id: usize,
     pub committed_offsets: HashMap<String, usize>,
+    //Each key's log, in local mode
     pub messages: HashMap<String, Vec<usize>>,
+    //Set when logs and committed offsets are kept in lin-kv, so any node can serve any key
+    pub lin_kv: Option<LinKvLog>,
+}
+
+pub struct LinKvLog {
+    pub kv: KvClient<KafkaNode>,
+    //Requests waiting on lin-kv for several keys, by an id of their own
+    gathers: HashMap<u64, Gather>,
+    next_gather: u64,
+    //The next free offset of each key, as far as this node has seen
+    next_offsets: HashMap<String, usize>,
+    //Where this node's last poll of each key stopped at a missing record, and since when
+    missing: HashMap<String, (usize, Instant)>,
+}
+
+impl LinKvLog {
+    pub fn new(node_id: &str) -> Self {
+        LinKvLog {
+            kv: KvClient::lin(node_id),
+            gathers: HashMap::new(),
+            next_gather: 0,
+            next_offsets: HashMap::new(),
+            missing: HashMap::new(),
+        }
+    }
+
+    pub fn from_env(node_id: &str) -> Option<Self> {
+        LinKvLog::from_vars(node_id, |name| env::var(name).ok())
+    }
+
+    pub fn from_vars(node_id: &str, var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        //! Picked with EVENT_HORIZON_KAFKA: `local` (the default) or `lin-kv`.
+        match var("EVENT_HORIZON_KAFKA").as_deref() {
+            Some("local") | None => None,
+            Some("lin-kv") => Some(LinKvLog::new(node_id)),
+            Some(other) => panic!("Unknown EVENT_HORIZON_KAFKA: {}", other),
+        }
+    }
+}
+
+struct Gather {
+    client: String,
+    in_reply_to: usize,
+    //Replies from lin-kv still to come
+    remaining: usize,
+    gathered: Gathered,
+}
+
+//What a request has collected so far, one entry per key
+enum Gathered {
+    //Each key's records read so far by offset, None where the record is missing
+    Poll(HashMap<String, BTreeMap<usize, Option<Value>>>),
+    Commit,
+    List(HashMap<String, usize>),
+}
+
+//A send in lin-kv mode, kept whole so it can be tried again
+struct PendingSend {
+    client: String,
+    msg_id: usize,
+    key: String,
+    msg: usize,
+    attempt: u32,
+}
+
+//A record still missing this long after polls first found its offset handed out is
+//taken to be abandoned by its send, which gave up or crashed, and polls skip it.
+const ABANDONED: Duration = Duration::from_secs(2);
+
+//Poll replies carry at most this many records per key
+const POLL_LIMIT: usize = 10;
+
+fn next_key(key: &str) -> String {
+    format!("next/{}", key)
+}
+
+fn record_key(key: &str, offset: usize) -> String {
+    format!("log/{}/{}", key, offset)
+}
+
+fn commit_key(key: &str) -> String {
+    format!("commit/{}", key)
+}
+
+fn records(messages: &[usize], offset: usize) -> Vec<(usize, usize)> {
+    //! Up to POLL_LIMIT (offset, message) pairs from a log, starting at `offset`.
+    messages
+        .iter()
+        .enumerate()
+        .skip(offset)
+        .take(POLL_LIMIT)
+        .map(|(offset, message)| (offset, *message))
+        .collect()
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -51,6 +150,12 @@ pub enum KafkaBody {
         msg_id: usize,
         offsets: HashMap<String, usize>,
     },
+    //Sent in lin-kv mode when a request gives up on lin-kv
+    Error {
+        in_reply_to: usize,
+        code: u64,
+        text: String,
+    },
 }
 
 impl Reply<KafkaNode> for KafkaBody {
@@ -68,19 +173,11 @@ impl Reply<KafkaNode> for KafkaBody {
             }
             KafkaBody::Poll { msg_id, offsets } => {
                 let mut poll_response = HashMap::new();
-                for (key, mut offset) in offsets.into_iter() {
+                for (key, offset) in offsets.into_iter() {
                     //If the key is already in the messages HashMap,
                     //add up to 10 items to the poll response.
                     if let Some(messages) = node_state.messages.get(&key) {
-                        let mut tuple_messages = Vec::new();
-                        while let Some(item) = messages.get(offset) {
-                            if tuple_messages.len() >= 10 {
-                                break;
-                            }
-                            tuple_messages.push((offset, *item));
-                            offset += 1;
-                        }
-                        poll_response.insert(key, tuple_messages);
+                        poll_response.insert(key, records(messages, offset));
                     }
                 }
                 Some(KafkaBody::PollOk {
@@ -122,28 +219,530 @@ impl Reply<KafkaNode> for KafkaBody {
     }
 }
 
-impl Node<KafkaBody> for KafkaNode {
-    fn node_init(
+impl KafkaNode {
+    pub fn with_log(
         node_metadata: crate::init::NodeMetadata,
+        lin_kv: Option<LinKvLog>,
         event_tx: Sender<Event<KafkaBody>>,
     ) -> Self {
-        let _ = event_tx;
+        //! A node keeping its logs in lin-kv if `lin_kv` is set, and locally if not.
+        if lin_kv.is_some() {
+            spawn_ticker(kv::TICK, event_tx);
+        }
         KafkaNode {
             node_id: node_metadata.node_id,
             current_message_id: 0,
             committed_offsets: HashMap::new(),
             messages: HashMap::new(),
+            lin_kv,
         }
     }
+}
+
+impl Node<KafkaBody> for KafkaNode {
+    fn node_init(
+        node_metadata: crate::init::NodeMetadata,
+        event_tx: Sender<Event<KafkaBody>>,
+    ) -> Self {
+        let lin_kv = LinKvLog::from_env(&node_metadata.node_id);
+        KafkaNode::with_log(node_metadata, lin_kv, event_tx)
+    }
 
-    fn handle_event(&mut self, event: Event<KafkaBody>, stdout_handle: &mut io::StdoutLock)
+    fn handle_event(&mut self, event: Event<KafkaBody>, output: &mut impl Write)
     where
         KafkaBody: Reply<Self>,
         Self: Sized,
     {
-        if let Event::Message(message) = event {
-            message.message_reply(stdout_handle, self);
-            self.current_message_id += 1;
+        match event {
+            Event::Message(message) if self.lin_kv.is_some() => {
+                let client = message.src;
+                match message.body {
+                    KafkaBody::Send { msg_id, key, msg } => {
+                        let send = PendingSend {
+                            client,
+                            msg_id,
+                            key,
+                            msg,
+                            attempt: 0,
+                        };
+                        self.send_record(send, output)
+                    }
+                    KafkaBody::Poll { msg_id, offsets } => {
+                        let gather_id = self.gather(
+                            client,
+                            msg_id,
+                            offsets.len(),
+                            Gathered::Poll(HashMap::new()),
+                            output,
+                        );
+                        for (key, offset) in offsets {
+                            self.poll_key(gather_id, key, offset, 0, output);
+                        }
+                    }
+                    KafkaBody::CommitOffsets { msg_id, offsets } => {
+                        let gather_id =
+                            self.gather(client, msg_id, offsets.len(), Gathered::Commit, output);
+                        for (key, offset) in offsets {
+                            self.commit_key(gather_id, key, offset, 0, output);
+                        }
+                    }
+                    KafkaBody::ListCommittedOffsets { msg_id, keys } => {
+                        let gather_id = self.gather(
+                            client,
+                            msg_id,
+                            keys.len(),
+                            Gathered::List(HashMap::new()),
+                            output,
+                        );
+                        for key in keys {
+                            self.list_key(gather_id, key, 0, output);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Message(message) => {
+                message.message_reply(output, self);
+                self.current_message_id += 1;
+            }
+            Event::ServiceReply(reply) => {
+                if self.lin_kv.is_some() {
+                    KvClient::dispatch(self, KafkaNode::kv, &reply, output);
+                }
+            }
+            Event::PropogateWrites => {
+                if self.lin_kv.is_some() {
+                    KvClient::expire(self, KafkaNode::kv, kv::REQUEST_TIMEOUT, output);
+                    KvClient::run_retries(self, KafkaNode::kv, output);
+                }
+            }
+        }
+    }
+}
+
+impl KvNode for KafkaNode {
+    type Body = KafkaBody;
+
+    fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    fn count_sent(&mut self) {
+        self.current_message_id += 1;
+    }
+
+    fn error_body(in_reply_to: usize, code: u64, text: String) -> KafkaBody {
+        KafkaBody::Error {
+            in_reply_to,
+            code,
+            text,
         }
     }
 }
+
+impl KafkaNode {
+    fn lin_kv(&mut self) -> &mut LinKvLog {
+        self.lin_kv.as_mut().expect("Only used in lin-kv mode")
+    }
+
+    fn kv(&mut self) -> &mut KvClient<KafkaNode> {
+        &mut self.lin_kv().kv
+    }
+
+    fn saw_next(&mut self, key: &str, next: usize) {
+        let known = self
+            .lin_kv()
+            .next_offsets
+            .entry(key.to_owned())
+            .or_default();
+        *known = (*known).max(next);
+    }
+
+    fn send_record(&mut self, send: PendingSend, output: &mut dyn Write) {
+        //! Claim the key's next offset by CAS on its counter, from the next free offset
+        //! this node knows of. If another node claimed it first, the CAS fails, and the
+        //! send re-reads the counter and tries again after a backoff.
+        let next = self.lin_kv().next_offsets.get(&send.key).copied();
+        let next = next.unwrap_or(0);
+        self.kv().cas(
+            output,
+            next_key(&send.key),
+            next,
+            next + 1,
+            true,
+            move |node: &mut KafkaNode, result, output| match result {
+                Ok(_) => {
+                    node.saw_next(&send.key, next + 1);
+                    node.store_record(send, next, output);
+                }
+                Err(err) if err.is_definite() => node.kv().read(
+                    output,
+                    next_key(&send.key),
+                    move |node: &mut KafkaNode, next, output| {
+                        if let Some(next) = next.ok().as_ref().and_then(Value::as_u64) {
+                            node.saw_next(&send.key, next as usize);
+                        }
+                        node.retry_send(send, err, output);
+                    },
+                ),
+                //The offset may have been claimed. If so, polls skip it once abandoned.
+                Err(err) => node.fail(send.client, send.msg_id, "send", &err, output),
+            },
+        );
+    }
+
+    fn store_record(&mut self, send: PendingSend, offset: usize, output: &mut dyn Write) {
+        //! Store a record at the offset its send claimed. The CAS from the record to
+        //! itself creates the key, or applies again when a retry finds the record
+        //! already there, so it is safe to repeat. It fails only if a poll gave the
+        //! offset up as abandoned, and then the send claims another.
+        self.kv().cas(
+            output,
+            record_key(&send.key, offset),
+            send.msg,
+            send.msg,
+            true,
+            move |node: &mut KafkaNode, result, output| match result {
+                Ok(_) => {
+                    let body = KafkaBody::SendOk {
+                        offset,
+                        in_reply_to: send.msg_id,
+                        msg_id: node.current_message_id,
+                    };
+                    node.reply(send.client, body, output);
+                }
+                Err(ServiceError::PreconditionFailed) => {
+                    node.retry_send(send, ServiceError::PreconditionFailed, output)
+                }
+                Err(err) => match node.kv().backoff(send.attempt, &err, true) {
+                    Some(delay) => node.kv().after(delay, move |node, output| {
+                        let attempt = send.attempt + 1;
+                        node.store_record(PendingSend { attempt, ..send }, offset, output)
+                    }),
+                    None => node.fail(send.client, send.msg_id, "send", &err, output),
+                },
+            },
+        );
+    }
+
+    fn retry_send(&mut self, send: PendingSend, err: ServiceError, output: &mut dyn Write) {
+        //! Claim a new offset after a backoff, unless the send is out of attempts.
+        match self.kv().backoff(send.attempt, &err, false) {
+            Some(delay) => self.kv().after(delay, move |node, output| {
+                let attempt = send.attempt + 1;
+                node.send_record(PendingSend { attempt, ..send }, output)
+            }),
+            None => self.fail(send.client, send.msg_id, "send", &err, output),
+        }
+    }
+
+    fn gather(
+        &mut self,
+        client: String,
+        in_reply_to: usize,
+        keys: usize,
+        gathered: Gathered,
+        output: &mut dyn Write,
+    ) -> u64 {
+        //! Start a request that waits on lin-kv once for each of `keys` keys.
+        let lin_kv = self.lin_kv();
+        let gather_id = lin_kv.next_gather;
+        lin_kv.next_gather += 1;
+        lin_kv.gathers.insert(
+            gather_id,
+            Gather {
+                client,
+                in_reply_to,
+                remaining: keys,
+                gathered,
+            },
+        );
+        if keys == 0 {
+            self.gathered(gather_id, 0, output, |_| {});
+        }
+        gather_id
+    }
+
+    fn gathered(
+        &mut self,
+        gather_id: u64,
+        more: usize,
+        output: &mut dyn Write,
+        add: impl FnOnce(&mut Gathered),
+    ) {
+        //! Add one reply from lin-kv to a request, which then waits on `more` replies
+        //! it led to, and reply once none are left. Does nothing if the request has
+        //! already failed.
+        let gathers = &mut self.lin_kv().gathers;
+        let Some(gather) = gathers.get_mut(&gather_id) else {
+            return;
+        };
+        add(&mut gather.gathered);
+        gather.remaining = (gather.remaining + more).saturating_sub(1);
+        if gather.remaining > 0 {
+            return;
+        }
+        let gather = gathers.remove(&gather_id).expect("Request is pending");
+        let (msg_id, in_reply_to) = (self.current_message_id, gather.in_reply_to);
+        let body = match gather.gathered {
+            Gathered::Poll(logs) => KafkaBody::PollOk {
+                msgs: self.polled(logs, output),
+                in_reply_to,
+                msg_id,
+            },
+            Gathered::Commit => KafkaBody::CommitOffsetsOk {
+                msg_id,
+                in_reply_to,
+            },
+            Gathered::List(offsets) => KafkaBody::ListCommittedOffsetsOk {
+                in_reply_to,
+                msg_id,
+                offsets,
+            },
+        };
+        self.reply(gather.client, body, output);
+    }
+
+    fn retry_key(
+        &mut self,
+        gather_id: u64,
+        attempt: u32,
+        err: ServiceError,
+        output: &mut dyn Write,
+        retry: impl FnOnce(&mut KafkaNode, u32, &mut dyn Write) + 'static,
+    ) {
+        //! Run `retry` with the next attempt after a backoff, or fail the whole request
+        //! once it is out of attempts. Reads and raising a committed offset are safe
+        //! to repeat.
+        match self.kv().backoff(attempt, &err, true) {
+            Some(delay) => self
+                .kv()
+                .after(delay, move |node, output| retry(node, attempt + 1, output)),
+            None => {
+                let Some(gather) = self.lin_kv().gathers.remove(&gather_id) else {
+                    return;
+                };
+                let action = match gather.gathered {
+                    Gathered::Poll(_) => "poll",
+                    Gathered::Commit => "commit_offsets",
+                    Gathered::List(_) => "list_committed_offsets",
+                };
+                self.fail(gather.client, gather.in_reply_to, action, &err, output);
+            }
+        }
+    }
+
+    fn polled(
+        &mut self,
+        logs: HashMap<String, BTreeMap<usize, Option<Value>>>,
+        output: &mut dyn Write,
+    ) -> HashMap<String, Vec<(usize, usize)>> {
+        //! Each key's records from the offset polled, up to the first that is missing,
+        //! since its send may still store it. Offsets given up as abandoned are skipped.
+        let mut msgs = HashMap::new();
+        for (key, records) in logs {
+            let mut polled = Vec::new();
+            for (offset, record) in records {
+                match record {
+                    Some(Value::Null) => {}
+                    Some(msg) => polled.push((offset, msg.as_u64().unwrap_or(0) as usize)),
+                    None => {
+                        self.missing_record(&key, offset, output);
+                        break;
+                    }
+                }
+            }
+            msgs.insert(key, polled);
+        }
+        msgs
+    }
+
+    fn missing_record(&mut self, key: &str, offset: usize, output: &mut dyn Write) {
+        //! Note that a poll stopped at a missing record, and once it has been missing
+        //! for ABANDONED, give its offset up by creating the key with a null record. A
+        //! send still holding the offset then fails to store there and claims another.
+        let lin_kv = self.lin_kv();
+        match lin_kv.missing.get(key) {
+            Some((missing, since)) if *missing == offset => {
+                if since.elapsed() < ABANDONED {
+                    return;
+                }
+            }
+            _ => {
+                lin_kv
+                    .missing
+                    .insert(key.to_owned(), (offset, Instant::now()));
+                return;
+            }
+        }
+        lin_kv.missing.remove(key);
+        //Fails, harmlessly, if the record was stored in the meantime.
+        self.kv().cas(
+            output,
+            record_key(key, offset),
+            Value::Null,
+            Value::Null,
+            true,
+            |_: &mut KafkaNode, _, _| {},
+        );
+    }
+
+    fn poll_key(
+        &mut self,
+        gather_id: u64,
+        key: String,
+        offset: usize,
+        attempt: u32,
+        output: &mut dyn Write,
+    ) {
+        //! Read how far the key's log goes, then each record from `offset` on, up to
+        //! POLL_LIMIT of them.
+        self.kv().read(
+            output,
+            next_key(&key),
+            move |node: &mut KafkaNode, next, output| match next {
+                Ok(next) => {
+                    let next = next.as_u64().unwrap_or(0) as usize;
+                    node.saw_next(&key, next);
+                    let offsets = offset..next.min(offset.saturating_add(POLL_LIMIT));
+                    node.gathered(gather_id, offsets.len(), output, |gathered| {
+                        if let Gathered::Poll(logs) = gathered {
+                            logs.insert(key.clone(), BTreeMap::new());
+                        }
+                    });
+                    for offset in offsets {
+                        node.poll_record(gather_id, key.clone(), offset, 0, output);
+                    }
+                }
+                Err(ServiceError::KeyDoesNotExist) => node.gathered(gather_id, 0, output, |_| {}),
+                Err(err) => node.retry_key(
+                    gather_id,
+                    attempt,
+                    err,
+                    output,
+                    move |node, attempt, output| {
+                        node.poll_key(gather_id, key, offset, attempt, output)
+                    },
+                ),
+            },
+        );
+    }
+
+    fn poll_record(
+        &mut self,
+        gather_id: u64,
+        key: String,
+        offset: usize,
+        attempt: u32,
+        output: &mut dyn Write,
+    ) {
+        self.kv().read(
+            output,
+            record_key(&key, offset),
+            move |node: &mut KafkaNode, record, output| {
+                let record = match record {
+                    Ok(record) => Some(record),
+                    Err(ServiceError::KeyDoesNotExist) => None,
+                    Err(err) => {
+                        return node.retry_key(
+                            gather_id,
+                            attempt,
+                            err,
+                            output,
+                            move |node, attempt, output| {
+                                node.poll_record(gather_id, key, offset, attempt, output)
+                            },
+                        )
+                    }
+                };
+                node.gathered(gather_id, 0, output, |gathered| {
+                    if let Gathered::Poll(logs) = gathered {
+                        logs.entry(key).or_default().insert(offset, record);
+                    }
+                });
+            },
+        );
+    }
+
+    fn commit_key(
+        &mut self,
+        gather_id: u64,
+        key: String,
+        offset: usize,
+        attempt: u32,
+        output: &mut dyn Write,
+    ) {
+        //! Raise a key's committed offset to `offset` by CAS, leaving it alone if it is
+        //! already there or past it, so committed offsets never go backwards. Raising
+        //! to a maximum is idempotent, so every failure is safe to retry.
+        self.kv().read(
+            output,
+            commit_key(&key),
+            move |node: &mut KafkaNode, committed, output| {
+                let committed = match committed {
+                    Ok(committed) => committed.as_u64().map(|committed| committed as usize),
+                    Err(ServiceError::KeyDoesNotExist) => None,
+                    Err(err) => {
+                        return node.retry_key(
+                            gather_id,
+                            attempt,
+                            err,
+                            output,
+                            move |node, attempt, output| {
+                                node.commit_key(gather_id, key, offset, attempt, output)
+                            },
+                        )
+                    }
+                };
+                if committed.is_some_and(|committed| committed >= offset) {
+                    return node.gathered(gather_id, 0, output, |_| {});
+                }
+                //A missing key is created whatever `from` is.
+                node.kv().cas(
+                    output,
+                    commit_key(&key),
+                    committed.unwrap_or(offset),
+                    offset,
+                    true,
+                    move |node: &mut KafkaNode, result, output| match result {
+                        Ok(_) => node.gathered(gather_id, 0, output, |_| {}),
+                        Err(err) => node.retry_key(
+                            gather_id,
+                            attempt,
+                            err,
+                            output,
+                            move |node, attempt, output| {
+                                node.commit_key(gather_id, key, offset, attempt, output)
+                            },
+                        ),
+                    },
+                );
+            },
+        );
+    }
+
+    fn list_key(&mut self, gather_id: u64, key: String, attempt: u32, output: &mut dyn Write) {
+        self.kv().read(
+            output,
+            commit_key(&key),
+            move |node: &mut KafkaNode, committed, output| match committed {
+                Ok(committed) => {
+                    let committed = committed.as_u64().unwrap_or(0) as usize;
+                    node.gathered(gather_id, 0, output, |gathered| {
+                        if let Gathered::List(offsets) = gathered {
+                            offsets.insert(key, committed);
+                        }
+                    });
+                }
+                Err(ServiceError::KeyDoesNotExist) => node.gathered(gather_id, 0, output, |_| {}),
+                Err(err) => node.retry_key(
+                    gather_id,
+                    attempt,
+                    err,
+                    output,
+                    move |node, attempt, output| node.list_key(gather_id, key, attempt, output),
+                ),
+            },
+        );
+    }
+}
@@ -1,18 +1,33 @@
+use crate::clock;
+use crate::node::spawn_ticker;
+use crate::service::tso::TsoClient;
 use crate::{Event, MaelstromMessage, Node, NodeMetadata, Reply};
 use serde::{self, Deserialize, Serialize};
 use std::collections::HashMap;
-use std::io;
+use std::io::Write;
 use std::sync::mpsc::Sender;
-use std::thread;
+use std::time::Duration;
 
 pub struct KVStoreNode {
     pub current_msg_id: usize,
     pub node_id: String,
     pub other_node_ids: Vec<String>,
     pub kv_store: HashMap<usize, usize>,
-    pub unpropogated_writes: HashMap<usize, usize>,
+    //The (timestamp, node_id) of the write currently held for each key.
+    //Conflicting writes are resolved last-writer-wins on this stamp.
+    pub write_stamps: HashMap<usize, (u64, String)>,
+    //Maps key to (value, timestamp) for writes not yet sent to other nodes
+    pub unpropogated_writes: HashMap<usize, (usize, u64)>,
+    //When set, writes are stamped with timestamps from lin-tso instead of the local
+    //clock, so last-writer-wins follows real-time order whatever the clocks say.
+    pub tso: Option<TsoClient<KVStoreNode>>,
+    //The lin-tso timestamp for the txn being applied
+    pub txn_timestamp: Option<u64>,
 }
 
+//How long to wait for lin-tso before asking again
+const TSO_TIMEOUT: Duration = Duration::from_secs(1);
+
 impl Node<KVStoreBody> for KVStoreNode {
     fn node_init(node_metadata: NodeMetadata, event_tx: Sender<Event<KVStoreBody>>) -> Self {
         let other_node_ids: Vec<_> = node_metadata
@@ -21,34 +36,43 @@ impl Node<KVStoreBody> for KVStoreNode {
             .filter(|node_id| node_id != &node_metadata.node_id)
             .collect();
 
-        thread::spawn(move || loop {
-            thread::sleep(std::time::Duration::from_millis(10));
-            event_tx
-                .send(Event::PropogateWrites)
-                .expect("Write Propogrator Thread failed to transmit");
-        });
+        spawn_ticker(Duration::from_millis(10), event_tx);
 
         KVStoreNode {
             current_msg_id: 0,
+            tso: TsoClient::from_env(&node_metadata.node_id),
             node_id: node_metadata.node_id,
             other_node_ids,
             kv_store: HashMap::new(),
+            write_stamps: HashMap::new(),
             unpropogated_writes: HashMap::new(),
+            txn_timestamp: None,
         }
     }
 
-    fn handle_event(&mut self, event: Event<KVStoreBody>, stdout_handle: &mut io::StdoutLock)
+    fn handle_event(&mut self, event: Event<KVStoreBody>, output: &mut impl Write)
     where
         KVStoreBody: Reply<Self>,
         Self: Sized,
     {
         match event {
+            Event::Message(message) if self.tso.is_some() && writes(&message.body) => {
+                self.apply_stamped(message, output);
+            }
             Event::Message(message) => {
-                message.message_reply(stdout_handle, self);
+                message.message_reply(output, self);
                 self.current_msg_id += 1;
             }
+            Event::ServiceReply(reply) => {
+                if self.tso.is_some() {
+                    TsoClient::dispatch(self, KVStoreNode::tso, &reply, output);
+                }
+            }
             Event::PropogateWrites => {
-                let unpropogated_writes: HashMap<usize, usize> =
+                if self.tso.is_some() {
+                    TsoClient::expire(self, KVStoreNode::tso, TSO_TIMEOUT, output);
+                }
+                let unpropogated_writes: HashMap<usize, (usize, u64)> =
                     self.unpropogated_writes.drain().collect();
                 if !unpropogated_writes.is_empty() {
                     for other_node in self.other_node_ids.iter() {
@@ -60,7 +84,7 @@ impl Node<KVStoreBody> for KVStoreNode {
                                 write_ops: unpropogated_writes.clone(),
                             },
                         };
-                        kv_writes.send(stdout_handle);
+                        kv_writes.send(output);
                     }
                 }
             }
@@ -68,6 +92,38 @@ impl Node<KVStoreBody> for KVStoreNode {
     }
 }
 
+impl KVStoreNode {
+    fn tso(&mut self) -> &mut TsoClient<KVStoreNode> {
+        self.tso.as_mut().expect("Only used in lin-tso mode")
+    }
+
+    fn apply_stamped(&mut self, message: MaelstromMessage<KVStoreBody>, output: &mut dyn Write) {
+        //! Apply a txn once lin-tso has given it a timestamp, asking again if the
+        //! request times out.
+        self.tso()
+            .timestamp(
+                output,
+                move |node: &mut KVStoreNode, timestamp, output| match timestamp {
+                    Ok(timestamp) => {
+                        node.txn_timestamp = Some(timestamp);
+                        message.message_reply(output, node);
+                        node.txn_timestamp = None;
+                        node.current_msg_id += 1;
+                    }
+                    Err(_) => node.apply_stamped(message, output),
+                },
+            );
+    }
+}
+
+fn writes(body: &KVStoreBody) -> bool {
+    //! Whether a message is a txn that writes. Reads need no timestamp.
+    match body {
+        KVStoreBody::Txn { txn, .. } => txn.iter().any(|(operation, _, _)| operation == "w"),
+        _ => false,
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum KVStoreBody {
@@ -82,35 +138,65 @@ pub enum KVStoreBody {
     },
     //WritePropogator is a node-to-node
     //message passing k-v writes. The write_ops
-    //HashMap contains the k-v pairs to write.
+    //HashMap maps each key to the (value, timestamp) to write.
     WritePropogater {
         msg_id: usize,
-        write_ops: HashMap<usize, usize>,
+        #[serde(deserialize_with = "integer_keys")]
+        write_ops: HashMap<usize, (usize, u64)>,
     },
 }
 
+fn integer_keys<'de, D>(deserializer: D) -> Result<HashMap<usize, (usize, u64)>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    //! JSON object keys are strings. Internally tagged enums buffer the body before
+    //! deserializing it, which loses serde_json's string-to-integer key conversion,
+    //! so the keys are parsed here instead.
+    let write_ops: HashMap<String, (usize, u64)> = HashMap::deserialize(deserializer)?;
+    write_ops
+        .into_iter()
+        .map(|(key, write)| {
+            key.parse()
+                .map(|key| (key, write))
+                .map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
+
 fn key_value_crud(
     rw_op: (String, usize, Option<usize>),
-    kv_store: &mut HashMap<usize, usize>,
-    unpropogated_writes: &mut HashMap<usize, usize>,
+    node_state: &mut KVStoreNode,
 ) -> (String, usize, Option<usize>) {
     //! Given a tuple of (operation, key, Option<value>), perform
-    //! the indicated operation on the passed in HashMap. If operation is 'r',
+    //! the indicated operation on the Node's store. If operation is 'r',
     //! return the value associated with the key if it exists.
     //! If the operation is 'w', write the value to the associated key.
 
     let (operation, key, value) = rw_op;
 
     if operation == "r" {
-        let read_value = kv_store.get(&key);
+        let read_value = node_state.kv_store.get(&key);
         (operation, key, read_value.copied())
     } else if operation == "w" {
         let (key, value) = (
             key,
             value.expect("Recieved a Write Op without a value to write."),
         );
-        kv_store.insert(key, value);
-        unpropogated_writes.insert(key, value);
+        //Stamp the write with this node's clock, or lin-tso's timestamp, but always after
+        //the write it replaces, so a local write wins locally even if the clock is behind.
+        let now = node_state.txn_timestamp.unwrap_or_else(clock::now_nanos);
+        let timestamp = match node_state.write_stamps.get(&key) {
+            Some((current, _)) => now.max(current + 1),
+            None => now,
+        };
+        node_state.kv_store.insert(key, value);
+        node_state
+            .write_stamps
+            .insert(key, (timestamp, node_state.node_id.clone()));
+        node_state
+            .unpropogated_writes
+            .insert(key, (value, timestamp));
         (operation, key, Some(value))
     } else {
         panic!(
@@ -121,20 +207,14 @@ fn key_value_crud(
 }
 
 impl Reply<KVStoreNode> for KVStoreBody {
-    fn into_reply(self, node_state: &mut KVStoreNode, _: &str) -> Option<Self> {
+    fn into_reply(self, node_state: &mut KVStoreNode, src: &str) -> Option<Self> {
         //!Consumes self, returns a Some(reply_body)
         //! if one exists.
         match self {
             KVStoreBody::Txn { msg_id, txn } => {
                 let txn: Vec<_> = txn
                     .into_iter()
-                    .map(|read_write_op| {
-                        key_value_crud(
-                            read_write_op,
-                            &mut node_state.kv_store,
-                            &mut node_state.unpropogated_writes,
-                        )
-                    })
+                    .map(|read_write_op| key_value_crud(read_write_op, node_state))
                     .collect();
                 Some(KVStoreBody::TxnOk {
                     msg_id: node_state.current_msg_id,
@@ -142,11 +222,20 @@ impl Reply<KVStoreNode> for KVStoreBody {
                     txn,
                 })
             }
-            //Currently, if a WritePropogator is recieved, simply add those values
-            //to the HashMap. No Ack at present.
+            //If a WritePropogator is recieved, keep each write only if it is newer
+            //than the one already held (last-writer-wins). No Ack at present.
             KVStoreBody::WritePropogater { write_ops, .. } => {
-                for (key, value) in write_ops.into_iter() {
+                for (key, (value, timestamp)) in write_ops.into_iter() {
+                    let stamp = (timestamp, src.to_owned());
+                    if node_state
+                        .write_stamps
+                        .get(&key)
+                        .is_some_and(|current| current >= &stamp)
+                    {
+                        continue;
+                    }
                     node_state.kv_store.insert(key, value);
+                    node_state.write_stamps.insert(key, stamp);
                 }
                 None
             }
@@ -0,0 +1,227 @@
+use crate::clock;
+use serde_json::{json, Map, Value};
+
+//Shorthand commands for poking a single node by hand. A command becomes a message
+//body, sent either from the client or, for node-to-node messages, from a simulated peer.
+
+pub const HELP: &str = "Client requests:
+  echo TEXT                    generate
+  topology [N:N,N ...]         (default: every node neighbors every other)
+  broadcast JSON               read
+  read_clock                   read_stable
+  add N
+  send KEY MSG                 poll KEY:OFFSET ...
+  commit KEY:OFFSET ...        list KEY ...
+  txn r KEY, w KEY VALUE, ...
+Simulated peer messages (sent from another node id):
+  gossip ID=JSON ...           gossip_ok ID ...
+  update_counters NODE=N ...   write_propogater KEY=VALUE[@TIMESTAMP] ...
+Other:
+  from SRC COMMAND             send any command from SRC
+  raw {JSON BODY}              send a body as-is (msg_id is filled in if missing)
+  help                         quit";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    //Send a body from `src` to the node. None means the REPL's client id,
+    //or for peer messages, the first other node.
+    Send { src: Option<String>, body: Value },
+    Help,
+    Quit,
+}
+
+fn number(word: &str) -> Result<u64, String> {
+    word.parse()
+        .map_err(|_| format!("Expected a number, got '{}'", word))
+}
+
+fn pairs<'a>(words: &[&'a str], separator: char) -> Result<Vec<(&'a str, &'a str)>, String> {
+    //! Split words such as `k1:3` into (key, value) pairs.
+    words
+        .iter()
+        .map(|word| {
+            word.split_once(separator)
+                .ok_or_else(|| format!("Expected KEY{}VALUE, got '{}'", separator, word))
+        })
+        .collect()
+}
+
+fn numbers(words: &[&str]) -> Result<Vec<u64>, String> {
+    words.iter().map(|word| number(word)).collect()
+}
+
+fn txn(ops: &str) -> Result<Value, String> {
+    //! Parse `r 1, w 2 5` into `[["r",1,null],["w",2,5]]`.
+    ops.split(',')
+        .map(|op| {
+            let words: Vec<&str> = op.split_whitespace().collect();
+            match words.as_slice() {
+                ["r", key] => Ok(json!(["r", number(key)?, null])),
+                ["w", key, value] => Ok(json!(["w", number(key)?, number(value)?])),
+                _ => Err(format!(
+                    "Expected 'r KEY' or 'w KEY VALUE', got '{}'",
+                    op.trim()
+                )),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Value::Array)
+}
+
+fn topology(node_ids: &[String], words: &[&str]) -> Result<Value, String> {
+    let mut topology = Map::new();
+    if words.is_empty() {
+        for node_id in node_ids {
+            let neighbors: Vec<&String> =
+                node_ids.iter().filter(|other| *other != node_id).collect();
+            topology.insert(node_id.clone(), json!(neighbors));
+        }
+    } else {
+        for (node_id, neighbors) in pairs(words, ':')? {
+            let neighbors: Vec<&str> = neighbors
+                .split(',')
+                .filter(|neighbor| !neighbor.is_empty())
+                .collect();
+            topology.insert(node_id.to_owned(), json!(neighbors));
+        }
+    }
+    Ok(Value::Object(topology))
+}
+
+pub fn parse(line: &str, node_ids: &[String]) -> Result<Command, String> {
+    //! Parse one line of shorthand. `node_ids` is the cluster the node was
+    //! initialised with, used to build default topologies.
+    let line = line.trim();
+    let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    let words: Vec<&str> = rest.split_whitespace().collect();
+    let body = match (command, words.as_slice()) {
+        ("help" | "?", _) => return Ok(Command::Help),
+        ("quit" | "exit", _) => return Ok(Command::Quit),
+        ("from", [src, ..]) => {
+            let command = rest[src.len()..].trim();
+            return match parse(command, node_ids)? {
+                Command::Send { body, .. } => Ok(Command::Send {
+                    src: Some((*src).to_owned()),
+                    body,
+                }),
+                _ => Err(format!("'{}' is not a message", command)),
+            };
+        }
+        ("raw", _) => {
+            let body: Value = serde_json::from_str(rest).map_err(|err| err.to_string())?;
+            if !body.is_object() {
+                return Err("A raw body must be a JSON object".to_owned());
+            }
+            body
+        }
+        ("echo", _) => json!({"type": "echo", "echo": rest}),
+        ("generate", []) => json!({"type": "generate"}),
+        ("topology", words) => json!({"type": "topology", "topology": topology(node_ids, words)?}),
+        ("broadcast", words) if !words.is_empty() => {
+            let message: Value = serde_json::from_str(rest).map_err(|err| err.to_string())?;
+            json!({"type": "broadcast", "message": message})
+        }
+        ("read", []) => json!({"type": "read"}),
+        ("read_clock", []) => json!({"type": "read_clock"}),
+        ("read_stable", []) => json!({"type": "read_stable"}),
+        ("add", [delta]) => json!({"type": "add", "delta": number(delta)?}),
+        ("send", [key, msg]) => json!({"type": "send", "key": key, "msg": number(msg)?}),
+        ("poll", offsets) | ("commit", offsets) if !offsets.is_empty() => {
+            let offsets = pairs(offsets, ':')?
+                .into_iter()
+                .map(|(key, offset)| Ok((key.to_owned(), json!(number(offset)?))))
+                .collect::<Result<Map<_, _>, String>>()?;
+            let message_type = if command == "poll" {
+                "poll"
+            } else {
+                "commit_offsets"
+            };
+            json!({"type": message_type, "offsets": offsets})
+        }
+        ("list", keys) if !keys.is_empty() => {
+            json!({"type": "list_committed_offsets", "keys": keys})
+        }
+        ("txn", _) if !rest.is_empty() => json!({"type": "txn", "txn": txn(rest)?}),
+        ("gossip", messages) => {
+            //Listed by id, as the node pairs payloads with ids in id order.
+            let mut messages = pairs(messages, '=')?
+                .into_iter()
+                .map(|(id, payload)| {
+                    let payload: Value =
+                        serde_json::from_str(payload).map_err(|err| err.to_string())?;
+                    Ok((number(id)?, payload))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            messages.sort_by_key(|(id, _)| *id);
+            messages.dedup_by_key(|(id, _)| *id);
+            let (ids, payloads): (Vec<_>, Vec<_>) = messages.into_iter().unzip();
+            json!({"type": "gossip", "message": ids, "payloads": payloads})
+        }
+        ("gossip_ok", messages) => json!({
+            "type": "gossip_ok",
+            "in_reply_to": 0,
+            "ack_message": numbers(messages)?,
+        }),
+        ("update_counters", counters) => {
+            let counters = pairs(counters, '=')?
+                .into_iter()
+                .map(|(node_id, value)| Ok((node_id.to_owned(), json!(number(value)?))))
+                .collect::<Result<Map<_, _>, String>>()?;
+            json!({"type": "update_counters", "node_counter_map": counters})
+        }
+        ("write_propogater", writes) if !writes.is_empty() => {
+            let writes = pairs(writes, '=')?
+                .into_iter()
+                .map(|(key, write)| {
+                    let (value, timestamp) = match write.split_once('@') {
+                        Some((value, timestamp)) => (number(value)?, number(timestamp)?),
+                        //Stamped now, so it wins over writes the node already holds.
+                        None => (number(write)?, clock::now_nanos()),
+                    };
+                    number(key)?;
+                    Ok((key.to_owned(), json!([value, timestamp])))
+                })
+                .collect::<Result<Map<_, _>, String>>()?;
+            json!({"type": "write_propogater", "write_ops": writes})
+        }
+        _ => {
+            return Err(format!(
+                "Unknown or malformed command '{}'. Type 'help'.",
+                line
+            ))
+        }
+    };
+    Ok(Command::Send { src: None, body })
+}
+
+pub fn is_peer_message(body: &Value) -> bool {
+    //! Whether a body is normally sent between nodes rather than by a client.
+    matches!(
+        body.get("type").and_then(Value::as_str),
+        Some("gossip" | "gossip_ok" | "update_counters" | "write_propogater")
+    )
+}
+
+pub fn pretty(message: &Value, client_id: &str) -> String {
+    //! Summarise a message the node wrote on one line: who it is to, its type,
+    //! what it replies to, and the rest of the body as `field=value` pairs.
+    let body = &message["body"];
+    let message_type = body["type"].as_str().unwrap_or("?");
+    let dest = message["dest"].as_str().unwrap_or("?");
+    let mut summary = match body.get("in_reply_to").and_then(Value::as_u64) {
+        Some(in_reply_to) if dest == client_id => {
+            format!("<- {} (reply to {})", message_type, in_reply_to)
+        }
+        Some(in_reply_to) => format!("-> {} {} (reply to {})", dest, message_type, in_reply_to),
+        None => format!("-> {} {}", dest, message_type),
+    };
+    if let Some(fields) = body.as_object() {
+        for (field, value) in fields {
+            if !matches!(field.as_str(), "type" | "msg_id" | "in_reply_to") {
+                summary.push_str(&format!(" {}={}", field, value));
+            }
+        }
+    }
+    summary
+}
@@ -0,0 +1,657 @@
+use crate::history::{self, History, OpType, Operation, Process};
+use crate::node::MaelstromMessage;
+use crate::rng::Rng;
+use crate::service;
+use crate::service::local::{LocalConfig, LocalServices};
+use crate::trace::TraceEntry;
+use serde_json::{json, Value};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub mod nemesis;
+pub mod network;
+pub mod workload;
+
+use network::{Grudge, LinkConfig, Network};
+
+pub struct Recorder {
+    //Shared by clients and the nemesis so every event lands in a single history.
+    start: Instant,
+    history: Mutex<History>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder {
+            start: Instant::now(),
+            history: Mutex::new(History::default()),
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+
+    pub fn record(&self, operation: Operation) {
+        self.history
+            .lock()
+            .expect("History lock poisoned")
+            .ops
+            .push(operation);
+    }
+
+    pub fn record_nemesis(&self, f: &str, value: Value) {
+        //! Log a fault event into the history, as Maelstrom does for its nemesis.
+        self.record(Operation {
+            op_type: OpType::Info,
+            process: Process::Named("nemesis".to_owned()),
+            time: self.now(),
+            f: f.to_owned(),
+            value,
+            error: None,
+            index: None,
+            msg_id: None,
+        });
+    }
+
+    pub fn history(&self) -> History {
+        let mut history = self.history.lock().expect("History lock poisoned").clone();
+        history.reindex();
+        history
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Recorder::new()
+    }
+}
+
+pub enum RouterEvent {
+    //A line written to stdout by a node
+    FromNode(String),
+    FromClient(MaelstromMessage<Value>),
+    AddClient(String, Sender<MaelstromMessage<Value>>),
+    SetGrudge(Grudge),
+    SetDefaultLink(LinkConfig),
+    //Start delivering messages to a node through its stdin
+    Attach(String, ChildStdin),
+    //Stop delivering to a node, dropping anything sent to it
+    Detach(String),
+    Shutdown,
+}
+
+struct Delivery {
+    at: Instant,
+    sequence: usize,
+    trace_index: usize,
+}
+
+impl PartialEq for Delivery {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.sequence) == (other.at, other.sequence)
+    }
+}
+
+impl Eq for Delivery {}
+
+impl PartialOrd for Delivery {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delivery {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.at, self.sequence).cmp(&(other.at, other.sequence))
+    }
+}
+
+struct Router {
+    network: Network,
+    rng: Rng,
+    recorder: Arc<Recorder>,
+    //Each node's stdin is fed by its own writer thread, so a paused node
+    //with a full pipe cannot stall the router.
+    nodes: HashMap<String, Sender<Vec<u8>>>,
+    clients: HashMap<String, Sender<MaelstromMessage<Value>>>,
+    //Stand-ins for seq-kv, lin-kv and lww-kv, reached through the network like a node
+    services: LocalServices,
+    queue: BinaryHeap<Reverse<Delivery>>,
+    sequence: usize,
+    trace: Vec<TraceEntry>,
+}
+
+impl Router {
+    fn run(mut self, events: Receiver<RouterEvent>) -> Vec<TraceEntry> {
+        //! Route messages between nodes and clients until shut down, returning
+        //! the trace of every message sent. Messages between nodes pass through
+        //! the network model. Clients are never partitioned.
+        loop {
+            let timeout = self
+                .queue
+                .peek()
+                .map_or(Duration::from_millis(100), |next| {
+                    next.0.at.saturating_duration_since(Instant::now())
+                });
+            match events.recv_timeout(timeout) {
+                Ok(RouterEvent::FromNode(line)) => match serde_json::from_str(&line) {
+                    Ok(message) => self.route(message),
+                    Err(_) => eprintln!("Runner received invalid output from a node: {}", line),
+                },
+                Ok(RouterEvent::FromClient(message)) => self.route(message),
+                Ok(RouterEvent::AddClient(client_id, mailbox)) => {
+                    self.clients.insert(client_id, mailbox);
+                }
+                Ok(RouterEvent::SetGrudge(grudge)) => self.network.grudge = grudge,
+                Ok(RouterEvent::SetDefaultLink(link)) => self.network.default_link = link,
+                Ok(RouterEvent::Attach(node_id, mut stdin)) => {
+                    let (line_tx, line_rx) = channel::<Vec<u8>>();
+                    thread::spawn(move || {
+                        for line in line_rx {
+                            if stdin.write_all(&line).and_then(|_| stdin.flush()).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                    self.nodes.insert(node_id, line_tx);
+                }
+                Ok(RouterEvent::Detach(node_id)) => {
+                    self.nodes.remove(&node_id);
+                }
+                Ok(RouterEvent::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            self.deliver_due();
+        }
+        self.trace
+    }
+
+    fn route(&mut self, message: MaelstromMessage<Value>) {
+        self.route_after(message, Duration::ZERO);
+    }
+
+    fn route_after(&mut self, message: MaelstromMessage<Value>, extra: Duration) {
+        //! Route a message, adding `extra` on top of the network's delay.
+        let trace_index = self.trace.len();
+        self.trace.push(TraceEntry {
+            time: self.recorder.now(),
+            message,
+            received: None,
+        });
+        let message = &self.trace[trace_index].message;
+        if let Some(mailbox) = self.clients.get(&message.dest) {
+            //A client that has gone away simply misses the reply.
+            let _ = mailbox.send(message.clone());
+            self.trace[trace_index].received = Some(self.recorder.now());
+            return;
+        }
+        let delays = if self.clients.contains_key(&message.src) {
+            vec![Duration::ZERO]
+        } else {
+            self.network
+                .plan(&message.src, &message.dest, &mut self.rng)
+        };
+        for delay in delays {
+            self.sequence += 1;
+            self.queue.push(Reverse(Delivery {
+                at: Instant::now() + extra + delay,
+                sequence: self.sequence,
+                trace_index,
+            }));
+        }
+    }
+
+    fn deliver_due(&mut self) {
+        while self
+            .queue
+            .peek()
+            .is_some_and(|next| next.0.at <= Instant::now())
+        {
+            let Reverse(delivery) = self.queue.pop().expect("Queue was just peeked");
+            let entry = &self.trace[delivery.trace_index];
+            if self.services.hosts(&entry.message.dest) {
+                let request = entry.message.clone();
+                self.trace[delivery.trace_index].received = Some(self.recorder.now());
+                if let Some((latency, reply)) = self.services.handle(&request) {
+                    self.route_after(reply, latency);
+                }
+            } else if let Some(node) = self.nodes.get(&entry.message.dest) {
+                let mut line =
+                    serde_json::to_vec(&entry.message).expect("Unable to serialize message");
+                line.push(b'\n');
+                //A node that died mid-write is detached by whoever killed it.
+                if node.send(line).is_ok() {
+                    self.trace[delivery.trace_index].received = Some(self.recorder.now());
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    //Path to the event-horizon binary
+    pub bin: PathBuf,
+    //Value for EVENT_HORIZON_NODE
+    pub node_type: String,
+    pub node_count: usize,
+    //Extra environment variables for every node process
+    pub env: Vec<(String, String)>,
+    pub network: Network,
+    //Latency and consistency settings for the seq-kv, lin-kv and lww-kv stand-ins
+    pub services: LocalConfig,
+    pub seed: u64,
+    //Directory for node stderr logs
+    pub log_dir: Option<PathBuf>,
+    //Directory nodes keep state in across restarts, emptied when the cluster starts
+    pub state_dir: Option<PathBuf>,
+}
+
+pub struct Cluster {
+    pub config: ClusterConfig,
+    pub node_ids: Vec<String>,
+    pub recorder: Arc<Recorder>,
+    router_tx: Sender<RouterEvent>,
+    router: Option<JoinHandle<Vec<TraceEntry>>>,
+    children: Mutex<HashMap<String, Child>>,
+    next_client: Mutex<usize>,
+}
+
+impl Cluster {
+    pub fn start(config: ClusterConfig, recorder: Arc<Recorder>) -> io::Result<Self> {
+        let node_ids: Vec<String> = (0..config.node_count)
+            .map(|index| format!("n{}", index))
+            .collect();
+        if let Some(state_dir) = &config.state_dir {
+            match fs::remove_dir_all(state_dir) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => fs::create_dir_all(state_dir)?,
+            }
+        }
+        let (router_tx, router_rx) = channel();
+        let router = Router {
+            network: config.network.clone(),
+            rng: Rng::seeded(config.seed),
+            recorder: recorder.clone(),
+            nodes: HashMap::new(),
+            clients: HashMap::new(),
+            services: LocalServices::new(LocalConfig {
+                seed: config.seed,
+                ..config.services
+            }),
+            queue: BinaryHeap::new(),
+            sequence: 0,
+            trace: Vec::new(),
+        };
+        let router = thread::spawn(move || router.run(router_rx));
+        let cluster = Cluster {
+            config,
+            node_ids,
+            recorder,
+            router_tx,
+            router: Some(router),
+            children: Mutex::new(HashMap::new()),
+            next_client: Mutex::new(0),
+        };
+        for node_id in cluster.node_ids.iter() {
+            cluster.spawn_node(node_id)?;
+        }
+        Ok(cluster)
+    }
+
+    pub fn spawn_node(&self, node_id: &str) -> io::Result<()> {
+        //! Start a node process, perform the init handshake, and attach it to the router.
+        let stderr = match &self.config.log_dir {
+            Some(log_dir) => Stdio::from(
+                File::options()
+                    .create(true)
+                    .append(true)
+                    .open(log_dir.join(format!("{}.log", node_id)))?,
+            ),
+            None => Stdio::null(),
+        };
+        let mut command = Command::new(&self.config.bin);
+        if let Some(state_dir) = &self.config.state_dir {
+            command.env("EVENT_HORIZON_STATE_DIR", state_dir);
+        }
+        let mut child = command
+            .env("EVENT_HORIZON_NODE", &self.config.node_type)
+            .envs(self.config.env.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(stderr)
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("Child stdin was piped");
+        let mut stdout = BufReader::new(child.stdout.take().expect("Child stdout was piped"));
+
+        let init = json!({
+            "src": "c0",
+            "dest": node_id,
+            "body": {"type": "init", "msg_id": 0, "node_id": node_id, "node_ids": self.node_ids},
+        });
+        writeln!(stdin, "{}", init)?;
+        stdin.flush()?;
+        let mut init_ok = String::new();
+        stdout.read_line(&mut init_ok)?;
+        if !init_ok.contains("init_ok") {
+            return Err(io::Error::other(format!(
+                "Node {} did not reply init_ok: {}",
+                node_id, init_ok
+            )));
+        }
+
+        let router_tx = self.router_tx.clone();
+        thread::spawn(move || {
+            for line in stdout.lines().map_while(Result::ok) {
+                if router_tx.send(RouterEvent::FromNode(line)).is_err() {
+                    break;
+                }
+            }
+        });
+        self.send(RouterEvent::Attach(node_id.to_owned(), stdin));
+        self.children
+            .lock()
+            .expect("Children lock poisoned")
+            .insert(node_id.to_owned(), child);
+        Ok(())
+    }
+
+    pub fn send(&self, event: RouterEvent) {
+        //The router only stops once the cluster is shut down.
+        let _ = self.router_tx.send(event);
+    }
+
+    pub fn with_child<T>(&self, node_id: &str, action: impl FnOnce(&mut Child) -> T) -> Option<T> {
+        self.children
+            .lock()
+            .expect("Children lock poisoned")
+            .get_mut(node_id)
+            .map(action)
+    }
+
+    pub fn kill_node(&self, node_id: &str) {
+        //! Kill a node with SIGKILL. Messages sent to it are dropped until it is restarted.
+        self.send(RouterEvent::Detach(node_id.to_owned()));
+        let child = self
+            .children
+            .lock()
+            .expect("Children lock poisoned")
+            .remove(node_id);
+        if let Some(mut child) = child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    pub fn signal_node(&self, node_id: &str, signal: &str) -> io::Result<()> {
+        //! Send a signal such as STOP or CONT to a running node.
+        let pid = self
+            .with_child(node_id, |child| child.id())
+            .ok_or_else(|| io::Error::other(format!("Node {} is not running", node_id)))?;
+        let status = Command::new("kill")
+            .arg(format!("-{}", signal))
+            .arg(pid.to_string())
+            .status()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("kill -{} {} failed", signal, pid)))
+        }
+    }
+
+    pub fn client(&self, node_id: &str) -> Client {
+        //! Create a client bound to the given node. Clients are named c1, c2, ...
+        let mut next_client = self.next_client.lock().expect("Client lock poisoned");
+        *next_client += 1;
+        let (mailbox_tx, mailbox) = channel();
+        let id = format!("c{}", *next_client);
+        self.send(RouterEvent::AddClient(id.clone(), mailbox_tx));
+        Client {
+            id,
+            process: *next_client,
+            node: node_id.to_owned(),
+            next_msg_id: 0,
+            mailbox,
+            router_tx: self.router_tx.clone(),
+            recorder: self.recorder.clone(),
+        }
+    }
+
+    pub fn shutdown(mut self) -> Vec<TraceEntry> {
+        //! Kill every node and return the trace of all messages.
+        for (_, mut child) in self
+            .children
+            .lock()
+            .expect("Children lock poisoned")
+            .drain()
+        {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        self.send(RouterEvent::Shutdown);
+        self.router
+            .take()
+            .and_then(|router| router.join().ok())
+            .unwrap_or_default()
+    }
+}
+
+pub struct Client {
+    pub id: String,
+    pub process: usize,
+    pub node: String,
+    next_msg_id: usize,
+    mailbox: Receiver<MaelstromMessage<Value>>,
+    router_tx: Sender<RouterEvent>,
+    recorder: Arc<Recorder>,
+}
+
+impl Client {
+    pub fn call(&mut self, mut body: Value, timeout: Duration) -> Operation {
+        //! Send a request to this client's node and wait for the reply, recording
+        //! the invoke and its completion. Returns the completion: ok, fail for a
+        //! definite error, or info if the outcome is unknown.
+        self.next_msg_id += 1;
+        let msg_id = self.next_msg_id;
+        body["msg_id"] = json!(msg_id);
+        let request = body.as_object().cloned().unwrap_or_default();
+        let invoke = Operation {
+            op_type: OpType::Invoke,
+            process: Process::Client(self.process),
+            time: self.recorder.now(),
+            f: history::body_type(&request),
+            value: history::payload(&request),
+            error: None,
+            index: None,
+            msg_id: Some(msg_id as u64),
+        };
+        self.recorder.record(invoke.clone());
+        let _ = self
+            .router_tx
+            .send(RouterEvent::FromClient(MaelstromMessage {
+                src: self.id.clone(),
+                dest: self.node.clone(),
+                body,
+            }));
+
+        let deadline = Instant::now() + timeout;
+        let reply = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.mailbox.recv_timeout(remaining) {
+                Ok(reply) if reply.body["in_reply_to"] == json!(msg_id) => break Some(reply),
+                //Late replies to requests that already timed out
+                Ok(_) => continue,
+                Err(_) => break None,
+            }
+        };
+
+        let completion = match reply.as_ref().and_then(|reply| reply.body.as_object()) {
+            Some(reply) if history::body_type(reply) == "error" => Operation {
+                op_type: history::error_type(reply),
+                error: Some(history::payload(reply)),
+                ..invoke
+            },
+            Some(reply) => Operation {
+                op_type: OpType::Ok,
+                value: history::payload(reply),
+                ..invoke
+            },
+            None => Operation {
+                op_type: OpType::Info,
+                error: Some(json!("timeout")),
+                ..invoke
+            },
+        };
+        let completion = Operation {
+            time: self.recorder.now(),
+            ..completion
+        };
+        self.recorder.record(completion.clone());
+        completion
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RunConfig {
+    pub cluster: ClusterConfig,
+    pub workload: workload::Workload,
+    //Number of client threads
+    pub concurrency: usize,
+    //Total requests per second across all clients
+    pub rate: f64,
+    pub time_limit: Duration,
+    //How long to wait after healing before the final reads
+    pub recovery_time: Duration,
+    pub request_timeout: Duration,
+    pub nemesis: Option<nemesis::NemesisConfig>,
+    //Where to write history.jsonl, trace.jsonl, results.json and node logs
+    pub out_dir: Option<PathBuf>,
+}
+
+pub fn net_stats(trace: &[TraceEntry], node_ids: &[String]) -> Value {
+    //! Count the messages sent during a run by who they were between, so modes
+    //! that trade gossip for service requests can be compared on message cost.
+    let is_node = |id: &String| node_ids.contains(id);
+    let (mut between_nodes, mut with_services, mut with_clients, mut dropped) = (0, 0, 0, 0);
+    for entry in trace {
+        let message = &entry.message;
+        if service::is_service(&message.src) || service::is_service(&message.dest) {
+            with_services += 1;
+        } else if is_node(&message.src) && is_node(&message.dest) {
+            between_nodes += 1;
+        } else {
+            with_clients += 1;
+        }
+        if entry.received.is_none() {
+            dropped += 1;
+        }
+    }
+    json!({
+        "messages": trace.len(),
+        "between_nodes": between_nodes,
+        "with_services": with_services,
+        "with_clients": with_clients,
+        "dropped": dropped,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct RunReport {
+    pub valid: bool,
+    pub results: Value,
+    pub history: History,
+    pub trace: Vec<TraceEntry>,
+}
+
+pub fn run(mut config: RunConfig) -> io::Result<RunReport> {
+    //! Run a workload against a local cluster: generate client requests at the
+    //! configured rate while the nemesis injects faults, heal, wait for recovery,
+    //! perform the final reads, and check the resulting history.
+    if let Some(out_dir) = &config.out_dir {
+        fs::create_dir_all(out_dir)?;
+        config.cluster.log_dir = Some(out_dir.clone());
+        config.cluster.state_dir = Some(out_dir.join("state"));
+    }
+    let recorder = Arc::new(Recorder::new());
+    let cluster = Arc::new(Cluster::start(config.cluster.clone(), recorder.clone())?);
+    let node_ids = cluster.node_ids.clone();
+
+    let mut setup_clients: Vec<Client> = node_ids.iter().map(|node| cluster.client(node)).collect();
+    config
+        .workload
+        .setup(&node_ids, &mut setup_clients, config.request_timeout);
+
+    let nemesis = config
+        .nemesis
+        .clone()
+        .map(|nemesis| nemesis::Nemesis::start(nemesis, cluster.clone(), config.cluster.seed));
+
+    let deadline = Instant::now() + config.time_limit;
+    let op_interval = Duration::from_secs_f64(config.concurrency as f64 / config.rate.max(0.001));
+    let workers: Vec<JoinHandle<()>> = (0..config.concurrency)
+        .map(|index| {
+            let mut client = cluster.client(&node_ids[index % node_ids.len()]);
+            let workload = config.workload.clone();
+            let request_timeout = config.request_timeout;
+            let mut rng = Rng::seeded(config.cluster.seed.wrapping_add(index as u64 + 1));
+            thread::spawn(move || {
+                //Stagger the clients so requests are spread over each interval.
+                thread::sleep(op_interval.mul_f64(rng.next_f64()));
+                while Instant::now() < deadline {
+                    let started = Instant::now();
+                    client.call(workload.generate(&mut rng), request_timeout);
+                    thread::sleep(op_interval.saturating_sub(started.elapsed()));
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    if let Some(nemesis) = nemesis {
+        nemesis.stop();
+    }
+    //Every node must answer the final request, which shows the cluster
+    //recovered from whatever the nemesis did to it.
+    thread::sleep(config.recovery_time);
+    let final_request = config.workload.final_request();
+    let final_completions: Vec<Operation> = setup_clients
+        .iter_mut()
+        .map(|client| client.call(final_request.clone(), config.request_timeout))
+        .collect();
+    let recovered = final_completions
+        .iter()
+        .all(|completion| completion.op_type == OpType::Ok);
+
+    let cluster = Arc::try_unwrap(cluster)
+        .map_err(|_| io::Error::other("Cluster still in use after the run"))?;
+    let trace = cluster.shutdown();
+    let history = recorder.history();
+    let (valid, mut results) = config.workload.check(&history);
+    let valid = valid && recovered;
+    results["valid"] = json!(valid);
+    results["recovered"] = json!(recovered);
+    results["net"] = net_stats(&trace, &node_ids);
+
+    if let Some(out_dir) = &config.out_dir {
+        history.write_jsonl(File::create(out_dir.join("history.jsonl"))?)?;
+        crate::trace::write_jsonl(&trace, File::create(out_dir.join("trace.jsonl"))?)?;
+        serde_json::to_writer_pretty(File::create(out_dir.join("results.json"))?, &results)?;
+    }
+    Ok(RunReport {
+        valid,
+        results,
+        history,
+        trace,
+    })
+}
@@ -0,0 +1,337 @@
+use super::network::{self, Latency, LinkConfig, PartitionKind};
+use super::{Cluster, RouterEvent};
+use crate::node::MaelstromMessage;
+use crate::rng::Rng;
+use serde_json::{json, Value};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    //Each time the nemesis strikes, one of these partitions is picked at random
+    Partition(Vec<PartitionKind>),
+    Loss(f64),
+    Latency(Latency),
+    Duplicate(f64),
+    Reorder(f64),
+    //kill -9 a random node, restarting it (with a fresh init handshake) after the delay
+    Kill { restart_delay: Duration },
+    //SIGSTOP a random node, and SIGCONT it when the network heals
+    Pause,
+    //Skew, drift or jump the clock of a random node by up to this many milliseconds
+    Clock { magnitude_ms: i64 },
+}
+
+impl Fault {
+    pub fn parse(spec: &str) -> Option<Self> {
+        //! Parse a fault such as `partition`, `partition:halves+isolate`,
+        //! `loss:0.2`, `latency:exp:50`, `duplicate:0.1`, `reorder:0.3`,
+        //! `kill`, `kill:2.5` (restart delay in seconds), `pause`, `clock` or
+        //! `clock:500` (largest skew in milliseconds).
+        let (name, argument) = spec.split_once(':').unwrap_or((spec, ""));
+        let probability = || network::parse_probability(argument);
+        match name {
+            "partition" if argument.is_empty() => Some(Fault::Partition(vec![
+                PartitionKind::RandomHalves,
+                PartitionKind::MajorityRing,
+                PartitionKind::IsolateOne,
+            ])),
+            "partition" => argument
+                .split('+')
+                .map(PartitionKind::parse)
+                .collect::<Option<Vec<_>>>()
+                .map(Fault::Partition),
+            "loss" => probability().map(Fault::Loss),
+            "latency" => Latency::parse(argument).map(Fault::Latency),
+            "duplicate" => probability().map(Fault::Duplicate),
+            "reorder" => probability().map(Fault::Reorder),
+            "kill" if argument.is_empty() => Some(Fault::Kill {
+                restart_delay: Duration::from_secs(1),
+            }),
+            "kill" => argument
+                .parse::<f64>()
+                .ok()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .map(|restart_delay| Fault::Kill { restart_delay }),
+            "pause" if argument.is_empty() => Some(Fault::Pause),
+            "clock" if argument.is_empty() => Some(Fault::Clock { magnitude_ms: 1000 }),
+            "clock" => argument
+                .parse()
+                .ok()
+                .map(|magnitude_ms| Fault::Clock { magnitude_ms }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NemesisConfig {
+    pub faults: Vec<Fault>,
+    //Mean time between starting faults and healing them, and back again.
+    //Each wait is jittered to between half and one and a half times this.
+    pub interval: Duration,
+}
+
+pub struct Nemesis {
+    stop_tx: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+struct Disruptions {
+    //Nodes that were killed, and when to restart them
+    killed: Vec<(Instant, String)>,
+    paused: Vec<String>,
+    //Nodes whose clocks were tampered with
+    skewed: Vec<String>,
+}
+
+impl Nemesis {
+    pub fn start(config: NemesisConfig, cluster: Arc<Cluster>, seed: u64) -> Self {
+        //! Alternate between injecting every configured fault and healing,
+        //! logging each change into the history.
+        let (stop_tx, stop_rx) = channel();
+        let handle = thread::spawn(move || {
+            //Keep the nemesis's choices independent of the router's, which uses the seed as is.
+            let mut rng = Rng::seeded(seed.rotate_left(32));
+            let baseline = cluster.config.network.default_link;
+            let mut disruptions = Disruptions {
+                killed: Vec::new(),
+                paused: Vec::new(),
+                skewed: Vec::new(),
+            };
+            //Control messages come from the nemesis, which like a client is never partitioned.
+            let (mailbox_tx, _mailbox) = channel();
+            cluster.send(RouterEvent::AddClient(NEMESIS.to_owned(), mailbox_tx));
+            loop {
+                let quiet = config.interval.mul_f64(0.5 + rng.next_f64());
+                if wait(quiet, &stop_rx, &cluster, &mut disruptions) {
+                    break;
+                }
+                let mut link = baseline;
+                for fault in config.faults.iter() {
+                    strike(
+                        fault,
+                        &config,
+                        &cluster,
+                        &mut link,
+                        &mut disruptions,
+                        &mut rng,
+                    );
+                }
+                cluster.send(RouterEvent::SetDefaultLink(link));
+
+                let active = config.interval.mul_f64(0.5 + rng.next_f64());
+                let stopped = wait(active, &stop_rx, &cluster, &mut disruptions);
+                heal(&config.faults, &cluster, baseline, &mut disruptions);
+                if stopped {
+                    break;
+                }
+            }
+            heal(&[], &cluster, baseline, &mut disruptions);
+        });
+        Nemesis { stop_tx, handle }
+    }
+
+    pub fn stop(self) {
+        //! Heal any active faults and wait for the nemesis to finish.
+        let _ = self.stop_tx.send(());
+        let _ = self.handle.join();
+    }
+}
+
+fn wait(
+    duration: Duration,
+    stop_rx: &Receiver<()>,
+    cluster: &Cluster,
+    disruptions: &mut Disruptions,
+) -> bool {
+    //! Sleep for the duration, restarting killed nodes as they come due.
+    //! Returns true if the nemesis was asked to stop.
+    let deadline = Instant::now() + duration;
+    loop {
+        let next_restart = disruptions.killed.iter().map(|(at, _)| *at).min();
+        let wake = next_restart.map_or(deadline, |at| at.min(deadline));
+        match stop_rx.recv_timeout(wake.saturating_duration_since(Instant::now())) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return true,
+        }
+        let now = Instant::now();
+        let (due, pending) = disruptions.killed.drain(..).partition(|(at, _)| *at <= now);
+        disruptions.killed = pending;
+        for (_, node_id) in due {
+            restart(cluster, &node_id);
+        }
+        if now >= deadline {
+            return false;
+        }
+    }
+}
+
+fn restart(cluster: &Cluster, node_id: &str) {
+    match cluster.spawn_node(node_id) {
+        Ok(()) => cluster
+            .recorder
+            .record_nemesis("start", json!({"node": node_id})),
+        Err(err) => cluster.recorder.record_nemesis(
+            "start-failed",
+            json!({"node": node_id, "error": err.to_string()}),
+        ),
+    }
+}
+
+fn strike(
+    fault: &Fault,
+    config: &NemesisConfig,
+    cluster: &Cluster,
+    link: &mut LinkConfig,
+    disruptions: &mut Disruptions,
+    rng: &mut Rng,
+) {
+    let recorder = &cluster.recorder;
+    match fault {
+        Fault::Partition(kinds) => {
+            let kind = *rng.choose(kinds).unwrap_or(&PartitionKind::RandomHalves);
+            let grudge = kind.grudge(&cluster.node_ids, rng);
+            let mut described: Vec<(&String, Vec<&String>)> = grudge
+                .iter()
+                .map(|(node, dropped)| {
+                    let mut dropped: Vec<&String> = dropped.iter().collect();
+                    dropped.sort();
+                    (node, dropped)
+                })
+                .collect();
+            described.sort();
+            recorder.record_nemesis(
+                "start-partition",
+                json!({"kind": kind, "grudge": described}),
+            );
+            cluster.send(RouterEvent::SetGrudge(grudge));
+        }
+        Fault::Loss(loss) => {
+            link.loss = *loss;
+            recorder.record_nemesis("start-loss", json!({"probability": loss}));
+        }
+        Fault::Latency(latency) => {
+            link.latency = *latency;
+            recorder.record_nemesis("start-latency", json!(latency));
+        }
+        Fault::Duplicate(duplicate) => {
+            link.duplicate = *duplicate;
+            recorder.record_nemesis("start-duplicate", json!({"probability": duplicate}));
+        }
+        Fault::Reorder(reorder) => {
+            link.reorder = *reorder;
+            recorder.record_nemesis("start-reorder", json!({"probability": reorder}));
+        }
+        Fault::Kill { restart_delay } => {
+            let node_id = match live_node(cluster, disruptions, rng) {
+                Some(node_id) => node_id,
+                None => return,
+            };
+            cluster.kill_node(&node_id);
+            recorder.record_nemesis("kill", json!({"node": node_id}));
+            disruptions
+                .killed
+                .push((Instant::now() + *restart_delay, node_id));
+        }
+        Fault::Pause => {
+            let node_id = match live_node(cluster, disruptions, rng) {
+                Some(node_id) => node_id,
+                None => return,
+            };
+            match cluster.signal_node(&node_id, "STOP") {
+                Ok(()) => {
+                    recorder.record_nemesis("pause", json!({"node": node_id}));
+                    disruptions.paused.push(node_id);
+                }
+                Err(err) => recorder.record_nemesis(
+                    "pause-failed",
+                    json!({"node": node_id, "error": err.to_string()}),
+                ),
+            }
+        }
+        Fault::Clock { magnitude_ms } => {
+            let node_id = match rng.choose(&cluster.node_ids) {
+                Some(node_id) => node_id.clone(),
+                None => return,
+            };
+            let amount_ms = (rng.next_f64() * 2.0 - 1.0) * *magnitude_ms as f64;
+            let (f, control) = match rng.below(3) {
+                0 => ("clock-skew", json!({"offset_ms": amount_ms as i64})),
+                1 => ("clock-jump", json!({"jump_ms": amount_ms as i64})),
+                _ => {
+                    //Drift fast enough to be off by about the amount once the fault ends.
+                    let drift_ppm = amount_ms * 1000.0 / config.interval.as_secs_f64().max(0.001);
+                    ("clock-drift", json!({"drift_ppm": drift_ppm}))
+                }
+            };
+            control_clock(cluster, &node_id, control.clone());
+            recorder.record_nemesis(f, json!({"node": node_id, "clock": control}));
+            disruptions.skewed.push(node_id);
+        }
+    }
+}
+
+const NEMESIS: &str = "nemesis";
+
+fn control_clock(cluster: &Cluster, node_id: &str, mut control: Value) {
+    control["type"] = json!("clock_nemesis");
+    cluster.send(RouterEvent::FromClient(MaelstromMessage {
+        src: NEMESIS.to_owned(),
+        dest: node_id.to_owned(),
+        body: control,
+    }));
+}
+
+fn live_node(cluster: &Cluster, disruptions: &Disruptions, rng: &mut Rng) -> Option<String> {
+    //! A random node that is neither killed nor paused.
+    let live: Vec<&String> = cluster
+        .node_ids
+        .iter()
+        .filter(|node_id| {
+            !disruptions.paused.contains(node_id)
+                && !disruptions
+                    .killed
+                    .iter()
+                    .any(|(_, killed)| killed == *node_id)
+        })
+        .collect();
+    rng.choose(&live).map(|node_id| node_id.to_string())
+}
+
+fn heal(faults: &[Fault], cluster: &Cluster, baseline: LinkConfig, disruptions: &mut Disruptions) {
+    //! Restore the baseline network, resume paused nodes and restart killed ones.
+    cluster.send(RouterEvent::SetGrudge(Default::default()));
+    cluster.send(RouterEvent::SetDefaultLink(baseline));
+    for fault in faults.iter() {
+        let f = match fault {
+            Fault::Partition(_) => "stop-partition",
+            Fault::Loss(_) => "stop-loss",
+            Fault::Latency(_) => "stop-latency",
+            Fault::Duplicate(_) => "stop-duplicate",
+            Fault::Reorder(_) => "stop-reorder",
+            //Process faults are logged per node below.
+            Fault::Kill { .. } | Fault::Pause | Fault::Clock { .. } => continue,
+        };
+        cluster.recorder.record_nemesis(f, Value::Null);
+    }
+    for node_id in disruptions.paused.drain(..) {
+        let _ = cluster.signal_node(&node_id, "CONT");
+        cluster
+            .recorder
+            .record_nemesis("resume", json!({"node": node_id}));
+    }
+    for (_, node_id) in disruptions.killed.drain(..) {
+        restart(cluster, &node_id);
+    }
+    disruptions.skewed.sort();
+    disruptions.skewed.dedup();
+    for node_id in disruptions.skewed.drain(..) {
+        control_clock(cluster, &node_id, json!({"reset": true}));
+        cluster
+            .recorder
+            .record_nemesis("clock-reset", json!({"node": node_id}));
+    }
+}
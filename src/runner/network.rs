@@ -0,0 +1,219 @@
+use crate::rng::Rng;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "distribution", rename_all = "snake_case")]
+pub enum Latency {
+    Constant { ms: f64 },
+    Uniform { min_ms: f64, max_ms: f64 },
+    Exponential { mean_ms: f64 },
+}
+
+impl Latency {
+    pub fn parse(spec: &str) -> Option<Self> {
+        //! Parse `10` (constant), `uniform:5:20` or `exp:10`, all in milliseconds.
+        //! Negative, infinite and NaN times are rejected, so every sample is a valid delay.
+        let millis = |part: &str| {
+            part.parse::<f64>()
+                .ok()
+                .filter(|ms| ms.is_finite() && *ms >= 0.0)
+        };
+        let parts: Vec<&str> = spec.split(':').collect();
+        let numbers: Option<Vec<f64>> = parts[1..].iter().map(|part| millis(part)).collect();
+        match (parts[0], numbers?.as_slice()) {
+            ("uniform", [min_ms, max_ms]) if min_ms <= max_ms => Some(Latency::Uniform {
+                min_ms: *min_ms,
+                max_ms: *max_ms,
+            }),
+            ("exp", [mean_ms]) => Some(Latency::Exponential { mean_ms: *mean_ms }),
+            (ms, []) => millis(ms).map(|ms| Latency::Constant { ms }),
+            _ => None,
+        }
+    }
+
+    pub fn sample(&self, rng: &mut Rng) -> Duration {
+        let ms = match *self {
+            Latency::Constant { ms } => ms,
+            Latency::Uniform { min_ms, max_ms } => min_ms + (max_ms - min_ms) * rng.next_f64(),
+            Latency::Exponential { mean_ms } => -mean_ms * (1.0 - rng.next_f64()).ln(),
+        };
+        //Only hand-built latencies can get here with a time that is not a valid delay.
+        Duration::try_from_secs_f64(ms / 1000.0).unwrap_or_default()
+    }
+}
+
+pub fn parse_probability(value: &str) -> Option<f64> {
+    value
+        .parse()
+        .ok()
+        .filter(|probability| (0.0..=1.0).contains(probability))
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct LinkConfig {
+    //Probability that a message is dropped
+    pub loss: f64,
+    pub latency: Latency,
+    //Probability that a message is delivered twice
+    pub duplicate: f64,
+    //Probability that a message is held back long enough for later messages to overtake it
+    pub reorder: f64,
+}
+
+impl LinkConfig {
+    pub fn set(&mut self, setting: &str) -> Option<()> {
+        //! Apply a `key=value` setting such as `loss=0.2` or `latency=exp:20`.
+        let (key, value) = setting.split_once('=')?;
+        match key {
+            "loss" => self.loss = parse_probability(value)?,
+            "duplicate" => self.duplicate = parse_probability(value)?,
+            "reorder" => self.reorder = parse_probability(value)?,
+            "latency" => self.latency = Latency::parse(value)?,
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig {
+            loss: 0.0,
+            latency: Latency::Constant { ms: 0.0 },
+            duplicate: 0.0,
+            reorder: 0.0,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionKind {
+    //Split the nodes into two randomly chosen halves
+    RandomHalves,
+    //Every node sees a majority of the cluster, but no two nodes see the same majority
+    MajorityRing,
+    //Cut a single random node off from the rest
+    IsolateOne,
+}
+
+impl PartitionKind {
+    pub fn parse(spec: &str) -> Option<Self> {
+        match spec {
+            "halves" => Some(PartitionKind::RandomHalves),
+            "majority-ring" => Some(PartitionKind::MajorityRing),
+            "isolate" => Some(PartitionKind::IsolateOne),
+            _ => None,
+        }
+    }
+
+    pub fn grudge(&self, node_ids: &[String], rng: &mut Rng) -> Grudge {
+        //! Build a grudge for this partition: a map of each node to the
+        //! nodes whose messages it drops.
+        let mut shuffled = node_ids.to_vec();
+        rng.shuffle(&mut shuffled);
+        let components: Vec<Vec<String>> = match self {
+            PartitionKind::RandomHalves => {
+                let (left, right) = shuffled.split_at(shuffled.len() / 2);
+                vec![left.to_vec(), right.to_vec()]
+            }
+            PartitionKind::IsolateOne => {
+                let (isolated, rest) = shuffled.split_at(1.min(shuffled.len()));
+                vec![isolated.to_vec(), rest.to_vec()]
+            }
+            PartitionKind::MajorityRing => {
+                //Each node hears from the majority of nodes closest to it on a shuffled ring.
+                let count = shuffled.len();
+                let majority = count / 2 + 1;
+                let mut grudge = Grudge::new();
+                for (position, node) in shuffled.iter().enumerate() {
+                    let visible: HashSet<usize> = (0..majority)
+                        .map(|offset| (position + count + offset - majority / 2) % count)
+                        .collect();
+                    let dropped = (0..count)
+                        .filter(|other| !visible.contains(other))
+                        .map(|other| shuffled[other].clone())
+                        .collect();
+                    grudge.insert(node.clone(), dropped);
+                }
+                return grudge;
+            }
+        };
+        let mut grudge = Grudge::new();
+        for component in components.iter() {
+            for node in component.iter() {
+                let dropped = node_ids
+                    .iter()
+                    .filter(|other| !component.contains(other))
+                    .cloned()
+                    .collect();
+                grudge.insert(node.clone(), dropped);
+            }
+        }
+        grudge
+    }
+}
+
+//Maps a node to the set of nodes it cannot hear from, as in Jepsen.
+pub type Grudge = HashMap<String, HashSet<String>>;
+
+#[derive(Debug, Clone, Default)]
+pub struct Network {
+    pub default_link: LinkConfig,
+    //Overrides for individual (src, dest) links
+    pub links: HashMap<(String, String), LinkConfig>,
+    pub grudge: Grudge,
+}
+
+impl Network {
+    pub fn add_link(&mut self, spec: &str) -> Option<()> {
+        //! Override one link with a spec such as `n0>n1,loss=0.5,latency=exp:20`.
+        //! Settings the spec leaves out are taken from the default link.
+        let mut settings = spec.split(',');
+        let (src, dest) = settings.next()?.split_once('>')?;
+        let mut link = self.default_link;
+        for setting in settings {
+            link.set(setting)?;
+        }
+        self.links.insert((src.to_owned(), dest.to_owned()), link);
+        Some(())
+    }
+
+    pub fn link(&self, src: &str, dest: &str) -> LinkConfig {
+        self.links
+            .get(&(src.to_owned(), dest.to_owned()))
+            .copied()
+            .unwrap_or(self.default_link)
+    }
+
+    pub fn is_partitioned(&self, src: &str, dest: &str) -> bool {
+        self.grudge
+            .get(dest)
+            .is_some_and(|dropped| dropped.contains(src))
+    }
+
+    pub fn plan(&self, src: &str, dest: &str, rng: &mut Rng) -> Vec<Duration> {
+        //! Decide the fate of a single message between nodes: returns the delay
+        //! until each copy is delivered. Empty if the message is dropped.
+        if self.is_partitioned(src, dest) {
+            return Vec::new();
+        }
+        let link = self.link(src, dest);
+        if rng.chance(link.loss) {
+            return Vec::new();
+        }
+        let copies = if rng.chance(link.duplicate) { 2 } else { 1 };
+        (0..copies)
+            .map(|_| {
+                let mut delay = link.latency.sample(rng);
+                if rng.chance(link.reorder) {
+                    //Hold the message back by a few typical latencies.
+                    delay += link.latency.sample(rng) * 3 + Duration::from_millis(5);
+                }
+                delay
+            })
+            .collect()
+    }
+}
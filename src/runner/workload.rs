@@ -0,0 +1,139 @@
+use super::Client;
+use crate::checker;
+use crate::history::History;
+use crate::node::broadcast::topology::Topology;
+use crate::rng::Rng;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub enum Workload {
+    Echo,
+    UniqueIds,
+    //Broadcast values are unique across all clients
+    Broadcast(Arc<AtomicU64>),
+    GCounter,
+}
+
+impl Workload {
+    pub fn parse(name: &str) -> Option<Self> {
+        //! Workload names follow Maelstrom's `-w` flag.
+        match name {
+            "echo" => Some(Workload::Echo),
+            "unique-ids" => Some(Workload::UniqueIds),
+            "broadcast" => Some(Workload::Broadcast(Arc::new(AtomicU64::new(0)))),
+            "g-counter" => Some(Workload::GCounter),
+            _ => None,
+        }
+    }
+
+    pub fn node_type(&self) -> &'static str {
+        //! The EVENT_HORIZON_NODE that implements this workload.
+        match self {
+            Workload::Echo => "echo",
+            Workload::UniqueIds => "generate_id",
+            Workload::Broadcast(_) => "broadcast",
+            Workload::GCounter => "grow_counter",
+        }
+    }
+
+    pub fn setup(&self, node_ids: &[String], clients: &mut [Client], timeout: Duration) {
+        //! Broadcast nodes are sent a grid topology, like Maelstrom's default.
+        if let Workload::Broadcast(_) = self {
+            let topology = grid_topology(node_ids);
+            for client in clients.iter_mut() {
+                client.call(json!({"type": "topology", "topology": topology}), timeout);
+            }
+        }
+    }
+
+    pub fn generate(&self, rng: &mut Rng) -> Value {
+        //! The next client request, without a msg_id.
+        match self {
+            Workload::Echo => {
+                json!({"type": "echo", "echo": format!("Please echo {}", rng.below(128))})
+            }
+            Workload::UniqueIds => json!({"type": "generate"}),
+            Workload::Broadcast(next_message) => {
+                if rng.chance(0.5) {
+                    json!({"type": "broadcast", "message": next_message.fetch_add(1, Ordering::SeqCst)})
+                } else {
+                    json!({"type": "read"})
+                }
+            }
+            Workload::GCounter => {
+                if rng.chance(0.5) {
+                    json!({"type": "add", "delta": rng.below(5)})
+                } else {
+                    json!({"type": "read"})
+                }
+            }
+        }
+    }
+
+    pub fn final_request(&self) -> Value {
+        //! A request sent to every node once the cluster has healed and recovered.
+        match self {
+            Workload::Echo => json!({"type": "echo", "echo": "Final echo"}),
+            Workload::UniqueIds => json!({"type": "generate"}),
+            Workload::Broadcast(_) | Workload::GCounter => json!({"type": "read"}),
+        }
+    }
+
+    pub fn check(&self, history: &History) -> (bool, Value) {
+        let pairs = history.pairs();
+        let completed = pairs.iter().filter(|(_, completion)| completion.is_some());
+        let ok = completed
+            .clone()
+            .filter(|(_, completion)| {
+                completion
+                    .is_some_and(|completion| completion.op_type == crate::history::OpType::Ok)
+            })
+            .count();
+        let stats = json!({"invoked": pairs.len(), "completed": completed.count(), "ok": ok});
+        let (valid, workload) = match self {
+            Workload::Echo => {
+                let mismatched = pairs
+                    .iter()
+                    .filter(|(invoke, completion)| {
+                        completion.is_some_and(|completion| {
+                            completion.op_type == crate::history::OpType::Ok
+                                && completion.value["echo"] != invoke.value["echo"]
+                        })
+                    })
+                    .count();
+                (
+                    mismatched == 0,
+                    json!({"valid": mismatched == 0, "mismatched": mismatched}),
+                )
+            }
+            Workload::UniqueIds => {
+                let report = checker::unique_ids::check(history);
+                (report.valid, json!(report))
+            }
+            Workload::Broadcast(_) => {
+                let report = checker::broadcast::check(history);
+                (report.valid, json!(report))
+            }
+            Workload::GCounter => {
+                let report = checker::counter::check(history);
+                (report.valid, json!(report))
+            }
+        };
+        (
+            valid,
+            json!({"valid": valid, "stats": stats, "workload": workload}),
+        )
+    }
+}
+
+pub fn grid_topology(node_ids: &[String]) -> HashMap<String, Vec<String>> {
+    //! Lay the nodes out row by row in a square grid, with each node
+    //! neighboring the nodes above, below, left and right of it.
+    Topology::Grid
+        .build(node_ids)
+        .expect("A grid is built from node IDs")
+}
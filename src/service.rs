@@ -0,0 +1,173 @@
+use crate::node::MaelstromMessage;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+pub mod kv;
+pub mod local;
+pub mod tso;
+
+//Maelstrom's built-in services. Nodes talk to them with ordinary messages, and
+//their replies arrive on stdin like any other message, but from the service's id.
+pub const SEQ_KV: &str = "seq-kv";
+pub const LIN_KV: &str = "lin-kv";
+pub const LWW_KV: &str = "lww-kv";
+pub const LIN_TSO: &str = "lin-tso";
+
+pub fn is_service(node_id: &str) -> bool {
+    matches!(node_id, SEQ_KV | LIN_KV | LWW_KV | LIN_TSO)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceError {
+    //Error code 20: the key has never been written
+    KeyDoesNotExist,
+    //Error code 22: a cas found a value other than `from`
+    PreconditionFailed,
+    //No reply arrived in time. The request may or may not have taken effect.
+    Timeout,
+    //Any other Maelstrom error code
+    Other { code: u64, text: String },
+}
+
+impl ServiceError {
+    pub fn from_body(body: &Value) -> Self {
+        //! Convert a Maelstrom `error` body.
+        let code = body.get("code").and_then(Value::as_u64).unwrap_or(0);
+        match code {
+            20 => ServiceError::KeyDoesNotExist,
+            22 => ServiceError::PreconditionFailed,
+            code => ServiceError::Other {
+                code,
+                text: body
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_owned(),
+            },
+        }
+    }
+
+    pub fn is_definite(&self) -> bool {
+        //! Whether the request definitely did not take effect. Timeouts, crashes (13)
+        //! and unclassified errors (0) leave the outcome unknown.
+        !matches!(
+            self,
+            ServiceError::Timeout | ServiceError::Other { code: 0 | 13, .. }
+        )
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::KeyDoesNotExist => write!(f, "key does not exist"),
+            ServiceError::PreconditionFailed => write!(f, "precondition failed"),
+            ServiceError::Timeout => write!(f, "timed out"),
+            ServiceError::Other { code, text } => write!(f, "error {}: {}", code, text),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+pub fn reply_result(body: &Value) -> Result<&Value, ServiceError> {
+    //! A reply body, or the error it carries.
+    match body.get("type").and_then(Value::as_str) {
+        Some("error") => Err(ServiceError::from_body(body)),
+        _ => Ok(body),
+    }
+}
+
+//Service requests from every client in this process share one msg_id sequence,
+//so a reply's in_reply_to always identifies a single request.
+static NEXT_MSG_ID: AtomicU64 = AtomicU64::new(1);
+
+//Callers blocked waiting for a reply, by (service, msg_id). The stdin thread hands
+//replies straight to them, so a handler can block without stalling its own reply.
+type Waiters = HashMap<(String, u64), Sender<Value>>;
+
+static WAITERS: Mutex<Option<Waiters>> = Mutex::new(None);
+
+fn waiters() -> MutexGuard<'static, Option<Waiters>> {
+    WAITERS.lock().expect("Service waiters lock poisoned")
+}
+
+fn send_request(
+    output: &mut dyn Write,
+    node_id: &str,
+    service: &str,
+    mut body: Value,
+    msg_id: u64,
+) {
+    body["msg_id"] = json!(msg_id);
+    let mut message = MaelstromMessage {
+        src: node_id.to_owned(),
+        dest: service.to_owned(),
+        body,
+    };
+    message.send(output);
+    //Blocking callers wait on this request, so it must not sit in a buffer.
+    let _ = output.flush();
+}
+
+pub fn request(output: &mut dyn Write, node_id: &str, service: &str, body: Value) -> u64 {
+    //! Send a request body to a service, returning the msg_id its reply will name.
+    let msg_id = NEXT_MSG_ID.fetch_add(1, Ordering::Relaxed);
+    send_request(output, node_id, service, body, msg_id);
+    msg_id
+}
+
+pub fn call_blocking(
+    output: &mut dyn Write,
+    node_id: &str,
+    service: &str,
+    body: Value,
+    timeout: Duration,
+) -> Result<Value, ServiceError> {
+    //! Send a request and wait for its reply. Only works under the real runtime,
+    //! where replies are delivered by the stdin thread while this one waits.
+    let (reply_tx, reply_rx) = channel();
+    let msg_id = NEXT_MSG_ID.fetch_add(1, Ordering::Relaxed);
+    let key = (service.to_owned(), msg_id);
+    //Registered before sending, so a fast reply cannot arrive ahead of its waiter.
+    waiters()
+        .get_or_insert_with(HashMap::new)
+        .insert(key.clone(), reply_tx);
+    send_request(output, node_id, service, body, msg_id);
+
+    let reply = reply_rx.recv_timeout(timeout);
+    if let Some(waiters) = waiters().as_mut() {
+        waiters.remove(&key);
+    }
+    match reply {
+        Ok(body) => reply_result(&body).cloned(),
+        Err(_) => Err(ServiceError::Timeout),
+    }
+}
+
+pub fn deliver(message: MaelstromMessage<Value>) -> Option<MaelstromMessage<Value>> {
+    //! Hand a service reply to the caller blocked on it. Returns the message if
+    //! nobody is waiting, so it can go to the node as an event instead.
+    let waiter = message
+        .body
+        .get("in_reply_to")
+        .and_then(Value::as_u64)
+        .and_then(|in_reply_to| {
+            waiters()
+                .as_mut()
+                .and_then(|waiters| waiters.remove(&(message.src.clone(), in_reply_to)))
+        });
+    match waiter {
+        Some(waiter) => {
+            let _ = waiter.send(message.body);
+            None
+        }
+        None => Some(message),
+    }
+}
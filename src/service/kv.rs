@@ -0,0 +1,346 @@
+use crate::node::MaelstromMessage;
+use crate::rng::Rng;
+use crate::service::{self, ServiceError};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+//A client for Maelstrom's key-value services: seq-kv, lin-kv and lww-kv.
+//Requests can be made two ways. The callback methods send a request and return
+//at once; the node hands each ServiceReply event to `dispatch`, which runs the
+//matching callback with the node's own state. The `_blocking` methods wait for
+//the reply in place, which suits handlers that must answer before returning.
+
+//Runs once the request completes. A read yields the stored value, writes and
+//cas yield Null. The node and output are passed back in, so the callback can
+//update state, reply to a client or issue further requests.
+pub type Callback<N> = Box<dyn FnOnce(&mut N, Result<Value, ServiceError>, &mut dyn Write)>;
+
+//Runs a request again once its backoff has passed
+pub type Retry<N> = Box<dyn FnOnce(&mut N, &mut dyn Write)>;
+
+//A request that failed is tried at most this many times in all. Each retry waits a
+//random delay of up to BASE_BACKOFF, doubling with every attempt up to MAX_BACKOFF,
+//so nodes racing on one key spread out instead of colliding again.
+pub const MAX_ATTEMPTS: u32 = 8;
+const BASE_BACKOFF: Duration = Duration::from_millis(5);
+const MAX_BACKOFF: Duration = Duration::from_millis(500);
+
+//Maelstrom's error codes for a client request that failed on a service: timeout
+//when it may or may not have taken effect, temporarily unavailable when it did not
+pub const TIMEOUT: u64 = 0;
+pub const TEMPORARILY_UNAVAILABLE: u64 = 11;
+
+//How often a node using callbacks should tick, to time out requests lost to the
+//network with `expire` and run retries whose backoff has passed with `run_retries`
+pub const TICK: Duration = Duration::from_millis(10);
+
+//How long a node waits for the service before giving up on a request
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub trait KvNode {
+    //A node that answers clients from its KV callbacks. It gives its id, its error
+    //body and how it counts sent messages, and gets `reply` and `fail` from these.
+    type Body: Serialize;
+
+    fn node_id(&self) -> &str;
+    fn count_sent(&mut self);
+    fn error_body(in_reply_to: usize, code: u64, text: String) -> Self::Body;
+
+    fn reply(&mut self, client: String, body: Self::Body, output: &mut dyn Write) {
+        let mut message = MaelstromMessage {
+            src: self.node_id().to_owned(),
+            dest: client,
+            body,
+        };
+        message.send(output);
+        self.count_sent();
+    }
+
+    fn fail(
+        &mut self,
+        client: String,
+        msg_id: usize,
+        action: &str,
+        err: &ServiceError,
+        output: &mut dyn Write,
+    ) {
+        //! Answer a client whose request gave up on `err`, as `client_error` describes.
+        let (code, text) = client_error(action, err);
+        self.reply(client, Self::error_body(msg_id, code, text), output);
+    }
+}
+
+pub fn client_error(action: &str, err: &ServiceError) -> (u64, String) {
+    //! The code and text to answer a client whose request gave up on `err`. A write
+    //! that failed indefinitely may have applied, and retrying it could apply it
+    //! twice, so the client is told the outcome is unknown instead.
+    if err.is_definite() {
+        (
+            TEMPORARILY_UNAVAILABLE,
+            format!("{} failed: {}", action, err),
+        )
+    } else {
+        (
+            TIMEOUT,
+            format!("{} may or may not have applied: {}", action, err),
+        )
+    }
+}
+
+pub struct KvClient<N> {
+    pub service: String,
+    pub node_id: String,
+    //Outstanding callback requests by msg_id, with when they were sent
+    pending: HashMap<u64, (Instant, Callback<N>)>,
+    //Requests waiting out their backoff, with when they are due
+    retries: Vec<(Instant, Retry<N>)>,
+    rng: Rng,
+}
+
+impl<N> KvClient<N> {
+    pub fn new(service: &str, node_id: &str) -> Self {
+        KvClient {
+            service: service.to_owned(),
+            node_id: node_id.to_owned(),
+            pending: HashMap::new(),
+            retries: Vec::new(),
+            rng: Rng::from_time(),
+        }
+    }
+
+    pub fn seq(node_id: &str) -> Self {
+        KvClient::new(service::SEQ_KV, node_id)
+    }
+
+    pub fn lin(node_id: &str) -> Self {
+        KvClient::new(service::LIN_KV, node_id)
+    }
+
+    pub fn lww(node_id: &str) -> Self {
+        KvClient::new(service::LWW_KV, node_id)
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn backoff(
+        &mut self,
+        attempt: u32,
+        err: &ServiceError,
+        idempotent: bool,
+    ) -> Option<Duration> {
+        //! How long to wait before trying again a request whose `attempt`th try,
+        //! counting from 0, failed with `err`. None if it must not be retried: its
+        //! attempts are used up, or it may have applied and is not idempotent.
+        if attempt + 1 >= MAX_ATTEMPTS || !(idempotent || err.is_definite()) {
+            return None;
+        }
+        let cap = BASE_BACKOFF
+            .saturating_mul(1 << attempt.min(16))
+            .min(MAX_BACKOFF);
+        Some(cap.mul_f64(self.rng.next_f64()))
+    }
+
+    pub fn after(&mut self, delay: Duration, retry: impl FnOnce(&mut N, &mut dyn Write) + 'static) {
+        //! Run `retry` once `delay` has passed, from `run_retries`.
+        self.retries.push((Instant::now() + delay, Box::new(retry)));
+    }
+
+    fn send(
+        &mut self,
+        output: &mut dyn Write,
+        body: Value,
+        callback: impl FnOnce(&mut N, Result<Value, ServiceError>, &mut dyn Write) + 'static,
+    ) {
+        let msg_id = service::request(output, &self.node_id, &self.service, body);
+        self.pending
+            .insert(msg_id, (Instant::now(), Box::new(callback)));
+    }
+
+    pub fn read(
+        &mut self,
+        output: &mut dyn Write,
+        key: impl Serialize,
+        callback: impl FnOnce(&mut N, Result<Value, ServiceError>, &mut dyn Write) + 'static,
+    ) {
+        self.send(output, json!({"type": "read", "key": key}), callback);
+    }
+
+    pub fn write(
+        &mut self,
+        output: &mut dyn Write,
+        key: impl Serialize,
+        value: impl Serialize,
+        callback: impl FnOnce(&mut N, Result<Value, ServiceError>, &mut dyn Write) + 'static,
+    ) {
+        self.send(
+            output,
+            json!({"type": "write", "key": key, "value": value}),
+            callback,
+        );
+    }
+
+    pub fn cas(
+        &mut self,
+        output: &mut dyn Write,
+        key: impl Serialize,
+        from: impl Serialize,
+        to: impl Serialize,
+        create_if_not_exists: bool,
+        callback: impl FnOnce(&mut N, Result<Value, ServiceError>, &mut dyn Write) + 'static,
+    ) {
+        self.send(
+            output,
+            cas_body(key, from, to, create_if_not_exists),
+            callback,
+        );
+    }
+
+    pub fn resolve(
+        &mut self,
+        reply: &MaelstromMessage<Value>,
+    ) -> Option<(Callback<N>, Result<Value, ServiceError>)> {
+        //! Match a service reply to its pending request. Returns None if the
+        //! reply is from another service or answers no request of this client.
+        if reply.src != self.service {
+            return None;
+        }
+        let in_reply_to = reply.body.get("in_reply_to").and_then(Value::as_u64)?;
+        let (_, callback) = self.pending.remove(&in_reply_to)?;
+        Some((callback, outcome(&reply.body)))
+    }
+
+    pub fn dispatch(
+        node: &mut N,
+        client: impl Fn(&mut N) -> &mut KvClient<N>,
+        reply: &MaelstromMessage<Value>,
+        output: &mut dyn Write,
+    ) -> bool {
+        //! Run the callback a service reply completes, if it belongs to the client
+        //! that `client` picks out of the node. Returns whether it did.
+        match client(node).resolve(reply) {
+            Some((callback, result)) => {
+                callback(node, result, output);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn expire(
+        node: &mut N,
+        client: impl Fn(&mut N) -> &mut KvClient<N>,
+        timeout: Duration,
+        output: &mut dyn Write,
+    ) -> usize {
+        //! Fail requests that have waited longer than `timeout` with
+        //! ServiceError::Timeout, returning how many there were. Nodes call this
+        //! from a timer, since a reply lost to a partition never arrives.
+        let client = client(node);
+        let expired: Vec<u64> = client
+            .pending
+            .iter()
+            .filter(|(_, (sent, _))| sent.elapsed() >= timeout)
+            .map(|(msg_id, _)| *msg_id)
+            .collect();
+        let callbacks: Vec<Callback<N>> = expired
+            .iter()
+            .filter_map(|msg_id| client.pending.remove(msg_id))
+            .map(|(_, callback)| callback)
+            .collect();
+        let count = callbacks.len();
+        for callback in callbacks {
+            callback(node, Err(ServiceError::Timeout), output);
+        }
+        count
+    }
+
+    pub fn run_retries(
+        node: &mut N,
+        client: impl Fn(&mut N) -> &mut KvClient<N>,
+        output: &mut dyn Write,
+    ) -> usize {
+        //! Run the retries whose backoff has passed, returning how many there were.
+        //! Nodes call this from a timer, alongside `expire`.
+        let now = Instant::now();
+        let (due, waiting) = std::mem::take(&mut client(node).retries)
+            .into_iter()
+            .partition(|(at, _)| *at <= now);
+        client(node).retries = waiting;
+        let due: Vec<(Instant, Retry<N>)> = due;
+        let count = due.len();
+        for (_, retry) in due {
+            retry(node, output);
+        }
+        count
+    }
+
+    pub fn read_blocking(
+        &self,
+        output: &mut dyn Write,
+        key: impl Serialize,
+        timeout: Duration,
+    ) -> Result<Value, ServiceError> {
+        let body = json!({"type": "read", "key": key});
+        let reply = self.call_blocking(output, body, timeout)?;
+        Ok(reply.get("value").cloned().unwrap_or(Value::Null))
+    }
+
+    pub fn write_blocking(
+        &self,
+        output: &mut dyn Write,
+        key: impl Serialize,
+        value: impl Serialize,
+        timeout: Duration,
+    ) -> Result<(), ServiceError> {
+        let body = json!({"type": "write", "key": key, "value": value});
+        self.call_blocking(output, body, timeout).map(|_| ())
+    }
+
+    pub fn cas_blocking(
+        &self,
+        output: &mut dyn Write,
+        key: impl Serialize,
+        from: impl Serialize,
+        to: impl Serialize,
+        create_if_not_exists: bool,
+        timeout: Duration,
+    ) -> Result<(), ServiceError> {
+        let body = cas_body(key, from, to, create_if_not_exists);
+        self.call_blocking(output, body, timeout).map(|_| ())
+    }
+
+    fn call_blocking(
+        &self,
+        output: &mut dyn Write,
+        body: Value,
+        timeout: Duration,
+    ) -> Result<Value, ServiceError> {
+        service::call_blocking(output, &self.node_id, &self.service, body, timeout)
+    }
+}
+
+fn cas_body(
+    key: impl Serialize,
+    from: impl Serialize,
+    to: impl Serialize,
+    create_if_not_exists: bool,
+) -> Value {
+    json!({
+        "type": "cas",
+        "key": key,
+        "from": from,
+        "to": to,
+        "create_if_not_exists": create_if_not_exists,
+    })
+}
+
+fn outcome(body: &Value) -> Result<Value, ServiceError> {
+    //! A read_ok yields its value, write_ok and cas_ok yield Null.
+    let body = service::reply_result(body)?;
+    Ok(body.get("value").cloned().unwrap_or(Value::Null))
+}
@@ -0,0 +1,333 @@
+use crate::node::MaelstromMessage;
+use crate::rng::Rng;
+use crate::runner::network::Latency;
+use crate::service;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+//Stand-ins for Maelstrom's key-value services, speaking the same JSON protocol,
+//so service-backed nodes can be run by the local runner or driven in-process.
+//Each keeps to its service's consistency model:
+//  lin-kv: one copy of every key, so every operation is linearizable
+//  seq-kv: one total order of writes, but a read may be served from any recent
+//          state no older than what that client has already seen
+//  lww-kv: each client sees its own writes at once and everyone else's after a
+//          propagation delay, and concurrent writes are settled by timestamp
+//A lin-tso stand-in hands out timestamps from a single counter.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalConfig {
+    //How long a service takes to answer a request
+    pub latency: Latency,
+    //A seq-kv read may be served from a state up to this many writes old...
+    pub max_lag: usize,
+    //...that was replaced no longer ago than this
+    pub max_age: Duration,
+    //How long an lww-kv write takes to reach clients other than its writer
+    pub propagation: Latency,
+    //Each lww-kv client's clock is off by up to this many milliseconds, so a
+    //later write can lose to an earlier one
+    pub clock_skew_ms: f64,
+    pub seed: u64,
+}
+
+impl Default for LocalConfig {
+    fn default() -> Self {
+        LocalConfig {
+            latency: Latency::Constant { ms: 0.0 },
+            max_lag: 16,
+            max_age: Duration::from_secs(1),
+            propagation: Latency::Constant { ms: 100.0 },
+            clock_skew_ms: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+//What separates the services is which value a client reads, and which value its
+//cas compares against. Requests are otherwise handled the same way.
+trait Store {
+    fn read(&mut self, client: &str, key: &str, now: Instant, rng: &mut Rng) -> Option<Value>;
+    //The value a cas from this client compares against
+    fn current(&mut self, client: &str, key: &str, now: Instant) -> Option<Value>;
+    fn write(&mut self, client: &str, key: &str, value: Value, now: Instant, rng: &mut Rng);
+}
+
+#[derive(Debug, Default)]
+struct LinKv {
+    values: HashMap<String, Value>,
+}
+
+impl Store for LinKv {
+    fn read(&mut self, client: &str, key: &str, now: Instant, _: &mut Rng) -> Option<Value> {
+        self.current(client, key, now)
+    }
+
+    fn current(&mut self, _: &str, key: &str, _: Instant) -> Option<Value> {
+        self.values.get(key).cloned()
+    }
+
+    fn write(&mut self, _: &str, key: &str, value: Value, _: Instant, _: &mut Rng) {
+        self.values.insert(key.to_owned(), value);
+    }
+}
+
+#[derive(Debug)]
+struct SeqKv {
+    max_lag: usize,
+    max_age: Duration,
+    //Number of writes so far. State `v` is the state after the first v writes.
+    latest: usize,
+    //When each retained state was replaced by the next write, oldest first
+    replaced_at: VecDeque<(usize, Instant)>,
+    //Each key's writes as (version written, value), oldest first
+    versions: HashMap<String, Vec<(usize, Value)>>,
+    //The latest state each client has observed. Its reads never go back past it.
+    seen: HashMap<String, usize>,
+}
+
+impl SeqKv {
+    fn new(config: &LocalConfig) -> Self {
+        SeqKv {
+            max_lag: config.max_lag,
+            max_age: config.max_age,
+            latest: 0,
+            replaced_at: VecDeque::new(),
+            versions: HashMap::new(),
+            seen: HashMap::new(),
+        }
+    }
+
+    fn value_at(&self, key: &str, version: usize) -> Option<Value> {
+        let versions = self.versions.get(key)?;
+        let index = versions.partition_point(|(written, _)| *written <= version);
+        versions[..index].last().map(|(_, value)| value.clone())
+    }
+
+    fn oldest_readable(&self, client: &str, now: Instant) -> usize {
+        //! The oldest state a read from this client may be served from.
+        let seen = self.seen.get(client).copied().unwrap_or(0);
+        let lag = self.latest.saturating_sub(self.max_lag);
+        let age = self
+            .replaced_at
+            .iter()
+            .find(|(_, replaced)| now.saturating_duration_since(*replaced) <= self.max_age)
+            .map_or(self.latest, |(version, _)| *version);
+        seen.max(lag).max(age)
+    }
+}
+
+impl Store for SeqKv {
+    fn read(&mut self, client: &str, key: &str, now: Instant, rng: &mut Rng) -> Option<Value> {
+        let oldest = self.oldest_readable(client, now);
+        let version = oldest + rng.below(self.latest - oldest + 1);
+        self.seen.insert(client.to_owned(), version);
+        self.value_at(key, version)
+    }
+
+    fn current(&mut self, client: &str, key: &str, _: Instant) -> Option<Value> {
+        self.seen.insert(client.to_owned(), self.latest);
+        self.value_at(key, self.latest)
+    }
+
+    fn write(&mut self, client: &str, key: &str, value: Value, now: Instant, _: &mut Rng) {
+        self.replaced_at.push_back((self.latest, now));
+        self.latest += 1;
+        self.seen.insert(client.to_owned(), self.latest);
+
+        //States further back than max_lag can never be read again.
+        let floor = self.latest.saturating_sub(self.max_lag);
+        while self
+            .replaced_at
+            .front()
+            .is_some_and(|(version, _)| *version < floor)
+        {
+            self.replaced_at.pop_front();
+        }
+        let versions = self.versions.entry(key.to_owned()).or_default();
+        versions.push((self.latest, value));
+        let superseded = versions
+            .iter()
+            .skip(1)
+            .take_while(|(version, _)| *version <= floor)
+            .count();
+        versions.drain(..superseded);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LwwWrite {
+    //The writer's clock, then its id to break ties
+    stamp: (u64, String),
+    value: Value,
+    //When other clients start to see the write
+    visible_at: Instant,
+}
+
+#[derive(Debug)]
+struct LwwKv {
+    start: Instant,
+    propagation: Latency,
+    clock_skew_ms: f64,
+    //Each client's clock offset in nanoseconds, picked on its first write
+    offsets: HashMap<String, i64>,
+    writes: HashMap<String, Vec<LwwWrite>>,
+}
+
+impl LwwKv {
+    fn new(config: &LocalConfig) -> Self {
+        LwwKv {
+            start: Instant::now(),
+            propagation: config.propagation,
+            clock_skew_ms: config.clock_skew_ms,
+            offsets: HashMap::new(),
+            writes: HashMap::new(),
+        }
+    }
+}
+
+impl Store for LwwKv {
+    fn read(&mut self, client: &str, key: &str, now: Instant, _: &mut Rng) -> Option<Value> {
+        self.current(client, key, now)
+    }
+
+    fn current(&mut self, client: &str, key: &str, now: Instant) -> Option<Value> {
+        self.writes
+            .get(key)?
+            .iter()
+            .filter(|write| write.stamp.1 == client || write.visible_at <= now)
+            .max_by(|left, right| left.stamp.cmp(&right.stamp))
+            .map(|write| write.value.clone())
+    }
+
+    fn write(&mut self, client: &str, key: &str, value: Value, now: Instant, rng: &mut Rng) {
+        let skew_ms = self.clock_skew_ms;
+        let offset = *self
+            .offsets
+            .entry(client.to_owned())
+            .or_insert_with(|| ((rng.next_f64() * 2.0 - 1.0) * skew_ms * 1e6) as i64);
+        let clock = now.saturating_duration_since(self.start).as_nanos() as i64 + offset;
+        let write = LwwWrite {
+            stamp: (clock.max(0) as u64, client.to_owned()),
+            value,
+            visible_at: now + self.propagation.sample(rng),
+        };
+        let writes = self.writes.entry(key.to_owned()).or_default();
+        writes.push(write);
+        //Once the winning write is visible everywhere, nothing it beats can be read again.
+        if let Some(winner) = writes
+            .iter()
+            .filter(|write| write.visible_at <= now)
+            .max_by(|left, right| left.stamp.cmp(&right.stamp))
+            .cloned()
+        {
+            writes.retain(|write| write.stamp >= winner.stamp);
+        }
+    }
+}
+
+pub struct LocalServices {
+    latency: Latency,
+    rng: Rng,
+    seq_kv: SeqKv,
+    lin_kv: LinKv,
+    lww_kv: LwwKv,
+    //The last timestamp lin-tso gave out
+    lin_tso: u64,
+}
+
+impl LocalServices {
+    pub fn new(config: LocalConfig) -> Self {
+        LocalServices {
+            latency: config.latency,
+            rng: Rng::seeded(config.seed),
+            seq_kv: SeqKv::new(&config),
+            lin_kv: LinKv::default(),
+            lww_kv: LwwKv::new(&config),
+            lin_tso: 0,
+        }
+    }
+
+    pub fn hosts(&self, node_id: &str) -> bool {
+        service::is_service(node_id)
+    }
+
+    pub fn handle(
+        &mut self,
+        request: &MaelstromMessage<Value>,
+    ) -> Option<(Duration, MaelstromMessage<Value>)> {
+        //! Apply a request to the service it is addressed to, returning the reply
+        //! and how long to hold it back. None if no service here has that id.
+        let now = Instant::now();
+        let client = &request.src;
+        let mut body = match request.dest.as_str() {
+            service::SEQ_KV => apply(&mut self.seq_kv, client, &request.body, now, &mut self.rng),
+            service::LIN_KV => apply(&mut self.lin_kv, client, &request.body, now, &mut self.rng),
+            service::LWW_KV => apply(&mut self.lww_kv, client, &request.body, now, &mut self.rng),
+            service::LIN_TSO => self.timestamp(&request.body),
+            _ => return None,
+        };
+        if let Some(msg_id) = request.body.get("msg_id") {
+            body["in_reply_to"] = msg_id.clone();
+        }
+        let reply = MaelstromMessage {
+            src: request.dest.clone(),
+            dest: request.src.clone(),
+            body,
+        };
+        Some((self.latency.sample(&mut self.rng), reply))
+    }
+
+    fn timestamp(&mut self, body: &Value) -> Value {
+        match body.get("type").and_then(Value::as_str) {
+            Some("ts") => {
+                self.lin_tso += 1;
+                json!({"type": "ts_ok", "ts": self.lin_tso})
+            }
+            _ => error(10, format!("unsupported request {}", body)),
+        }
+    }
+}
+
+fn error(code: u64, text: String) -> Value {
+    json!({"type": "error", "code": code, "text": text})
+}
+
+fn apply(store: &mut dyn Store, client: &str, body: &Value, now: Instant, rng: &mut Rng) -> Value {
+    //Keys are compared as JSON, so the key 1 and the key "1" are different keys.
+    let key = body.get("key").unwrap_or(&Value::Null);
+    let key_text = key.to_string();
+    match body.get("type").and_then(Value::as_str) {
+        Some("read") => match store.read(client, &key_text, now, rng) {
+            Some(value) => json!({"type": "read_ok", "value": value}),
+            None => error(20, format!("key {} does not exist", key)),
+        },
+        Some("write") => {
+            let value = body.get("value").cloned().unwrap_or(Value::Null);
+            store.write(client, &key_text, value, now, rng);
+            json!({"type": "write_ok"})
+        }
+        Some("cas") => {
+            let from = body.get("from").unwrap_or(&Value::Null);
+            let to = body.get("to").cloned().unwrap_or(Value::Null);
+            let create = body
+                .get("create_if_not_exists")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            match store.current(client, &key_text, now) {
+                Some(current) if &current == from => {
+                    store.write(client, &key_text, to, now, rng);
+                    json!({"type": "cas_ok"})
+                }
+                Some(current) => error(22, format!("current value {} is not {}", current, from)),
+                None if create => {
+                    store.write(client, &key_text, to, now, rng);
+                    json!({"type": "cas_ok"})
+                }
+                None => error(20, format!("key {} does not exist", key)),
+            }
+        }
+        _ => error(10, format!("unsupported request {}", body)),
+    }
+}
@@ -0,0 +1,170 @@
+use crate::node::MaelstromMessage;
+use crate::service::{self, ServiceError};
+use serde_json::{json, Value};
+use std::env;
+use std::io::Write;
+use std::mem;
+use std::time::{Duration, Instant};
+
+//A client for Maelstrom's lin-tso timestamp oracle, which answers each `ts`
+//request with a `ts_ok` holding a timestamp greater than any it gave out before.
+//
+//At most one request is outstanding. Callers that ask while it is in flight are
+//batched onto the next request, and every caller in a batch gets the same
+//timestamp. They cannot share the request already in flight: its timestamp may
+//be older than one another caller saw before they asked.
+
+pub type Callback<N> = Box<dyn FnOnce(&mut N, Result<u64, ServiceError>, &mut dyn Write)>;
+
+//Set to `lin-tso` to have nodes that support it take timestamps from lin-tso
+//instead of their local clock.
+pub const TIMESTAMPS_ENV: &str = "EVENT_HORIZON_TIMESTAMPS";
+
+struct Batch<N> {
+    msg_id: u64,
+    sent: Instant,
+    callbacks: Vec<Callback<N>>,
+}
+
+pub struct TsoClient<N> {
+    pub node_id: String,
+    in_flight: Option<Batch<N>>,
+    //Callers waiting for the next request
+    queued: Vec<Callback<N>>,
+    //Requests sent so far, for comparing against the number of callers
+    pub requests_sent: u64,
+}
+
+impl<N> TsoClient<N> {
+    pub fn new(node_id: &str) -> Self {
+        TsoClient {
+            node_id: node_id.to_owned(),
+            in_flight: None,
+            queued: Vec::new(),
+            requests_sent: 0,
+        }
+    }
+
+    pub fn from_env(node_id: &str) -> Option<Self> {
+        //! A client if EVENT_HORIZON_TIMESTAMPS is `lin-tso`, otherwise None.
+        match env::var(TIMESTAMPS_ENV).as_deref() {
+            Ok(service::LIN_TSO) => Some(TsoClient::new(node_id)),
+            Ok("clock") | Err(_) => None,
+            Ok(other) => panic!("Unknown {}: {}", TIMESTAMPS_ENV, other),
+        }
+    }
+
+    pub fn waiting(&self) -> usize {
+        //! Callers waiting for a timestamp, in flight or queued.
+        self.in_flight
+            .as_ref()
+            .map_or(0, |batch| batch.callbacks.len())
+            + self.queued.len()
+    }
+
+    pub fn timestamp(
+        &mut self,
+        output: &mut dyn Write,
+        callback: impl FnOnce(&mut N, Result<u64, ServiceError>, &mut dyn Write) + 'static,
+    ) {
+        //! Ask for a timestamp, sending a request now if none is in flight.
+        self.queued.push(Box::new(callback));
+        if self.in_flight.is_none() {
+            self.send_queued(output);
+        }
+    }
+
+    fn send_queued(&mut self, output: &mut dyn Write) {
+        if self.queued.is_empty() {
+            return;
+        }
+        let msg_id = service::request(
+            output,
+            &self.node_id,
+            service::LIN_TSO,
+            json!({"type": "ts"}),
+        );
+        self.requests_sent += 1;
+        self.in_flight = Some(Batch {
+            msg_id,
+            sent: Instant::now(),
+            callbacks: mem::take(&mut self.queued),
+        });
+    }
+
+    pub fn dispatch(
+        node: &mut N,
+        client: impl Fn(&mut N) -> &mut TsoClient<N>,
+        reply: &MaelstromMessage<Value>,
+        output: &mut dyn Write,
+    ) -> bool {
+        //! Complete the batch a lin-tso reply answers and send the next one.
+        //! Returns whether the reply answered the request in flight.
+        let tso = client(node);
+        let answers_in_flight = reply.src == service::LIN_TSO
+            && tso.in_flight.as_ref().is_some_and(|batch| {
+                reply.body.get("in_reply_to").and_then(Value::as_u64) == Some(batch.msg_id)
+            });
+        if !answers_in_flight {
+            return false;
+        }
+        let batch = tso.in_flight.take().expect("A request is in flight");
+        tso.send_queued(output);
+        let result = service::reply_result(&reply.body).and_then(timestamp_of);
+        for callback in batch.callbacks {
+            callback(node, result.clone(), output);
+        }
+        true
+    }
+
+    pub fn expire(
+        node: &mut N,
+        client: impl Fn(&mut N) -> &mut TsoClient<N>,
+        timeout: Duration,
+        output: &mut dyn Write,
+    ) -> usize {
+        //! Fail the batch in flight with ServiceError::Timeout if it has waited
+        //! longer than `timeout`, and send the queued callers a fresh request.
+        //! Returns how many callers failed.
+        let tso = client(node);
+        if tso
+            .in_flight
+            .as_ref()
+            .is_none_or(|batch| batch.sent.elapsed() < timeout)
+        {
+            return 0;
+        }
+        let batch = tso.in_flight.take().expect("A request is in flight");
+        tso.send_queued(output);
+        let count = batch.callbacks.len();
+        for callback in batch.callbacks {
+            callback(node, Err(ServiceError::Timeout), output);
+        }
+        count
+    }
+
+    pub fn timestamp_blocking(
+        &self,
+        output: &mut dyn Write,
+        timeout: Duration,
+    ) -> Result<u64, ServiceError> {
+        //! Wait for a timestamp of this caller's own, without batching.
+        let reply = service::call_blocking(
+            output,
+            &self.node_id,
+            service::LIN_TSO,
+            json!({"type": "ts"}),
+            timeout,
+        )?;
+        timestamp_of(&reply)
+    }
+}
+
+fn timestamp_of(body: &Value) -> Result<u64, ServiceError> {
+    body.get("ts")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| ServiceError::Other {
+            code: 0,
+            text: format!("ts_ok without a timestamp: {}", body),
+        })
+}
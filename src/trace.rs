@@ -0,0 +1,39 @@
+use crate::node::MaelstromMessage;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TraceEntry {
+    //Nanoseconds since the start of the trace, when the message was sent
+    pub time: u64,
+    #[serde(flatten)]
+    pub message: MaelstromMessage<Value>,
+    //When the message reached its destination. None if it was dropped,
+    //or if the recorder only saw one side of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received: Option<u64>,
+}
+
+pub fn read_jsonl(reader: impl BufRead) -> io::Result<Vec<TraceEntry>> {
+    //! Read a trace with one message per line. Blank lines are skipped.
+    let mut trace = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        trace.push(entry);
+    }
+    Ok(trace)
+}
+
+pub fn write_jsonl(trace: &[TraceEntry], mut writer: impl Write) -> io::Result<()> {
+    for entry in trace.iter() {
+        serde_json::to_writer(&mut writer, entry)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
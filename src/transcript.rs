@@ -0,0 +1,193 @@
+use crate::init::MaelstromInit;
+use crate::node::parse_event;
+use crate::{Event, Node, Reply};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::channel;
+
+//A transcript drives a single node through a script of inbound messages and timer
+//ticks, one JSON value per line, and collects everything the node writes out.
+//The first line must be the node's init message. Every other line is either a
+//Maelstrom message or a tick such as `{"tick": "propogate_writes"}`.
+//Blank lines and lines starting with `//` are skipped.
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Tick {
+    PropogateWrites,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptError {
+    //1-based line number in the transcript
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TranscriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for TranscriptError {}
+
+pub fn run<Body, NodeState>(transcript: &str) -> Result<Vec<Value>, TranscriptError>
+where
+    NodeState: Node<Body>,
+    Body: DeserializeOwned + Reply<NodeState>,
+{
+    //! Run a transcript through the node's real Node and Reply implementations,
+    //! returning every message it writes, the init_ok included, in order.
+    //! Timer threads the node spawns are never listened to, so only the
+    //! transcript's ticks fire timed events.
+    let mut lines = transcript
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with("//"));
+    let mut output = Vec::new();
+
+    let (line_number, init_line) = lines.next().ok_or_else(|| TranscriptError {
+        line: 1,
+        message: "transcript is empty, expected an init message".to_owned(),
+    })?;
+    let init: MaelstromInit = parse(line_number, init_line)?;
+    let node_metadata = init.respond(&mut output);
+
+    let (event_tx, event_rx) = channel();
+    let mut node = NodeState::node_init(node_metadata, event_tx);
+    for (line_number, line) in lines {
+        let mut step: Value = parse(line_number, line)?;
+        let event = match step.get_mut("tick").map(Value::take) {
+            Some(tick) => match from_value(line_number, tick)? {
+                Tick::PropogateWrites => Event::PropogateWrites,
+            },
+            //Messages from services such as lin-kv become ServiceReply events.
+            None => match parse_event(line) {
+                Ok(Some(event)) => event,
+                Ok(None) => continue,
+                Err(err) => {
+                    return Err(TranscriptError {
+                        line: line_number,
+                        message: format!("could not parse {}: {}", line, err),
+                    })
+                }
+            },
+        };
+        node.handle_event(event, &mut output);
+    }
+    drop(event_rx);
+
+    String::from_utf8_lossy(&output)
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|err| TranscriptError {
+                line: index + 1,
+                message: format!("node wrote invalid JSON: {}", err),
+            })
+        })
+        .collect()
+}
+
+fn parse<T: DeserializeOwned>(line_number: usize, line: &str) -> Result<T, TranscriptError> {
+    serde_json::from_str(line).map_err(|err| TranscriptError {
+        line: line_number,
+        message: format!("could not parse {}: {}", line, err),
+    })
+}
+
+fn from_value<T: DeserializeOwned>(line_number: usize, value: Value) -> Result<T, TranscriptError> {
+    serde_json::from_value(value).map_err(|err| TranscriptError {
+        line: line_number,
+        message: err.to_string(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    //1-based line number in the output
+    pub line: usize,
+    //Where in the line's JSON the values differ, such as `body.messages[2]`
+    pub path: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "the whole line"
+        } else {
+            &self.path
+        };
+        write!(
+            f,
+            "line {}, at {}: expected {}, got {}",
+            self.line, path, self.expected, self.actual
+        )
+    }
+}
+
+pub fn compare(expected: &[Value], actual: &[Value]) -> Result<(), Mismatch> {
+    //! Compare output line by line as JSON values, so field order and formatting
+    //! do not matter. Returns the first difference found.
+    for line in 0..expected.len().max(actual.len()) {
+        let mismatch = match (expected.get(line), actual.get(line)) {
+            (Some(expected), Some(actual)) => compare_value(expected, actual, String::new()),
+            (expected, actual) => Some((String::new(), render(expected), render(actual))),
+        };
+        if let Some((path, expected, actual)) = mismatch {
+            return Err(Mismatch {
+                line: line + 1,
+                path,
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn compare_value(
+    expected: &Value,
+    actual: &Value,
+    path: String,
+) -> Option<(String, String, String)> {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            let mut fields: Vec<&String> = expected.keys().chain(actual.keys()).collect();
+            fields.sort_unstable();
+            fields.dedup();
+            fields.into_iter().find_map(|field| {
+                let path = if path.is_empty() {
+                    field.clone()
+                } else {
+                    format!("{}.{}", path, field)
+                };
+                match (expected.get(field), actual.get(field)) {
+                    (Some(expected), Some(actual)) => compare_value(expected, actual, path),
+                    (expected, actual) => Some((path, render(expected), render(actual))),
+                }
+            })
+        }
+        (Value::Array(expected), Value::Array(actual)) => (0..expected.len().max(actual.len()))
+            .find_map(|index| {
+                let path = format!("{}[{}]", path, index);
+                match (expected.get(index), actual.get(index)) {
+                    (Some(expected), Some(actual)) => compare_value(expected, actual, path),
+                    (expected, actual) => Some((path, render(expected), render(actual))),
+                }
+            }),
+        (expected, actual) if expected == actual => None,
+        (expected, actual) => Some((path, expected.to_string(), actual.to_string())),
+    }
+}
+
+fn render(value: Option<&Value>) -> String {
+    value.map_or_else(|| "nothing".to_owned(), Value::to_string)
+}
@@ -0,0 +1,124 @@
+//Digest anti-entropy: the range digests themselves, and two nodes repairing a
+//difference by pulling only what is missing.
+
+mod common;
+
+use event_horizon::node::broadcast::digest::{self, RangeDigest};
+use event_horizon::node::broadcast::interval_set::IntervalSet;
+use event_horizon::node::broadcast::{BroadcastBody, BroadcastNode};
+use event_horizon::{Event, MaelstromMessage, Node};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+
+#[test]
+fn digests_match_only_equal_sets() {
+    let mine: IntervalSet = (0..100).collect();
+    let mut theirs = mine.clone();
+    assert!(
+        RangeDigest::of(&mine, 0, usize::MAX).matches(&RangeDigest::of(&theirs, 0, usize::MAX))
+    );
+    theirs = theirs.difference(&[40].into_iter().collect());
+    theirs.insert(1000);
+    assert!(
+        !RangeDigest::of(&mine, 0, usize::MAX).matches(&RangeDigest::of(&theirs, 0, usize::MAX))
+    );
+    //Outside the changed values the ranges still agree.
+    assert!(RangeDigest::of(&mine, 0, 39).matches(&RangeDigest::of(&theirs, 0, 39)));
+    //A range that cuts runs counts and hashes only the values inside it.
+    let part: IntervalSet = (20..=60).collect();
+    assert_eq!(
+        RangeDigest::of(&mine, 20, 60),
+        RangeDigest::of(&part, 20, 60)
+    );
+    assert_eq!(RangeDigest::of(&mine, 20, 60).2, 41);
+}
+
+#[test]
+fn split_covers_the_range() {
+    let set: IntervalSet = (0..1000).map(|value| value * 3).collect();
+    let ranges = digest::split(&set, 0, usize::MAX, 16);
+    assert_eq!(ranges.len(), 16);
+    assert_eq!(ranges[0].0, 0);
+    assert_eq!(ranges[ranges.len() - 1].1, usize::MAX);
+    for pair in ranges.windows(2) {
+        assert_eq!(pair[0].1 + 1, pair[1].0);
+    }
+    //Parts hold about as many values each, even where values run together.
+    let set: IntervalSet = (0..800).chain(10_000..10_800).collect();
+    let ranges = digest::split(&set, 0, usize::MAX, 16);
+    assert_eq!(ranges.len(), 16);
+    for (start, end) in ranges {
+        assert_eq!(RangeDigest::of(&set, start, end).2, 100);
+    }
+    //A range with fewer values than parts still splits without empty ranges.
+    let ranges = digest::split(&[5, 6].into_iter().collect(), 5, 6, 16);
+    assert_eq!(ranges, vec![(5, 5), (6, 6)]);
+}
+
+fn node(node_id: &str, messages: impl Iterator<Item = usize>) -> BroadcastNode {
+    //! One of two nodes that neighbor each other, repairing by digest.
+    let mut node = common::node(
+        node_id,
+        &common::node_ids(2),
+        &[
+            ("EVENT_HORIZON_GOSSIP", "digest"),
+            ("EVENT_HORIZON_TOPOLOGY", "star"),
+        ],
+    );
+    let ids: IntervalSet = messages.collect();
+    node.deliver(&ids, ids.iter().map(|id| json!(id)).collect(), Vec::new());
+    node
+}
+
+#[test]
+fn round_pulls_only_missing_messages() {
+    //n2 lacks 12 and the values above 7000 that n1 has, and has two n1 lacks.
+    let n1 = (0..5000).chain([7001, 7002, 9000]);
+    let n2 = (0..5000).filter(|value| *value != 12).chain([6000, 8000]);
+    let mut nodes = BTreeMap::from([
+        ("n1".to_owned(), node("n1", n1)),
+        ("n2".to_owned(), node("n2", n2)),
+    ]);
+    let mut in_flight = VecDeque::new();
+    let mut shipped = 0;
+    let handle = |nodes: &mut BTreeMap<String, BroadcastNode>,
+                  in_flight: &mut VecDeque<MaelstromMessage<Value>>,
+                  node_id: &str,
+                  event: Event<BroadcastBody>| {
+        let mut output = Vec::new();
+        nodes
+            .get_mut(node_id)
+            .unwrap()
+            .handle_event(event, &mut output);
+        for line in String::from_utf8(output).unwrap().lines() {
+            in_flight.push_back(serde_json::from_str(line).unwrap());
+        }
+    };
+    //Each node pulls what the other's digest shows it lacks.
+    handle(&mut nodes, &mut in_flight, "n1", Event::PropogateWrites);
+    handle(&mut nodes, &mut in_flight, "n2", Event::PropogateWrites);
+    while let Some(message) = in_flight.pop_front() {
+        for field in ["messages", "have"] {
+            shipped += serde_json::from_value::<IntervalSet>(message.body[field].clone())
+                .map_or(0, |ids| ids.len());
+        }
+        let dest = message.dest.clone();
+        let line = serde_json::to_string(&message).unwrap();
+        handle(
+            &mut nodes,
+            &mut in_flight,
+            &dest,
+            Event::Message(serde_json::from_str(&line).unwrap()),
+        );
+    }
+    assert_eq!(nodes["n1"].messages, nodes["n2"].messages);
+    assert_eq!(nodes["n1"].messages.len(), 5005);
+    assert_eq!(nodes["n1"].payloads, nodes["n2"].payloads);
+    //Leaves hold at most a handful of values each, so far fewer than all 5000 move.
+    assert!(shipped < 500, "Shipped {} values", shipped);
+    let pulled: u64 = nodes
+        .values()
+        .map(|node| node.anti_entropy.as_ref().unwrap().pulled)
+        .sum();
+    assert_eq!(pulled, 6);
+}
@@ -0,0 +1,128 @@
+//Causal delivery: messages held until what their clocks count has been delivered,
+//and reads that list messages in an order consistent with those clocks.
+
+mod common;
+
+use common::Net;
+use event_horizon::node::broadcast::MessageId;
+use event_horizon::rng::Rng;
+use serde_json::{json, Value};
+
+fn causal(count: usize) -> Net {
+    Net::mesh(count, &[("EVENT_HORIZON_ORDER", "causal")])
+}
+
+fn read_clock(net: &mut Net, node_id: &str) -> Value {
+    net.client(node_id, json!({"type": "read_clock", "msg_id": 1}))["clock"].take()
+}
+
+#[test]
+fn a_reply_waits_for_what_it_answers() {
+    let mut net = causal(3);
+    net.broadcast("n1", json!("question"));
+    net.tick("n1");
+    //n2 hears the question and answers it, but n3 only hears the answer.
+    let to_n3 = net.take_to("n3");
+    net.deliver(0);
+    net.broadcast("n2", json!("answer"));
+    let answer = MessageId { origin: 1, seq: 0 }.pack();
+    let clock = net.nodes["n2"].causal.as_ref().unwrap().clock_of(answer);
+    assert_eq!(clock, vec![1, 1]);
+    net.in_flight.clear();
+    net.in_flight.push(
+        serde_json::from_value(json!({"src": "n2", "dest": "n3", "body": {
+            "type": "gossip", "msg_id": 1, "message": [answer], "payloads": ["answer"],
+            "clocks": [clock]}}))
+        .unwrap(),
+    );
+    net.deliver(0);
+    assert!(net.read("n3").is_empty());
+    assert_eq!(net.nodes["n3"].causal.as_ref().unwrap().held(), 1);
+    assert_eq!(
+        read_clock(&mut net, "n3"),
+        json!({"n1": 0, "n2": 0, "n3": 0})
+    );
+
+    //Once the question arrives both are delivered, question first.
+    net.in_flight = to_n3;
+    net.deliver(0);
+    assert_eq!(net.read("n3"), vec![json!("question"), json!("answer")]);
+    assert_eq!(net.nodes["n3"].causal.as_ref().unwrap().held(), 0);
+    assert_eq!(
+        read_clock(&mut net, "n3"),
+        json!({"n1": 1, "n2": 1, "n3": 0})
+    );
+}
+
+#[test]
+fn clock_counts_contiguous_broadcasts_without_causal_delivery() {
+    let mut net = Net::mesh(2, &[]);
+    for message in 0..3 {
+        net.broadcast("n1", json!(message));
+    }
+    //n2 hears only n1's first and third broadcasts.
+    let first = MessageId { origin: 0, seq: 0 }.pack();
+    let third = MessageId { origin: 0, seq: 2 }.pack();
+    net.in_flight.push(
+        serde_json::from_value(json!({"src": "n1", "dest": "n2", "body": {
+            "type": "gossip", "msg_id": 1, "message": [first, third], "payloads": [0, 2]}}))
+        .unwrap(),
+    );
+    net.deliver(0);
+    assert_eq!(read_clock(&mut net, "n1"), json!({"n1": 3, "n2": 0}));
+    assert_eq!(read_clock(&mut net, "n2"), json!({"n1": 1, "n2": 0}));
+}
+
+#[test]
+fn reads_respect_causality_under_any_interleaving() {
+    for seed in 0..20 {
+        let mut rng = Rng::seeded(seed);
+        let mut net = causal(4);
+        let node_ids: Vec<String> = net.nodes.keys().cloned().collect();
+        for step in 0..300 {
+            let node_id = rng.choose(&node_ids).unwrap().clone();
+            match rng.below(10) {
+                0 => net.broadcast(&node_id, json!(step)),
+                1 | 2 => net.tick(&node_id),
+                _ if !net.in_flight.is_empty() => {
+                    let index = rng.below(net.in_flight.len());
+                    net.deliver(index);
+                }
+                _ => {}
+            }
+        }
+        //Then let gossip finish, in order.
+        for _ in 0..10 {
+            for node_id in &node_ids {
+                net.tick(node_id);
+            }
+            while !net.in_flight.is_empty() {
+                net.deliver(0);
+            }
+        }
+
+        let expected = net.nodes["n1"].messages.clone();
+        for (node_id, node) in &net.nodes {
+            let causal = node.causal.as_ref().unwrap();
+            assert_eq!(node.messages, expected, "seed {}", seed);
+            assert_eq!(causal.held(), 0, "seed {} {}", seed, node_id);
+            assert_eq!(causal.order.len(), expected.len(), "seed {}", seed);
+            //Everything a message's clock counts was delivered before it.
+            let mut delivered: Vec<usize> = vec![0; node_ids.len()];
+            for id in &causal.order {
+                let MessageId { origin, seq } = MessageId::unpack(*id);
+                assert_eq!(delivered[origin], seq, "seed {} {}", seed, node_id);
+                for (other, count) in causal.clock_of(*id).iter().enumerate() {
+                    assert!(
+                        other == origin || delivered[other] >= *count,
+                        "seed {} {}: {} delivered early",
+                        seed,
+                        node_id,
+                        id
+                    );
+                }
+                delivered[origin] += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use event_horizon::clock::{self, ClockControl};
+use event_horizon::node::generate_id::{GenerateGuidBody, GenerateGuidNode};
+use event_horizon::{Node, NodeMetadata};
+use std::sync::mpsc::channel;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[test]
+fn incarnations_ignore_clock_faults() {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+    let start = || {
+        let (event_tx, _event_rx) = channel();
+        let metadata = NodeMetadata {
+            node_id: "n1".to_owned(),
+            node_ids: vec!["n1".to_owned()],
+        };
+        <GenerateGuidNode as Node<GenerateGuidBody>>::node_init(metadata, event_tx).incarnation
+    };
+    let first = start();
+
+    //A node restarted with its clock thrown back or stopped still gets a new incarnation.
+    clock::global().apply(&ClockControl {
+        jump_ms: Some(-60_000),
+        ..ClockControl::default()
+    });
+    let jumped = start();
+    clock::global().freeze_at(Some(first));
+    let frozen = start();
+    let restarted = start();
+    assert!(started <= first);
+    assert!(first < jumped && jumped < frozen && frozen < restarted);
+    assert_eq!(clock::now_nanos(), first);
+
+    clock::global().pin_incarnation(Some(7));
+    assert_eq!(start(), 7);
+}
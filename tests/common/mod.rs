@@ -0,0 +1,218 @@
+//A cluster of broadcast nodes in one process, built from EVENT_HORIZON_* settings
+//the way the binary builds them, with messages between them delivered by hand.
+
+#![allow(dead_code)]
+
+pub mod services;
+
+use event_horizon::node::broadcast::{BroadcastBody, BroadcastConfig, BroadcastNode};
+use event_horizon::{Event, MaelstromMessage, Node, NodeMetadata};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::mpsc::channel;
+
+pub fn node_ids(count: usize) -> Vec<String> {
+    (1..=count).map(|index| format!("n{}", index)).collect()
+}
+
+pub fn vars<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+    //! Look settings up in `vars` as if they were the environment.
+    |name| {
+        vars.iter()
+            .find(|(var, _)| *var == name)
+            .map(|(_, value)| value.to_string())
+    }
+}
+
+pub fn node(node_id: &str, node_ids: &[String], settings: &[(&str, &str)]) -> BroadcastNode {
+    //! A node configured as if `settings` were its environment.
+    let (event_tx, _event_rx) = channel();
+    let metadata = NodeMetadata {
+        node_id: node_id.to_owned(),
+        node_ids: node_ids.to_vec(),
+    };
+    let config = BroadcastConfig::from_vars(vars(settings));
+    BroadcastNode::with_config(metadata, config, event_tx)
+}
+
+pub struct Net {
+    pub nodes: BTreeMap<String, BroadcastNode>,
+    //Nodes cut off from every other
+    pub cut: HashSet<String>,
+    //Messages between a node in here and one outside are dropped
+    pub side: HashSet<String>,
+    pub in_flight: Vec<MaelstromMessage<Value>>,
+    //Messages delivered between nodes, by type
+    pub sent: BTreeMap<String, usize>,
+    //Payloads carried between nodes
+    pub shipped: usize,
+    //Client replies, by the node that sent them
+    pub replies: BTreeMap<String, Vec<Value>>,
+}
+
+impl Net {
+    pub fn new(count: usize, vars: &[(&str, &str)]) -> Self {
+        //! Nodes n1 to n`count`, with no neighbors unless `vars` pick a topology.
+        let node_ids = node_ids(count);
+        Net {
+            nodes: node_ids
+                .iter()
+                .map(|node_id| (node_id.clone(), node(node_id, &node_ids, vars)))
+                .collect(),
+            cut: HashSet::new(),
+            side: HashSet::new(),
+            in_flight: Vec::new(),
+            sent: BTreeMap::new(),
+            shipped: 0,
+            replies: BTreeMap::new(),
+        }
+    }
+
+    pub fn mesh(count: usize, vars: &[(&str, &str)]) -> Self {
+        //! Nodes that Maelstrom tells to neighbor every other node.
+        let mut net = Net::new(count, vars);
+        let node_ids: Vec<String> = net.nodes.keys().cloned().collect();
+        net.topology(|node_id| {
+            node_ids
+                .iter()
+                .filter(|id| *id != node_id)
+                .cloned()
+                .collect()
+        });
+        net
+    }
+
+    pub fn topology(&mut self, neighbors: impl Fn(&str) -> Vec<String>) {
+        //! Send every node the topology Maelstrom would.
+        let node_ids: Vec<String> = self.nodes.keys().cloned().collect();
+        let topology: HashMap<&String, Vec<String>> = node_ids
+            .iter()
+            .map(|node_id| (node_id, neighbors(node_id)))
+            .collect();
+        for node_id in &node_ids {
+            self.client(
+                node_id,
+                json!({"type": "topology", "msg_id": 1, "topology": topology}),
+            );
+        }
+    }
+
+    pub fn route(&mut self, node_id: &str, output: Vec<u8>) {
+        //! Queue what a node sent other nodes, and keep its replies to clients.
+        for line in String::from_utf8(output).unwrap().lines() {
+            let message: MaelstromMessage<Value> = serde_json::from_str(line).unwrap();
+            if message.dest.starts_with('c') {
+                self.replies
+                    .entry(node_id.to_owned())
+                    .or_default()
+                    .push(message.body);
+            } else {
+                self.in_flight.push(message);
+            }
+        }
+    }
+
+    pub fn handle(&mut self, node_id: &str, event: Event<BroadcastBody>) {
+        let mut output = Vec::new();
+        self.nodes
+            .get_mut(node_id)
+            .unwrap()
+            .handle_event(event, &mut output);
+        self.route(node_id, output);
+    }
+
+    pub fn client(&mut self, node_id: &str, body: Value) -> Value {
+        //! Send a request from a client, and return the node's reply.
+        let line = json!({"src": "c1", "dest": node_id, "body": body});
+        self.handle(
+            node_id,
+            Event::Message(serde_json::from_str(&line.to_string()).unwrap()),
+        );
+        self.replies.get_mut(node_id).unwrap().pop().unwrap()
+    }
+
+    pub fn broadcast(&mut self, node_id: &str, message: Value) {
+        self.client(
+            node_id,
+            json!({"type": "broadcast", "msg_id": 1, "message": message}),
+        );
+    }
+
+    pub fn read(&mut self, node_id: &str) -> Vec<Value> {
+        match self.client(node_id, json!({"type": "read", "msg_id": 1}))["messages"].take() {
+            Value::Array(messages) => messages,
+            other => panic!("Read returned {}", other),
+        }
+    }
+
+    pub fn reads(&mut self) -> BTreeMap<String, Vec<Value>> {
+        let node_ids: Vec<String> = self.nodes.keys().cloned().collect();
+        node_ids
+            .into_iter()
+            .map(|node_id| {
+                let read = self.read(&node_id);
+                (node_id, read)
+            })
+            .collect()
+    }
+
+    pub fn deliver(&mut self, index: usize) {
+        //! Deliver one message in flight, unless the nodes are cut off from each other.
+        let message = self.in_flight.remove(index);
+        if !self.nodes.contains_key(&message.dest)
+            || self.cut.contains(&message.dest)
+            || self.cut.contains(&message.src)
+            || self.side.contains(&message.src) != self.side.contains(&message.dest)
+        {
+            return;
+        }
+        *self
+            .sent
+            .entry(message.body["type"].as_str().unwrap().to_owned())
+            .or_default() += 1;
+        self.shipped += message.body["payloads"].as_array().map_or(0, Vec::len);
+        let line = serde_json::to_string(&message).unwrap();
+        let dest = message.dest.clone();
+        self.handle(&dest, Event::Message(serde_json::from_str(&line).unwrap()));
+    }
+
+    pub fn settle(&mut self) {
+        //! Deliver everything in flight, in the order it was sent.
+        while !self.in_flight.is_empty() {
+            self.deliver(0);
+        }
+    }
+
+    pub fn tick(&mut self, node_id: &str) {
+        self.handle(node_id, Event::PropogateWrites);
+    }
+
+    pub fn rounds(&mut self, count: usize) {
+        //! Tick every node, then deliver what they send, `count` times.
+        let node_ids: Vec<String> = self.nodes.keys().cloned().collect();
+        for _ in 0..count {
+            for node_id in &node_ids {
+                self.tick(node_id);
+            }
+            self.settle();
+        }
+    }
+
+    pub fn restart(&mut self, node_id: &str, vars: &[(&str, &str)]) {
+        //! Kill a node and start it again from `vars`, losing what was in flight to it.
+        //! Like Maelstrom, nothing sends the new node the topology.
+        let node_ids: Vec<String> = self.nodes.keys().cloned().collect();
+        self.take_to(node_id);
+        self.nodes
+            .insert(node_id.to_owned(), node(node_id, &node_ids, vars));
+    }
+
+    pub fn take_to(&mut self, dest: &str) -> Vec<MaelstromMessage<Value>> {
+        //! Take the messages in flight to one node, to deliver later.
+        let (taken, kept) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition(|message| message.dest == dest);
+        self.in_flight = kept;
+        taken
+    }
+}
@@ -0,0 +1,155 @@
+//Nodes that keep their state in Maelstrom's services, with the services run in this
+//process and every message between them delivered by hand.
+
+use event_horizon::service::is_service;
+use event_horizon::service::local::LocalServices;
+use event_horizon::{Event, MaelstromMessage, Node, NodeMetadata, Reply};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const NODE_IDS: [&str; 3] = ["n1", "n2", "n3"];
+
+pub fn cluster<N>(build: impl Fn(NodeMetadata) -> N) -> BTreeMap<String, N> {
+    NODE_IDS
+        .iter()
+        .map(|node_id| {
+            let metadata = NodeMetadata {
+                node_id: node_id.to_string(),
+                node_ids: NODE_IDS.iter().map(|id| id.to_string()).collect(),
+            };
+            (node_id.to_string(), build(metadata))
+        })
+        .collect()
+}
+
+pub fn lines(output: Vec<u8>) -> Vec<MaelstromMessage<Value>> {
+    String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+pub fn request<N, Body>(
+    nodes: &mut BTreeMap<String, N>,
+    node_id: &str,
+    body: Value,
+) -> Vec<MaelstromMessage<Value>>
+where
+    N: Node<Body>,
+    Body: Reply<N> + DeserializeOwned,
+{
+    let line = json!({"src": "c1", "dest": node_id, "body": body}).to_string();
+    let mut output = Vec::new();
+    nodes.get_mut(node_id).unwrap().handle_event(
+        Event::Message(serde_json::from_str(&line).unwrap()),
+        &mut output,
+    );
+    lines(output)
+}
+
+pub fn settle<N, Body>(
+    nodes: &mut BTreeMap<String, N>,
+    services: &mut LocalServices,
+    mut in_flight: Vec<MaelstromMessage<Value>>,
+    expected: usize,
+) -> Vec<MaelstromMessage<Value>>
+where
+    N: Node<Body>,
+    Body: Reply<N>,
+{
+    //! Deliver messages, newest first, until `expected` replies to clients have been
+    //! sent, and return them. Once nothing is in flight every service request has
+    //! been answered, so the nodes are ticked to send the retries whose backoff has
+    //! passed.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut replies = Vec::new();
+    loop {
+        while let Some(message) = in_flight.pop() {
+            match services.handle(&message) {
+                Some((_, reply)) => {
+                    let mut output = Vec::new();
+                    nodes
+                        .get_mut(&reply.dest)
+                        .unwrap()
+                        .handle_event(Event::ServiceReply(reply), &mut output);
+                    in_flight.extend(lines(output));
+                }
+                None => replies.push(message),
+            }
+        }
+        if replies.len() >= expected {
+            return replies;
+        }
+        assert!(Instant::now() < deadline, "Only {} replies", replies.len());
+        thread::sleep(Duration::from_millis(1));
+        for node in nodes.values_mut() {
+            let mut output = Vec::new();
+            node.handle_event(Event::PropogateWrites, &mut output);
+            in_flight.extend(lines(output));
+        }
+    }
+}
+
+pub fn ask<N, Body>(
+    nodes: &mut BTreeMap<String, N>,
+    services: &mut LocalServices,
+    node_id: &str,
+    body: Value,
+) -> Value
+where
+    N: Node<Body>,
+    Body: Reply<N> + DeserializeOwned,
+{
+    //! Make one request, and return the only reply once everything has settled.
+    let in_flight = request(nodes, node_id, body);
+    let replies = settle(nodes, services, in_flight, 1);
+    assert_eq!(replies.len(), 1);
+    replies[0].body.clone()
+}
+
+pub fn refused<N, Body>(
+    node: &mut N,
+    mut in_flight: Vec<MaelstromMessage<Value>>,
+) -> (Vec<MaelstromMessage<Value>>, usize)
+where
+    N: Node<Body>,
+    Body: Reply<N>,
+{
+    //! Answer every request a node makes of a service with an error that says it did
+    //! not apply, ticking the node to send its retries, until it replies to a client.
+    //! Returns the replies and how many requests were refused.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut refused = 0;
+    loop {
+        let (replies, requests): (Vec<_>, Vec<_>) = in_flight
+            .drain(..)
+            .partition(|message| !is_service(&message.dest));
+        if !replies.is_empty() {
+            return (replies, refused);
+        }
+        for request in requests {
+            refused += 1;
+            let mut body = json!({"type": "error", "code": 11, "text": "unavailable"});
+            body["in_reply_to"] = request.body["msg_id"].clone();
+            let reply = MaelstromMessage {
+                src: request.dest,
+                dest: request.src,
+                body,
+            };
+            let mut output = Vec::new();
+            node.handle_event(Event::ServiceReply(reply), &mut output);
+            in_flight.extend(lines(output));
+        }
+        if in_flight.is_empty() {
+            assert!(Instant::now() < deadline, "No reply");
+            thread::sleep(Duration::from_millis(1));
+            let mut output = Vec::new();
+            node.handle_event(Event::PropogateWrites, &mut output);
+            in_flight.extend(lines(output));
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use event_horizon::diagram::{ArrowKind, Diagram, DiagramFilter};
+use event_horizon::trace::TraceEntry;
+use event_horizon::MaelstromMessage;
+use serde_json::{json, Value};
+
+fn entry(time: u64, src: &str, dest: &str, body: Value, received: Option<u64>) -> TraceEntry {
+    TraceEntry {
+        time,
+        message: MaelstromMessage {
+            src: src.to_owned(),
+            dest: dest.to_owned(),
+            body,
+        },
+        received,
+    }
+}
+
+fn trace() -> Vec<TraceEntry> {
+    vec![
+        entry(
+            10,
+            "c1",
+            "n0",
+            json!({"type": "broadcast", "msg_id": 1, "message": 5}),
+            Some(12),
+        ),
+        entry(
+            13,
+            "n0",
+            "c1",
+            json!({"type": "broadcast_ok", "msg_id": 0, "in_reply_to": 1}),
+            Some(14),
+        ),
+        entry(
+            20,
+            "n0",
+            "n1",
+            json!({"type": "gossip", "msg_id": 1, "message": [5]}),
+            None,
+        ),
+        entry(
+            30,
+            "n0",
+            "n1",
+            json!({"type": "gossip", "msg_id": 2, "message": [5]}),
+            Some(32),
+        ),
+        //The first gossip was dropped, so only the second is answered.
+        entry(
+            33,
+            "n1",
+            "n0",
+            json!({"type": "gossip_ok", "msg_id": 0, "in_reply_to": 2, "ack_message": [5]}),
+            Some(35),
+        ),
+        entry(
+            40,
+            "c2",
+            "n1",
+            json!({"type": "read", "msg_id": 1}),
+            Some(41),
+        ),
+    ]
+}
+
+#[test]
+fn pairs_requests_with_replies() {
+    let diagram = Diagram::from_trace(&trace(), &DiagramFilter::default());
+    assert_eq!(diagram.participants, ["n0", "n1", "c1", "c2"]);
+    let kinds: Vec<_> = diagram
+        .arrows
+        .iter()
+        .map(|arrow| (arrow.kind, arrow.pair))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (ArrowKind::Request, Some(0)),
+            (ArrowKind::Reply, Some(0)),
+            (ArrowKind::Oneway, None),
+            (ArrowKind::Request, Some(1)),
+            (ArrowKind::Reply, Some(1)),
+            (ArrowKind::Oneway, None),
+        ]
+    );
+
+    let mermaid = diagram.to_mermaid();
+    assert!(mermaid.contains("c1->>+n0: broadcast #35;1 {\"message\":5}"));
+    assert!(mermaid.contains("n0-->>-c1: broadcast_ok ↩1"));
+    assert!(mermaid.contains("n0-xn1: gossip #35;1"));
+}
+
+#[test]
+fn filters_by_type_node_and_time() {
+    let by_type = DiagramFilter {
+        types: ["gossip".to_owned()].into(),
+        ..DiagramFilter::default()
+    };
+    let diagram = Diagram::from_trace(&trace(), &by_type);
+    assert_eq!(diagram.arrows.len(), 3);
+    assert_eq!(diagram.participants, ["n0", "n1"]);
+
+    let by_node = DiagramFilter {
+        nodes: ["c2".to_owned()].into(),
+        ..DiagramFilter::default()
+    };
+    assert_eq!(Diagram::from_trace(&trace(), &by_node).arrows.len(), 1);
+
+    //The window cuts the reply off, so the request is no longer paired.
+    let by_time = DiagramFilter {
+        from: Some(15),
+        to: Some(31),
+        ..DiagramFilter::default()
+    };
+    let diagram = Diagram::from_trace(&trace(), &by_time);
+    assert_eq!(diagram.arrows.len(), 2);
+    assert!(diagram
+        .arrows
+        .iter()
+        .all(|arrow| arrow.kind == ArrowKind::Oneway));
+}
+
+#[test]
+fn renders_svg() {
+    let svg = Diagram::from_trace(&trace(), &DiagramFilter::default()).to_svg();
+    assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(svg.trim_end().ends_with("</svg>"));
+    //One lifeline per participant, one group per arrow, and a cross for the dropped gossip
+    assert_eq!(svg.matches("stroke-dasharray=\"4 3\"").count(), 4);
+    assert_eq!(svg.matches("<g>").count(), 6);
+    assert!(svg.contains("(dropped)"));
+    assert!(svg.contains("{&quot;message&quot;:5}"));
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use event_horizon::node::broadcast::batching::{BatchConfig, GossipBatcher, MIN_INTERVAL};
+use event_horizon::node::broadcast::BroadcastBody;
+use event_horizon::{Event, MaelstromMessage, Node};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+#[test]
+fn parses_batching_specs() {
+    assert_eq!(BatchConfig::parse("batched"), Some(BatchConfig::default()));
+    let config =
+        BatchConfig::parse("batched:size=8,max_delay=300,budget=900,msgs_per_op=20").unwrap();
+    assert_eq!(config.size, 8);
+    assert_eq!(config.max_delay, ms(300));
+    assert_eq!(config.budget, Some(ms(900)));
+    assert_eq!(config.msgs_per_op, 20.0);
+    assert_eq!(BatchConfig::parse("batched:size=0"), None);
+    assert_eq!(BatchConfig::parse("batched:speed=2"), None);
+    assert_eq!(BatchConfig::parse("periodic"), None);
+}
+
+#[test]
+fn flushes_on_size_delay_or_budget() {
+    let start = Instant::now();
+    let config = BatchConfig {
+        size: 3,
+        max_delay: ms(200),
+        ..BatchConfig::default()
+    };
+    let mut batcher = GossipBatcher::new(config.clone(), 5, start);
+    batcher.added(2, start);
+    assert!(!batcher.due(start));
+    batcher.added(1, start);
+    assert!(batcher.due(start));
+
+    let mut batcher = GossipBatcher::new(config.clone(), 5, start);
+    batcher.added(1, start);
+    assert!(!batcher.due(start + ms(199)));
+    assert!(batcher.due(start + ms(200)));
+
+    //Four hops share a 400ms budget, so no message waits more than 100ms here.
+    let mut batcher = GossipBatcher::new(
+        BatchConfig {
+            budget: Some(ms(400)),
+            ..config
+        },
+        5,
+        start,
+    );
+    batcher.hops = 4;
+    batcher.added(1, start);
+    assert_eq!(batcher.hold_limit(), ms(100));
+    assert!(batcher.due(start + ms(100)));
+    batcher.flushed(vec![1, 2], start + ms(100));
+    assert_eq!(batcher.pending(), 0);
+    assert!(!batcher.retry_due(start + ms(299)));
+    assert!(batcher.retry_due(start + ms(300)));
+}
+
+#[test]
+fn the_interval_adapts_to_load() {
+    let start = Instant::now();
+    let config = BatchConfig {
+        max_delay: ms(1000),
+        msgs_per_op: 20.0,
+        median_latency: ms(1000),
+        ..BatchConfig::default()
+    };
+    let mut batcher = GossipBatcher::new(config, 25, start);
+    batcher.hops = 2;
+    //25 nodes with 2 neighbors spend 100 messages a flush, so 20 msgs-per-op at
+    //100 broadcasts a second calls for a flush every 50ms.
+    let mut now = start;
+    for _ in 0..30 {
+        now += ms(1000);
+        batcher.added(100, now);
+        batcher.tick(2, now);
+    }
+    assert!(
+        batcher.interval >= ms(45) && batcher.interval <= ms(60),
+        "{:?}",
+        batcher.interval
+    );
+
+    //Fewer broadcasts call for longer batches, until latency caps them: 500ms a
+    //hop, less half the round trip, with messages waiting half an interval.
+    batcher.flushed(vec![7], now);
+    batcher.acked(7, now + ms(200));
+    assert_eq!(batcher.round_trip, Some(ms(200)));
+    for _ in 0..30 {
+        now += ms(1000);
+        batcher.added(1, now);
+        batcher.tick(2, now);
+    }
+    assert_eq!(batcher.target_interval(2), ms(800));
+    assert!(
+        batcher.interval > ms(700) && batcher.interval <= ms(800),
+        "{:?}",
+        batcher.interval
+    );
+    assert!(batcher.interval >= MIN_INTERVAL);
+}
+
+#[test]
+fn broadcasts_are_acked_before_they_are_gossiped() {
+    let mut node = common::node(
+        "n1",
+        &common::node_ids(2),
+        &[
+            ("EVENT_HORIZON_GOSSIP", "batched:size=2,max_delay=60000"),
+            ("EVENT_HORIZON_TOPOLOGY", "star"),
+        ],
+    );
+
+    let mut broadcast = |message: usize| -> Vec<MaelstromMessage<Value>> {
+        let line = json!({"src": "c1", "dest": "n1",
+            "body": {"type": "broadcast", "msg_id": message, "message": message}});
+        let mut output = Vec::new();
+        node.handle_event(
+            Event::Message(
+                serde_json::from_str::<MaelstromMessage<BroadcastBody>>(&line.to_string()).unwrap(),
+            ),
+            &mut output,
+        );
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    };
+    let first = broadcast(1);
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].body["type"], "broadcast_ok");
+    //The second fills the batch, and both go out in one gossip.
+    let second = broadcast(2);
+    assert_eq!(second.len(), 2);
+    assert_eq!(second[0].body["type"], "broadcast_ok");
+    assert_eq!(second[1].dest, "n2");
+    //n1 is the first node, so its broadcasts are numbered from 0.
+    assert_eq!(second[1].body["message"], json!([0, 1]));
+    assert_eq!(second[1].body["payloads"], json!([1, 2]));
+}
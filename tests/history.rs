@@ -0,0 +1,301 @@
+use event_horizon::history::{edn, History, OpType, Operation, Process};
+use event_horizon::trace::{self, TraceEntry};
+use serde_json::{json, Value};
+
+fn op(op_type: OpType, process: Process, time: u64, f: &str, value: Value) -> Operation {
+    Operation {
+        op_type,
+        process,
+        time,
+        f: f.to_owned(),
+        value,
+        error: None,
+        index: None,
+        msg_id: None,
+    }
+}
+
+const EDN_HISTORY: &str = r#"
+; A Maelstrom history, as written to store/latest/history.edn
+{:type :invoke, :f :write, :value [1 3], :process 0, :time 100, :index 0}
+{:type :invoke, :f :read, :value nil, :process 1, :time 150, :index 1}
+#jepsen.history.Op{:type :ok, :f :write, :value [1 3], :process 0, :time 200, :index 2}
+{:type :info, :f :start-partition, :value #{"n1" "n2"}, :process :nemesis, :time 250, :index 3}
+{:type :fail, :f :read, :value nil, :process 1, :time 300, :index 4,
+ :error [:key-does-not-exist "no key \"1\""] #_ :ignored}
+#_ {:type :ok}
+"#;
+
+#[test]
+fn converts_maelstrom_edn_histories() {
+    let history = History::from_edn(EDN_HISTORY).unwrap();
+    let mut write = op(
+        OpType::Invoke,
+        Process::Client(0),
+        100,
+        "write",
+        json!([1, 3]),
+    );
+    write.index = Some(0);
+    let mut partition = op(
+        OpType::Info,
+        Process::Named("nemesis".to_owned()),
+        250,
+        "start-partition",
+        json!(["n1", "n2"]),
+    );
+    partition.index = Some(3);
+    assert_eq!(history.ops.len(), 5);
+    assert_eq!(history.ops[0], write);
+    assert_eq!(history.ops[2].op_type, OpType::Ok);
+    assert_eq!(history.ops[3], partition);
+    assert_eq!(
+        history.ops[4].error,
+        Some(json!(["key-does-not-exist", "no key \"1\""]))
+    );
+
+    //Histories wrapped in one vector read the same.
+    let wrapped = format!("[{}]", EDN_HISTORY);
+    assert_eq!(History::from_edn(&wrapped).unwrap(), history);
+
+    //Converted histories survive a round trip through JSON lines.
+    let mut jsonl = Vec::new();
+    history.write_jsonl(&mut jsonl).unwrap();
+    assert_eq!(History::read_jsonl(jsonl.as_slice()).unwrap(), history);
+}
+
+#[test]
+fn parses_edn_forms() {
+    assert_eq!(
+        edn::parse_all(r#"[1 #_2] (a, :b) {:k #_ :x "v\n"} #{} 12N -3 1.5 \space nil true"#)
+            .unwrap(),
+        vec![
+            json!([1]),
+            json!(["a", "b"]),
+            json!({"k": "v\n"}),
+            json!([]),
+            json!(12),
+            json!(-3),
+            json!(1.5),
+            json!(" "),
+            Value::Null,
+            json!(true),
+        ]
+    );
+    assert_eq!(edn::parse_all("[#_ #_ 1 2 3]").unwrap(), vec![json!([3])]);
+    assert_eq!(
+        edn::parse_all("#_ 1 ; only a comment").unwrap(),
+        Vec::<Value>::new()
+    );
+    for (source, position) in [
+        ("[1 2", 4),
+        ("{:a}", 4),
+        ("]", 0),
+        ("[#_]", 3),
+        (r#""\u00g9""#, 3),
+        (r#""\ud83d""#, 7),
+        (r#""\ude00""#, 7),
+    ] {
+        assert_eq!(
+            edn::parse_all(source).unwrap_err().position,
+            Some(position),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn decodes_unicode_escapes() {
+    assert_eq!(
+        edn::parse_all(r#""caf\u00e9 \u2603 \ud83d\ude00""#).unwrap(),
+        vec![json!("café ☃ 😀")]
+    );
+}
+
+#[test]
+fn ops_that_cannot_be_converted_have_no_position() {
+    let error = History::from_edn("{:type :ok, :f :read} {:type :bogus}").unwrap_err();
+    assert_eq!(error.position, None);
+    assert!(error
+        .to_string()
+        .starts_with("EDN error: op has no :process"));
+}
+
+#[test]
+fn round_trips_json_lines() {
+    let mut read = op(OpType::Invoke, Process::Client(4), 10, "read", json!({}));
+    read.msg_id = Some(2);
+    let mut failed = op(OpType::Fail, Process::Client(4), 20, "read", json!({}));
+    failed.error = Some(json!({"code": 20}));
+    failed.msg_id = Some(2);
+    let mut history = History {
+        ops: vec![
+            read,
+            failed,
+            op(
+                OpType::Info,
+                Process::Named("nemesis".to_owned()),
+                30,
+                "kill",
+                json!(["n1"]),
+            ),
+        ],
+    };
+    history.reindex();
+    let mut jsonl = Vec::new();
+    history.write_jsonl(&mut jsonl).unwrap();
+    let text = String::from_utf8(jsonl.clone()).unwrap();
+    assert!(text
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(r#""process":"nemesis""#));
+    //Blank lines are skipped.
+    jsonl.extend_from_slice(b"\n\n");
+    assert_eq!(History::read_jsonl(jsonl.as_slice()).unwrap(), history);
+    assert!(History::read_jsonl(&b"{\"type\": \"ok\"}\n"[..]).is_err());
+}
+
+fn entry(time: u64, src: &str, dest: &str, body: Value) -> TraceEntry {
+    serde_json::from_value(json!({"time": time, "src": src, "dest": dest, "body": body})).unwrap()
+}
+
+fn trace() -> Vec<TraceEntry> {
+    vec![
+        //c1 has two requests in flight, answered out of order.
+        entry(
+            0,
+            "c1",
+            "n1",
+            json!({"type": "read", "msg_id": 1, "key": 1}),
+        ),
+        entry(
+            1,
+            "c1",
+            "n1",
+            json!({"type": "write", "msg_id": 2, "key": 1, "value": 5}),
+        ),
+        entry(2, "n1", "n2", json!({"type": "gossip", "msg_id": 7})),
+        entry(
+            3,
+            "n1",
+            "c1",
+            json!({"type": "write_ok", "msg_id": 8, "in_reply_to": 2}),
+        ),
+        entry(
+            4,
+            "n1",
+            "c1",
+            json!({"type": "read_ok", "msg_id": 9, "in_reply_to": 1, "value": 5}),
+        ),
+        entry(
+            5,
+            "c2",
+            "n2",
+            json!({"type": "cas", "msg_id": 1, "key": 1, "from": 4, "to": 6}),
+        ),
+        entry(
+            6,
+            "n2",
+            "c2",
+            json!({"type": "error", "in_reply_to": 1, "code": 22}),
+        ),
+        entry(
+            7,
+            "c2",
+            "n2",
+            json!({"type": "cas", "msg_id": 2, "key": 1, "from": 5, "to": 6}),
+        ),
+        entry(
+            8,
+            "n2",
+            "c2",
+            json!({"type": "error", "in_reply_to": 2, "code": 0}),
+        ),
+        entry(
+            9,
+            "c3",
+            "n1",
+            json!({"type": "read", "msg_id": 1, "key": 1}),
+        ),
+        entry(
+            12,
+            "n2",
+            "n1",
+            json!({"type": "gossip_ok", "in_reply_to": 7}),
+        ),
+    ]
+}
+
+#[test]
+fn derives_histories_from_traces() {
+    let history = History::from_trace(&trace());
+    let summary: Vec<(OpType, &Process, u64, &str)> = history
+        .ops
+        .iter()
+        .map(|operation| {
+            (
+                operation.op_type,
+                &operation.process,
+                operation.time,
+                operation.f.as_str(),
+            )
+        })
+        .collect();
+    let client = Process::Client;
+    assert_eq!(
+        summary,
+        vec![
+            (OpType::Invoke, &client(1), 0, "read"),
+            (OpType::Invoke, &client(1), 1, "write"),
+            (OpType::Ok, &client(1), 3, "write"),
+            (OpType::Ok, &client(1), 4, "read"),
+            (OpType::Invoke, &client(2), 5, "cas"),
+            //A definite error fails, and an indefinite one leaves the outcome unknown.
+            (OpType::Fail, &client(2), 6, "cas"),
+            (OpType::Invoke, &client(2), 7, "cas"),
+            (OpType::Info, &client(2), 8, "cas"),
+            (OpType::Invoke, &client(3), 9, "read"),
+            //Never answered, so completed as info when the trace ends
+            (OpType::Info, &client(3), 12, "read"),
+        ]
+    );
+    assert_eq!(history.ops[0].value, json!({"key": 1}));
+    assert_eq!(history.ops[3].value, json!({"value": 5}));
+    assert_eq!(history.ops[5].error, Some(json!({"code": 22})));
+    assert_eq!(history.ops[9].index, Some(9));
+
+    //Requests in flight together from one client pair with their own replies.
+    let pairs: Vec<(u64, Option<u64>)> = history
+        .pairs()
+        .into_iter()
+        .map(|(invoke, completion)| (invoke.time, completion.map(|completion| completion.time)))
+        .collect();
+    assert_eq!(
+        pairs,
+        vec![
+            (0, Some(4)),
+            (1, Some(3)),
+            (5, Some(6)),
+            (7, Some(8)),
+            (9, Some(12))
+        ]
+    );
+}
+
+#[test]
+fn round_trips_traces() {
+    let trace = trace();
+    let mut jsonl = Vec::new();
+    trace::write_jsonl(&trace, &mut jsonl).unwrap();
+    let read = trace::read_jsonl(jsonl.as_slice()).unwrap();
+    assert_eq!(read.len(), trace.len());
+    for (read, written) in read.iter().zip(&trace) {
+        assert_eq!(
+            serde_json::to_value(read).unwrap(),
+            serde_json::to_value(written).unwrap()
+        );
+    }
+    assert_eq!(History::from_trace(&read), History::from_trace(&trace));
+}
@@ -0,0 +1,76 @@
+//The run-encoded set must behave exactly like a BTreeSet. Values are drawn from
+//u8 so that generated sets are dense enough to form runs.
+
+use event_horizon::node::broadcast::interval_set::IntervalSet;
+use quickcheck::quickcheck;
+use serde_json::json;
+use std::collections::BTreeSet;
+
+fn wide(values: &BTreeSet<u8>) -> BTreeSet<usize> {
+    values.iter().map(|value| usize::from(*value)).collect()
+}
+
+fn set(values: &BTreeSet<u8>) -> IntervalSet {
+    values.iter().map(|value| usize::from(*value)).collect()
+}
+
+fn well_formed(set: &IntervalSet) -> bool {
+    //! Runs are sorted, never touch, and add up to the length.
+    let runs = set.runs();
+    runs.iter().all(|(start, end)| start <= end)
+        && runs.windows(2).all(|pair| pair[0].1 + 1 < pair[1].0)
+        && runs
+            .iter()
+            .map(|(start, end)| end - start + 1)
+            .sum::<usize>()
+            == set.len()
+}
+
+quickcheck! {
+    fn matches_a_btree_set(values: Vec<u8>, probe: u8) -> bool {
+        let mut expected = BTreeSet::new();
+        let mut actual = IntervalSet::new();
+        let inserts_agree = values
+            .iter()
+            .all(|value| expected.insert(usize::from(*value)) == actual.insert(usize::from(*value)));
+        inserts_agree
+            && well_formed(&actual)
+            && actual.iter().collect::<BTreeSet<_>>() == expected
+            && actual.contains(usize::from(probe)) == expected.contains(&usize::from(probe))
+    }
+
+    fn union_matches(a: BTreeSet<u8>, b: BTreeSet<u8>) -> bool {
+        let mut union = set(&a);
+        union.union_with(&set(&b));
+        well_formed(&union) && union.iter().collect::<BTreeSet<_>>() == &wide(&a) | &wide(&b)
+    }
+
+    fn difference_matches(a: BTreeSet<u8>, b: BTreeSet<u8>) -> bool {
+        let difference = set(&a).difference(&set(&b));
+        well_formed(&difference)
+            && difference.iter().collect::<BTreeSet<_>>() == &wide(&a) - &wide(&b)
+    }
+
+    fn round_trips(a: BTreeSet<u8>, runs: Vec<(u8, u8)>) -> bool {
+        let mut original = set(&a);
+        for (start, length) in runs {
+            original.insert_run(usize::from(start), usize::from(start) + usize::from(length));
+        }
+        let encoded = serde_json::to_string(&original).unwrap();
+        let decoded: IntervalSet = serde_json::from_str(&encoded).unwrap();
+        well_formed(&original) && decoded == original
+    }
+}
+
+#[test]
+fn encodes_long_runs_as_pairs() {
+    let values: IntervalSet = [1, 2, 4, 5, 6, 7, 9].into_iter().collect();
+    assert_eq!(
+        serde_json::to_value(&values).unwrap(),
+        json!([1, 2, [4, 7], 9])
+    );
+    //A flat list, in any order and with repeats, reads as the same set.
+    let flat: IntervalSet = serde_json::from_value(json!([9, 7, 1, 2, 4, 5, 6, 5])).unwrap();
+    assert_eq!(flat, values);
+    assert!(serde_json::from_value::<IntervalSet>(json!([[7, 4]])).is_err());
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use common::services::{self, ask, lines, request, settle, NODE_IDS};
+use event_horizon::node::kafka::{KafkaBody, KafkaNode, LinKvLog};
+use event_horizon::service::kv::{self, KvClient};
+use event_horizon::service::local::{LocalConfig, LocalServices};
+use event_horizon::{Event, MaelstromMessage, Node};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+fn cluster() -> BTreeMap<String, KafkaNode> {
+    services::cluster(|metadata| {
+        let settings = [("EVENT_HORIZON_KAFKA", "lin-kv")];
+        let lin_kv = LinKvLog::from_vars(&metadata.node_id, common::vars(&settings));
+        KafkaNode::with_log(metadata, lin_kv, channel().0)
+    })
+}
+
+#[test]
+fn sends_to_any_node_get_unique_offsets() {
+    let mut nodes = cluster();
+    let mut services = LocalServices::new(LocalConfig::default());
+    let mut in_flight = Vec::new();
+    //Sends to the same keys race each other from every node.
+    for (index, node_id) in NODE_IDS.iter().cycle().take(12).enumerate() {
+        let key = if index % 3 == 2 { "k2" } else { "k1" };
+        in_flight.extend(request(
+            &mut nodes,
+            node_id,
+            json!({"type": "send", "msg_id": index, "key": key, "msg": 100 + index}),
+        ));
+    }
+    let replies = settle(&mut nodes, &mut services, in_flight, 12);
+    assert_eq!(replies.len(), 12);
+    let mut sent: BTreeMap<&str, BTreeMap<u64, u64>> = BTreeMap::new();
+    for reply in &replies {
+        assert_eq!(reply.body["type"], "send_ok");
+        let index = reply.body["in_reply_to"].as_u64().unwrap();
+        let key = if index % 3 == 2 { "k2" } else { "k1" };
+        let offset = reply.body["offset"].as_u64().unwrap();
+        assert!(sent
+            .entry(key)
+            .or_default()
+            .insert(offset, 100 + index)
+            .is_none());
+    }
+    assert_eq!(
+        sent["k1"].keys().copied().collect::<Vec<_>>(),
+        (0..8).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        sent["k2"].keys().copied().collect::<Vec<_>>(),
+        (0..4).collect::<Vec<_>>()
+    );
+
+    //Every node serves every record at the offset its send was given.
+    for node_id in NODE_IDS {
+        let poll = ask(
+            &mut nodes,
+            &mut services,
+            node_id,
+            json!({"type": "poll", "msg_id": 50, "offsets": {"k1": 2, "k2": 0, "k3": 0}}),
+        );
+        let expected = |key: &str, from: u64| -> Value {
+            sent[key]
+                .range(from..)
+                .map(|(offset, msg)| json!([offset, msg]))
+                .collect()
+        };
+        assert_eq!(poll["msgs"]["k1"], expected("k1", 2), "{}", node_id);
+        assert_eq!(poll["msgs"]["k2"], expected("k2", 0), "{}", node_id);
+        assert!(poll["msgs"].get("k3").is_none());
+    }
+}
+
+#[test]
+fn committed_offsets_are_shared_and_never_go_back() {
+    let mut nodes = cluster();
+    let mut services = LocalServices::new(LocalConfig::default());
+    let commit =
+        |offsets: Value| json!({"type": "commit_offsets", "msg_id": 1, "offsets": offsets});
+    let reply = ask(
+        &mut nodes,
+        &mut services,
+        "n1",
+        commit(json!({"k1": 5, "k2": 1})),
+    );
+    assert_eq!(reply["type"], "commit_offsets_ok");
+    let reply = ask(&mut nodes, &mut services, "n2", commit(json!({"k1": 3})));
+    assert_eq!(reply["type"], "commit_offsets_ok");
+
+    let reply = ask(
+        &mut nodes,
+        &mut services,
+        "n3",
+        json!({"type": "list_committed_offsets", "msg_id": 2, "keys": ["k1", "k2", "k3"]}),
+    );
+    assert_eq!(reply["offsets"], json!({"k1": 5, "k2": 1}));
+}
+
+#[test]
+fn a_send_with_an_unknown_outcome_is_an_error() {
+    let mut nodes = cluster();
+    let node = nodes.get_mut("n1").unwrap();
+    let line = json!({"src": "c1", "dest": "n1", "body": {"type": "send", "msg_id": 1, "key": "k1", "msg": 7}});
+    let mut output = Vec::new();
+    node.handle_event(
+        Event::Message(serde_json::from_str(&line.to_string()).unwrap()),
+        &mut output,
+    );
+    assert_eq!(lines(output)[0].body["type"], "cas");
+
+    //The cas is never answered.
+    let mut output = Vec::new();
+    KvClient::expire(
+        node,
+        |node| &mut node.lin_kv.as_mut().unwrap().kv,
+        Duration::ZERO,
+        &mut output,
+    );
+    let error: MaelstromMessage<KafkaBody> =
+        serde_json::from_slice(&output[..output.len() - 1]).unwrap();
+    assert!(matches!(
+        error.body,
+        KafkaBody::Error {
+            in_reply_to: 1,
+            code: 0,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn polls_skip_an_offset_its_send_abandoned() {
+    let mut nodes = cluster();
+    let mut services = LocalServices::new(LocalConfig::default());
+    let send = |msg_id: usize, msg: usize| json!({"type": "send", "msg_id": msg_id, "key": "k1", "msg": msg});
+    //n1 claims offset 0, but its record never reaches lin-kv.
+    let claim = request(&mut nodes, "n1", send(1, 7));
+    let (_, reply) = services.handle(&claim[0]).unwrap();
+    let mut output = Vec::new();
+    nodes
+        .get_mut("n1")
+        .unwrap()
+        .handle_event(Event::ServiceReply(reply), &mut output);
+    assert_eq!(lines(output)[0].body["type"], "cas");
+    let reply = ask(&mut nodes, &mut services, "n2", send(2, 8));
+    assert_eq!(reply["offset"], 1);
+
+    let poll = json!({"type": "poll", "msg_id": 3, "offsets": {"k1": 0}});
+    //Polls stop at the missing record, since it may yet be stored...
+    let reply = ask(&mut nodes, &mut services, "n3", poll.clone());
+    assert_eq!(reply["msgs"]["k1"], json!([]));
+    //...until it has been missing long enough to be given up.
+    thread::sleep(Duration::from_millis(2100));
+    ask(&mut nodes, &mut services, "n3", poll.clone());
+    let reply = ask(&mut nodes, &mut services, "n3", poll);
+    assert_eq!(reply["msgs"]["k1"], json!([[1, 8]]));
+
+    //n1's retry then finds the offset given up, and claims another.
+    let mut output = Vec::new();
+    KvClient::expire(
+        nodes.get_mut("n1").unwrap(),
+        |node| &mut node.lin_kv.as_mut().unwrap().kv,
+        Duration::ZERO,
+        &mut output,
+    );
+    let replies = settle(&mut nodes, &mut services, lines(output), 1);
+    assert_eq!(replies[0].body["type"], "send_ok");
+    assert_eq!(replies[0].body["offset"], 2);
+}
+
+#[test]
+fn requests_give_up_once_out_of_attempts() {
+    let mut nodes = cluster();
+    for body in [
+        json!({"type": "send", "msg_id": 1, "key": "k1", "msg": 7}),
+        json!({"type": "poll", "msg_id": 1, "offsets": {"k1": 0, "k2": 0}}),
+        json!({"type": "commit_offsets", "msg_id": 1, "offsets": {"k1": 1}}),
+        json!({"type": "list_committed_offsets", "msg_id": 1, "keys": ["k1"]}),
+    ] {
+        let in_flight = request(&mut nodes, "n1", body.clone());
+        //lin-kv refuses every try.
+        let (replies, _) = services::refused(nodes.get_mut("n1").unwrap(), in_flight);
+        assert_eq!(replies.len(), 1, "{}", body);
+        assert_eq!(replies[0].body["type"], "error", "{}", body);
+        assert_eq!(
+            replies[0].body["code"],
+            kv::TEMPORARILY_UNAVAILABLE,
+            "{}",
+            body
+        );
+    }
+}
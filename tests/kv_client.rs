@@ -0,0 +1,251 @@
+use event_horizon::node::parse_event;
+use event_horizon::service::kv::{self, KvClient};
+use event_horizon::service::{self, ServiceError};
+use event_horizon::{Event, MaelstromMessage};
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+struct TestNode {
+    kv: KvClient<TestNode>,
+    results: Vec<Result<Value, ServiceError>>,
+}
+
+fn node() -> TestNode {
+    TestNode {
+        kv: KvClient::lin("n1"),
+        results: Vec::new(),
+    }
+}
+
+fn sent(output: &[u8]) -> Vec<Value> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn reply(request: &Value, body: Value) -> MaelstromMessage<Value> {
+    let mut body = body;
+    body["in_reply_to"] = request["body"]["msg_id"].clone();
+    MaelstromMessage {
+        src: request["dest"].as_str().unwrap().to_owned(),
+        dest: request["src"].as_str().unwrap().to_owned(),
+        body,
+    }
+}
+
+#[test]
+fn callbacks_run_with_the_node_state() {
+    let mut node = node();
+    let mut output = Vec::new();
+    node.kv
+        .read(&mut output, "counter", |node: &mut TestNode, result, _| {
+            node.results.push(result)
+        });
+    node.kv.cas(
+        &mut output,
+        "counter",
+        1,
+        2,
+        true,
+        |node: &mut TestNode, result, _| node.results.push(result),
+    );
+    let requests = sent(&output);
+    assert_eq!(requests[0]["dest"], "lin-kv");
+    assert_eq!(requests[0]["body"]["type"], "read");
+    assert_eq!(requests[1]["body"]["create_if_not_exists"], true);
+    assert_eq!(node.kv.pending(), 2);
+
+    //Replies complete out of order, each with its own callback.
+    let cas_ok = reply(&requests[1], json!({"type": "cas_ok"}));
+    let read_ok = reply(&requests[0], json!({"type": "read_ok", "value": 7}));
+    assert!(KvClient::dispatch(
+        &mut node,
+        |node| &mut node.kv,
+        &cas_ok,
+        &mut output
+    ));
+    assert!(KvClient::dispatch(
+        &mut node,
+        |node| &mut node.kv,
+        &read_ok,
+        &mut output
+    ));
+    assert!(!KvClient::dispatch(
+        &mut node,
+        |node| &mut node.kv,
+        &read_ok,
+        &mut output
+    ));
+    assert_eq!(node.results, [Ok(Value::Null), Ok(json!(7))]);
+    assert_eq!(node.kv.pending(), 0);
+}
+
+#[test]
+fn error_codes_are_typed() {
+    let mut node = node();
+    let mut output = Vec::new();
+    for _ in 0..3 {
+        node.kv
+            .read(&mut output, 1, |node: &mut TestNode, result, _| {
+                node.results.push(result)
+            });
+    }
+    let requests = sent(&output);
+    let errors = [
+        json!({"type": "error", "code": 20, "text": "not found"}),
+        json!({"type": "error", "code": 22, "text": "expected 1, had 2"}),
+        json!({"type": "error", "code": 11, "text": "unavailable"}),
+    ];
+    for (request, error) in requests.iter().zip(errors) {
+        let error = reply(request, error);
+        KvClient::dispatch(&mut node, |node| &mut node.kv, &error, &mut output);
+    }
+    assert_eq!(
+        node.results,
+        [
+            Err(ServiceError::KeyDoesNotExist),
+            Err(ServiceError::PreconditionFailed),
+            Err(ServiceError::Other {
+                code: 11,
+                text: "unavailable".to_owned()
+            }),
+        ]
+    );
+    assert!(ServiceError::PreconditionFailed.is_definite());
+    assert!(!ServiceError::Timeout.is_definite());
+}
+
+#[test]
+fn unanswered_requests_expire() {
+    let mut node = node();
+    let mut output = Vec::new();
+    node.kv
+        .write(&mut output, "k", 5, |node: &mut TestNode, result, _| {
+            node.results.push(result)
+        });
+    assert_eq!(
+        KvClient::expire(
+            &mut node,
+            |node| &mut node.kv,
+            Duration::from_secs(60),
+            &mut output
+        ),
+        0
+    );
+    assert_eq!(
+        KvClient::expire(&mut node, |node| &mut node.kv, Duration::ZERO, &mut output),
+        1
+    );
+    assert_eq!(node.results, [Err(ServiceError::Timeout)]);
+}
+
+#[test]
+fn retries_back_off_and_give_up() {
+    let mut node = node();
+    let busy = ServiceError::PreconditionFailed;
+    //Delays are random, but never more than the doubling cap allows.
+    for attempt in 0..kv::MAX_ATTEMPTS - 1 {
+        let delay = node.kv.backoff(attempt, &busy, false).unwrap();
+        assert!(delay <= Duration::from_millis(5 << attempt).min(Duration::from_millis(500)));
+    }
+    assert_eq!(node.kv.backoff(kv::MAX_ATTEMPTS - 1, &busy, false), None);
+    //A write that may have applied is only retried if applying it twice is harmless.
+    assert_eq!(node.kv.backoff(0, &ServiceError::Timeout, false), None);
+    assert!(node.kv.backoff(0, &ServiceError::Timeout, true).is_some());
+    assert_eq!(
+        kv::client_error("add", &busy).0,
+        kv::TEMPORARILY_UNAVAILABLE
+    );
+    assert_eq!(
+        kv::client_error("add", &ServiceError::Timeout).0,
+        kv::TIMEOUT
+    );
+
+    //Retries run once their delay has passed, and only once.
+    let mut output = Vec::new();
+    node.kv.after(Duration::ZERO, |node: &mut TestNode, _| {
+        node.results.push(Ok(json!("retried")))
+    });
+    node.kv
+        .after(Duration::from_secs(60), |node: &mut TestNode, _| {
+            node.results.push(Ok(json!("too soon")))
+        });
+    assert_eq!(
+        KvClient::run_retries(&mut node, |node| &mut node.kv, &mut output),
+        1
+    );
+    assert_eq!(
+        KvClient::run_retries(&mut node, |node| &mut node.kv, &mut output),
+        0
+    );
+    assert_eq!(node.results, [Ok(json!("retried"))]);
+}
+
+//Stands in for stdout: every line written is passed to the fake service.
+struct Pipe(Sender<String>, Vec<u8>);
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.1.extend_from_slice(buf);
+        while let Some(end) = self.1.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.1.drain(..=end).collect();
+            let _ = self.0.send(String::from_utf8_lossy(&line).into_owned());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn blocking_calls_wait_for_their_reply() {
+    let (line_tx, line_rx) = channel::<String>();
+    //Plays lin-kv and the runtime's stdin thread: answers each request
+    //and delivers the reply straight to the blocked caller.
+    thread::spawn(move || {
+        for line in line_rx {
+            let request: Value = serde_json::from_str(&line).unwrap();
+            let body = match request["body"]["type"].as_str() {
+                Some("read") => json!({"type": "read_ok", "value": [1, 2]}),
+                Some("cas") => json!({"type": "error", "code": 22, "text": "mismatch"}),
+                _ => json!({"type": "write_ok"}),
+            };
+            let line = serde_json::to_string(&reply(&request, body)).unwrap();
+            match parse_event::<Value>(&line).unwrap() {
+                None => {}
+                Some(_) => panic!("reply was not taken by the waiting caller"),
+            }
+        }
+    });
+
+    let client: KvClient<()> = KvClient::seq("n2");
+    let mut output = Pipe(line_tx, Vec::new());
+    let timeout = Duration::from_secs(5);
+    assert_eq!(client.write_blocking(&mut output, "k", 1, timeout), Ok(()));
+    assert_eq!(
+        client.read_blocking(&mut output, "k", timeout),
+        Ok(json!([1, 2]))
+    );
+    assert_eq!(
+        client.cas_blocking(&mut output, "k", 1, 2, false, timeout),
+        Err(ServiceError::PreconditionFailed)
+    );
+}
+
+#[test]
+fn unclaimed_service_replies_become_events() {
+    let line =
+        r#"{"src":"lin-kv","dest":"n1","body":{"type":"read_ok","in_reply_to":999999,"value":3}}"#;
+    match parse_event::<Value>(line).unwrap() {
+        Some(Event::ServiceReply(message)) => assert_eq!(message.body["value"], 3),
+        _ => panic!("expected a ServiceReply"),
+    }
+    assert!(service::is_service("lww-kv"));
+    assert!(!service::is_service("n1"));
+}
@@ -0,0 +1,232 @@
+use event_horizon::checker::linearizability::{
+    self, Counterexample, RegisterOp, RegisterOperation,
+};
+use event_horizon::history::{History, OpType, Operation, Process};
+use event_horizon::rng::Rng;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+fn op(process: usize, call: u64, ret: Option<u64>, op: RegisterOp) -> RegisterOperation {
+    RegisterOperation {
+        process,
+        call,
+        ret,
+        op,
+    }
+}
+
+fn read(value: usize) -> RegisterOp {
+    RegisterOp::Read { value: Some(value) }
+}
+
+fn write(value: usize) -> RegisterOp {
+    RegisterOp::Write { value }
+}
+
+#[test]
+fn accepts_a_concurrent_linearizable_history() {
+    let history = vec![
+        op(0, 0, Some(50), write(1)),
+        op(1, 10, Some(60), write(2)),
+        //Overlaps both writes, so it may see either
+        op(2, 20, Some(30), read(2)),
+        op(2, 40, Some(70), read(1)),
+        op(3, 0, Some(5), RegisterOp::Read { value: None }),
+        op(3, 80, Some(90), read(1)),
+    ];
+    assert_eq!(linearizability::check(&history), Ok(()));
+}
+
+#[test]
+fn reports_the_culprit_and_its_window() {
+    let write_3 = op(3, 55, Some(80), write(3));
+    let stale_read = op(1, 60, Some(70), read(1));
+    let history = vec![
+        op(0, 0, Some(10), write(1)),
+        op(1, 20, Some(30), read(1)),
+        op(2, 40, Some(50), write(2)),
+        write_3.clone(),
+        stale_read.clone(),
+    ];
+    assert_eq!(
+        linearizability::check(&history),
+        Err(Counterexample {
+            culprit: stale_read.clone(),
+            window: vec![write_3, stale_read],
+            states: vec![Some(2)],
+        })
+    );
+}
+
+#[test]
+fn info_ops_may_take_effect_or_not() {
+    let took_effect = vec![
+        op(0, 0, Some(10), write(1)),
+        op(1, 20, None, RegisterOp::Cas { from: 1, to: 2 }),
+        op(2, 30, Some(40), read(2)),
+        op(3, 50, Some(60), RegisterOp::Cas { from: 2, to: 3 }),
+        op(2, 70, Some(80), read(3)),
+    ];
+    assert_eq!(linearizability::check(&took_effect), Ok(()));
+
+    let never_did = vec![
+        op(0, 0, Some(10), write(1)),
+        op(1, 20, None, write(5)),
+        op(2, 30, Some(40), read(1)),
+        op(2, 50, Some(60), read(1)),
+    ];
+    assert_eq!(linearizability::check(&never_did), Ok(()));
+
+    //An info op may take effect long after its call, past quiescent points.
+    let info_write = op(1, 5, None, write(2));
+    let mut late = vec![
+        op(0, 0, Some(10), write(1)),
+        info_write.clone(),
+        op(2, 20, Some(30), read(1)),
+        op(0, 40, Some(50), write(3)),
+        op(2, 60, Some(70), read(2)),
+    ];
+    assert_eq!(linearizability::check(&late), Ok(()));
+    //It stays in the window of a later culprit while it may still take effect.
+    let stale_read = op(2, 60, Some(70), read(1));
+    late[4] = stale_read.clone();
+    assert_eq!(
+        linearizability::check(&late),
+        Err(Counterexample {
+            culprit: stale_read.clone(),
+            window: vec![info_write, stale_read],
+            states: vec![Some(3)],
+        })
+    );
+
+    //A cas that completed cannot apply from a state it does not match.
+    let bad_cas = op(3, 50, Some(60), RegisterOp::Cas { from: 1, to: 4 });
+    let failing = vec![
+        op(0, 0, Some(10), write(1)),
+        op(1, 20, Some(30), RegisterOp::Cas { from: 1, to: 2 }),
+        bad_cas.clone(),
+    ];
+    let counterexample = linearizability::check(&failing).unwrap_err();
+    assert_eq!(counterexample.culprit, bad_cas);
+    assert_eq!(counterexample.states, vec![Some(2)]);
+}
+
+fn client(op_type: OpType, process: usize, time: u64, f: &str, value: Value) -> Operation {
+    Operation {
+        op_type,
+        process: Process::Client(process),
+        time,
+        f: f.to_owned(),
+        value,
+        error: None,
+        index: None,
+        msg_id: None,
+    }
+}
+
+#[test]
+fn splits_client_histories_by_key() {
+    let history = History {
+        ops: vec![
+            client(OpType::Invoke, 0, 0, "write", json!([1, 3])),
+            client(OpType::Invoke, 1, 1, "cas", json!([1, [3, 4]])),
+            client(OpType::Ok, 0, 2, "write", json!([1, 3])),
+            client(OpType::Info, 1, 3, "cas", json!([1, [3, 4]])),
+            client(OpType::Invoke, 2, 4, "read", json!([2, null])),
+            client(OpType::Ok, 2, 5, "read", json!([2, null])),
+            client(OpType::Invoke, 0, 6, "cas", json!([2, [1, 2]])),
+            client(OpType::Fail, 0, 7, "cas", json!([2, [1, 2]])),
+            client(OpType::Invoke, 2, 8, "read", json!([1, null])),
+            client(OpType::Ok, 2, 9, "read", json!([1, 4])),
+        ],
+    };
+    let registers = linearizability::register_histories(&history);
+    assert_eq!(
+        registers["1"],
+        vec![
+            op(0, 0, Some(2), write(3)),
+            op(1, 1, None, RegisterOp::Cas { from: 3, to: 4 }),
+            op(2, 8, Some(9), read(4)),
+        ]
+    );
+    //The failed cas is dropped.
+    assert_eq!(
+        registers["2"],
+        vec![op(2, 4, Some(5), RegisterOp::Read { value: None })]
+    );
+    assert!(registers
+        .values()
+        .all(|history| linearizability::check(history).is_ok()));
+}
+
+fn random_history(count: usize, processes: usize, seed: u64) -> Vec<RegisterOperation> {
+    //! Ops from several processes against one atomic register, each taking effect at a
+    //! random instant between its call and return, with some outcomes left unknown.
+    let mut rng = Rng::seeded(seed);
+    let mut free_at = vec![0; processes];
+    let mut ops = Vec::new();
+    for _ in 0..count {
+        let process = rng.below(processes);
+        let call = free_at[process] + rng.below(20) as u64;
+        let takes_effect = call + 1 + rng.below(30) as u64;
+        let ret = takes_effect + 1 + rng.below(30) as u64;
+        free_at[process] = ret;
+        let register_op = match rng.below(3) {
+            0 => write(rng.below(5)),
+            1 => RegisterOp::Cas {
+                from: rng.below(5),
+                to: rng.below(5),
+            },
+            _ => RegisterOp::Read { value: None },
+        };
+        ops.push((takes_effect, op(process, call, Some(ret), register_op)));
+    }
+    //Apply the ops in the order they took effect, filling in reads and
+    //turning cas ops that would not apply into reads.
+    ops.sort_by_key(|(takes_effect, _)| *takes_effect);
+    let mut state = None;
+    for (_, operation) in ops.iter_mut() {
+        operation.op = match operation.op {
+            RegisterOp::Write { value } => {
+                state = Some(value);
+                operation.op
+            }
+            RegisterOp::Cas { from, to } if state == Some(from) => {
+                state = Some(to);
+                operation.op
+            }
+            _ => RegisterOp::Read { value: state },
+        };
+        if !matches!(operation.op, RegisterOp::Read { .. }) && rng.chance(0.02) {
+            operation.ret = None;
+        }
+    }
+    let mut history: Vec<_> = ops.into_iter().map(|(_, operation)| operation).collect();
+    history.sort_by_key(|operation| operation.call);
+    history
+}
+
+#[test]
+fn checks_thousands_of_ops_in_time() {
+    let history = random_history(5000, 8, 11);
+    let started = Instant::now();
+    assert_eq!(linearizability::check(&history), Ok(()));
+
+    //One read of a value no op ever wrote breaks the history, and is what gets reported.
+    let mut broken = history.clone();
+    let index = broken
+        .iter()
+        .position(|operation| {
+            operation.call > 10_000 && matches!(operation.op, RegisterOp::Read { .. })
+        })
+        .unwrap();
+    broken[index].op = read(99);
+    let counterexample = linearizability::check(&broken).unwrap_err();
+    assert_eq!(counterexample.culprit, broken[index]);
+    assert!(counterexample.window.contains(&broken[index]));
+    assert!(
+        started.elapsed() < Duration::from_secs(10),
+        "Took {:?}",
+        started.elapsed()
+    );
+}
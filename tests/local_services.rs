@@ -0,0 +1,182 @@
+use event_horizon::runner::network::Latency;
+use event_horizon::service::kv::KvClient;
+use event_horizon::service::local::{LocalConfig, LocalServices};
+use event_horizon::service::ServiceError;
+use event_horizon::MaelstromMessage;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+fn call(services: &mut LocalServices, client: &str, service: &str, body: Value) -> Value {
+    let mut body = body;
+    body["msg_id"] = json!(1);
+    let request = MaelstromMessage {
+        src: client.to_owned(),
+        dest: service.to_owned(),
+        body,
+    };
+    let (_, reply) = services.handle(&request).expect("service is hosted");
+    assert_eq!(reply.dest, client);
+    assert_eq!(reply.body["in_reply_to"], 1);
+    reply.body
+}
+
+#[test]
+fn lin_kv_speaks_the_maelstrom_protocol() {
+    let mut services = LocalServices::new(LocalConfig::default());
+    let read = json!({"type": "read", "key": "x"});
+    assert_eq!(
+        call(&mut services, "n1", "lin-kv", read.clone())["code"],
+        20
+    );
+    let cas = json!({"type": "cas", "key": "x", "from": 1, "to": 2});
+    assert_eq!(call(&mut services, "n1", "lin-kv", cas.clone())["code"], 20);
+
+    let create =
+        json!({"type": "cas", "key": "x", "from": 1, "to": 2, "create_if_not_exists": true});
+    assert_eq!(
+        call(&mut services, "n1", "lin-kv", create)["type"],
+        "cas_ok"
+    );
+    assert_eq!(
+        call(&mut services, "n2", "lin-kv", read.clone())["value"],
+        2
+    );
+    assert_eq!(call(&mut services, "n2", "lin-kv", cas)["code"], 22);
+
+    let write = json!({"type": "write", "key": "x", "value": [1, 2]});
+    assert_eq!(
+        call(&mut services, "n2", "lin-kv", write)["type"],
+        "write_ok"
+    );
+    assert_eq!(
+        call(&mut services, "n1", "lin-kv", read)["value"],
+        json!([1, 2])
+    );
+    //Keys are compared as JSON.
+    let numeric = json!({"type": "read", "key": 1});
+    assert_eq!(call(&mut services, "n1", "lin-kv", numeric)["code"], 20);
+}
+
+#[test]
+fn seq_kv_reads_are_stale_but_never_go_backwards() {
+    let mut services = LocalServices::new(LocalConfig {
+        max_lag: 5,
+        max_age: Duration::from_secs(60),
+        seed: 7,
+        ..LocalConfig::default()
+    });
+    let mut stale = 0;
+    let mut last_seen = 0;
+    for value in 1..=100 {
+        let write = json!({"type": "write", "key": "k", "value": value});
+        call(&mut services, "n1", "seq-kv", write);
+        //The writer always sees its own writes.
+        let read = json!({"type": "read", "key": "k"});
+        assert_eq!(
+            call(&mut services, "n1", "seq-kv", read.clone())["value"],
+            value
+        );
+
+        let seen = call(&mut services, "n2", "seq-kv", read)["value"]
+            .as_u64()
+            .unwrap_or(0);
+        assert!(seen >= last_seen, "n2 read {} after {}", seen, last_seen);
+        assert!(
+            seen + 5 >= value,
+            "n2 read {} when {} was written",
+            seen,
+            value
+        );
+        stale += usize::from(seen < value);
+        last_seen = seen;
+    }
+    assert!(stale > 0, "seq-kv never served a stale read");
+
+    //A cas always works against the latest state.
+    let cas = json!({"type": "cas", "key": "k", "from": 100, "to": 101});
+    assert_eq!(call(&mut services, "n3", "seq-kv", cas)["type"], "cas_ok");
+}
+
+#[test]
+fn lww_kv_writes_reach_other_clients_later() {
+    let mut services = LocalServices::new(LocalConfig {
+        propagation: Latency::Constant { ms: 60_000.0 },
+        ..LocalConfig::default()
+    });
+    let write = json!({"type": "write", "key": "k", "value": 1});
+    call(&mut services, "n1", "lww-kv", write);
+    let read = json!({"type": "read", "key": "k"});
+    assert_eq!(
+        call(&mut services, "n1", "lww-kv", read.clone())["value"],
+        1
+    );
+    assert_eq!(
+        call(&mut services, "n2", "lww-kv", read.clone())["code"],
+        20
+    );
+
+    let mut services = LocalServices::new(LocalConfig {
+        propagation: Latency::Constant { ms: 0.0 },
+        ..LocalConfig::default()
+    });
+    for (client, value) in [("n1", 1), ("n2", 2)] {
+        let write = json!({"type": "write", "key": "k", "value": value});
+        call(&mut services, client, "lww-kv", write);
+    }
+    assert_eq!(call(&mut services, "n1", "lww-kv", read)["value"], 2);
+}
+
+struct Counter {
+    kv: KvClient<Counter>,
+    value: Option<u64>,
+}
+
+#[test]
+fn drives_a_client_in_process() {
+    let mut services = LocalServices::new(LocalConfig::default());
+    let mut node = Counter {
+        kv: KvClient::lin("n1"),
+        value: None,
+    };
+    let mut output = Vec::new();
+    node.kv.cas(
+        &mut output,
+        "counter",
+        0,
+        5,
+        true,
+        |node: &mut Counter, result, output| {
+            assert_eq!(result, Ok(Value::Null));
+            node.kv
+                .read(output, "counter", |node: &mut Counter, result, _| {
+                    node.value = result.ok().and_then(|value| value.as_u64());
+                });
+        },
+    );
+    //Feed every request to the services and every reply back to the node,
+    //until nothing is left in flight.
+    while !output.is_empty() {
+        let requests = String::from_utf8(std::mem::take(&mut output)).unwrap();
+        for line in requests.lines() {
+            let request: MaelstromMessage<Value> = serde_json::from_str(line).unwrap();
+            let (_, reply) = services.handle(&request).unwrap();
+            KvClient::dispatch(&mut node, |node| &mut node.kv, &reply, &mut output);
+        }
+    }
+    assert_eq!(node.value, Some(5));
+    assert_eq!(node.kv.pending(), 0);
+
+    let mut output = Vec::new();
+    node.kv
+        .cas(&mut output, "counter", 0, 1, false, |_, result, _| {
+            assert_eq!(result, Err(ServiceError::PreconditionFailed))
+        });
+    let request = serde_json::from_slice(&output[..output.len() - 1]).unwrap();
+    let (_, reply) = services.handle(&request).unwrap();
+    assert!(KvClient::dispatch(
+        &mut node,
+        |node| &mut node.kv,
+        &reply,
+        &mut output
+    ));
+}
@@ -0,0 +1,172 @@
+//Process faults against a real cluster of node processes.
+
+use event_horizon::history::{OpType, Process};
+use event_horizon::node::broadcast::interval_set::IntervalSet;
+use event_horizon::runner::nemesis::{Fault, Nemesis, NemesisConfig};
+use event_horizon::runner::network::Network;
+use event_horizon::runner::{Client, Cluster, ClusterConfig, Recorder};
+use event_horizon::service::local::LocalConfig;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn config(node_type: &str, node_count: usize) -> ClusterConfig {
+    ClusterConfig {
+        bin: PathBuf::from(env!("CARGO_BIN_EXE_event-horizon")),
+        node_type: node_type.to_owned(),
+        node_count,
+        env: Vec::new(),
+        network: Network::default(),
+        services: LocalConfig::default(),
+        seed: 1,
+        log_dir: None,
+        state_dir: None,
+    }
+}
+
+fn cluster(node_count: usize) -> Cluster {
+    Cluster::start(config("echo", node_count), Arc::new(Recorder::new())).unwrap()
+}
+
+fn echo(client: &mut Client, timeout: Duration) -> OpType {
+    client
+        .call(json!({"type": "echo", "echo": "hello"}), timeout)
+        .op_type
+}
+
+#[test]
+fn killed_nodes_miss_messages_until_restarted() {
+    let cluster = cluster(2);
+    let mut client = cluster.client("n0");
+    let timeout = Duration::from_millis(300);
+    assert_eq!(echo(&mut client, timeout), OpType::Ok);
+    cluster.kill_node("n0");
+    assert_eq!(echo(&mut client, timeout), OpType::Info);
+    cluster.spawn_node("n0").unwrap();
+    assert_eq!(echo(&mut client, timeout), OpType::Ok);
+
+    let trace = cluster.shutdown();
+    let requests: Vec<bool> = trace
+        .iter()
+        .filter(|entry| entry.message.dest == "n0")
+        .map(|entry| entry.received.is_some())
+        .collect();
+    assert_eq!(requests, vec![true, false, true]);
+}
+
+#[test]
+fn paused_nodes_answer_once_resumed() {
+    let cluster = cluster(2);
+    let mut client = cluster.client("n1");
+    cluster.signal_node("n1", "STOP").unwrap();
+    assert_eq!(echo(&mut client, Duration::from_millis(300)), OpType::Info);
+    cluster.signal_node("n1", "CONT").unwrap();
+    //The late reply to the first request is skipped.
+    assert_eq!(echo(&mut client, Duration::from_secs(5)), OpType::Ok);
+    let trace = cluster.shutdown();
+    assert_eq!(
+        trace
+            .iter()
+            .filter(|entry| entry.message.src == "n1")
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn nemesis_heals_every_process_fault() {
+    let cluster = Arc::new(cluster(3));
+    let config = NemesisConfig {
+        faults: vec![
+            Fault::Kill {
+                restart_delay: Duration::from_millis(100),
+            },
+            Fault::Pause,
+        ],
+        interval: Duration::from_millis(150),
+    };
+    let nemesis = Nemesis::start(config, cluster.clone(), 2);
+    thread::sleep(Duration::from_secs(1));
+    nemesis.stop();
+    for node_id in cluster.node_ids.iter() {
+        let mut client = cluster.client(node_id);
+        assert_eq!(echo(&mut client, Duration::from_secs(5)), OpType::Ok);
+    }
+
+    let history = cluster.recorder.history();
+    let count = |f: &str| {
+        history
+            .ops
+            .iter()
+            .filter(|operation| {
+                operation.process == Process::Named("nemesis".to_owned()) && operation.f == f
+            })
+            .count()
+    };
+    assert!(count("kill") > 0 && count("pause") > 0);
+    assert_eq!(count("kill"), count("start"));
+    assert_eq!(count("pause"), count("resume"));
+    assert_eq!(count("start-failed") + count("pause-failed"), 0);
+    Arc::try_unwrap(cluster).ok().unwrap().shutdown();
+}
+
+fn read_until(client: &mut Client, message: u64) -> Vec<Value> {
+    //! Read until the node has the message, or give up after a few seconds.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let read = client.call(json!({"type": "read"}), Duration::from_secs(1));
+        let messages = read.value["messages"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        if messages.contains(&json!(message)) || Instant::now() > deadline {
+            return messages;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn restarted_broadcast_nodes_never_reuse_ids() {
+    let mut config = config("broadcast", 2);
+    config.env = vec![("EVENT_HORIZON_TOPOLOGY".to_owned(), "star".to_owned())];
+    config.state_dir = Some(env::temp_dir().join(format!("event-horizon-state-{}", process::id())));
+    let cluster = Cluster::start(config.clone(), Arc::new(Recorder::new())).unwrap();
+    let mut to_n0 = cluster.client("n0");
+    let mut to_n1 = cluster.client("n1");
+    let broadcast = |client: &mut Client, message: u64| {
+        let body = json!({"type": "broadcast", "message": message});
+        assert_eq!(
+            client.call(body, Duration::from_secs(1)).op_type,
+            OpType::Ok
+        );
+    };
+    broadcast(&mut to_n0, 1);
+    assert_eq!(read_until(&mut to_n1, 1), vec![json!(1)]);
+    cluster.kill_node("n0");
+    cluster.spawn_node("n0").unwrap();
+    //A restarted node numbering its broadcasts from zero again would reuse the
+    //first one's id, and n1 would take the second for one it already has.
+    broadcast(&mut to_n0, 2);
+    assert_eq!(read_until(&mut to_n1, 2), vec![json!(1), json!(2)]);
+
+    //Every id n0 gossiped, before and after the restart, stood for one message.
+    let mut gossiped: HashMap<usize, Value> = HashMap::new();
+    for entry in cluster.shutdown() {
+        let body = &entry.message.body;
+        if entry.message.src != "n0" || body["type"] != "gossip" {
+            continue;
+        }
+        let ids: IntervalSet = serde_json::from_value(body["message"].clone()).unwrap();
+        for (id, payload) in ids.iter().zip(body["payloads"].as_array().unwrap()) {
+            assert_eq!(gossiped.entry(id).or_insert(payload.clone()), payload);
+        }
+    }
+    assert_eq!(gossiped.len(), 2);
+    let _ = std::fs::remove_dir_all(config.state_dir.unwrap());
+}
@@ -0,0 +1,253 @@
+use event_horizon::rng::Rng;
+use event_horizon::runner::nemesis::Fault;
+use event_horizon::runner::network::{Grudge, Latency, LinkConfig, Network, PartitionKind};
+use std::collections::HashSet;
+use std::time::Duration;
+
+fn node_ids(count: usize) -> Vec<String> {
+    (0..count).map(|index| format!("n{}", index)).collect()
+}
+
+fn visible<'a>(grudge: &Grudge, node_ids: &'a [String], node: &str) -> HashSet<&'a String> {
+    node_ids
+        .iter()
+        .filter(|other| !grudge[node].contains(*other))
+        .collect()
+}
+
+#[test]
+fn parses_latencies() {
+    assert_eq!(Latency::parse("10"), Some(Latency::Constant { ms: 10.0 }));
+    assert_eq!(
+        Latency::parse("uniform:5:20"),
+        Some(Latency::Uniform {
+            min_ms: 5.0,
+            max_ms: 20.0
+        })
+    );
+    assert_eq!(
+        Latency::parse("exp:2.5"),
+        Some(Latency::Exponential { mean_ms: 2.5 })
+    );
+    for spec in [
+        "-1",
+        "NaN",
+        "inf",
+        "uniform:5",
+        "uniform:20:5",
+        "uniform:0:inf",
+        "exp:-3",
+        "exp:NaN",
+        "normal:3",
+    ] {
+        assert_eq!(Latency::parse(spec), None, "{}", spec);
+    }
+    //A hand-built latency that is not a valid delay samples as none rather than panicking.
+    let mut rng = Rng::seeded(1);
+    assert_eq!(
+        Latency::Constant { ms: f64::NAN }.sample(&mut rng),
+        Duration::ZERO
+    );
+    assert_eq!(
+        Latency::Constant { ms: f64::INFINITY }.sample(&mut rng),
+        Duration::ZERO
+    );
+}
+
+#[test]
+fn samples_latencies_within_their_bounds() {
+    let mut rng = Rng::seeded(2);
+    let uniform = Latency::Uniform {
+        min_ms: 5.0,
+        max_ms: 20.0,
+    };
+    let exponential = Latency::Exponential { mean_ms: 10.0 };
+    let mut total = Duration::ZERO;
+    for _ in 0..10_000 {
+        let delay = uniform.sample(&mut rng);
+        assert!(delay >= Duration::from_millis(5) && delay <= Duration::from_millis(20));
+        total += exponential.sample(&mut rng);
+    }
+    let mean_ms = total.as_secs_f64() * 1000.0 / 10_000.0;
+    assert!((9.0..11.0).contains(&mean_ms), "Mean {}ms", mean_ms);
+}
+
+#[test]
+fn splits_nodes_into_halves() {
+    let node_ids = node_ids(5);
+    let grudge = PartitionKind::RandomHalves.grudge(&node_ids, &mut Rng::seeded(3));
+    let mut components: Vec<Vec<&String>> = node_ids
+        .iter()
+        .map(|node| {
+            let mut component: Vec<&String> =
+                visible(&grudge, &node_ids, node).into_iter().collect();
+            component.sort();
+            component
+        })
+        .collect();
+    components.sort();
+    components.dedup();
+    //Each node hears exactly the nodes on its own side.
+    assert_eq!(components.len(), 2);
+    let mut sizes: Vec<usize> = components.iter().map(Vec::len).collect();
+    sizes.sort();
+    assert_eq!(sizes, vec![2, 3]);
+    for node in node_ids.iter() {
+        assert!(visible(&grudge, &node_ids, node).contains(node));
+    }
+}
+
+#[test]
+fn isolates_one_node() {
+    let node_ids = node_ids(5);
+    let grudge = PartitionKind::IsolateOne.grudge(&node_ids, &mut Rng::seeded(4));
+    let isolated: Vec<&String> = node_ids
+        .iter()
+        .filter(|node| grudge[*node].len() == 4)
+        .collect();
+    assert_eq!(isolated.len(), 1);
+    for node in node_ids.iter().filter(|node| *node != isolated[0]) {
+        assert_eq!(grudge[node], HashSet::from([isolated[0].clone()]));
+    }
+}
+
+#[test]
+fn majority_ring_gives_every_node_a_different_majority() {
+    for count in [3, 4, 5, 7] {
+        let node_ids = node_ids(count);
+        for seed in 0..10 {
+            let grudge = PartitionKind::MajorityRing.grudge(&node_ids, &mut Rng::seeded(seed));
+            let majorities: Vec<HashSet<&String>> = node_ids
+                .iter()
+                .map(|node| visible(&grudge, &node_ids, node))
+                .collect();
+            for (node, majority) in node_ids.iter().zip(majorities.iter()) {
+                assert!(majority.contains(node));
+                assert_eq!(majority.len(), count / 2 + 1);
+            }
+            for (index, majority) in majorities.iter().enumerate() {
+                assert!(!majorities[index + 1..].contains(majority));
+            }
+        }
+    }
+}
+
+#[test]
+fn grudges_drop_messages_to_the_nodes_holding_them() {
+    let mut network = Network::default();
+    network
+        .grudge
+        .insert("n1".to_owned(), HashSet::from(["n0".to_owned()]));
+    let mut rng = Rng::seeded(5);
+    assert!(network.is_partitioned("n0", "n1"));
+    assert!(network.plan("n0", "n1", &mut rng).is_empty());
+    //Grudges are one-way.
+    assert_eq!(network.plan("n1", "n0", &mut rng), vec![Duration::ZERO]);
+}
+
+#[test]
+fn samples_loss_duplication_and_reordering() {
+    let mut rng = Rng::seeded(6);
+    let mut network = Network {
+        default_link: LinkConfig {
+            loss: 0.3,
+            latency: Latency::Constant { ms: 10.0 },
+            duplicate: 0.2,
+            reorder: 0.1,
+        },
+        ..Network::default()
+    };
+    let (mut lost, mut duplicated, mut held_back) = (0, 0, 0);
+    for _ in 0..10_000 {
+        let delays = network.plan("n0", "n1", &mut rng);
+        match delays.len() {
+            0 => lost += 1,
+            1 => {}
+            _ => duplicated += 1,
+        }
+        for delay in delays {
+            if delay == Duration::from_millis(10) {
+                continue;
+            }
+            //Held back by three more latencies and a little.
+            assert_eq!(delay, Duration::from_millis(45));
+            held_back += 1;
+        }
+    }
+    assert!((2700..3300).contains(&lost), "Lost {}", lost);
+    //Duplicated and reordered among the messages that survive
+    assert!(
+        (1200..1600).contains(&duplicated),
+        "Duplicated {}",
+        duplicated
+    );
+    assert!((700..1000).contains(&held_back), "Held back {}", held_back);
+
+    network.default_link = LinkConfig::default();
+    for _ in 0..100 {
+        assert_eq!(network.plan("n0", "n1", &mut rng), vec![Duration::ZERO]);
+    }
+}
+
+#[test]
+fn overrides_single_links() {
+    let mut network = Network::default();
+    network.default_link.set("latency=uniform:1:2").unwrap();
+    network.default_link.set("reorder=0.1").unwrap();
+    network.add_link("n0>n1,loss=1,latency=7").unwrap();
+    assert_eq!(
+        network.link("n0", "n1"),
+        LinkConfig {
+            loss: 1.0,
+            latency: Latency::Constant { ms: 7.0 },
+            duplicate: 0.0,
+            //Left to the default link
+            reorder: 0.1,
+        }
+    );
+    assert_eq!(network.link("n1", "n0"), network.default_link);
+    let mut rng = Rng::seeded(7);
+    for _ in 0..100 {
+        assert!(network.plan("n0", "n1", &mut rng).is_empty());
+        assert_eq!(network.plan("n1", "n0", &mut rng).len(), 1);
+    }
+
+    for spec in [
+        "n0",
+        "n0-n1,loss=1",
+        "n0>n1,loss=1.5",
+        "n0>n1,loss",
+        "n0>n1,jitter=3",
+        "n0>n1,latency=-2",
+    ] {
+        assert_eq!(network.add_link(spec), None, "{}", spec);
+    }
+    assert_eq!(network.links.len(), 1);
+}
+
+#[test]
+fn parses_faults() {
+    assert_eq!(
+        Fault::parse("partition:halves+isolate"),
+        Some(Fault::Partition(vec![
+            PartitionKind::RandomHalves,
+            PartitionKind::IsolateOne
+        ]))
+    );
+    assert_eq!(Fault::parse("loss:0.2"), Some(Fault::Loss(0.2)));
+    assert_eq!(
+        Fault::parse("kill:0.5"),
+        Some(Fault::Kill {
+            restart_delay: Duration::from_millis(500)
+        })
+    );
+    for spec in [
+        "partition:ring",
+        "loss:2",
+        "duplicate:NaN",
+        "latency:exp:-1",
+        "kill:-1",
+    ] {
+        assert_eq!(Fault::parse(spec), None, "{}", spec);
+    }
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::Net;
+use serde_json::json;
+use std::thread;
+use std::time::Duration;
+
+fn net(count: usize) -> Net {
+    //! A cluster where every node neighbors every other, so Plumtree has plenty of
+    //! redundant links to prune.
+    Net::mesh(count, &[("EVENT_HORIZON_GOSSIP", "plumtree")])
+}
+
+fn broadcast(net: &mut Net, node_id: &str, message: usize) {
+    net.broadcast(node_id, json!(message));
+    net.settle();
+}
+
+fn pushes(net: &mut Net) -> usize {
+    std::mem::take(&mut net.sent)
+        .get("gossip")
+        .copied()
+        .unwrap_or(0)
+}
+
+fn holding(net: &Net, message: usize) -> usize {
+    net.nodes
+        .values()
+        .filter(|node| node.payloads.values().any(|payload| *payload == message))
+        .count()
+}
+
+#[test]
+fn eager_links_are_pruned_to_a_tree() {
+    let mut net = net(6);
+    broadcast(&mut net, "n1", 1);
+    assert_eq!(holding(&net, 1), 6);
+    //The first broadcast floods every link, and every duplicate prunes one.
+    let flood = pushes(&mut net);
+    assert!(flood > 5);
+    let prunes: u64 = net
+        .nodes
+        .values()
+        .map(|node| node.plumtree.as_ref().unwrap().prunes)
+        .sum();
+    assert!(prunes > 0);
+
+    //Later broadcasts from the same root only travel the tree.
+    for message in 2..5 {
+        broadcast(&mut net, "n1", message);
+        assert_eq!(holding(&net, message), 6);
+        assert_eq!(pushes(&mut net), 5, "broadcast {}", message);
+    }
+}
+
+#[test]
+fn lazy_announcements_graft_around_a_cut_node() {
+    let mut net = net(5);
+    broadcast(&mut net, "n1", 1);
+    pushes(&mut net);
+    //Whatever tree n3 was on, it hears nothing while it is cut off.
+    net.cut.insert("n3".to_owned());
+    broadcast(&mut net, "n1", 2);
+    broadcast(&mut net, "n2", 3);
+    assert_eq!(holding(&net, 2), 4);
+    net.cut.clear();
+
+    //Announcements reach n3 within a second, and it grafts what it missed.
+    for _ in 0..30 {
+        thread::sleep(Duration::from_millis(100));
+        net.rounds(1);
+        if holding(&net, 2) == 5 && holding(&net, 3) == 5 {
+            break;
+        }
+    }
+    assert_eq!(holding(&net, 2), 5);
+    assert_eq!(holding(&net, 3), 5);
+    let n3 = net.nodes["n3"].plumtree.as_ref().unwrap();
+    assert!(n3.grafts > 0);
+    assert!(n3.missing().is_empty());
+}
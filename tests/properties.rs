@@ -0,0 +1,438 @@
+//Property tests for the message bodies and the state merges between nodes.
+//Every body variant is round-tripped through JSON, and the merges must be
+//commutative, associative and idempotent. quickcheck shrinks failing inputs.
+
+use event_horizon::node::broadcast::digest::RangeDigest;
+use event_horizon::node::broadcast::interval_set::IntervalSet;
+use event_horizon::node::broadcast::topology::Topology;
+use event_horizon::node::broadcast::{BroadcastBody, BroadcastNode};
+use event_horizon::node::grow_counter::{CounterBody, CounterNode};
+use event_horizon::node::kafka::KafkaBody;
+use event_horizon::node::kv_store::KVStoreBody;
+use event_horizon::{MaelstromMessage, Reply};
+use quickcheck::quickcheck;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+fn round_trips<Body>(body: Body, type_name: &str) -> bool
+where
+    Body: Serialize + DeserializeOwned,
+{
+    //! Send the body as a node would and read it back as the runtime does. The
+    //! JSON must survive unchanged and carry the expected Maelstrom type.
+    let message = MaelstromMessage {
+        src: "n1".to_owned(),
+        dest: "n2".to_owned(),
+        body,
+    };
+    let line = serde_json::to_string(&message).expect("Could not serialize body");
+    let parsed: MaelstromMessage<Body> = match serde_json::from_str(&line) {
+        Ok(parsed) => parsed,
+        Err(_) => return false,
+    };
+    let sent: Value = serde_json::from_str(&line).expect("Serialized invalid JSON");
+    let received = serde_json::to_value(&parsed).expect("Could not serialize body");
+    sent == received && sent["body"]["type"] == type_name
+}
+
+//Exhaustive, so adding a variant without a round-trip property fails to compile.
+fn broadcast_type(body: &BroadcastBody) -> &'static str {
+    match body {
+        BroadcastBody::Broadcast { .. } => "broadcast",
+        BroadcastBody::BroadcastOk { .. } => "broadcast_ok",
+        BroadcastBody::Read { .. } => "read",
+        BroadcastBody::ReadOk { .. } => "read_ok",
+        BroadcastBody::Topology { .. } => "topology",
+        BroadcastBody::TopologyOk { .. } => "topology_ok",
+        BroadcastBody::Gossip { .. } => "gossip",
+        BroadcastBody::GossipOk { .. } => "gossip_ok",
+        BroadcastBody::IHave { .. } => "ihave",
+        BroadcastBody::Graft { .. } => "graft",
+        BroadcastBody::Prune { .. } => "prune",
+        BroadcastBody::Digest { .. } => "digest",
+        BroadcastBody::Pull { .. } => "pull",
+        BroadcastBody::PullOk { .. } => "pull_ok",
+        BroadcastBody::ReadClock { .. } => "read_clock",
+        BroadcastBody::ReadClockOk { .. } => "read_clock_ok",
+        BroadcastBody::Sequence { .. } => "sequence",
+        BroadcastBody::SequenceOk { .. } => "sequence_ok",
+        BroadcastBody::RequestVote { .. } => "request_vote",
+        BroadcastBody::RequestVoteOk { .. } => "request_vote_ok",
+        BroadcastBody::Stability { .. } => "stability",
+        BroadcastBody::StabilityOk { .. } => "stability_ok",
+        BroadcastBody::ReadStable { .. } => "read_stable",
+        BroadcastBody::ReadStableOk { .. } => "read_stable_ok",
+        BroadcastBody::Exchange { .. } => "exchange",
+        BroadcastBody::ExchangeOk { .. } => "exchange_ok",
+        BroadcastBody::Error { .. } => "error",
+    }
+}
+
+fn counter_type(body: &CounterBody) -> &'static str {
+    match body {
+        CounterBody::Add { .. } => "add",
+        CounterBody::AddOk { .. } => "add_ok",
+        CounterBody::Read { .. } => "read",
+        CounterBody::ReadOk { .. } => "read_ok",
+        CounterBody::UpdateCounters { .. } => "update_counters",
+        CounterBody::Error { .. } => "error",
+    }
+}
+
+fn kafka_type(body: &KafkaBody) -> &'static str {
+    match body {
+        KafkaBody::Send { .. } => "send",
+        KafkaBody::SendOk { .. } => "send_ok",
+        KafkaBody::Poll { .. } => "poll",
+        KafkaBody::PollOk { .. } => "poll_ok",
+        KafkaBody::CommitOffsets { .. } => "commit_offsets",
+        KafkaBody::CommitOffsetsOk { .. } => "commit_offsets_ok",
+        KafkaBody::ListCommittedOffsets { .. } => "list_committed_offsets",
+        KafkaBody::ListCommittedOffsetsOk { .. } => "list_committed_offsets_ok",
+        KafkaBody::Error { .. } => "error",
+    }
+}
+
+fn kv_store_type(body: &KVStoreBody) -> &'static str {
+    match body {
+        KVStoreBody::Txn { .. } => "txn",
+        KVStoreBody::TxnOk { .. } => "txn_ok",
+        KVStoreBody::WritePropogater { .. } => "write_propogater",
+    }
+}
+
+fn broadcast_round_trips(body: BroadcastBody) -> bool {
+    let type_name = broadcast_type(&body);
+    round_trips(body, type_name)
+}
+
+fn counter_round_trips(body: CounterBody) -> bool {
+    let type_name = counter_type(&body);
+    round_trips(body, type_name)
+}
+
+fn kafka_round_trips(body: KafkaBody) -> bool {
+    let type_name = kafka_type(&body);
+    round_trips(body, type_name)
+}
+
+fn kv_store_round_trips(body: KVStoreBody) -> bool {
+    let type_name = kv_store_type(&body);
+    round_trips(body, type_name)
+}
+
+fn node_topology(topology: HashMap<u8, Vec<u8>>) -> HashMap<String, Vec<String>> {
+    //! Name nodes like Maelstrom does, which also keeps generated topologies small.
+    let node = |id: u8| format!("n{}", id);
+    topology
+        .into_iter()
+        .map(|(id, neighbors)| (node(id), neighbors.into_iter().map(node).collect()))
+        .collect()
+}
+
+fn payloads(ids: impl IntoIterator<Item = usize>) -> Vec<Value> {
+    //! Payloads of mixed JSON types, one per id.
+    ids.into_iter()
+        .map(|id| match id % 3 {
+            0 => json!(id),
+            1 => json!(id.to_string()),
+            _ => json!({"id": id, "tags": [id % 7]}),
+        })
+        .collect()
+}
+
+fn txn_ops(ops: Vec<(bool, usize, Option<usize>)>) -> Vec<(String, usize, Option<usize>)> {
+    ops.into_iter()
+        .map(|(write, key, value)| (if write { "w" } else { "r" }.to_owned(), key, value))
+        .collect()
+}
+
+quickcheck! {
+    fn broadcast_bodies_round_trip(msg_id: usize, in_reply_to: usize, message: usize,
+        messages: Vec<usize>, topology: HashMap<u8, Vec<u8>>) -> bool {
+        broadcast_round_trips(BroadcastBody::Broadcast { msg_id, message: payloads([message]).remove(0) })
+            && broadcast_round_trips(BroadcastBody::BroadcastOk { msg_id, in_reply_to })
+            && broadcast_round_trips(BroadcastBody::Read { msg_id })
+            && broadcast_round_trips(BroadcastBody::ReadOk {
+                msg_id,
+                in_reply_to,
+                messages: payloads(messages.iter().copied()),
+            })
+            && broadcast_round_trips(BroadcastBody::Topology {
+                msg_id,
+                topology: node_topology(topology),
+            })
+            && broadcast_round_trips(BroadcastBody::TopologyOk { in_reply_to, msg_id })
+            && broadcast_round_trips(BroadcastBody::Gossip {
+                msg_id,
+                message: messages.iter().copied().collect(),
+                payloads: payloads(messages.iter().copied()),
+                clocks: messages.iter().map(|seq| vec![message, *seq]).collect(),
+            })
+            && broadcast_round_trips(BroadcastBody::GossipOk {
+                in_reply_to,
+                msg_id,
+                ack_message: messages.iter().copied().collect(),
+            })
+            && broadcast_round_trips(BroadcastBody::IHave {
+                msg_id,
+                messages: messages.iter().copied().collect(),
+                ack: message.is_multiple_of(2),
+            })
+            && broadcast_round_trips(BroadcastBody::Graft {
+                msg_id,
+                messages: messages.iter().copied().collect(),
+            })
+            && broadcast_round_trips(BroadcastBody::Prune { msg_id })
+            && broadcast_round_trips(BroadcastBody::Digest {
+                msg_id,
+                ranges: vec![RangeDigest(message, in_reply_to, messages.len(), message as u64)],
+            })
+            && broadcast_round_trips(BroadcastBody::Pull {
+                msg_id,
+                ranges: messages.iter().map(|start| (*start, message)).collect(),
+                have: messages.iter().copied().collect(),
+            })
+            && broadcast_round_trips(BroadcastBody::ReadClock { msg_id })
+            && broadcast_round_trips(BroadcastBody::ReadClockOk {
+                in_reply_to,
+                msg_id,
+                clock: messages.iter().map(|seq| (format!("n{}", seq), *seq)).collect(),
+            })
+            && broadcast_round_trips(BroadcastBody::Sequence {
+                msg_id,
+                term: message as u64,
+                start: in_reply_to,
+                previous_term: 0,
+                entries: messages
+                    .iter()
+                    .map(|id| (message as u64, (*id != message).then_some(*id)))
+                    .collect(),
+                payloads: payloads(messages.iter().copied()),
+                committed: messages.len(),
+            })
+            && broadcast_round_trips(BroadcastBody::SequenceOk {
+                in_reply_to,
+                msg_id,
+                term: message as u64,
+                have: messages.len(),
+                success: message.is_multiple_of(2),
+            })
+            && broadcast_round_trips(BroadcastBody::RequestVote {
+                msg_id,
+                term: message as u64,
+                length: messages.len(),
+                last_term: in_reply_to as u64,
+            })
+            && broadcast_round_trips(BroadcastBody::RequestVoteOk {
+                in_reply_to,
+                msg_id,
+                term: message as u64,
+                granted: message.is_multiple_of(3),
+            })
+            && broadcast_round_trips(BroadcastBody::Stability {
+                msg_id,
+                clocks: messages
+                    .iter()
+                    .map(|seq| (format!("n{}", seq), vec![*seq, message]))
+                    .collect(),
+            })
+            && broadcast_round_trips(BroadcastBody::StabilityOk {
+                in_reply_to,
+                msg_id,
+                clocks: messages
+                    .iter()
+                    .map(|seq| (format!("n{}", seq), vec![message, *seq]))
+                    .collect(),
+            })
+            && broadcast_round_trips(BroadcastBody::ReadStable { msg_id })
+            && broadcast_round_trips(BroadcastBody::ReadStableOk {
+                in_reply_to,
+                msg_id,
+                stable: messages.iter().copied().collect(),
+                low_watermark: messages.iter().map(|seq| (format!("n{}", seq), *seq)).collect(),
+            })
+            && broadcast_round_trips(BroadcastBody::Exchange {
+                msg_id,
+                have: messages.iter().copied().collect(),
+            })
+            && broadcast_round_trips(BroadcastBody::ExchangeOk {
+                in_reply_to,
+                msg_id,
+                message: messages.iter().copied().collect(),
+                payloads: payloads(messages.iter().copied()),
+                clocks: Vec::new(),
+                have: messages.iter().copied().filter(|seq| seq % 2 == 0).collect(),
+            })
+            && broadcast_round_trips(BroadcastBody::PullOk {
+                in_reply_to,
+                msg_id,
+                payloads: payloads(messages.iter().copied()),
+                clocks: Vec::new(),
+                messages: messages.iter().copied().collect(),
+            })
+            && broadcast_round_trips(BroadcastBody::Error {
+                in_reply_to,
+                code: message as u64,
+                text: format!("error {}", message),
+            })
+    }
+
+    fn counter_bodies_round_trip(msg_id: usize, in_reply_to: usize, value: usize,
+        node_counter_map: HashMap<String, usize>, text: String) -> bool {
+        counter_round_trips(CounterBody::Add { msg_id, delta: value })
+            && counter_round_trips(CounterBody::AddOk { msg_id, in_reply_to })
+            && counter_round_trips(CounterBody::Read { msg_id })
+            && counter_round_trips(CounterBody::ReadOk { msg_id, in_reply_to, value })
+            && counter_round_trips(CounterBody::UpdateCounters { msg_id, node_counter_map })
+            && counter_round_trips(CounterBody::Error { in_reply_to, code: value as u64, text })
+    }
+
+    fn kafka_bodies_round_trip(msg_id: usize, in_reply_to: usize, key: String, msg: usize,
+        offsets: HashMap<String, usize>, msgs: HashMap<String, Vec<(usize, usize)>>,
+        keys: Vec<String>) -> bool {
+        kafka_round_trips(KafkaBody::Send { msg_id, key: key.clone(), msg })
+            && kafka_round_trips(KafkaBody::SendOk { offset: msg, in_reply_to, msg_id })
+            && kafka_round_trips(KafkaBody::Poll { msg_id, offsets: offsets.clone() })
+            && kafka_round_trips(KafkaBody::PollOk { msgs, in_reply_to, msg_id })
+            && kafka_round_trips(KafkaBody::CommitOffsets { msg_id, offsets: offsets.clone() })
+            && kafka_round_trips(KafkaBody::CommitOffsetsOk { msg_id, in_reply_to })
+            && kafka_round_trips(KafkaBody::ListCommittedOffsets { msg_id, keys })
+            && kafka_round_trips(KafkaBody::ListCommittedOffsetsOk { in_reply_to, msg_id, offsets })
+            && kafka_round_trips(KafkaBody::Error { in_reply_to, code: msg as u64, text: key })
+    }
+
+    fn kv_store_bodies_round_trip(msg_id: usize, in_reply_to: usize,
+        txn: Vec<(bool, usize, Option<usize>)>,
+        write_ops: HashMap<usize, (usize, u64)>) -> bool {
+        kv_store_round_trips(KVStoreBody::Txn { msg_id, txn: txn_ops(txn.clone()) })
+            && kv_store_round_trips(KVStoreBody::TxnOk { msg_id, in_reply_to, txn: txn_ops(txn) })
+            && kv_store_round_trips(KVStoreBody::WritePropogater { msg_id, write_ops })
+    }
+}
+
+fn counter_node(node_counter_map: HashMap<String, usize>) -> CounterNode {
+    CounterNode {
+        node_id: "n1".to_owned(),
+        other_node_ids: Vec::new(),
+        current_msg_id: 0,
+        node_counter_map,
+        seq_kv: None,
+    }
+}
+
+fn merge_counters(
+    state: HashMap<String, usize>,
+    update: HashMap<String, usize>,
+) -> HashMap<String, usize> {
+    //! The counters a node holds after receiving an UpdateCounters message.
+    let mut node = counter_node(state);
+    let body = CounterBody::UpdateCounters {
+        msg_id: 0,
+        node_counter_map: update,
+    };
+    assert!(body.into_reply(&mut node, "n2").is_none());
+    node.node_counter_map
+}
+
+fn broadcast_node(messages: IntervalSet) -> BroadcastNode {
+    let mut node = BroadcastNode {
+        node_id: "n1".to_owned(),
+        neighbors: Vec::new(),
+        current_msg_id: 0,
+        node_ids: vec!["n1".to_owned()],
+        origin: 0,
+        next_seq: 0,
+        seq_file: None,
+        messages: IntervalSet::new(),
+        payloads: BTreeMap::new(),
+        confirmed_seen: HashMap::new(),
+        stability: None,
+        topology: Topology::Provided,
+        batching: None,
+        plumtree: None,
+        anti_entropy: None,
+        push_pull: None,
+        causal: None,
+        total: None,
+    };
+    node.deliver(&messages, payloads(messages.iter()), Vec::new());
+    node
+}
+
+fn gossip(node: &mut BroadcastNode, ids: &BTreeSet<usize>) {
+    let body = BroadcastBody::Gossip {
+        msg_id: 0,
+        message: ids.iter().copied().collect(),
+        payloads: payloads(ids.iter().copied()),
+        clocks: Vec::new(),
+    };
+    body.into_reply(node, "n2");
+}
+
+fn read(node: &mut BroadcastNode) -> Vec<Value> {
+    let body = BroadcastBody::Read { msg_id: 0 };
+    match body.into_reply(node, "c1") {
+        Some(BroadcastBody::ReadOk { messages, .. }) => messages,
+        other => panic!("Read was answered with {:?}", other),
+    }
+}
+
+fn merge_gossip(state: BTreeSet<usize>, ids: BTreeSet<usize>) -> BTreeSet<usize> {
+    //! The messages a node holds after receiving a Gossip message.
+    let mut node = broadcast_node(state.into_iter().collect());
+    gossip(&mut node, &ids);
+    let gossip = ids;
+    //Whatever was gossiped is known to have been seen by the sender.
+    assert_eq!(
+        node.confirmed_seen.get("n2").cloned().unwrap_or_default(),
+        gossip.into_iter().collect::<IntervalSet>()
+    );
+    node.messages.iter().collect()
+}
+
+quickcheck! {
+    fn counter_merge_is_commutative(a: HashMap<String, usize>, b: HashMap<String, usize>) -> bool {
+        merge_counters(a.clone(), b.clone()) == merge_counters(b, a)
+    }
+
+    fn counter_merge_is_associative(a: HashMap<String, usize>, b: HashMap<String, usize>,
+        c: HashMap<String, usize>) -> bool {
+        merge_counters(merge_counters(a.clone(), b.clone()), c.clone())
+            == merge_counters(a, merge_counters(b, c))
+    }
+
+    fn counter_merge_is_idempotent(a: HashMap<String, usize>, b: HashMap<String, usize>) -> bool {
+        let merged = merge_counters(a.clone(), b.clone());
+        merge_counters(a.clone(), a.clone()) == a && merge_counters(merged.clone(), b) == merged
+    }
+
+    fn gossip_union_is_commutative(a: BTreeSet<usize>, b: BTreeSet<usize>) -> bool {
+        merge_gossip(a.clone(), b.clone()) == merge_gossip(b, a)
+    }
+
+    fn gossip_union_is_associative(a: BTreeSet<usize>, b: BTreeSet<usize>,
+        c: BTreeSet<usize>) -> bool {
+        merge_gossip(merge_gossip(a.clone(), b.clone()), c.clone())
+            == merge_gossip(a, merge_gossip(b, c))
+    }
+
+    fn gossip_union_is_idempotent(a: BTreeSet<usize>, b: BTreeSet<usize>) -> bool {
+        let merged = merge_gossip(a.clone(), b.clone());
+        merge_gossip(a.clone(), a.clone()) == a && merge_gossip(merged.clone(), b) == merged
+    }
+
+    fn reads_do_not_depend_on_delivery_order(a: BTreeSet<usize>, b: BTreeSet<usize>) -> bool {
+        let mut a_first = broadcast_node(IntervalSet::new());
+        gossip(&mut a_first, &a);
+        gossip(&mut a_first, &b);
+        let mut b_first = broadcast_node(IntervalSet::new());
+        gossip(&mut b_first, &b);
+        gossip(&mut b_first, &a);
+        let ordered = &a | &b;
+        read(&mut a_first) == read(&mut b_first)
+            && read(&mut a_first) == payloads(ordered.iter().copied())
+    }
+}
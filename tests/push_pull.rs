@@ -0,0 +1,174 @@
+//Push-pull gossip with random peers: its settings, convergence whatever topology the
+//nodes are handed, and the convergence times the broadcast checker reports.
+
+mod common;
+
+use common::Net;
+use event_horizon::checker::broadcast;
+use event_horizon::history::{History, OpType, Operation, Process};
+use event_horizon::node::broadcast::push_pull::{PushPull, PushPullConfig};
+use event_horizon::node::broadcast::GossipMode;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::thread;
+use std::time::Duration;
+
+fn push_pull(count: usize) -> Net {
+    Net::new(
+        count,
+        &[(
+            "EVENT_HORIZON_GOSSIP",
+            "push-pull:fanout=2,interval=1,seed=7",
+        )],
+    )
+}
+
+fn round(net: &mut Net) {
+    //! Tick every node once its interval has passed, and deliver what it sends.
+    thread::sleep(Duration::from_millis(2));
+    net.rounds(1);
+}
+
+fn rounds_until_converged(net: &mut Net, limit: usize) -> Option<usize> {
+    (1..=limit).find(|_| {
+        round(net);
+        let counts: Vec<usize> = net.nodes.values().map(|node| node.messages.len()).collect();
+        let most = counts.iter().max().copied().unwrap_or(0);
+        counts.iter().all(|count| *count == most)
+    })
+}
+
+#[test]
+fn parses_settings() {
+    assert_eq!(
+        PushPullConfig::parse("push-pull"),
+        Some(PushPullConfig::default())
+    );
+    assert_eq!(
+        PushPullConfig::parse("push-pull:fanout=5,interval=50,seed=9"),
+        Some(PushPullConfig {
+            fanout: 5,
+            interval: Duration::from_millis(50),
+            seed: Some(9),
+        })
+    );
+    assert_eq!(PushPullConfig::parse("push-pull:fanout=0"), None);
+    assert_eq!(PushPullConfig::parse("push-pull:interval=0"), None);
+    assert_eq!(PushPullConfig::parse("push-pull:size=3"), None);
+    assert_eq!(PushPullConfig::parse("pull"), None);
+    assert!(matches!(
+        GossipMode::parse("push-pull:fanout=2"),
+        Some(GossipMode::PushPull(PushPullConfig { fanout: 2, .. }))
+    ));
+}
+
+#[test]
+fn seeded_peer_choice_repeats_and_differs_by_node() {
+    let node_ids = common::node_ids(10);
+    let config = PushPullConfig {
+        seed: Some(3),
+        ..PushPullConfig::default()
+    };
+    let picks = |origin: usize| {
+        let mut push_pull = PushPull::new(config.clone(), origin);
+        (0..5)
+            .map(|_| push_pull.peers(&node_ids, &node_ids[origin]))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(picks(1), picks(1));
+    assert_ne!(picks(1), picks(2));
+    for peers in picks(1) {
+        assert_eq!(peers.len(), 3);
+        assert!(!peers.contains(&node_ids[1]));
+        assert_eq!(peers.iter().collect::<HashSet<_>>().len(), 3);
+    }
+}
+
+#[test]
+fn converges_whatever_the_topology() {
+    //No node is handed a neighbor that exists, and then each is sent a map that
+    //leaves it out.
+    let mut net = push_pull(12);
+    net.topology(|_| vec!["n99".to_owned()]);
+    for node_id in net.nodes.keys().cloned().collect::<Vec<_>>() {
+        let topology = json!({"n99": ["n1"]});
+        let reply = net.client(
+            &node_id,
+            json!({"type": "topology", "msg_id": 2, "topology": topology}),
+        );
+        assert_eq!(reply["type"], "topology_ok");
+    }
+    for message in 0..30 {
+        net.broadcast(&format!("n{}", message % 12 + 1), json!(message));
+    }
+    let rounds = rounds_until_converged(&mut net, 30);
+    assert!(rounds.is_some(), "Did not converge");
+    assert!(net.nodes.values().all(|node| node.messages.len() == 30));
+    //Only missing messages move, so each node is shipped each message about once.
+    assert!(net.shipped < 2 * 11 * 30, "Shipped {}", net.shipped);
+    let (exchanges, pulled): (u64, u64) = net
+        .nodes
+        .values()
+        .map(|node| node.push_pull.as_ref().unwrap())
+        .fold((0, 0), |(exchanges, pulled), push_pull| {
+            (exchanges + push_pull.exchanges, pulled + push_pull.pulled)
+        });
+    assert!(exchanges > 0 && pulled > 0);
+}
+
+#[test]
+fn converges_after_a_partition_heals() {
+    let mut net = push_pull(10);
+    net.side = (1..=5).map(|index| format!("n{}", index)).collect();
+    for message in 0..20 {
+        net.broadcast(&format!("n{}", message % 10 + 1), json!(message));
+    }
+    for _ in 0..20 {
+        round(&mut net);
+    }
+    //Each side converges on its own broadcasts.
+    assert!(net.nodes.values().all(|node| node.messages.len() == 10));
+    net.side.clear();
+    assert!(
+        rounds_until_converged(&mut net, 30).is_some(),
+        "Did not converge"
+    );
+    assert!(net.nodes.values().all(|node| node.messages.len() == 20));
+}
+
+fn op(op_type: OpType, process: usize, millis: u64, f: &str, value: Value) -> Operation {
+    Operation {
+        op_type,
+        process: Process::Client(process),
+        time: millis * 1_000_000,
+        f: f.to_owned(),
+        value,
+        error: None,
+        index: None,
+        msg_id: None,
+    }
+}
+
+#[test]
+fn checker_reports_convergence_times() {
+    let history = History {
+        ops: vec![
+            op(OpType::Invoke, 0, 0, "broadcast", json!({"message": 1})),
+            op(OpType::Ok, 0, 1, "broadcast", json!({})),
+            //Misses 1 until 3ms, so it takes 2ms to converge.
+            op(OpType::Invoke, 1, 2, "read", json!({})),
+            op(OpType::Ok, 1, 3, "read", json!({"messages": []})),
+            op(OpType::Invoke, 0, 5, "broadcast", json!({"message": 2})),
+            op(OpType::Ok, 0, 6, "broadcast", json!({})),
+            op(OpType::Invoke, 1, 10, "read", json!({})),
+            op(OpType::Ok, 1, 12, "read", json!({"messages": [1, 2]})),
+            op(OpType::Invoke, 2, 20, "read", json!({})),
+            op(OpType::Ok, 2, 21, "read", json!({"messages": [2, 1]})),
+        ],
+    };
+    let report = broadcast::check(&history);
+    assert!(report.valid);
+    let convergence = report.convergence.unwrap();
+    assert_eq!(convergence.max_ms, 2);
+    assert_eq!(convergence.p99_ms, 2);
+}
@@ -0,0 +1,98 @@
+use event_horizon::repl::{self, Command};
+use serde_json::json;
+
+fn node_ids() -> Vec<String> {
+    vec!["n1".to_owned(), "n2".to_owned()]
+}
+
+fn body(line: &str) -> serde_json::Value {
+    match repl::parse(line, &node_ids()) {
+        Ok(Command::Send { body, .. }) => body,
+        other => panic!("{} did not parse to a message: {:?}", line, other),
+    }
+}
+
+#[test]
+fn parses_client_shorthand() {
+    assert_eq!(
+        body("send k1 42"),
+        json!({"type": "send", "key": "k1", "msg": 42})
+    );
+    assert_eq!(
+        body("txn r 1, w 2 5"),
+        json!({"type": "txn", "txn": [["r", 1, null], ["w", 2, 5]]})
+    );
+    assert_eq!(
+        body("broadcast 7"),
+        json!({"type": "broadcast", "message": 7})
+    );
+    assert_eq!(
+        body("broadcast {\"event\": \"joined\", \"ids\": [1, 2]}"),
+        json!({"type": "broadcast", "message": {"event": "joined", "ids": [1, 2]}})
+    );
+    assert_eq!(body("read_clock"), json!({"type": "read_clock"}));
+    assert_eq!(body("read_stable"), json!({"type": "read_stable"}));
+    assert_eq!(
+        body("poll k1:0 k2:3"),
+        json!({"type": "poll", "offsets": {"k1": 0, "k2": 3}})
+    );
+    assert_eq!(
+        body("topology"),
+        json!({"type": "topology", "topology": {"n1": ["n2"], "n2": ["n1"]}})
+    );
+    assert_eq!(
+        body("echo hello there"),
+        json!({"type": "echo", "echo": "hello there"})
+    );
+}
+
+#[test]
+fn parses_peer_messages() {
+    assert_eq!(
+        body("gossip 2={\"k\":1} 1=5"),
+        json!({"type": "gossip", "message": [1, 2], "payloads": [5, {"k": 1}]})
+    );
+    assert_eq!(
+        body("update_counters n1=3 n2=5"),
+        json!({"type": "update_counters", "node_counter_map": {"n1": 3, "n2": 5}})
+    );
+    assert_eq!(
+        body("write_propogater 1=5@100"),
+        json!({"type": "write_propogater", "write_ops": {"1": [5, 100]}})
+    );
+    assert!(repl::is_peer_message(&body("gossip 1=1")));
+    assert_eq!(
+        repl::parse("from n2 gossip 3=7", &node_ids()),
+        Ok(Command::Send {
+            src: Some("n2".to_owned()),
+            body: json!({"type": "gossip", "message": [3], "payloads": [7]}),
+        })
+    );
+}
+
+#[test]
+fn rejects_malformed_commands() {
+    for line in [
+        "txn r",
+        "txn x 1",
+        "send k1",
+        "poll k1",
+        "broadcast seven",
+        "bogus",
+    ] {
+        assert!(repl::parse(line, &node_ids()).is_err(), "{} parsed", line);
+    }
+    assert_eq!(repl::parse("quit", &node_ids()), Ok(Command::Quit));
+}
+
+#[test]
+fn summarises_output() {
+    let reply = json!({"src": "n1", "dest": "c1", "body": {"type": "read_ok", "msg_id": 3, "in_reply_to": 2, "messages": [1, 2]}});
+    assert_eq!(
+        repl::pretty(&reply, "c1"),
+        "<- read_ok (reply to 2) messages=[1,2]"
+    );
+    let gossip =
+        json!({"src": "n1", "dest": "n2", "body": {"type": "gossip", "msg_id": 4, "message": [1]}});
+    assert_eq!(repl::pretty(&gossip, "c1"), "-> n2 gossip message=[1]");
+}
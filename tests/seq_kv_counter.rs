@@ -0,0 +1,115 @@
+mod common;
+
+use common::services::{self, lines, request, settle, NODE_IDS};
+use event_horizon::node::grow_counter::{CounterBody, CounterMode, CounterNode};
+use event_horizon::service::kv::{self, KvClient};
+use event_horizon::service::local::{LocalConfig, LocalServices};
+use event_horizon::{Event, MaelstromMessage, Node};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+fn cluster(mode: &str) -> BTreeMap<String, CounterNode> {
+    let mode = CounterMode::from_vars(common::vars(&[("EVENT_HORIZON_COUNTER", mode)]));
+    services::cluster(|metadata| CounterNode::with_mode(metadata, mode, channel().0))
+}
+
+fn services() -> LocalServices {
+    //Reads may be served from anywhere in the last 50 writes.
+    LocalServices::new(LocalConfig {
+        max_lag: 50,
+        max_age: Duration::from_secs(60),
+        seed: 3,
+        ..LocalConfig::default()
+    })
+}
+
+fn check_counter(mode: &str) {
+    let mut nodes = cluster(mode);
+    let mut services = services();
+    let mut in_flight = Vec::new();
+    //Adds from every node race each other.
+    for (index, node_id) in NODE_IDS.iter().cycle().take(12).enumerate() {
+        in_flight.extend(request(
+            &mut nodes,
+            node_id,
+            json!({"type": "add", "msg_id": index, "delta": index}),
+        ));
+    }
+    let replies = settle(&mut nodes, &mut services, in_flight, 12);
+    assert_eq!(replies.len(), 12);
+    assert!(replies.iter().all(|reply| reply.body["type"] == "add_ok"));
+
+    //Reads are fresh however stale seq-kv is willing to be.
+    for node_id in NODE_IDS {
+        let in_flight = request(&mut nodes, node_id, json!({"type": "read", "msg_id": 99}));
+        let replies = settle(&mut nodes, &mut services, in_flight, 1);
+        assert_eq!(
+            replies[0].body["value"],
+            (0..12).sum::<u64>(),
+            "{}",
+            node_id
+        );
+    }
+}
+
+#[test]
+fn shared_counter_adds_by_cas() {
+    check_counter("seq-kv");
+}
+
+#[test]
+fn per_node_counters_are_summed() {
+    check_counter("seq-kv-per-node");
+}
+
+#[test]
+fn an_add_with_an_unknown_outcome_is_an_error() {
+    let mut nodes = cluster("seq-kv");
+    let mut services = services();
+    let read = request(
+        &mut nodes,
+        "n1",
+        json!({"type": "add", "msg_id": 1, "delta": 2}),
+    );
+    let (_, reply) = services.handle(&read[0]).unwrap();
+    let node = nodes.get_mut("n1").unwrap();
+    let mut output = Vec::new();
+    node.handle_event(Event::ServiceReply(reply), &mut output);
+    assert_eq!(lines(output)[0].body["type"], "cas");
+
+    //The cas is never answered.
+    let mut output = Vec::new();
+    KvClient::expire(
+        node,
+        |node| &mut node.seq_kv.as_mut().unwrap().kv,
+        Duration::ZERO,
+        &mut output,
+    );
+    let error: MaelstromMessage<CounterBody> =
+        serde_json::from_slice(&output[..output.len() - 1]).unwrap();
+    assert!(matches!(
+        error.body,
+        CounterBody::Error {
+            in_reply_to: 1,
+            code: 0,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn an_add_gives_up_once_out_of_attempts() {
+    let mut nodes = cluster("seq-kv");
+    let in_flight = request(
+        &mut nodes,
+        "n1",
+        json!({"type": "add", "msg_id": 1, "delta": 2}),
+    );
+    //seq-kv refuses every try.
+    let (replies, tries) = services::refused(nodes.get_mut("n1").unwrap(), in_flight);
+    assert_eq!(tries, kv::MAX_ATTEMPTS as usize);
+    assert_eq!(replies[0].body["type"], "error");
+    assert_eq!(replies[0].body["code"], kv::TEMPORARILY_UNAVAILABLE);
+}
@@ -0,0 +1,169 @@
+//Stability: which messages every node has, found by spreading each node's counts
+//across the topology, and the per-neighbor bookkeeping it lets nodes drop.
+
+mod common;
+
+use common::Net;
+use event_horizon::node::broadcast::MessageId;
+use serde_json::{json, Value};
+
+fn line(count: usize) -> Net {
+    //! Nodes in a line, so counts take several rounds to reach the far end.
+    Net::new(
+        count,
+        &[
+            ("EVENT_HORIZON_TOPOLOGY", "tree:1"),
+            ("EVENT_HORIZON_STABILITY", "on"),
+        ],
+    )
+}
+
+fn read_stable(net: &mut Net, node_id: &str) -> Value {
+    net.client(node_id, json!({"type": "read_stable", "msg_id": 1}))
+}
+
+fn stability_rounds(net: &mut Net, count: usize) {
+    let node_ids: Vec<String> = net.nodes.keys().cloned().collect();
+    for _ in 0..count {
+        for node_id in &node_ids {
+            let mut output = Vec::new();
+            net.nodes
+                .get_mut(node_id)
+                .unwrap()
+                .stability_round(&mut output);
+            net.route(node_id, output);
+        }
+        net.settle();
+    }
+}
+
+fn ids(origin: usize, count: usize) -> Vec<usize> {
+    (0..count)
+        .map(|seq| MessageId { origin, seq }.pack())
+        .collect()
+}
+
+#[test]
+fn messages_are_stable_once_every_node_has_them() {
+    let mut net = line(4);
+    net.broadcast("n1", json!("a"));
+    net.broadcast("n1", json!("b"));
+    net.broadcast("n4", json!("c"));
+    net.rounds(3);
+    //Known to n1 alone, so never stable yet.
+    net.broadcast("n1", json!("d"));
+
+    //Counts from n4 take three rounds to reach n1.
+    stability_rounds(&mut net, 2);
+    assert_eq!(read_stable(&mut net, "n1")["stable"], json!([]));
+    stability_rounds(&mut net, 1);
+    let reply = read_stable(&mut net, "n1");
+    let mut stable = ids(0, 2);
+    stable.extend(ids(3, 1));
+    assert_eq!(
+        reply["stable"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|id| id.as_u64().unwrap() as usize)
+            .collect::<Vec<_>>(),
+        stable
+    );
+    assert_eq!(
+        reply["low_watermark"],
+        json!({"n1": 2, "n2": 0, "n3": 0, "n4": 1})
+    );
+
+    //Once d has spread and been counted everywhere, it is stable too.
+    net.rounds(3);
+    stability_rounds(&mut net, 3);
+    for node_id in ["n1", "n4"] {
+        assert_eq!(
+            read_stable(&mut net, node_id)["low_watermark"],
+            json!({"n1": 3, "n2": 0, "n3": 0, "n4": 1})
+        );
+    }
+}
+
+#[test]
+fn a_node_that_never_reports_holds_back_stability() {
+    let mut net = line(3);
+    net.cut.insert("n3".to_owned());
+    net.broadcast("n1", json!(1));
+    net.rounds(3);
+    stability_rounds(&mut net, 3);
+    assert_eq!(read_stable(&mut net, "n2")["stable"], json!([]));
+    net.cut.clear();
+    net.rounds(3);
+    stability_rounds(&mut net, 3);
+    assert_eq!(read_stable(&mut net, "n2")["stable"], json!(ids(0, 1)));
+}
+
+#[test]
+fn stable_messages_are_compacted_out_of_what_neighbors_confirmed() {
+    let mut net = line(3);
+    for message in 0..20 {
+        net.broadcast("n2", json!(message));
+    }
+    net.rounds(2);
+    assert!(net.nodes["n2"]
+        .confirmed_seen
+        .values()
+        .all(|known| known.len() == 20));
+    stability_rounds(&mut net, 2);
+    for node in net.nodes.values() {
+        assert_eq!(node.stability.as_ref().unwrap().stable.len(), 20);
+        assert!(node.confirmed_seen.values().all(|known| known.is_empty()));
+    }
+
+    //Stable messages are never gossiped again, and new ones still are.
+    net.sent.clear();
+    net.rounds(1);
+    assert_eq!(net.sent.get("gossip"), None);
+    net.broadcast("n2", json!(20));
+    net.rounds(1);
+    assert_eq!(net.sent.get("gossip"), Some(&2));
+    let n2 = &net.nodes["n2"];
+    let latest = MessageId { origin: 1, seq: 20 }.pack();
+    assert!(n2
+        .confirmed_seen
+        .values()
+        .all(|known| known.iter().collect::<Vec<_>>() == vec![latest]));
+}
+
+#[test]
+fn counts_are_only_sent_until_acknowledged() {
+    let mut net = line(3);
+    net.broadcast("n1", json!(1));
+    net.rounds(2);
+    stability_rounds(&mut net, 3);
+    assert_eq!(read_stable(&mut net, "n3")["stable"], json!(ids(0, 1)));
+    //Every neighbor has acknowledged every count, so a quiet cluster sends nothing.
+    net.sent.clear();
+    stability_rounds(&mut net, 2);
+    assert_eq!(net.sent.get("stability"), None);
+
+    //A new broadcast changes counts, which are sent until acknowledged again.
+    net.broadcast("n3", json!(2));
+    net.rounds(2);
+    net.sent.clear();
+    stability_rounds(&mut net, 3);
+    assert!(net.sent.contains_key("stability"));
+    assert_eq!(net.sent.get("stability"), net.sent.get("stability_ok"));
+    let mut stable = ids(0, 1);
+    stable.extend(ids(2, 1));
+    assert_eq!(read_stable(&mut net, "n1")["stable"], json!(stable));
+    net.sent.clear();
+    stability_rounds(&mut net, 1);
+    assert_eq!(net.sent.get("stability"), None);
+}
+
+#[test]
+fn stability_is_not_tracked_by_default() {
+    let mut net = Net::new(2, &[("EVENT_HORIZON_TOPOLOGY", "star")]);
+    stability_rounds(&mut net, 1);
+    assert!(net.sent.is_empty());
+    let reply = read_stable(&mut net, "n1");
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["code"], 10);
+}
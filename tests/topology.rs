@@ -0,0 +1,151 @@
+mod common;
+
+use event_horizon::node::broadcast::topology::Topology;
+use event_horizon::node::broadcast::BroadcastBody;
+use event_horizon::Reply;
+use std::collections::{HashMap, HashSet};
+
+const BUILT: [Topology; 6] = [
+    Topology::Tree { fanout: 1 },
+    Topology::Tree { fanout: 4 },
+    Topology::Star,
+    Topology::Ring { chords: 0 },
+    Topology::Ring { chords: 3 },
+    Topology::Grid,
+];
+
+fn node_ids(count: usize) -> Vec<String> {
+    (0..count).map(|index| format!("n{}", index)).collect()
+}
+
+fn reachable(topology: &HashMap<String, Vec<String>>, from: &str) -> HashSet<String> {
+    let mut seen = HashSet::from([from.to_owned()]);
+    let mut frontier = vec![from.to_owned()];
+    while let Some(node_id) = frontier.pop() {
+        for neighbor in &topology[&node_id] {
+            if seen.insert(neighbor.clone()) {
+                frontier.push(neighbor.clone());
+            }
+        }
+    }
+    seen
+}
+
+#[test]
+fn built_topologies_are_connected_and_symmetric() {
+    for topology in BUILT {
+        for count in 1..=30 {
+            let ids = node_ids(count);
+            let built = topology.build(&ids).unwrap();
+            assert_eq!(built.len(), count, "{:?}", topology);
+            assert_eq!(
+                reachable(&built, "n0").len(),
+                count,
+                "{:?} of {}",
+                topology,
+                count
+            );
+            for (node_id, neighbors) in &built {
+                assert!(!neighbors.contains(node_id));
+                for neighbor in neighbors {
+                    assert!(built[neighbor].contains(node_id), "{:?}", topology);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn every_node_builds_the_same_topology() {
+    let ids = node_ids(25);
+    let mut shuffled = ids.clone();
+    shuffled.reverse();
+    shuffled.swap(3, 17);
+    for topology in BUILT {
+        assert_eq!(topology.build(&ids), topology.build(&shuffled));
+    }
+}
+
+#[test]
+fn degrees_follow_the_shape() {
+    let ids = node_ids(25);
+    let degrees = |topology: Topology| -> Vec<usize> {
+        let built = topology.build(&ids).unwrap();
+        ids.iter().map(|node_id| built[node_id].len()).collect()
+    };
+    //n0 is the root or hub, and nodes are numbered in order, so n2 comes before n10.
+    let tree = Topology::Tree { fanout: 4 }.build(&ids).unwrap();
+    assert_eq!(tree["n0"], ["n1", "n2", "n3", "n4"]);
+    assert_eq!(tree["n2"], ["n0", "n9", "n10", "n11", "n12"]);
+    assert!(degrees(Topology::Tree { fanout: 4 })
+        .iter()
+        .all(|degree| *degree <= 5));
+    assert_eq!(degrees(Topology::Star)[0], 24);
+    assert!(degrees(Topology::Star)[1..]
+        .iter()
+        .all(|degree| *degree == 1));
+    assert!(degrees(Topology::Ring { chords: 0 })
+        .iter()
+        .all(|degree| *degree == 2));
+    assert!(degrees(Topology::Ring { chords: 2 })
+        .iter()
+        .all(|degree| *degree <= 6));
+    assert!(degrees(Topology::Grid).iter().all(|degree| *degree <= 4));
+}
+
+#[test]
+fn parses_topology_specs() {
+    assert_eq!(Topology::parse("provided"), Some(Topology::Provided));
+    assert_eq!(Topology::parse("tree"), Some(Topology::Tree { fanout: 4 }));
+    assert_eq!(
+        Topology::parse("tree:2"),
+        Some(Topology::Tree { fanout: 2 })
+    );
+    assert_eq!(
+        Topology::parse("ring:3"),
+        Some(Topology::Ring { chords: 3 })
+    );
+    assert_eq!(Topology::parse("star"), Some(Topology::Star));
+    assert_eq!(Topology::parse("grid"), Some(Topology::Grid));
+    assert_eq!(Topology::parse("tree:0"), None);
+    assert_eq!(Topology::parse("star:2"), None);
+    assert_eq!(Topology::parse("mesh"), None);
+    assert_eq!(Topology::Provided.build(&node_ids(3)), None);
+}
+
+#[test]
+fn a_built_topology_ignores_maelstroms() {
+    let mut node = common::node("n0", &node_ids(5), &[("EVENT_HORIZON_TOPOLOGY", "star")]);
+    assert_eq!(node.topology, Topology::Star);
+    assert_eq!(node.neighbors.len(), 4);
+    let provided = HashMap::from([("n0".to_owned(), vec!["n1".to_owned()])]);
+    let reply = BroadcastBody::Topology {
+        msg_id: 1,
+        topology: provided,
+    }
+    .into_reply(&mut node, "c1");
+    assert!(matches!(reply, Some(BroadcastBody::TopologyOk { .. })));
+    assert_eq!(node.neighbors.len(), 4);
+    //Nor does a map that leaves the node out.
+    let provided = HashMap::from([("n1".to_owned(), vec!["n2".to_owned()])]);
+    let reply = BroadcastBody::Topology {
+        msg_id: 2,
+        topology: provided,
+    }
+    .into_reply(&mut node, "c1");
+    assert!(matches!(reply, Some(BroadcastBody::TopologyOk { .. })));
+    assert_eq!(node.neighbors.len(), 4);
+}
+
+#[test]
+fn a_provided_map_that_leaves_the_node_out_gives_no_neighbors() {
+    let mut node = common::node("n0", &node_ids(3), &[]);
+    let provided = HashMap::from([("n1".to_owned(), vec!["n0".to_owned()])]);
+    let reply = BroadcastBody::Topology {
+        msg_id: 1,
+        topology: provided,
+    }
+    .into_reply(&mut node, "c1");
+    assert!(matches!(reply, Some(BroadcastBody::TopologyOk { .. })));
+    assert!(node.neighbors.is_empty());
+}
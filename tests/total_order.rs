@@ -0,0 +1,261 @@
+//Total-order delivery: a sequencer places messages in one order for every node,
+//followers fetch what they missed, and a new sequencer is elected when the old one
+//is cut off, without any two nodes ever reading a different order.
+
+mod common;
+
+use common::Net;
+use event_horizon::node::broadcast::{BroadcastConfig, DeliveryOrder};
+use event_horizon::rng::Rng;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fs;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+fn total(count: usize, election_timeout_ms: u64) -> Net {
+    let order = format!("total:election_timeout={}", election_timeout_ms);
+    Net::mesh(count, &[("EVENT_HORIZON_ORDER", &order)])
+}
+
+fn sequencers(net: &Net) -> Vec<(u64, String)> {
+    net.nodes
+        .iter()
+        .map(|(node_id, node)| (node.total.as_ref().unwrap(), node_id))
+        .filter(|(total, _)| total.is_sequencer())
+        .map(|(total, node_id)| (total.term, node_id.clone()))
+        .collect()
+}
+
+fn prefix_consistent(reads: &BTreeMap<String, Vec<Value>>) -> bool {
+    //! Every read is a prefix of every longer one.
+    reads.values().all(|a| {
+        reads.values().all(|b| {
+            let shorter = a.len().min(b.len());
+            a[..shorter] == b[..shorter]
+        })
+    })
+}
+
+#[test]
+fn every_node_reads_the_sequencer_order() {
+    let mut net = total(3, 60_000);
+    net.broadcast("n2", json!("b"));
+    net.broadcast("n3", json!({"c": 3}));
+    net.broadcast("n1", json!("a"));
+    net.broadcast("n3", json!("d"));
+    net.rounds(3);
+    let reads = net.reads();
+    assert_eq!(reads["n1"].len(), 4);
+    assert!(reads.values().all(|read| *read == reads["n1"]));
+    assert_eq!(sequencers(&net), vec![(0, "n1".to_owned())]);
+}
+
+#[test]
+fn a_follower_catches_up_after_being_cut_off() {
+    let mut net = total(3, 60_000);
+    net.cut.insert("n3".to_owned());
+    for message in 0..5 {
+        net.broadcast("n2", json!(message));
+        net.rounds(1);
+    }
+    //n1 and n2 are a majority, so commit without n3.
+    net.rounds(3);
+    assert_eq!(net.read("n2").len(), 5);
+    assert!(net.read("n3").is_empty());
+
+    //The sequencer resends from what n3 last acknowledged.
+    net.cut.clear();
+    net.broadcast("n1", json!(5));
+    net.rounds(2);
+    let reads = net.reads();
+    assert_eq!(reads["n3"].len(), 6);
+    assert!(reads.values().all(|read| *read == reads["n1"]));
+}
+
+#[test]
+fn a_new_sequencer_is_elected_when_the_old_one_is_cut_off() {
+    let mut net = total(5, 20);
+    net.broadcast("n2", json!("before"));
+    net.rounds(2);
+    net.cut.insert("n1".to_owned());
+    net.broadcast("n3", json!("during"));
+    //The cut off sequencer places a message it is sent, but commits nothing.
+    net.broadcast("n1", json!("late"));
+    for _ in 0..400 {
+        thread::sleep(Duration::from_millis(5));
+        net.rounds(1);
+        if net.read("n3").len() == 2 {
+            break;
+        }
+    }
+    let elected = sequencers(&net);
+    assert!(elected
+        .iter()
+        .any(|(term, node_id)| *term > 0 && node_id != "n1"));
+    assert_eq!(net.read("n3"), vec![json!("before"), json!("during")]);
+    assert_eq!(net.read("n1"), vec![json!("before")]);
+
+    //Rejoined, n1 follows the new sequencer, whose sequence replaces what it placed.
+    net.cut.clear();
+    for _ in 0..400 {
+        thread::sleep(Duration::from_millis(5));
+        net.rounds(1);
+        let reads = net.reads();
+        if reads["n1"].len() == 3 && reads.values().all(|read| *read == reads["n1"]) {
+            break;
+        }
+    }
+    let reads = net.reads();
+    assert_eq!(reads["n1"].len(), 3);
+    assert!(reads.values().all(|read| *read == reads["n1"]));
+    assert_eq!(sequencers(&net).len(), 1);
+}
+
+#[test]
+fn a_restarted_follower_remembers_what_it_voted_for_and_holds() {
+    let state_dir = env::temp_dir().join(format!("event-horizon-total-{}", process::id()));
+    fs::create_dir_all(&state_dir).unwrap();
+    let vars = [
+        ("EVENT_HORIZON_ORDER", "total:election_timeout=20"),
+        ("EVENT_HORIZON_STATE_DIR", state_dir.to_str().unwrap()),
+    ];
+    let mut net = Net::mesh(3, &vars);
+    //n1 and n2 commit two messages that n3 never hears of.
+    net.cut.insert("n3".to_owned());
+    net.broadcast("n1", json!("a"));
+    net.broadcast("n1", json!("b"));
+    net.rounds(3);
+    assert_eq!(net.read("n2"), vec![json!("a"), json!("b")]);
+
+    //The sequencer is cut off, and n2 is killed while n3 stands for election. Come
+    //back empty, n2 would vote for n3, which would then replace what was committed.
+    net.cut = HashSet::from(["n1".to_owned()]);
+    net.restart("n2", &vars);
+    assert_eq!(net.read("n2"), vec![json!("a"), json!("b")]);
+    for _ in 0..400 {
+        thread::sleep(Duration::from_millis(5));
+        net.rounds(1);
+        if sequencers(&net).iter().any(|(term, _)| *term > 0) {
+            break;
+        }
+    }
+    assert!(sequencers(&net)
+        .iter()
+        .all(|(term, node_id)| *term == 0 || node_id == "n2"));
+
+    net.cut.clear();
+    net.broadcast("n3", json!("c"));
+    for _ in 0..400 {
+        thread::sleep(Duration::from_millis(5));
+        net.rounds(1);
+        let reads = net.reads();
+        if reads["n1"].len() == 3 && reads.values().all(|read| *read == reads["n1"]) {
+            break;
+        }
+    }
+    let reads = net.reads();
+    assert!(reads.values().all(|read| *read == reads["n1"]));
+    assert_eq!(reads["n1"][..2], [json!("a"), json!("b")]);
+    let _ = fs::remove_dir_all(state_dir);
+}
+
+#[test]
+fn reads_never_disagree_under_any_interleaving() {
+    for seed in 0..10 {
+        let mut rng = Rng::seeded(seed);
+        //Short enough that nodes stand for election throughout.
+        let mut net = total(5, 2);
+        let node_ids: Vec<String> = net.nodes.keys().cloned().collect();
+        for step in 0..600 {
+            let node_id = rng.choose(&node_ids).unwrap().clone();
+            match rng.below(20) {
+                0..=2 => net.broadcast(&node_id, json!(step)),
+                3..=4 => net.tick(&node_id),
+                5 => {
+                    net.cut.clear();
+                    if rng.chance(0.5) {
+                        net.cut.insert(node_id);
+                    }
+                }
+                6 if !net.in_flight.is_empty() => {
+                    //Dropped
+                    let index = rng.below(net.in_flight.len());
+                    net.in_flight.remove(index);
+                }
+                _ if !net.in_flight.is_empty() => {
+                    let index = rng.below(net.in_flight.len());
+                    net.deliver(index);
+                }
+                _ => {}
+            }
+            if step % 5 == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            if step % 50 == 0 {
+                assert!(prefix_consistent(&net.reads()), "seed {}", seed);
+            }
+        }
+
+        //Then heal, and let the latest sequencer finish, electing one if there is none.
+        net.cut.clear();
+        net.settle();
+        for round in 0..50 {
+            let sequencer = sequencers(&net).into_iter().max();
+            match sequencer {
+                Some((_, node_id)) => net.tick(&node_id),
+                None => net.tick(&node_ids[round % node_ids.len()]),
+            }
+            net.settle();
+            net.rounds(1);
+            net.tick(&node_ids[round % node_ids.len()]);
+            net.settle();
+        }
+        let reads = net.reads();
+        assert!(prefix_consistent(&reads), "seed {}", seed);
+        let messages = net.nodes["n1"].messages.len();
+        assert!(
+            reads.values().all(|read| read.len() == messages),
+            "seed {}: {:?}",
+            seed,
+            reads.values().map(Vec::len).collect::<Vec<_>>()
+        );
+    }
+}
+
+#[test]
+fn parses_orders() {
+    assert_eq!(
+        DeliveryOrder::parse("total"),
+        Some(DeliveryOrder::Total {
+            election_timeout: Duration::from_secs(1)
+        })
+    );
+    assert_eq!(
+        DeliveryOrder::parse("total:election_timeout=200"),
+        Some(DeliveryOrder::Total {
+            election_timeout: Duration::from_millis(200)
+        })
+    );
+    for spec in [
+        "total:election_timeout=0",
+        "total:timeout=5",
+        "causal:election_timeout=5",
+        "fifo",
+    ] {
+        assert_eq!(DeliveryOrder::parse(spec), None, "{}", spec);
+    }
+    let config = BroadcastConfig::from_vars(|name| {
+        (name == "EVENT_HORIZON_ORDER").then(|| "causal".to_owned())
+    });
+    assert_eq!(config.order, DeliveryOrder::Causal);
+    assert!(!config.stability);
+}
+
+#[test]
+#[should_panic(expected = "Unknown EVENT_HORIZON_ORDER: fifo")]
+fn unknown_orders_are_refused() {
+    BroadcastConfig::from_vars(|name| (name == "EVENT_HORIZON_ORDER").then(|| "fifo".to_owned()));
+}
@@ -0,0 +1,116 @@
+//Golden transcript tests. Each `tests/transcripts/<node>/<case>.jsonl` is run through
+//the node, and its output is compared with `<case>.expected.jsonl`.
+//Run with EVENT_HORIZON_BLESS=1 to rewrite the expected files from the current output.
+
+use event_horizon::clock;
+use event_horizon::node::{broadcast, echo, generate_id, grow_counter, kafka, kv_store};
+use event_horizon::transcript;
+use event_horizon::{Node, Reply};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+//Unique IDs and write timestamps read the clock, so it and the incarnation it
+//hands out are fixed for every transcript.
+const FROZEN_CLOCK_NANOS: u64 = 1_700_000_000_000_000_000;
+
+fn fixtures(node: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/transcripts")
+        .join(node);
+    let mut fixtures: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("Could not read {}: {}", dir.display(), err))
+        .map(|entry| entry.expect("Could not read fixture entry").path())
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.ends_with(".jsonl") && !name.ends_with(".expected.jsonl")
+        })
+        .collect();
+    fixtures.sort();
+    assert!(!fixtures.is_empty(), "No transcripts in {}", dir.display());
+    fixtures
+}
+
+fn check_transcripts<Body, NodeState>(node: &str)
+where
+    NodeState: Node<Body>,
+    Body: DeserializeOwned + Reply<NodeState>,
+{
+    clock::global().freeze_at(Some(FROZEN_CLOCK_NANOS));
+    clock::global().pin_incarnation(Some(FROZEN_CLOCK_NANOS));
+    let bless = env::var_os("EVENT_HORIZON_BLESS").is_some();
+    let mut failures = Vec::new();
+
+    for fixture in fixtures(node) {
+        let transcript = fs::read_to_string(&fixture).expect("Could not read transcript");
+        let actual = match transcript::run::<Body, NodeState>(&transcript) {
+            Ok(actual) => actual,
+            Err(err) => {
+                failures.push(format!("{}: {}", fixture.display(), err));
+                continue;
+            }
+        };
+        let expected_path = fixture.with_extension("expected.jsonl");
+        if bless {
+            let mut expected = String::new();
+            for line in actual.iter() {
+                expected.push_str(&line.to_string());
+                expected.push('\n');
+            }
+            fs::write(&expected_path, expected).expect("Could not bless transcript");
+            continue;
+        }
+        let expected: Vec<Value> = match fs::read_to_string(&expected_path) {
+            Ok(expected) => expected
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str(line).expect("Invalid expected JSON"))
+                .collect(),
+            Err(_) => {
+                failures.push(format!(
+                    "{}: missing, run with EVENT_HORIZON_BLESS=1 to create it",
+                    expected_path.display()
+                ));
+                continue;
+            }
+        };
+        if let Err(mismatch) = transcript::compare(&expected, &actual) {
+            failures.push(format!("{}: {}", fixture.display(), mismatch));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn echo() {
+    check_transcripts::<echo::EchoBody, echo::EchoNode>("echo");
+}
+
+#[test]
+fn generate_id() {
+    check_transcripts::<generate_id::GenerateGuidBody, generate_id::GenerateGuidNode>(
+        "generate_id",
+    );
+}
+
+#[test]
+fn broadcast() {
+    check_transcripts::<broadcast::BroadcastBody, broadcast::BroadcastNode>("broadcast");
+}
+
+#[test]
+fn grow_counter() {
+    check_transcripts::<grow_counter::CounterBody, grow_counter::CounterNode>("grow_counter");
+}
+
+#[test]
+fn kafka() {
+    check_transcripts::<kafka::KafkaBody, kafka::KafkaNode>("kafka");
+}
+
+#[test]
+fn kv_store() {
+    check_transcripts::<kv_store::KVStoreBody, kv_store::KVStoreNode>("kv_store");
+}
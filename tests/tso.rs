@@ -0,0 +1,181 @@
+use event_horizon::node::generate_id::{GenerateGuidBody, GenerateGuidNode};
+use event_horizon::node::kv_store::{KVStoreBody, KVStoreNode};
+use event_horizon::service::local::{LocalConfig, LocalServices};
+use event_horizon::service::tso::TsoClient;
+use event_horizon::service::ServiceError;
+use event_horizon::{Event, MaelstromMessage, Node, NodeMetadata, Reply};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+struct Stamps {
+    tso: TsoClient<Stamps>,
+    stamps: Vec<Result<u64, ServiceError>>,
+}
+
+fn record(node: &mut Stamps, timestamp: Result<u64, ServiceError>, _: &mut dyn std::io::Write) {
+    node.stamps.push(timestamp);
+}
+
+fn sent(output: &mut Vec<u8>) -> Vec<MaelstromMessage<Value>> {
+    String::from_utf8(std::mem::take(output))
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn batches_callers_onto_one_request() {
+    let mut services = LocalServices::new(LocalConfig::default());
+    let mut node = Stamps {
+        tso: TsoClient::new("n1"),
+        stamps: Vec::new(),
+    };
+    let mut output = Vec::new();
+    node.tso.timestamp(&mut output, record);
+    let first = sent(&mut output);
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].dest, "lin-tso");
+    assert_eq!(first[0].body["type"], "ts");
+
+    //Callers arriving while a request is in flight share the next one.
+    for _ in 0..4 {
+        node.tso.timestamp(&mut output, record);
+    }
+    assert!(output.is_empty());
+    assert_eq!(node.tso.waiting(), 5);
+
+    let (_, reply) = services.handle(&first[0]).unwrap();
+    assert!(TsoClient::dispatch(
+        &mut node,
+        |node| &mut node.tso,
+        &reply,
+        &mut output
+    ));
+    let second = sent(&mut output);
+    assert_eq!(second.len(), 1);
+    let (_, reply) = services.handle(&second[0]).unwrap();
+    TsoClient::dispatch(&mut node, |node| &mut node.tso, &reply, &mut output);
+
+    assert_eq!(node.stamps, [Ok(1), Ok(2), Ok(2), Ok(2), Ok(2)]);
+    assert_eq!(node.tso.requests_sent, 2);
+    assert_eq!(node.tso.waiting(), 0);
+}
+
+#[test]
+fn a_lost_request_times_out_and_the_queue_moves_on() {
+    let mut node = Stamps {
+        tso: TsoClient::new("n1"),
+        stamps: Vec::new(),
+    };
+    let mut output = Vec::new();
+    node.tso.timestamp(&mut output, record);
+    node.tso.timestamp(&mut output, record);
+    let lost = sent(&mut output);
+    assert_eq!(
+        TsoClient::expire(&mut node, |node| &mut node.tso, Duration::ZERO, &mut output),
+        1
+    );
+    assert_eq!(node.stamps, [Err(ServiceError::Timeout)]);
+    assert_eq!(sent(&mut output).len(), 1);
+
+    //A late reply to the lost request completes nobody.
+    let late = MaelstromMessage {
+        src: "lin-tso".to_owned(),
+        dest: "n1".to_owned(),
+        body: json!({"type": "ts_ok", "ts": 9, "in_reply_to": lost[0].body["msg_id"]}),
+    };
+    assert!(!TsoClient::dispatch(
+        &mut node,
+        |node| &mut node.tso,
+        &late,
+        &mut output
+    ));
+    assert_eq!(node.tso.waiting(), 1);
+}
+
+fn run_with_tso<Body, NodeState>(
+    node: &mut NodeState,
+    services: &mut LocalServices,
+    requests: Vec<Value>,
+) -> Vec<MaelstromMessage<Value>>
+where
+    NodeState: Node<Body>,
+    Body: Serialize + DeserializeOwned + Reply<NodeState>,
+{
+    //! Send client requests to the node, answer its lin-tso requests from the
+    //! stand-in, and return what it sends the client.
+    let mut output = Vec::new();
+    for body in requests {
+        let line = json!({"src": "c1", "dest": "n1", "body": body}).to_string();
+        node.handle_event(
+            Event::Message(serde_json::from_str(&line).unwrap()),
+            &mut output,
+        );
+    }
+    let mut replies = Vec::new();
+    while !output.is_empty() {
+        for message in sent(&mut output) {
+            match services.handle(&message) {
+                Some((_, reply)) => node.handle_event(Event::ServiceReply(reply), &mut output),
+                None => replies.push(message),
+            }
+        }
+    }
+    replies
+}
+
+fn metadata() -> NodeMetadata {
+    NodeMetadata {
+        node_id: "n1".to_owned(),
+        node_ids: vec!["n1".to_owned()],
+    }
+}
+
+#[test]
+fn kv_store_stamps_writes_from_lin_tso() {
+    let mut services = LocalServices::new(LocalConfig::default());
+    let (event_tx, _event_rx) = channel();
+    let mut node = KVStoreNode::node_init(metadata(), event_tx);
+    node.tso = Some(TsoClient::new("n1"));
+    let replies = run_with_tso::<KVStoreBody, _>(
+        &mut node,
+        &mut services,
+        vec![
+            json!({"type": "txn", "msg_id": 1, "txn": [["w", 1, 10]]}),
+            json!({"type": "txn", "msg_id": 2, "txn": [["w", 1, 20], ["r", 1, null]]}),
+        ],
+    );
+    let in_reply_to: Vec<&Value> = replies
+        .iter()
+        .map(|reply| &reply.body["in_reply_to"])
+        .collect();
+    assert_eq!(in_reply_to, [1, 2]);
+    assert_eq!(replies[1].body["txn"], json!([["w", 1, 20], ["r", 1, 20]]));
+    //The second txn waited for its own timestamp.
+    assert_eq!(node.write_stamps[&1], (2, "n1".to_owned()));
+}
+
+#[test]
+fn generated_ids_sort_in_timestamp_order() {
+    let mut services = LocalServices::new(LocalConfig::default());
+    let (event_tx, _event_rx) = channel();
+    let mut node = GenerateGuidNode::node_init(metadata(), event_tx);
+    node.tso = Some(TsoClient::new("n1"));
+    let mut ids = Vec::new();
+    for msg_id in 0..12 {
+        let replies = run_with_tso::<GenerateGuidBody, _>(
+            &mut node,
+            &mut services,
+            vec![json!({"type": "generate", "msg_id": msg_id})],
+        );
+        ids.push(replies[0].body["id"].as_str().unwrap().to_owned());
+    }
+    let mut sorted = ids.clone();
+    sorted.sort();
+    assert_eq!(ids, sorted);
+    assert!(ids[0].starts_with("00000000000000000001|n1|"));
+}